@@ -0,0 +1,374 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Drives a headless session with arbitrary sequences of content requests
+//! and test messages, looking for panics in the session thread or device.
+//!
+//! Content never gives the session any WebGL images, so the device never
+//! touches GL, which lets this run with a GL whose functions are not loaded.
+
+#![no_main]
+
+use libfuzzer_sys::arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+
+use webxr::headless::HeadlessMockDiscovery;
+use webxr_api::ApiSpace;
+use webxr_api::BaseSpace;
+use webxr_api::ColorSpace;
+use webxr_api::EntityType;
+use webxr_api::Error;
+use webxr_api::Feature;
+use webxr_api::Frame;
+use webxr_api::FrameRequestCallback;
+use webxr_api::Hand;
+use webxr_api::Handedness;
+use webxr_api::HapticPulse;
+use webxr_api::HighResTimeStamp;
+use webxr_api::HitTestId;
+use webxr_api::HitTestInit;
+use webxr_api::InputId;
+use webxr_api::InputSource;
+use webxr_api::JointFrame;
+use webxr_api::LayerId;
+use webxr_api::LayerInit;
+use webxr_api::MainThreadRegistry;
+use webxr_api::MockClock;
+use webxr_api::MockDeviceCallback;
+use webxr_api::MockDeviceInit;
+use webxr_api::MockDeviceMsg;
+use webxr_api::MockInputInit;
+use webxr_api::MockInputMsg;
+use webxr_api::MockRegion;
+use webxr_api::MockWorld;
+use webxr_api::Ray;
+use webxr_api::SelectEvent;
+use webxr_api::SelectKind;
+use webxr_api::Session;
+use webxr_api::SessionInit;
+use webxr_api::SessionMode;
+use webxr_api::SessionRequestCallback;
+use webxr_api::TargetRayMode;
+use webxr_api::Triangle;
+use webxr_api::View;
+use webxr_api::Views;
+use webxr_api::Visibility;
+
+use euclid::Point2D;
+use euclid::Rect;
+use euclid::RigidTransform3D;
+use euclid::Size2D;
+use euclid::Transform3D;
+use euclid::Vector3D;
+
+use gleam::gl::GlFns;
+
+use std::ptr;
+use std::sync::mpsc;
+use std::sync::mpsc::Sender;
+use std::thread;
+
+#[derive(Arbitrary, Debug)]
+enum Op {
+    // Content requests
+    RequestAnimationFrame,
+    RenderAnimationFrame,
+    CreateQuadLayer(f32, f32),
+    DestroyLayer(u8),
+    SetLayers(Vec<u8>),
+    RequestHitTest(u8),
+    CancelHitTest(u8),
+    CreateAnchor(f32, f32, f32),
+    DeleteAnchor(u8),
+    RequestViewportScale(f32),
+    UpdateTargetFrameRate(f32),
+    InputHaptic(u8, f32, f64),
+    // Test messages
+    SetViewerOrigin(f32, f32, f32),
+    SetViewport(i32, i32, i32, i32),
+    SetBoundsGeometry(Option<Vec<(f32, f32)>>),
+    AddInputSource(u8, bool, bool),
+    SetPointerOrigin(u8, f32, f32, f32),
+    SetGripOrigin(u8, Option<(f32, f32, f32)>),
+    SetPinch(u8, Option<f32>),
+    DisconnectInput(u8),
+    ReconnectInput(u8),
+    TriggerSelect(u8, bool),
+    SetWorld(Vec<[f32; 9]>),
+    ClearWorld,
+    VisibilityChange(u8),
+    Disconnect,
+}
+
+struct Reply<T>(Sender<T>);
+
+impl MockDeviceCallback for Reply<Result<webxr_api::Sender<MockDeviceMsg>, Error>> {
+    fn callback(&mut self, result: Result<webxr_api::Sender<MockDeviceMsg>, Error>) {
+        let _ = self.0.send(result);
+    }
+}
+
+impl SessionRequestCallback for Reply<Result<Session, Error>> {
+    fn callback(&mut self, result: Result<Session, Error>) {
+        let _ = self.0.send(result);
+    }
+}
+
+struct IgnoreFrame;
+
+impl FrameRequestCallback for IgnoreFrame {
+    fn callback(&mut self, _: HighResTimeStamp, _: Frame) {}
+}
+
+fuzz_target!(|ops: Vec<Op>| {
+    // Safe because the mock device only uses GL to render and capture frames,
+    // which it never does without WebGL images.
+    let gl = unsafe { GlFns::load_with(|_| ptr::null()) };
+    let mut registry = MainThreadRegistry::new().unwrap();
+    registry.register_mock(HeadlessMockDiscovery::new(gl));
+
+    let (sender, receiver) = mpsc::channel();
+    registry
+        .registry()
+        .simulate_device_connection(device_init(), Reply(sender));
+    registry.run_one_frame();
+    let device = receiver.recv().unwrap().unwrap();
+
+    let (sender, receiver) = mpsc::channel();
+    let init = SessionInit {
+        optional_features: vec![
+            Feature::HitTest,
+            Feature::Anchors,
+            Feature::Layers,
+            Feature::HandTracking,
+        ],
+        ..Default::default()
+    };
+    registry
+        .registry()
+        .request_session(SessionMode::ImmersiveVR, init, Reply(sender));
+    registry.run_one_frame();
+    let session = receiver.recv().unwrap().unwrap();
+
+    // Content blocks waiting for some requests, so it has to be on another thread.
+    let content = thread::spawn(move || run_content(session, device, ops));
+    while registry.running() {
+        registry.run_one_frame();
+    }
+    content.join().unwrap();
+});
+
+fn device_init() -> MockDeviceInit {
+    let viewport = Rect::new(Point2D::new(0, 0), Size2D::new(64, 64));
+    let view = View {
+        transform: RigidTransform3D::identity(),
+        projection: Transform3D::identity(),
+        viewport,
+    };
+    MockDeviceInit {
+        floor_origin: RigidTransform3D::identity(),
+        supports_immersive: true,
+        supports_unbounded: true,
+        viewer_origin: RigidTransform3D::identity(),
+        views: Views::Mono(view),
+        supported_features: vec![
+            Feature::BoundedFloor,
+            Feature::HitTest,
+            Feature::Anchors,
+            Feature::Layers,
+            Feature::HandTracking,
+        ],
+        supported_frame_rates: vec![60.0, 90.0],
+        supported_color_spaces: vec![ColorSpace::Rec709, ColorSpace::DisplayP3],
+        bounds_geometry: None,
+        world: None,
+        clock: MockClock::FixedTimestep(16.0),
+    }
+}
+
+fn run_content(mut session: Session, device: webxr_api::Sender<MockDeviceMsg>, ops: Vec<Op>) {
+    for op in ops {
+        match op {
+            Op::RequestAnimationFrame => {
+                let _ = session.request_animation_frame(IgnoreFrame);
+            }
+            Op::RenderAnimationFrame => session.render_animation_frame(),
+            Op::CreateQuadLayer(width, height) => {
+                let _ = session.create_layer(LayerInit::Quad {
+                    transform: RigidTransform3D::identity(),
+                    size: Size2D::new(width, height),
+                });
+            }
+            Op::DestroyLayer(id) => {
+                let _ = session.destroy_layer(LayerId(id.into()));
+            }
+            Op::SetLayers(ids) => {
+                session.set_layers(ids.into_iter().map(|id| LayerId(id.into())).collect())
+            }
+            Op::RequestHitTest(space) => {
+                let space = match space % 4 {
+                    0 => BaseSpace::Local,
+                    1 => BaseSpace::Floor,
+                    2 => BaseSpace::Viewer,
+                    _ => BaseSpace::Input(InputId(space.into())),
+                };
+                let ray = Ray::<ApiSpace> {
+                    origin: Vector3D::new(0.0, 0.0, 0.0),
+                    direction: Vector3D::new(0.0, 0.0, -1.0),
+                };
+                let entity_types = vec![EntityType::Plane, EntityType::Mesh];
+                let _ = session.request_hit_test(HitTestInit {
+                    space,
+                    ray,
+                    entity_types,
+                });
+            }
+            Op::CancelHitTest(id) => session.cancel_hit_test(HitTestId(id.into())),
+            Op::CreateAnchor(x, y, z) => {
+                let _ = session
+                    .create_anchor(RigidTransform3D::from_translation(Vector3D::new(x, y, z)));
+            }
+            Op::DeleteAnchor(id) => session.delete_anchor(webxr_api::AnchorId(id.into())),
+            Op::RequestViewportScale(scale) => session.request_viewport_scale(scale),
+            Op::UpdateTargetFrameRate(rate) => {
+                let _ = session.update_target_frame_rate(rate);
+            }
+            Op::InputHaptic(id, intensity, duration) => session.input_haptic(
+                InputId(id.into()),
+                HapticPulse {
+                    intensity,
+                    duration,
+                },
+            ),
+            Op::SetViewerOrigin(x, y, z) => {
+                let origin = RigidTransform3D::from_translation(Vector3D::new(x, y, z));
+                let _ = device.send(MockDeviceMsg::SetViewerOrigin(origin));
+            }
+            Op::SetViewport(x, y, width, height) => {
+                let view = View {
+                    transform: RigidTransform3D::identity(),
+                    projection: Transform3D::identity(),
+                    viewport: Rect::new(Point2D::new(x, y), Size2D::new(width, height)),
+                };
+                let _ = device.send(MockDeviceMsg::SetViews(Views::Mono(view)));
+            }
+            Op::SetBoundsGeometry(bounds) => {
+                let bounds = bounds.map(|points| {
+                    points
+                        .into_iter()
+                        .map(|(x, y)| Point2D::new(x, y))
+                        .collect()
+                });
+                let _ = device.send(MockDeviceMsg::SetBoundsGeometry(bounds));
+            }
+            Op::AddInputSource(id, gaze, pinch_select) => {
+                let target_ray_mode = if gaze {
+                    TargetRayMode::Gaze
+                } else {
+                    TargetRayMode::TrackedPointer
+                };
+                let source = InputSource {
+                    handedness: Handedness::None,
+                    target_ray_mode,
+                    id: InputId(id.into()),
+                    profiles: vec![],
+                    has_grip: !gaze,
+                };
+                // Only tracked pointers have grips
+                let grip_origin = if gaze {
+                    None
+                } else {
+                    Some(RigidTransform3D::identity())
+                };
+                let init = MockInputInit {
+                    source,
+                    pointer_origin: RigidTransform3D::identity(),
+                    grip_origin,
+                    gamepad: None,
+                    hand: None,
+                    pinch_select,
+                };
+                let _ = device.send(MockDeviceMsg::AddInputSource(init));
+            }
+            Op::SetPointerOrigin(id, x, y, z) => {
+                let origin = RigidTransform3D::from_translation(Vector3D::new(x, y, z));
+                let msg = MockInputMsg::SetPointerOrigin(origin);
+                let _ = device.send(MockDeviceMsg::MessageInputSource(InputId(id.into()), msg));
+            }
+            Op::SetGripOrigin(id, origin) => {
+                let origin = origin
+                    .map(|(x, y, z)| RigidTransform3D::from_translation(Vector3D::new(x, y, z)));
+                let msg = MockInputMsg::SetGripOrigin(origin);
+                let _ = device.send(MockDeviceMsg::MessageInputSource(InputId(id.into()), msg));
+            }
+            Op::SetPinch(id, distance) => {
+                // A hand with only its thumb and index finger tips tracked, this far apart
+                let hand = distance.map(|distance| {
+                    let joint = |x| JointFrame {
+                        pose: RigidTransform3D::from_translation(Vector3D::new(x, 0.0, 0.0)),
+                        radius: 0.01,
+                    };
+                    let mut hand = Hand::default();
+                    hand.thumb_phalanx_tip = Some(joint(0.0));
+                    hand.index.phalanx_tip = Some(joint(distance));
+                    Box::new(hand)
+                });
+                let msg = MockInputMsg::SetHand(hand);
+                let _ = device.send(MockDeviceMsg::MessageInputSource(InputId(id.into()), msg));
+            }
+            Op::DisconnectInput(id) => {
+                let msg = MockInputMsg::Disconnect;
+                let _ = device.send(MockDeviceMsg::MessageInputSource(InputId(id.into()), msg));
+            }
+            Op::ReconnectInput(id) => {
+                let msg = MockInputMsg::Reconnect;
+                let _ = device.send(MockDeviceMsg::MessageInputSource(InputId(id.into()), msg));
+            }
+            Op::TriggerSelect(id, squeeze) => {
+                let kind = if squeeze {
+                    SelectKind::Squeeze
+                } else {
+                    SelectKind::Select
+                };
+                let msg = MockInputMsg::TriggerSelect(kind, SelectEvent::Select);
+                let _ = device.send(MockDeviceMsg::MessageInputSource(InputId(id.into()), msg));
+            }
+            Op::SetWorld(triangles) => {
+                let faces = triangles
+                    .into_iter()
+                    .map(|t| Triangle {
+                        first: Vector3D::new(t[0], t[1], t[2]),
+                        second: Vector3D::new(t[3], t[4], t[5]),
+                        third: Vector3D::new(t[6], t[7], t[8]),
+                    })
+                    .collect();
+                let region = MockRegion {
+                    faces,
+                    ty: EntityType::Plane,
+                };
+                let world = MockWorld {
+                    regions: vec![region],
+                };
+                let _ = device.send(MockDeviceMsg::SetWorld(world));
+            }
+            Op::ClearWorld => {
+                let _ = device.send(MockDeviceMsg::ClearWorld);
+            }
+            Op::VisibilityChange(visibility) => {
+                let visibility = match visibility % 3 {
+                    0 => Visibility::Visible,
+                    1 => Visibility::VisibleBlurred,
+                    _ => Visibility::Hidden,
+                };
+                let _ = device.send(MockDeviceMsg::VisibilityChange(visibility));
+            }
+            Op::Disconnect => {
+                let (sender, _receiver) = webxr_api::channel().unwrap();
+                let _ = device.send(MockDeviceMsg::Disconnect(sender));
+            }
+        }
+    }
+    // Make sure the session ends, so the fuzz target returns.
+    session.end_session();
+}
@@ -0,0 +1,53 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Anchors, as used by the WebXR Anchors module.
+
+use crate::Anchor;
+use crate::Native;
+
+use euclid::RigidTransform3D;
+
+#[cfg(feature = "ipc")]
+use serde::{Deserialize, Serialize};
+
+/// An identifier for an anchor, unique within its session
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub struct AnchorId(pub u32);
+
+/// The pose of an anchor the device is tracking this frame
+/// https://immersive-web.github.io/anchors/#xranchor
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub struct AnchorFrame {
+    pub id: AnchorId,
+    pub pose: RigidTransform3D<f32, Anchor, Native>,
+}
+
+/// An anchor exported from a device, as an opaque blob which can be stored or
+/// shared, and later imported into another session. What is in it is up to the
+/// device, and it can only be imported by devices which understand it, such as
+/// ones using the same runtime or cloud anchor service.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub struct ExportedAnchor(pub Vec<u8>);
+
+/// Where on Earth to create an anchor, as used by geospatial anchors.
+/// Devices which can work out where they are, for example from GPS and
+/// a map of the surroundings, resolve this to a pose, and keep updating
+/// the pose as their estimate of where they are improves.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub struct GeospatialAnchorInit {
+    /// In degrees north of the equator
+    pub latitude: f64,
+    /// In degrees east of the prime meridian
+    pub longitude: f64,
+    /// In meters above the WGS84 ellipsoid
+    pub altitude: f64,
+    /// Which way the anchor's -Z axis faces, in degrees clockwise from north.
+    /// The anchor's Y axis points up.
+    pub heading: f64,
+}
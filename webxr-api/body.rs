@@ -0,0 +1,194 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Full body joint data, as used by the WebXR Body Tracking module.
+
+use crate::Hand;
+use crate::HandJoint;
+
+#[cfg(feature = "ipc")]
+use serde::{Deserialize, Serialize};
+
+/// The joints of the user's body. Joints which are not
+/// currently tracked are `None`.
+/// https://immersive-web.github.io/body-tracking/#xrbody-interface
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub struct Body<J> {
+    pub hips: Option<J>,
+    pub spine_lower: Option<J>,
+    pub spine_middle: Option<J>,
+    pub spine_upper: Option<J>,
+    pub chest: Option<J>,
+    pub neck: Option<J>,
+    pub head: Option<J>,
+    pub left: BodySide<J>,
+    pub right: BodySide<J>,
+}
+
+/// The joints of one side of the body
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub struct BodySide<J> {
+    pub shoulder: Option<J>,
+    pub scapula: Option<J>,
+    pub arm_upper: Option<J>,
+    pub arm_lower: Option<J>,
+    pub hand_wrist_twist: Option<J>,
+    pub hand: Hand<J>,
+    pub upper_leg: Option<J>,
+    pub lower_leg: Option<J>,
+    pub foot_ankle_twist: Option<J>,
+    pub foot_ankle: Option<J>,
+    pub foot_subtalar: Option<J>,
+    pub foot_transverse: Option<J>,
+    pub foot_ball: Option<J>,
+}
+
+/// The names of the joints of a body
+/// https://immersive-web.github.io/body-tracking/#xrbody-interface
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub enum BodyJoint {
+    Hips,
+    SpineLower,
+    SpineMiddle,
+    SpineUpper,
+    Chest,
+    Neck,
+    Head,
+    Left(BodySideJoint),
+    Right(BodySideJoint),
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub enum BodySideJoint {
+    Shoulder,
+    Scapula,
+    ArmUpper,
+    ArmLower,
+    HandWristTwist,
+    Hand(HandJoint),
+    UpperLeg,
+    LowerLeg,
+    FootAnkleTwist,
+    FootAnkle,
+    FootSubtalar,
+    FootTransverse,
+    FootBall,
+}
+
+impl<J> Default for Body<J> {
+    fn default() -> Self {
+        Body {
+            hips: None,
+            spine_lower: None,
+            spine_middle: None,
+            spine_upper: None,
+            chest: None,
+            neck: None,
+            head: None,
+            left: BodySide::default(),
+            right: BodySide::default(),
+        }
+    }
+}
+
+impl<J> Default for BodySide<J> {
+    fn default() -> Self {
+        BodySide {
+            shoulder: None,
+            scapula: None,
+            arm_upper: None,
+            arm_lower: None,
+            hand_wrist_twist: None,
+            hand: Hand::default(),
+            upper_leg: None,
+            lower_leg: None,
+            foot_ankle_twist: None,
+            foot_ankle: None,
+            foot_subtalar: None,
+            foot_transverse: None,
+            foot_ball: None,
+        }
+    }
+}
+
+impl<J> Body<J> {
+    pub fn get(&self, joint: BodyJoint) -> Option<&J> {
+        match joint {
+            BodyJoint::Hips => self.hips.as_ref(),
+            BodyJoint::SpineLower => self.spine_lower.as_ref(),
+            BodyJoint::SpineMiddle => self.spine_middle.as_ref(),
+            BodyJoint::SpineUpper => self.spine_upper.as_ref(),
+            BodyJoint::Chest => self.chest.as_ref(),
+            BodyJoint::Neck => self.neck.as_ref(),
+            BodyJoint::Head => self.head.as_ref(),
+            BodyJoint::Left(joint) => self.left.get(joint),
+            BodyJoint::Right(joint) => self.right.get(joint),
+        }
+    }
+
+    pub fn map<R>(&self, mut map: impl FnMut(Option<&J>, BodyJoint) -> Option<R>) -> Body<R> {
+        Body {
+            hips: map(self.hips.as_ref(), BodyJoint::Hips),
+            spine_lower: map(self.spine_lower.as_ref(), BodyJoint::SpineLower),
+            spine_middle: map(self.spine_middle.as_ref(), BodyJoint::SpineMiddle),
+            spine_upper: map(self.spine_upper.as_ref(), BodyJoint::SpineUpper),
+            chest: map(self.chest.as_ref(), BodyJoint::Chest),
+            neck: map(self.neck.as_ref(), BodyJoint::Neck),
+            head: map(self.head.as_ref(), BodyJoint::Head),
+            left: self.left.map(|j, s| map(j, BodyJoint::Left(s))),
+            right: self.right.map(|j, s| map(j, BodyJoint::Right(s))),
+        }
+    }
+}
+
+impl<J> BodySide<J> {
+    pub fn get(&self, joint: BodySideJoint) -> Option<&J> {
+        match joint {
+            BodySideJoint::Shoulder => self.shoulder.as_ref(),
+            BodySideJoint::Scapula => self.scapula.as_ref(),
+            BodySideJoint::ArmUpper => self.arm_upper.as_ref(),
+            BodySideJoint::ArmLower => self.arm_lower.as_ref(),
+            BodySideJoint::HandWristTwist => self.hand_wrist_twist.as_ref(),
+            BodySideJoint::Hand(joint) => self.hand.get(joint),
+            BodySideJoint::UpperLeg => self.upper_leg.as_ref(),
+            BodySideJoint::LowerLeg => self.lower_leg.as_ref(),
+            BodySideJoint::FootAnkleTwist => self.foot_ankle_twist.as_ref(),
+            BodySideJoint::FootAnkle => self.foot_ankle.as_ref(),
+            BodySideJoint::FootSubtalar => self.foot_subtalar.as_ref(),
+            BodySideJoint::FootTransverse => self.foot_transverse.as_ref(),
+            BodySideJoint::FootBall => self.foot_ball.as_ref(),
+        }
+    }
+
+    pub fn map<R>(
+        &self,
+        mut map: impl FnMut(Option<&J>, BodySideJoint) -> Option<R>,
+    ) -> BodySide<R> {
+        BodySide {
+            shoulder: map(self.shoulder.as_ref(), BodySideJoint::Shoulder),
+            scapula: map(self.scapula.as_ref(), BodySideJoint::Scapula),
+            arm_upper: map(self.arm_upper.as_ref(), BodySideJoint::ArmUpper),
+            arm_lower: map(self.arm_lower.as_ref(), BodySideJoint::ArmLower),
+            hand_wrist_twist: map(
+                self.hand_wrist_twist.as_ref(),
+                BodySideJoint::HandWristTwist,
+            ),
+            hand: self.hand.map(|j, h| map(j, BodySideJoint::Hand(h))),
+            upper_leg: map(self.upper_leg.as_ref(), BodySideJoint::UpperLeg),
+            lower_leg: map(self.lower_leg.as_ref(), BodySideJoint::LowerLeg),
+            foot_ankle_twist: map(
+                self.foot_ankle_twist.as_ref(),
+                BodySideJoint::FootAnkleTwist,
+            ),
+            foot_ankle: map(self.foot_ankle.as_ref(), BodySideJoint::FootAnkle),
+            foot_subtalar: map(self.foot_subtalar.as_ref(), BodySideJoint::FootSubtalar),
+            foot_transverse: map(self.foot_transverse.as_ref(), BodySideJoint::FootTransverse),
+            foot_ball: map(self.foot_ball.as_ref(), BodySideJoint::FootBall),
+        }
+    }
+}
@@ -0,0 +1,33 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Images from the device's cameras, as used by the WebXR Raw Camera Access module.
+
+use crate::Camera;
+use crate::Display;
+use crate::Viewer;
+
+use euclid::RigidTransform3D;
+use euclid::Size2D;
+use euclid::Transform3D;
+
+use gleam::gl::GLuint;
+
+#[cfg(feature = "ipc")]
+use serde::{Deserialize, Serialize};
+
+/// The image from the camera used for a view
+/// https://immersive-web.github.io/raw-camera-access/#xr-camera-section
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub struct CameraImage {
+    /// A GL texture containing the image, valid until the next frame
+    pub texture_id: GLuint,
+    /// The size of the image, in pixels
+    pub size: Size2D<i32, Camera>,
+    /// The transform from the viewer to the camera
+    pub transform: RigidTransform3D<f32, Viewer, Camera>,
+    /// The camera intrinsics, as a projection matrix
+    pub projection: Transform3D<f32, Camera, Display>,
+}
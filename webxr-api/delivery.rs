@@ -0,0 +1,197 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Giving frames to content from a thread of their own, so a content process which
+//! stops taking them, and whose channel fills up, can't block the session thread.
+//! Only the newest frame waits to be given, replacing any older one.
+
+use crate::Frame;
+use crate::FrameRequestCallback;
+use crate::HighResTimeStamp;
+
+use std::sync::Arc;
+use std::sync::Condvar;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+type PendingFrame = (Box<dyn FrameRequestCallback>, HighResTimeStamp, Frame);
+
+#[derive(Default)]
+struct DeliveryState {
+    // The frame waiting to be given to content
+    pending: Option<PendingFrame>,
+    // When content started taking the frame it is being given, if it is
+    delivering: Option<Instant>,
+    closed: bool,
+}
+
+#[derive(Default)]
+struct Shared {
+    state: Mutex<DeliveryState>,
+    condvar: Condvar,
+}
+
+/// The thread a session's frames are given to content from
+pub(crate) struct FrameDelivery {
+    shared: Arc<Shared>,
+}
+
+impl FrameDelivery {
+    pub(crate) fn new() -> FrameDelivery {
+        let shared = Arc::new(Shared::default());
+        let thread_shared = shared.clone();
+        // If spawning fails, frames are given to content on the session thread instead
+        let _ = thread::Builder::new()
+            .name("WebXRFrameDelivery".into())
+            .spawn(move || deliver_frames(&thread_shared));
+        FrameDelivery { shared }
+    }
+
+    /// Give a frame to content without waiting for it to be taken, returning
+    /// whether it replaced an older frame content hadn't been given yet.
+    /// The replaced frame's callback is dropped without being called, so content
+    /// is only ever given the newest frame.
+    pub(crate) fn deliver(
+        &self,
+        mut callback: Box<dyn FrameRequestCallback>,
+        time: HighResTimeStamp,
+        frame: Frame,
+    ) -> bool {
+        if Arc::strong_count(&self.shared) == 1 {
+            let _span = crate::span("deliver_frame");
+            callback.callback(time, frame);
+            return false;
+        }
+        let mut state = self.shared.state.lock().unwrap();
+        let replaced = state.pending.replace((callback, time, frame)).is_some();
+        self.shared.condvar.notify_one();
+        replaced
+    }
+
+    /// How long content has been taking the frame it is being given, if it is
+    pub(crate) fn stalled_for(&self) -> Option<Duration> {
+        let state = self.shared.state.lock().unwrap();
+        state.delivering.map(|start| start.elapsed())
+    }
+}
+
+impl Drop for FrameDelivery {
+    // Frames which haven't been given to content by the time the session ends never are.
+    // The thread isn't waited for, since content may never take the frame it is giving.
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.pending = None;
+        state.closed = true;
+        self.shared.condvar.notify_one();
+    }
+}
+
+fn deliver_frames(shared: &Shared) {
+    let mut state = shared.state.lock().unwrap();
+    loop {
+        if let Some((mut callback, time, frame)) = state.pending.take() {
+            state.delivering = Some(Instant::now());
+            drop(state);
+            {
+                let _span = crate::span("deliver_frame");
+                callback.callback(time, frame);
+            }
+            state = shared.state.lock().unwrap();
+            state.delivering = None;
+        } else if state.closed {
+            return;
+        } else {
+            state = shared.condvar.wait(state).unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::Receiver;
+    use crate::Sender;
+
+    use euclid::RigidTransform3D;
+
+    #[cfg(feature = "ipc")]
+    use serde::{Deserialize, Serialize};
+
+    // Sends the time of the frame it is given
+    #[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+    struct SendTime(Sender<HighResTimeStamp>);
+
+    #[cfg_attr(feature = "ipc", typetag::serde)]
+    impl FrameRequestCallback for SendTime {
+        fn callback(&mut self, time: HighResTimeStamp, _frame: Frame) {
+            let _ = self.0.send(time);
+        }
+    }
+
+    // Sends the time of the frame it is given, then takes it until told to stop
+    #[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+    struct Stall(Sender<HighResTimeStamp>, Receiver<()>);
+
+    #[cfg_attr(feature = "ipc", typetag::serde)]
+    impl FrameRequestCallback for Stall {
+        fn callback(&mut self, time: HighResTimeStamp, _frame: Frame) {
+            let _ = self.0.send(time);
+            let _ = self.1.recv();
+        }
+    }
+
+    fn frame() -> Frame {
+        Frame {
+            transform: RigidTransform3D::identity(),
+            velocity: Default::default(),
+            inputs: vec![],
+            depth: vec![],
+            occlusion_depth: vec![],
+            light_estimate: None,
+            camera_images: vec![],
+            hit_test_results: vec![],
+            anchors: vec![],
+            tracked_images: vec![],
+            body: None,
+            face: None,
+            viewports: Default::default(),
+            device_images: vec![],
+            timing: Default::default(),
+        }
+    }
+
+    #[test]
+    fn frames_are_given_in_order() {
+        let delivery = FrameDelivery::new();
+        let (sender, times) = crate::channel().unwrap();
+        for time in 1..4 {
+            assert!(!delivery.deliver(Box::new(SendTime(sender.clone())), time as f64, frame()));
+            assert_eq!(times.recv().unwrap(), time as f64);
+        }
+    }
+
+    #[test]
+    fn newer_frames_replace_ones_not_yet_given() {
+        let delivery = FrameDelivery::new();
+        let (stalled, stalled_times) = crate::channel().unwrap();
+        let (resume, resumed) = crate::channel().unwrap();
+        delivery.deliver(Box::new(Stall(stalled, resumed)), 1.0, frame());
+        assert_eq!(stalled_times.recv().unwrap(), 1.0);
+        assert!(delivery.stalled_for().is_some());
+
+        // Content is still taking the first frame, so the second waits and is replaced
+        let (replaced, replaced_times) = crate::channel().unwrap();
+        let (newest, newest_times) = crate::channel().unwrap();
+        assert!(!delivery.deliver(Box::new(SendTime(replaced)), 2.0, frame()));
+        assert!(delivery.deliver(Box::new(SendTime(newest)), 3.0, frame()));
+        // The replaced frame's callback was dropped without being called
+        assert!(replaced_times.recv().is_err());
+
+        resume.send(()).unwrap();
+        assert_eq!(newest_times.recv().unwrap(), 3.0);
+    }
+}
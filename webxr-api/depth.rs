@@ -0,0 +1,102 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Per-view depth information, as used by the WebXR Depth Sensing module.
+
+use crate::DepthBuffer;
+use crate::NormalizedView;
+
+use euclid::RigidTransform3D;
+use euclid::Size2D;
+
+use gleam::gl::GLuint;
+
+#[cfg(feature = "ipc")]
+use serde::{Deserialize, Serialize};
+
+/// Whether content wants to read depth on the CPU or the GPU
+/// https://immersive-web.github.io/depth-sensing/#enumdef-xrdepthusage
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub enum DepthUsage {
+    CpuOptimized,
+    GpuOptimized,
+}
+
+/// The format of each depth value
+/// https://immersive-web.github.io/depth-sensing/#enumdef-xrdepthdataformat
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub enum DepthDataFormat {
+    /// Two bytes per value, as an unsigned 16 bit integer
+    LuminanceAlpha,
+    /// Four bytes per value, as a float
+    Float32,
+}
+
+/// The depth sensing configurations content will accept, most preferred first
+/// https://immersive-web.github.io/depth-sensing/#dictdef-xrdepthstateinit
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub struct DepthSensingInit {
+    pub usage_preference: Vec<DepthUsage>,
+    pub data_format_preference: Vec<DepthDataFormat>,
+}
+
+/// The depth sensing configuration the device chose
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub struct DepthSensingConfig {
+    pub usage: DepthUsage,
+    pub data_format: DepthDataFormat,
+}
+
+impl DepthSensingInit {
+    /// Pick the most preferred configuration the device supports.
+    /// An empty preference list means content will accept anything.
+    pub fn negotiate(
+        &self,
+        usages: &[DepthUsage],
+        data_formats: &[DepthDataFormat],
+    ) -> Option<DepthSensingConfig> {
+        let usage = pick(&self.usage_preference, usages)?;
+        let data_format = pick(&self.data_format_preference, data_formats)?;
+        Some(DepthSensingConfig { usage, data_format })
+    }
+}
+
+fn pick<T: Copy + PartialEq>(preferred: &[T], supported: &[T]) -> Option<T> {
+    if preferred.is_empty() {
+        supported.first().cloned()
+    } else {
+        preferred
+            .iter()
+            .find(|value| supported.contains(value))
+            .cloned()
+    }
+}
+
+/// The depth buffer for one view
+/// https://immersive-web.github.io/depth-sensing/#xrdepthinformation
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub struct DepthInfo {
+    /// The size of the depth buffer, in values
+    pub size: Size2D<u32, DepthBuffer>,
+    /// Converts normalized view coordinates to normalized depth buffer coordinates
+    pub norm_depth_buffer_from_norm_view: RigidTransform3D<f32, NormalizedView, DepthBuffer>,
+    /// Multiply a raw depth value by this to get meters
+    pub raw_value_to_meters: f32,
+    pub data: DepthData,
+}
+
+/// Where the depth values are, depending on the negotiated usage
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub enum DepthData {
+    /// The values, row by row, in the negotiated data format
+    Cpu(Vec<u8>),
+    /// A GL texture containing the values, valid until the next frame
+    Gpu(GLuint),
+}
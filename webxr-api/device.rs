@@ -4,28 +4,94 @@
 
 //! Traits to be implemented by backends
 
+use crate::Anchor;
+use crate::AnchorId;
+use crate::ColorSpace;
+use crate::DepthSensingConfig;
+use crate::DeviceImage;
+use crate::EnvironmentBlendMode;
 use crate::Error;
 use crate::EventCallback;
+use crate::EventFilter;
+use crate::ExportedAnchor;
+use crate::Feature;
 use crate::Floor;
 use crate::Frame;
+use crate::FrameCapture;
+use crate::GeospatialAnchorInit;
+use crate::HapticPulse;
+use crate::HighResTimeStamp;
+use crate::HitTestId;
+use crate::HitTestInit;
+use crate::InputId;
 use crate::InputSource;
+use crate::LayerId;
+use crate::LayerInit;
+use crate::LayerTexture;
 use crate::Native;
+use crate::PerformanceDomain;
+use crate::PerformanceLevel;
+use crate::Poses;
 use crate::Session;
 use crate::SessionBuilder;
+use crate::SessionCapabilities;
+use crate::SessionInit;
 use crate::SessionMode;
+use crate::SwapchainId;
+use crate::SwapchainInit;
+use crate::SwapchainLayout;
+use crate::TextureHandle;
+use crate::TrackedImageId;
+use crate::TrackedImageInit;
+use crate::TrackedImageScore;
 use crate::Viewport;
 use crate::Views;
 
 use euclid::default::Size2D as UntypedSize2D;
+use euclid::Point2D;
+use euclid::Rect;
 use euclid::RigidTransform3D;
 use euclid::Size2D;
 
 use gleam::gl::GLsync;
 
+use std::ptr;
+use std::time::Duration;
+
 /// A trait for discovering XR devices
 pub trait Discovery: 'static {
-    fn request_session(&mut self, mode: SessionMode, xr: SessionBuilder) -> Result<Session, Error>;
+    /// Request a session. Discoveries should refuse sessions whose
+    /// required features they do not support, see `SessionInit::validate`.
+    fn request_session(
+        &mut self,
+        mode: SessionMode,
+        init: &SessionInit,
+        xr: SessionBuilder,
+    ) -> Result<Session, Error>;
     fn supports_session(&self, mode: SessionMode) -> bool;
+
+    /// The features a session of this mode could be granted, if this is known
+    /// without requesting one. When several devices support a session, the
+    /// registry prefers the one supporting the most of the features asked for.
+    fn supported_features(&self, _mode: SessionMode) -> Option<Vec<Feature>> {
+        None
+    }
+
+    /// What a session of this mode could be given, or None if the mode is unsupported.
+    /// By default this only reports the features, so discoveries which know more
+    /// without requesting a session should say so.
+    fn capabilities(&self, mode: SessionMode) -> Option<SessionCapabilities> {
+        if !self.supports_session(mode) {
+            return None;
+        }
+        Some(SessionCapabilities {
+            mode,
+            features: self.supported_features(mode),
+            environment_blend_modes: vec![],
+            frame_rates: vec![],
+            view_count: None,
+        })
+    }
 }
 
 /// A trait for using an XR device
@@ -33,38 +99,336 @@ pub trait Device: 'static {
     /// The transform from native coordinates to the floor.
     fn floor_transform(&self) -> RigidTransform3D<f32, Native, Floor>;
 
+    /// The boundary of the play area, as a polygon on the floor, for devices which have one.
+    /// Changes should be communicated with an `Event::UpdateBoundsGeometry`.
+    /// https://immersive-web.github.io/webxr/#dom-xrboundedreferencespace-boundsgeometry
+    fn bounds_geometry(&self) -> Option<Vec<Point2D<f32, Floor>>> {
+        None
+    }
+
     /// The transforms from viewer coordinates to the eyes, and their associated viewports.
+    /// Changes should be communicated with an `Event::UpdateViews`.
     fn views(&self) -> Views;
 
+    /// Like `views`, but replacing `views`, so its buffer can be reused rather than
+    /// allocated every frame. Devices with `Views::Multi` can override this.
+    fn views_into(&self, views: &mut Views) {
+        *views = self.views();
+    }
+
     /// A resolution large enough to contain all the viewports.
     /// https://immersive-web.github.io/webxr/#native-webgl-framebuffer-resolution
     fn recommended_framebuffer_resolution(&self) -> Size2D<i32, Viewport> {
-        let viewport = match self.views() {
-            Views::Mono(view) => view.viewport,
-            Views::Stereo(left, right) => left.viewport.union(&right.viewport),
-        };
+        let viewports = self.views().viewports();
+        let viewport = viewports
+            .iter()
+            .fold(Rect::zero(), |union, viewport| union.union(viewport));
         Size2D::new(viewport.max_x(), viewport.max_y())
     }
 
+    /// The frame rates the device can run at, in Hz.
+    /// This is empty if the frame rate cannot be changed.
+    /// https://immersive-web.github.io/webxr/#dom-xrsession-supportedframerates
+    fn supported_frame_rates(&self) -> Vec<f32> {
+        vec![]
+    }
+
+    /// Ask the device to run at a different frame rate, which will be
+    /// one of the supported frame rates. If the frame rate changes,
+    /// it should be communicated with an `Event::FrameRateChange`.
+    fn update_frame_rate(&mut self, _rate: f32) {}
+
+    /// The color spaces the device can display frames in.
+    /// This is empty if the color space cannot be changed.
+    fn supported_color_spaces(&self) -> Vec<ColorSpace> {
+        vec![]
+    }
+
+    /// The color space the device displays frames in, if it knows
+    fn color_space(&self) -> Option<ColorSpace> {
+        None
+    }
+
+    /// Ask the device to display frames in a different color space, which will be
+    /// one of the supported color spaces. If the color space changes,
+    /// it should be communicated with an `Event::ColorSpaceChange`.
+    fn update_color_space(&mut self, _space: ColorSpace) {}
+
+    /// Content would like the device to run a processor at a performance level.
+    /// Devices which can't change their performance can leave this as the default.
+    fn set_performance_level(&mut self, _domain: PerformanceDomain, _level: PerformanceLevel) {}
+
+    /// Start or stop reporting the real world's depth for occlusion in each frame.
+    /// Devices which can't sense depth can leave this as the default, which refuses
+    /// to start.
+    fn set_occlusion(&mut self, enabled: bool) -> Result<(), Error> {
+        if enabled {
+            Err(Error::UnsupportedFeature)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Content changed the depths of its near and far clip planes, in meters.
+    /// Devices should use them for the projections of their views, and for
+    /// reprojecting frames, sending an `Event::UpdateViews` if the views change.
+    /// Devices whose projections are fixed can leave this as the default.
+    /// https://immersive-web.github.io/webxr/#dom-xrrenderstate-depthnear
+    fn update_clip_planes(&mut self, _near: f32, _far: f32) {}
+
+    /// Ask the device to render each view from the part of its viewport scaled by
+    /// `scale`, as given by `Views::scaled_viewports`, from the next frame on.
+    /// This returns the scale the device will use, devices which cannot scale
+    /// their viewports can leave this as the default, which always uses 1.0.
+    /// https://immersive-web.github.io/webxr/#dom-xrview-requestviewportscale
+    fn update_viewport_scale(&mut self, _scale: f32) -> f32 {
+        1.0
+    }
+
+    /// Whether content renders projection layers to one shared swapchain or to a
+    /// swapchain per view. Devices which support per-view swapchains should pick
+    /// the layout with `SessionInit::negotiate_swapchain_layout`.
+    fn swapchain_layout(&self) -> SwapchainLayout {
+        SwapchainLayout::Shared
+    }
+
+    /// Whether content can render the views to the layers of a texture array swapchain,
+    /// as with OVR_multiview2, rather than side by side. Devices which support this
+    /// must accept swapchains with an `array_size` up to the number of views.
+    fn supports_multiview(&self) -> bool {
+        false
+    }
+
+    /// The most frames content can be given before rendering the first of them.
+    /// Devices which need each frame rendered before waiting for the next can
+    /// leave this as the default. Frames are always rendered in order.
+    fn max_frames_in_flight(&self) -> usize {
+        1
+    }
+
+    /// How long content has to render a frame after being given it, before the
+    /// device must display something anyway. Devices whose runtime times out
+    /// without a frame each display period should return that period, and
+    /// devices which can wait for content can leave this as the default, which is none.
+    fn frame_deadline(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Content has not rendered its frame by the deadline. The device should
+    /// display its previous frame again, or nothing, so the runtime reprojects
+    /// rather than stalling. This is called each deadline until content renders.
+    fn missed_frame(&mut self) {}
+
     /// This method should block waiting for the next frame,
     /// and return the information for it.
     fn wait_for_animation_frame(&mut self) -> Frame;
 
+    /// Like `wait_for_animation_frame`, but filling in a frame content has finished
+    /// with, so its buffers can be reused rather than allocated every frame.
+    /// Devices can leave this as the default, which replaces the frame.
+    fn fill_animation_frame(&mut self, frame: &mut Frame) {
+        *frame = self.wait_for_animation_frame();
+    }
+
+    /// Predict the poses of the viewer and input sources at a time, which is usually
+    /// between the last frame's time and its predicted display time.
+    /// Devices which cannot predict poses can leave this as the default, which refuses.
+    fn poses_at(&mut self, _time: HighResTimeStamp) -> Result<Poses, Error> {
+        Err(Error::UnsupportedFeature)
+    }
+
     /// This method should render a GL texture to the device.
     /// While this method is being called, the device has unique access
     /// to the texture. The texture should be sync'd using glWaitSync before being used.
     fn render_animation_frame(&mut self, texture_id: u32, size: UntypedSize2D<i32>, sync: GLsync);
 
+    /// A sync object which is signalled once the GPU has finished with the textures
+    /// the device was just given to render. Content's textures are unlocked with it,
+    /// so content can wait for it on the GPU before rendering to them again, rather
+    /// than the device having to finish its work before they are unlocked.
+    /// The sync object should stay valid until the next frame is rendered.
+    /// Devices which finish with the textures before returning from rendering
+    /// can leave this as the default, which is null.
+    fn render_sync(&mut self) -> GLsync {
+        ptr::null()
+    }
+
+    /// Read back the frame which has just been rendered. This is called after
+    /// rendering, while content's textures are still locked.
+    /// Devices which cannot do this can leave this as the default.
+    fn capture_frame(&mut self) -> Option<FrameCapture> {
+        None
+    }
+
+    /// Create a new layer. Devices which only support projection layers
+    /// can leave this as the default, which refuses anything else.
+    fn create_layer(&mut self, _id: LayerId, init: LayerInit) -> Result<(), Error> {
+        if init.is_projection() {
+            Ok(())
+        } else {
+            Err(Error::UnsupportedFeature)
+        }
+    }
+
+    /// Change the parameters of a layer, keeping its kind.
+    /// The new parameters are also passed to `render_layers` from then on.
+    fn update_layer(&mut self, _id: LayerId, _init: LayerInit) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Destroy a layer created by `create_layer`.
+    fn destroy_layer(&mut self, _id: LayerId) {}
+
+    /// The texture content should render a layer to this frame, for devices which
+    /// can share their own textures, such as those of their swapchain. If content
+    /// renders to it, the layer's `LayerTexture` says so, and the device can display
+    /// it without copying. Other devices can leave this as the default.
+    fn device_image(&mut self, _id: LayerId) -> Option<DeviceImage> {
+        None
+    }
+
+    /// Create a swapchain of images content can render layers to, returning handles to
+    /// the images. The images must have the format and allow the usage asked for,
+    /// or the device should refuse. Devices which cannot share their textures can leave
+    /// this as the default, which refuses.
+    fn create_swapchain(
+        &mut self,
+        _id: SwapchainId,
+        _init: SwapchainInit,
+    ) -> Result<Vec<TextureHandle>, Error> {
+        Err(Error::UnsupportedFeature)
+    }
+
+    /// Wait for an image of a swapchain to be free for content to render to,
+    /// and return its index.
+    fn acquire_swapchain_image(&mut self, _id: SwapchainId) -> Result<usize, Error> {
+        Err(Error::UnsupportedFeature)
+    }
+
+    /// Content has finished rendering to an image it acquired. Layers rendered
+    /// from the swapchain are displayed from this image until the next is released.
+    fn release_swapchain_image(&mut self, _id: SwapchainId, _index: usize) {}
+
+    /// Destroy a swapchain created by `create_swapchain`.
+    fn destroy_swapchain(&mut self, _id: SwapchainId) {}
+
+    /// This method should composite the layers, back to front. The same rules
+    /// apply to each texture as to `render_animation_frame`.
+    /// Devices which only support projection layers can leave this as the default,
+    /// which renders the frontmost projection layer with a GL texture.
+    fn render_layers(&mut self, layers: &[LayerTexture]) {
+        let layer = layers
+            .iter()
+            .rev()
+            .find(|layer| layer.init.is_projection() && layer.texture_id != 0);
+        if let Some(layer) = layer {
+            self.render_animation_frame(layer.texture_id, layer.size, layer.sync);
+        }
+    }
+
+    /// The features granted to this session
+    fn granted_features(&self) -> Vec<Feature>;
+
+    /// The depth sensing configuration, if depth sensing was granted.
+    /// Depth information is then reported in every frame.
+    fn depth_sensing_config(&self) -> Option<DepthSensingConfig> {
+        None
+    }
+
+    /// How content is shown over the real world. Devices which don't show the
+    /// real world can leave this as the default.
+    fn environment_blend_mode(&self) -> EnvironmentBlendMode {
+        EnvironmentBlendMode::Opaque
+    }
+
+    /// Start hit testing. Results are then reported in every frame.
+    /// Devices which cannot hit test can leave this as the default, which refuses.
+    fn request_hit_test(&mut self, _id: HitTestId, _init: HitTestInit) -> Result<(), Error> {
+        Err(Error::UnsupportedFeature)
+    }
+
+    /// Stop a hit test started by `request_hit_test`.
+    fn cancel_hit_test(&mut self, _id: HitTestId) {}
+
+    /// Create an anchor at a pose. Its pose is then reported in every frame
+    /// for which the device is tracking it. Devices which cannot track
+    /// anchors can leave this as the default, which refuses.
+    fn create_anchor(
+        &mut self,
+        _id: AnchorId,
+        _pose: RigidTransform3D<f32, Anchor, Native>,
+    ) -> Result<(), Error> {
+        Err(Error::UnsupportedFeature)
+    }
+
+    /// Create an anchor at a place on Earth, which is then reported in every
+    /// frame like other anchors. Devices should keep its pose up to date as they
+    /// work out where they are, and leave it out of frames until they know.
+    /// Devices which cannot locate themselves can leave this as the default,
+    /// which refuses.
+    fn create_geospatial_anchor(
+        &mut self,
+        _id: AnchorId,
+        _init: GeospatialAnchorInit,
+    ) -> Result<(), Error> {
+        Err(Error::UnsupportedFeature)
+    }
+
+    /// Stop tracking an anchor created by `create_anchor` or `create_geospatial_anchor`.
+    fn delete_anchor(&mut self, _id: AnchorId) {}
+
+    /// Export an anchor, so it can be stored or shared and later imported, for
+    /// example to build shared anchors on top of a cloud anchor service.
+    /// Devices which cannot export anchors can leave this as the default, which refuses.
+    fn export_anchor(&mut self, _id: AnchorId) -> Result<ExportedAnchor, Error> {
+        Err(Error::UnsupportedFeature)
+    }
+
+    /// Create an anchor from one exported by `export_anchor`, which is then reported
+    /// in every frame for which the device finds it in the world. Devices which
+    /// cannot import anchors can leave this as the default, which refuses.
+    fn import_anchor(&mut self, _id: AnchorId, _anchor: ExportedAnchor) -> Result<(), Error> {
+        Err(Error::UnsupportedFeature)
+    }
+
+    /// Start looking for a reference image, scoring whether it can be tracked.
+    /// Trackable images are reported in every frame for which the device knows
+    /// where they are. Devices which cannot track images can leave this as the
+    /// default, which refuses.
+    fn add_tracked_image(
+        &mut self,
+        _id: TrackedImageId,
+        _init: TrackedImageInit,
+    ) -> Result<TrackedImageScore, Error> {
+        Err(Error::UnsupportedFeature)
+    }
+
+    /// Stop looking for an image added by `add_tracked_image`.
+    fn remove_tracked_image(&mut self, _id: TrackedImageId) {}
+
     /// Inputs registered with the device on initialization. More may be added, which
     /// should be communicated through a yet-undecided event mechanism
     fn initial_inputs(&self) -> Vec<InputSource>;
 
-    /// Sets the event handling callback
+    /// Vibrate an input source. Devices without haptic actuators can ignore this.
+    fn input_haptic(&mut self, _id: InputId, _pulse: HapticPulse) {}
+
+    /// Sets the event handling callback, which receives any events
+    /// not matched by a subscription
     fn set_event_callback(&mut self, callback: Box<dyn EventCallback>);
 
-    /// Whether the device is still connected
+    /// Adds a callback which receives the events matching the filter
+    fn subscribe_events(&mut self, filter: EventFilter, callback: Box<dyn EventCallback>);
+
+    /// Whether the device is still connected. This is checked before
+    /// each message from content, and regularly while content is idle.
     fn connected(&mut self) -> bool;
 
+    /// Handle the device's input and events without producing a frame, which is done
+    /// while the session is suspended, so the user using an input or putting the headset
+    /// back on resumes it. Devices which always handle these needn't do anything.
+    fn poll_events(&mut self) {}
+
     /// Quit the session
     fn quit(&mut self);
 }
@@ -5,13 +5,72 @@
 #[cfg(feature = "ipc")]
 use serde::{Deserialize, Serialize};
 
-/// Errors that can be produced by XR.
-
-// TODO: this is currently incomplete!
+use std::error;
+use std::fmt;
 
-#[derive(Debug)]
+/// Errors that can be produced by XR.
+#[derive(Clone, Debug, PartialEq)]
 #[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
 pub enum Error {
     NoMatchingDevice,
     CommunicationError,
+    UnsupportedFeature,
+    /// The embedder refused the session, or one of its required features,
+    /// such as because the user didn't agree to it
+    PermissionDenied,
+    /// The layer can't be used this way, such as destroying the default projection layer
+    InvalidLayer,
+    /// There are more views than the array layers or surfaces they would be rendered to,
+    /// such as a swapchain per view for fewer views than the device has
+    NotEnoughViewTargets,
+    /// A call into the backend's runtime or graphics API failed
+    Backend(BackendError),
 }
+
+/// The details of a failure in a backend, for diagnosing it
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub struct BackendError {
+    /// The backend that failed, such as `glwindow`
+    pub backend: String,
+    /// What failed, such as the call that was made
+    pub message: String,
+    /// The error code the runtime or graphics API returned, if it has them
+    pub code: Option<i64>,
+}
+
+impl Error {
+    pub fn backend(backend: &str, message: impl Into<String>, code: Option<i64>) -> Error {
+        Error::Backend(BackendError {
+            backend: backend.into(),
+            message: message.into(),
+            code,
+        })
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::NoMatchingDevice => formatter.write_str("No matching device"),
+            Error::CommunicationError => formatter.write_str("Failed to communicate"),
+            Error::UnsupportedFeature => formatter.write_str("Unsupported feature"),
+            Error::PermissionDenied => formatter.write_str("Permission denied"),
+            Error::InvalidLayer => formatter.write_str("Invalid layer"),
+            Error::NotEnoughViewTargets => formatter.write_str("Not enough view targets"),
+            Error::Backend(ref error) => error.fmt(formatter),
+        }
+    }
+}
+
+impl fmt::Display for BackendError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "{}: {}", self.backend, self.message)?;
+        if let Some(code) = self.code {
+            write!(formatter, " ({:#x})", code)?;
+        }
+        Ok(())
+    }
+}
+
+impl error::Error for Error {}
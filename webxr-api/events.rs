@@ -1,20 +1,163 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use crate::ColorSpace;
+use crate::DetectedMesh;
+use crate::DeviceStatus;
+use crate::Error;
+use crate::Floor;
+use crate::Frame;
 use crate::InputId;
 use crate::InputSource;
+use crate::MeshId;
+use crate::Native;
+use crate::PerformanceNotification;
+use crate::SelectEvent;
+use crate::SelectKind;
+use crate::Views;
+
+use euclid::Point2D;
+use euclid::RigidTransform3D;
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Clone, Debug)]
 #[cfg_attr(feature = "ipc", derive(serde::Serialize, serde::Deserialize))]
 pub enum Event {
     /// Input source connected
     AddInput(InputSource),
     /// Input source disconnected
     RemoveInput(InputId),
-    /// Session ended by device
-    SessionEnd,
+    /// Input source changed, e.g. its handedness or profiles
+    UpdateInput(InputId, InputSource),
+    /// Input source performed a primary action, with the frame it happened in
+    /// https://immersive-web.github.io/webxr/#primary-action
+    Select(InputId, SelectKind, SelectEvent, Box<Frame>),
+    /// Session ended by device, or because content asked to end it
+    SessionEnd(SessionEndReason),
     /// Session focused/blurred/etc
     VisibilityChange(Visibility),
+    /// The user put on or took off the headset, as its proximity sensor or the runtime
+    /// tells, so the embedder can pause media and timers while it isn't worn.
+    /// Devices may also change the session's visibility, which is reported separately.
+    UserPresenceChange(bool),
+    /// The boundary of the play area changed
+    /// https://immersive-web.github.io/webxr/#dom-xrboundedreferencespace-boundsgeometry
+    UpdateBoundsGeometry(Option<Vec<Point2D<f32, Floor>>>),
+    /// The floor moved, so the transform from native coordinates to the floor changed
+    /// https://immersive-web.github.io/webxr/#eventdef-xrreferencespace-reset
+    UpdateFloorTransform(RigidTransform3D<f32, Native, Floor>),
+    /// The device detected a mesh of the real world, or the mesh changed,
+    /// if mesh detection is enabled
+    UpdateMesh(DetectedMesh),
+    /// The device discarded a mesh
+    RemoveMesh(MeshId),
+    /// The device changed the rate it produces frames at, in Hz
+    /// https://immersive-web.github.io/webxr/#eventdef-xrsession-frameratechange
+    FrameRateChange(f32),
+    /// The device changed the color space it displays frames in
+    ColorSpaceChange(ColorSpace),
+    /// The views changed, for example because the window showing them was resized.
+    /// Content should render to a framebuffer large enough for the new viewports.
+    UpdateViews(Views),
+    /// How well the device is coping with content changed
+    PerformanceNotification(PerformanceNotification),
+    /// The device's battery or temperature changed
+    DeviceStatus(DeviceStatus),
+    /// The device failed in a way it recovered from, such as failing to draw a frame,
+    /// and the session continues
+    Error(Error),
+}
+
+/// Why a session ended
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "ipc", derive(serde::Serialize, serde::Deserialize))]
+pub enum SessionEndReason {
+    /// Content ended the session
+    Requested,
+    /// The device was disconnected, such as by the headset being unplugged,
+    /// or the user closing the window showing the session
+    Disconnected,
+    /// The runtime lost the session, such as by crashing
+    RuntimeLost,
+    /// The GL context used to display the session was lost
+    ContextLost,
+}
+
+/// The kinds of event, used to subscribe to only some events
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "ipc", derive(serde::Serialize, serde::Deserialize))]
+pub enum EventCategory {
+    /// Input sources being added, removed or changed
+    Input,
+    /// Visibility changes, and the user putting on or taking off the headset
+    Visibility,
+    /// Changes to reference spaces, such as the bounds geometry
+    Spaces,
+    /// Changes to the session as a whole, such as its frame rate or it ending
+    Session,
+    /// Changes to the device's battery or temperature
+    DeviceStatus,
+    /// Changes to the device's understanding of the real world, such as its meshes
+    World,
+    /// Changes to how content should render its layers, such as the views or color space
+    Layers,
+}
+
+/// A set of event categories
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "ipc", derive(serde::Serialize, serde::Deserialize))]
+pub struct EventFilter(u8);
+
+impl Event {
+    pub fn category(&self) -> EventCategory {
+        match *self {
+            Event::AddInput(..)
+            | Event::RemoveInput(..)
+            | Event::UpdateInput(..)
+            | Event::Select(..) => EventCategory::Input,
+            Event::SessionEnd(..)
+            | Event::FrameRateChange(..)
+            | Event::PerformanceNotification(..)
+            | Event::Error(..) => EventCategory::Session,
+            Event::ColorSpaceChange(..) | Event::UpdateViews(..) => EventCategory::Layers,
+            Event::VisibilityChange(..) | Event::UserPresenceChange(..) => {
+                EventCategory::Visibility
+            }
+            Event::DeviceStatus(..) => EventCategory::DeviceStatus,
+            Event::UpdateMesh(..) | Event::RemoveMesh(..) => EventCategory::World,
+            Event::UpdateBoundsGeometry(..) | Event::UpdateFloorTransform(..) => {
+                EventCategory::Spaces
+            }
+        }
+    }
+}
+
+impl EventFilter {
+    pub const ALL: EventFilter = EventFilter(!0);
+
+    pub fn new(categories: &[EventCategory]) -> EventFilter {
+        EventFilter(
+            categories
+                .iter()
+                .fold(0, |bits, category| bits | EventFilter::bit(*category)),
+        )
+    }
+
+    pub fn contains(&self, category: EventCategory) -> bool {
+        self.0 & EventFilter::bit(category) != 0
+    }
+
+    pub fn matches(&self, event: &Event) -> bool {
+        self.contains(event.category())
+    }
+
+    fn bit(category: EventCategory) -> u8 {
+        1 << category as u8
+    }
 }
 
-#[derive(Copy, Clone, Debug)]
+/// https://immersive-web.github.io/webxr/#xrvisibilitystate-enum
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
 #[cfg_attr(feature = "ipc", derive(serde::Serialize, serde::Deserialize))]
 pub enum Visibility {
     /// Session fully displayed to user
@@ -30,33 +173,127 @@ pub trait EventCallback: 'static + Send {
     fn callback(&mut self, event: Event);
 }
 
-/// Convenience structure for buffering up events
-/// when no event callback has been set
-pub enum EventBuffer {
-    Buffered(Vec<Event>),
-    Sink(Box<dyn EventCallback>),
-}
-
-impl Default for EventBuffer {
-    fn default() -> Self {
-        EventBuffer::Buffered(vec![])
-    }
+/// Convenience structure for dispatching events to subscribers,
+/// and buffering them up when nobody is interested yet.
+///
+/// Events go to every subscription whose filter matches them.
+/// Events which no subscription matches go to the event callback,
+/// or are buffered if no event callback has been set.
+#[derive(Default)]
+pub struct EventBuffer {
+    buffered: Vec<Event>,
+    sink: Option<Box<dyn EventCallback>>,
+    subscriptions: Vec<(EventFilter, Box<dyn EventCallback>)>,
 }
 
 impl EventBuffer {
     pub fn callback(&mut self, event: Event) {
-        match *self {
-            EventBuffer::Buffered(ref mut events) => events.push(event),
-            EventBuffer::Sink(ref mut sink) => sink.callback(event),
+        let mut claimed = false;
+        for (filter, subscription) in &mut self.subscriptions {
+            if filter.matches(&event) {
+                subscription.callback(event.clone());
+                claimed = true;
+            }
+        }
+        if !claimed {
+            match self.sink {
+                Some(ref mut sink) => sink.callback(event),
+                None => self.buffered.push(event),
+            }
         }
     }
 
+    /// Set the event callback, delivering any buffered events to it
     pub fn upgrade(&mut self, mut sink: Box<dyn EventCallback>) {
-        if let EventBuffer::Buffered(ref mut events) = *self {
-            for event in events.drain(..) {
-                sink.callback(event)
-            }
+        for event in self.buffered.drain(..) {
+            sink.callback(event)
+        }
+        self.sink = Some(sink)
+    }
+
+    /// Add a subscription, delivering any matching buffered events to it
+    pub fn subscribe(&mut self, filter: EventFilter, mut subscription: Box<dyn EventCallback>) {
+        let (matching, rest) = self
+            .buffered
+            .drain(..)
+            .partition(|event| filter.matches(event));
+        self.buffered = rest;
+        for event in matching {
+            subscription.callback(event);
         }
-        *self = EventBuffer::Sink(sink)
+        self.subscriptions.push((filter, subscription));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::Sender;
+
+    #[cfg_attr(feature = "ipc", derive(serde::Serialize, serde::Deserialize))]
+    struct SendEvent(Sender<Event>);
+
+    #[cfg_attr(feature = "ipc", typetag::serde)]
+    impl EventCallback for SendEvent {
+        fn callback(&mut self, event: Event) {
+            let _ = self.0.send(event);
+        }
+    }
+
+    #[test]
+    fn filter_contains_its_categories() {
+        let filter = EventFilter::new(&[EventCategory::Input, EventCategory::World]);
+        assert!(filter.contains(EventCategory::Input));
+        assert!(filter.contains(EventCategory::World));
+        assert!(!filter.contains(EventCategory::Session));
+        assert!(filter.matches(&Event::RemoveInput(InputId(0))));
+        assert!(!filter.matches(&Event::UserPresenceChange(true)));
+        let layers = EventFilter::new(&[EventCategory::Layers]);
+        assert!(layers.matches(&Event::ColorSpaceChange(ColorSpace::Rec709)));
+        assert!(!layers.matches(&Event::FrameRateChange(90.0)));
+        assert!(EventFilter::ALL.contains(EventCategory::DeviceStatus));
+        assert!(!EventFilter::new(&[]).contains(EventCategory::Input));
+    }
+
+    #[test]
+    fn subscriptions_claim_matching_events() {
+        let (sink_sender, sink) = crate::channel().unwrap();
+        let (input_sender, input) = crate::channel().unwrap();
+        let mut buffer = EventBuffer::default();
+        buffer.upgrade(Box::new(SendEvent(sink_sender)));
+        buffer.subscribe(
+            EventFilter::new(&[EventCategory::Input]),
+            Box::new(SendEvent(input_sender)),
+        );
+
+        buffer.callback(Event::RemoveInput(InputId(0)));
+        buffer.callback(Event::FrameRateChange(90.0));
+        let category = input.try_recv().unwrap().category();
+        assert_eq!(category, EventCategory::Input);
+        assert!(input.try_recv().is_err());
+        assert_eq!(sink.try_recv().unwrap().category(), EventCategory::Session);
+        assert!(sink.try_recv().is_err());
+    }
+
+    #[test]
+    fn subscribing_delivers_matching_buffered_events() {
+        let (sink_sender, sink) = crate::channel().unwrap();
+        let (visibility_sender, visibility) = crate::channel().unwrap();
+        let mut buffer = EventBuffer::default();
+        buffer.callback(Event::UserPresenceChange(false));
+        buffer.callback(Event::FrameRateChange(60.0));
+
+        buffer.subscribe(
+            EventFilter::new(&[EventCategory::Visibility]),
+            Box::new(SendEvent(visibility_sender)),
+        );
+        let category = visibility.try_recv().unwrap().category();
+        assert_eq!(category, EventCategory::Visibility);
+        assert!(visibility.try_recv().is_err());
+
+        buffer.upgrade(Box::new(SendEvent(sink_sender)));
+        assert_eq!(sink.try_recv().unwrap().category(), EventCategory::Session);
+        assert!(sink.try_recv().is_err());
     }
 }
@@ -0,0 +1,34 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Facial expressions, as blend shape weights for animating avatars.
+
+#[cfg(feature = "ipc")]
+use serde::{Deserialize, Serialize};
+
+/// The blend shapes a device reports the weights of, which differ between runtimes
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub enum FaceExpressionSet {
+    /// The expressions of XR_FB_face_tracking2, in the order of `XrFaceExpression2FB`
+    /// https://registry.khronos.org/OpenXR/specs/1.0/html/xrspec.html#XrFaceExpression2FB
+    Fb,
+    /// The eye expressions of XR_HTC_facial_tracking, in the order of
+    /// `XrEyeExpressionHTC`, followed by its lip expressions in the order of
+    /// `XrLipExpressionHTC`
+    /// https://registry.khronos.org/OpenXR/specs/1.0/html/xrspec.html#XR_HTC_facial_tracking
+    Htc,
+}
+
+/// The user's facial expression this frame
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub struct FaceExpressions {
+    pub set: FaceExpressionSet,
+    /// How far each expression of the set is made, from 0.0 to 1.0
+    pub weights: Vec<f32>,
+    /// Whether the device saw the user's face this frame. If not,
+    /// the weights are its best guess, or neutral.
+    pub tracked: bool,
+}
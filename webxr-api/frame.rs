@@ -2,11 +2,28 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
 
+use crate::AnchorFrame;
+use crate::Body;
+use crate::CameraImage;
+use crate::DepthInfo;
+use crate::DeviceImage;
+use crate::FaceExpressions;
+use crate::HighResTimeStamp;
+use crate::HitTestResult;
 use crate::InputFrame;
+use crate::JointFrame;
+use crate::LightEstimate;
 use crate::Native;
+use crate::TrackedImageFrame;
 use crate::Viewer;
+use crate::Viewport;
+use crate::Viewports;
 
 use euclid::RigidTransform3D;
+use euclid::Size2D;
+use euclid::Vector3D;
+
+use std::f32::EPSILON;
 
 /// The per-frame data that is provided by the device.
 /// https://www.w3.org/TR/webxr/#xrframe
@@ -20,6 +37,170 @@ pub struct Frame {
     /// This is the inverse of the view matrix.
     pub transform: RigidTransform3D<f32, Viewer, Native>,
 
+    /// How fast the viewer is moving and turning, if the device knows
+    pub velocity: Velocity,
+
     /// Frame information for each connected input source
     pub inputs: Vec<InputFrame>,
+
+    /// The depth buffer for each view, in view order, if depth sensing is enabled
+    pub depth: Vec<DepthInfo>,
+
+    /// The real world's depth for each view, in view order, if the embedder enabled
+    /// occlusion, for it to occlude rendered content with. Devices give these as
+    /// GL textures where they can.
+    pub occlusion_depth: Vec<DepthInfo>,
+
+    /// The estimated real-world lighting, if light estimation is enabled
+    pub light_estimate: Option<LightEstimate>,
+
+    /// The camera image for each view, in view order, if camera access is enabled
+    pub camera_images: Vec<CameraImage>,
+
+    /// The results of every hit test source, nearest first for each source,
+    /// if hit testing is enabled
+    pub hit_test_results: Vec<HitTestResult>,
+
+    /// The anchors which are being tracked this frame, if anchors are enabled
+    pub anchors: Vec<AnchorFrame>,
+
+    /// The reference images the device knows the poses of this frame,
+    /// if image tracking is enabled
+    pub tracked_images: Vec<TrackedImageFrame>,
+
+    /// The joints of the user's body, if body tracking is enabled
+    pub body: Option<Box<Body<JointFrame>>>,
+
+    /// The user's facial expression, if face tracking is enabled
+    pub face: Option<FaceExpressions>,
+
+    /// Where to render each view this frame, in view order, which is smaller than
+    /// the view's viewport if a viewport scale was requested, and laid out for the
+    /// swapchains the default projection layer is rendered to.
+    /// This is filled in by the session thread.
+    pub viewports: Viewports,
+
+    /// The device's textures which content can render each layer to this frame,
+    /// for layers whose device can share them. This is filled in by the session thread.
+    pub device_images: Vec<DeviceImage>,
+
+    /// Timing information, filled in by the session thread
+    /// apart from the predicted display time
+    pub timing: FrameTiming,
+}
+
+/// How fast something is moving and turning, in native coordinates, so content
+/// can throw things or predict where they will be without working it out from poses.
+/// Parts the device doesn't know are `None`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "ipc", derive(serde::Serialize, serde::Deserialize))]
+pub struct Velocity {
+    /// In meters per second
+    pub linear: Option<Vector3D<f32, Native>>,
+    /// Turning around the axis this points along, by its length in radians per second
+    pub angular: Option<Vector3D<f32, Native>>,
+}
+
+impl Velocity {
+    /// The velocity of something which moved between two poses in `elapsed` seconds,
+    /// for devices which only know poses
+    pub fn between<Src>(
+        from: &RigidTransform3D<f32, Src, Native>,
+        to: &RigidTransform3D<f32, Src, Native>,
+        elapsed: f32,
+    ) -> Velocity {
+        if elapsed <= 0.0 {
+            return Velocity::default();
+        }
+        let linear = (to.translation - from.translation) / elapsed;
+        let turn = from.rotation.inverse().post_rotate(&to.rotation);
+        // Take the shorter way round
+        let sign = if turn.r < 0.0 { -1.0 } else { 1.0 };
+        let axis = Vector3D::new(turn.i, turn.j, turn.k) * sign;
+        let sin = axis.length();
+        let angular = if sin <= EPSILON {
+            Vector3D::zero()
+        } else {
+            let angle = 2.0 * sin.atan2(turn.r * sign);
+            axis * (angle / sin / elapsed)
+        };
+        Velocity {
+            linear: Some(linear),
+            angular: Some(angular),
+        }
+    }
+}
+
+/// Timing information for a frame.
+/// Durations are in milliseconds, and refer to the most recently rendered frame.
+#[derive(Clone, Copy, Debug, Default)]
+#[cfg_attr(feature = "ipc", derive(serde::Serialize, serde::Deserialize))]
+pub struct FrameTiming {
+    /// When the device expects this frame to be displayed, if it knows.
+    /// This is also the time passed to the frame request callback.
+    pub predicted_display_time: Option<HighResTimeStamp>,
+    /// The time content spent between receiving the previous frame and asking for it to be rendered
+    pub cpu_frame_time: Option<f64>,
+    /// The time the device spent submitting the previous frame
+    pub gpu_submit_time: Option<f64>,
+    /// The number of frames content has been given but never rendered, since the session started
+    pub dropped_frames: u32,
+}
+
+/// The poses of the viewer and input sources at a particular time, such as
+/// a frame's predicted display time, for content which samples poses late
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "ipc", derive(serde::Serialize, serde::Deserialize))]
+pub struct Poses {
+    /// The time the poses are predicted for
+    pub time: HighResTimeStamp,
+    /// The transform from the viewer to native coordinates, as in `Frame`
+    pub transform: RigidTransform3D<f32, Viewer, Native>,
+    pub velocity: Velocity,
+    pub inputs: Vec<InputFrame>,
+}
+
+/// Aggregate timing statistics for a session
+#[derive(Clone, Copy, Debug, Default)]
+#[cfg_attr(feature = "ipc", derive(serde::Serialize, serde::Deserialize))]
+pub struct FrameStats {
+    /// The number of frames rendered
+    pub frame_count: u32,
+    /// The number of frames content has been given but never rendered
+    pub dropped_frames: u32,
+    /// The number of times content didn't render in time, so the device
+    /// displayed its previous frame again
+    pub missed_frames: u32,
+    /// The number of frames replaced by a newer one before content was given them,
+    /// because it was still taking an earlier frame
+    pub replaced_frames: u32,
+    /// How long content has been taking the frame it is being given, in milliseconds,
+    /// which keeps growing if content has stalled, or 0 if it isn't being given one
+    pub stalled_time: f64,
+    pub mean_cpu_frame_time: f64,
+    pub max_cpu_frame_time: f64,
+    pub mean_gpu_submit_time: f64,
+    pub max_gpu_submit_time: f64,
+}
+
+impl FrameStats {
+    pub(crate) fn record(&mut self, cpu_frame_time: f64, gpu_submit_time: f64) {
+        let count = f64::from(self.frame_count);
+        self.mean_cpu_frame_time =
+            (self.mean_cpu_frame_time * count + cpu_frame_time) / (count + 1.0);
+        self.mean_gpu_submit_time =
+            (self.mean_gpu_submit_time * count + gpu_submit_time) / (count + 1.0);
+        self.max_cpu_frame_time = self.max_cpu_frame_time.max(cpu_frame_time);
+        self.max_gpu_submit_time = self.max_gpu_submit_time.max(gpu_submit_time);
+        self.frame_count += 1;
+    }
+}
+
+/// A copy of a frame, as the device displayed it
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "ipc", derive(serde::Serialize, serde::Deserialize))]
+pub struct FrameCapture {
+    pub size: Size2D<i32, Viewport>,
+    /// The pixels, four bytes of RGBA each, row by row from the bottom as GL reads them
+    pub data: Vec<u8>,
 }
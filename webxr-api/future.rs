@@ -0,0 +1,166 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Futures for the replies to requests, for embedders which use async rather than callbacks.
+//! The replies are shared in-process, so these are not available with the `ipc` feature,
+//! whose callbacks must be serializable.
+
+use crate::Error;
+use crate::Frame;
+use crate::FrameRequestCallback;
+use crate::HighResTimeStamp;
+use crate::Registry;
+use crate::Session;
+use crate::SessionCapabilities;
+use crate::SessionCapabilitiesCallback;
+use crate::SessionInit;
+use crate::SessionMode;
+use crate::SessionRequestCallback;
+use crate::SessionSupportCallback;
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::task::Context;
+use std::task::Poll;
+use std::task::Waker;
+
+struct ReplyState<T> {
+    result: Option<Result<T, Error>>,
+    // Whether the request was dropped without a reply, such as when its session ends
+    dropped: bool,
+    waker: Option<Waker>,
+}
+
+/// A future that resolves to the reply to a request.
+/// Requests whose callback is dropped without replying resolve to `Error::CommunicationError`.
+pub struct Reply<T> {
+    state: Arc<Mutex<ReplyState<T>>>,
+}
+
+// The callback side of a reply
+struct Replier<T> {
+    state: Arc<Mutex<ReplyState<T>>>,
+}
+
+fn reply<T>() -> (Replier<T>, Reply<T>) {
+    let state = Arc::new(Mutex::new(ReplyState {
+        result: None,
+        dropped: false,
+        waker: None,
+    }));
+    (
+        Replier {
+            state: state.clone(),
+        },
+        Reply { state },
+    )
+}
+
+impl<T> Replier<T> {
+    fn reply(&mut self, result: Result<T, Error>) {
+        let waker = {
+            let mut state = self.state.lock().unwrap();
+            state.result = Some(result);
+            state.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+impl<T> Drop for Replier<T> {
+    fn drop(&mut self) {
+        let waker = {
+            let mut state = self.state.lock().unwrap();
+            state.dropped = true;
+            state.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+impl<T> Future for Reply<T> {
+    type Output = Result<T, Error>;
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context) -> Poll<Result<T, Error>> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(result) = state.result.take() {
+            Poll::Ready(result)
+        } else if state.dropped {
+            Poll::Ready(Err(Error::CommunicationError))
+        } else {
+            state.waker = Some(context.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+impl SessionSupportCallback for Replier<()> {
+    fn callback(&mut self, result: Result<(), Error>) {
+        self.reply(result);
+    }
+}
+
+impl SessionCapabilitiesCallback for Replier<Vec<SessionCapabilities>> {
+    fn callback(&mut self, capabilities: Vec<SessionCapabilities>) {
+        self.reply(Ok(capabilities));
+    }
+}
+
+impl SessionRequestCallback for Replier<Session> {
+    fn callback(&mut self, result: Result<Session, Error>) {
+        self.reply(result);
+    }
+}
+
+impl FrameRequestCallback for Replier<(HighResTimeStamp, Frame)> {
+    fn callback(&mut self, time: HighResTimeStamp, frame: Frame) {
+        self.reply(Ok((time, frame)));
+    }
+}
+
+impl Registry {
+    /// The same as `supports_session`, but replying with a future.
+    /// The reply comes once the main thread registry has run a frame.
+    pub fn supports_session_async(&mut self, mode: SessionMode) -> Reply<()> {
+        let (replier, reply) = reply();
+        self.supports_session(mode, replier);
+        reply
+    }
+
+    /// The same as `session_capabilities`, but replying with a future.
+    /// The reply comes once the main thread registry has run a frame.
+    pub fn session_capabilities_async(&mut self) -> Reply<Vec<SessionCapabilities>> {
+        let (replier, reply) = reply();
+        self.session_capabilities(replier);
+        reply
+    }
+
+    /// The same as `request_session`, but replying with a future.
+    /// The reply comes once the main thread registry has run a frame.
+    pub fn request_session_async(
+        &mut self,
+        mode: SessionMode,
+        init: SessionInit,
+    ) -> Reply<Session> {
+        let (replier, reply) = reply();
+        self.request_session(mode, init, replier);
+        reply
+    }
+}
+
+impl Session {
+    /// The same as `request_animation_frame`, but replying with a future
+    /// which resolves to the frame and its time.
+    pub fn request_animation_frame_async(&mut self) -> Reply<(HighResTimeStamp, Frame)> {
+        let (replier, reply) = reply();
+        self.request_animation_frame(replier);
+        reply
+    }
+}
@@ -0,0 +1,132 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Saving and restoring GL state around a device's rendering, for devices which
+//! share a GL context with the embedder, so submitting a frame never disturbs
+//! the embedder's own rendering.
+
+use gleam::gl;
+use gleam::gl::GLenum;
+use gleam::gl::GLfloat;
+use gleam::gl::GLint;
+use gleam::gl::GLuint;
+use gleam::gl::Gl;
+
+use std::rc::Rc;
+
+/// The GL state that devices change while rendering, which is put back as it
+/// was when this is dropped. Devices should save the state before their GL work
+/// and drop it afterwards, with the same context current.
+#[must_use]
+pub struct GlState {
+    gl: Rc<dyn Gl>,
+    read_framebuffer: GLint,
+    draw_framebuffer: GLint,
+    pixel_pack_buffer: GLint,
+    pixel_unpack_buffer: GLint,
+    viewport: [GLint; 4],
+    clear_color: [GLfloat; 4],
+    scissor_test: bool,
+    blend: bool,
+    // The source and destination RGB factors, then the alpha ones
+    blend_func: [GLint; 4],
+    blend_color: [GLfloat; 4],
+    program: GLint,
+    vertex_array: GLint,
+    active_texture: GLint,
+    // The textures bound to the first texture unit, which devices draw with
+    texture_2d: GLint,
+    texture_2d_array: GLint,
+    texture_cube_map: GLint,
+}
+
+impl GlState {
+    /// Save the current context's state, then reset the parts devices rely on,
+    /// so the embedder's scissor test, blending and pixel buffers don't affect them
+    pub fn save(gl: &Rc<dyn Gl>) -> GlState {
+        let integer = |name: GLenum| {
+            let mut value = [0];
+            unsafe { gl.get_integer_v(name, &mut value) };
+            value[0]
+        };
+        let mut viewport = [0; 4];
+        let mut clear_color = [0.0; 4];
+        let mut blend_color = [0.0; 4];
+        unsafe {
+            gl.get_integer_v(gl::VIEWPORT, &mut viewport);
+            gl.get_float_v(gl::COLOR_CLEAR_VALUE, &mut clear_color);
+            gl.get_float_v(gl::BLEND_COLOR, &mut blend_color);
+        }
+        let active_texture = integer(gl::ACTIVE_TEXTURE);
+        gl.active_texture(gl::TEXTURE0);
+        let state = GlState {
+            gl: gl.clone(),
+            read_framebuffer: integer(gl::READ_FRAMEBUFFER_BINDING),
+            draw_framebuffer: integer(gl::DRAW_FRAMEBUFFER_BINDING),
+            pixel_pack_buffer: integer(gl::PIXEL_PACK_BUFFER_BINDING),
+            pixel_unpack_buffer: integer(gl::PIXEL_UNPACK_BUFFER_BINDING),
+            viewport,
+            clear_color,
+            scissor_test: gl.is_enabled(gl::SCISSOR_TEST) != 0,
+            blend: gl.is_enabled(gl::BLEND) != 0,
+            blend_func: [
+                integer(gl::BLEND_SRC_RGB),
+                integer(gl::BLEND_DST_RGB),
+                integer(gl::BLEND_SRC_ALPHA),
+                integer(gl::BLEND_DST_ALPHA),
+            ],
+            blend_color,
+            program: integer(gl::CURRENT_PROGRAM),
+            vertex_array: integer(gl::VERTEX_ARRAY_BINDING),
+            active_texture,
+            texture_2d: integer(gl::TEXTURE_BINDING_2D),
+            texture_2d_array: integer(gl::TEXTURE_BINDING_2D_ARRAY),
+            texture_cube_map: integer(gl::TEXTURE_BINDING_CUBE_MAP),
+        };
+        gl.disable(gl::SCISSOR_TEST);
+        gl.disable(gl::BLEND);
+        gl.bind_buffer(gl::PIXEL_PACK_BUFFER, 0);
+        gl.bind_buffer(gl::PIXEL_UNPACK_BUFFER, 0);
+        state
+    }
+}
+
+impl Drop for GlState {
+    fn drop(&mut self) {
+        let gl = &*self.gl;
+        let [x, y, width, height] = self.viewport;
+        let [red, green, blue, alpha] = self.clear_color;
+        let [src_rgb, dst_rgb, src_alpha, dst_alpha] = self.blend_func;
+        let [blend_red, blend_green, blend_blue, blend_alpha] = self.blend_color;
+        let enable = |cap, enabled| {
+            if enabled {
+                gl.enable(cap)
+            } else {
+                gl.disable(cap)
+            }
+        };
+        gl.bind_framebuffer(gl::READ_FRAMEBUFFER, self.read_framebuffer as GLuint);
+        gl.bind_framebuffer(gl::DRAW_FRAMEBUFFER, self.draw_framebuffer as GLuint);
+        gl.bind_buffer(gl::PIXEL_PACK_BUFFER, self.pixel_pack_buffer as GLuint);
+        gl.bind_buffer(gl::PIXEL_UNPACK_BUFFER, self.pixel_unpack_buffer as GLuint);
+        gl.viewport(x, y, width, height);
+        gl.clear_color(red, green, blue, alpha);
+        enable(gl::SCISSOR_TEST, self.scissor_test);
+        enable(gl::BLEND, self.blend);
+        gl.blend_func_separate(
+            src_rgb as GLenum,
+            dst_rgb as GLenum,
+            src_alpha as GLenum,
+            dst_alpha as GLenum,
+        );
+        gl.blend_color(blend_red, blend_green, blend_blue, blend_alpha);
+        gl.use_program(self.program as GLuint);
+        gl.bind_vertex_array(self.vertex_array as GLuint);
+        gl.active_texture(gl::TEXTURE0);
+        gl.bind_texture(gl::TEXTURE_2D, self.texture_2d as GLuint);
+        gl.bind_texture(gl::TEXTURE_2D_ARRAY, self.texture_2d_array as GLuint);
+        gl.bind_texture(gl::TEXTURE_CUBE_MAP, self.texture_cube_map as GLuint);
+        gl.active_texture(self.active_texture as GLenum);
+    }
+}
@@ -0,0 +1,269 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Articulated hand data, as used by the WebXR Hand Input module.
+
+use crate::Joint;
+use crate::Native;
+use crate::SelectEvent;
+
+use euclid::RigidTransform3D;
+
+#[cfg(feature = "ipc")]
+use serde::{Deserialize, Serialize};
+
+/// The pose and radius of a single hand joint
+/// https://immersive-web.github.io/webxr-hand-input/#xrjointpose-interface
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub struct JointFrame {
+    pub pose: RigidTransform3D<f32, Joint, Native>,
+    pub radius: f32,
+}
+
+/// The 25 joints of an articulated hand. Joints which are not
+/// currently tracked are `None`.
+/// https://immersive-web.github.io/webxr-hand-input/#skeleton-joints-section
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub struct Hand<J> {
+    pub wrist: Option<J>,
+    pub thumb_metacarpal: Option<J>,
+    pub thumb_phalanx_proximal: Option<J>,
+    pub thumb_phalanx_distal: Option<J>,
+    pub thumb_phalanx_tip: Option<J>,
+    pub index: Finger<J>,
+    pub middle: Finger<J>,
+    pub ring: Finger<J>,
+    pub little: Finger<J>,
+}
+
+/// The joints of one of the four non-thumb fingers
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub struct Finger<J> {
+    pub metacarpal: Option<J>,
+    pub phalanx_proximal: Option<J>,
+    pub phalanx_intermediate: Option<J>,
+    pub phalanx_distal: Option<J>,
+    pub phalanx_tip: Option<J>,
+}
+
+/// The names of the joints of a hand
+/// https://immersive-web.github.io/webxr-hand-input/#xrhand-interface
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub enum HandJoint {
+    Wrist,
+    ThumbMetacarpal,
+    ThumbPhalanxProximal,
+    ThumbPhalanxDistal,
+    ThumbPhalanxTip,
+    Index(FingerJoint),
+    Middle(FingerJoint),
+    Ring(FingerJoint),
+    Little(FingerJoint),
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub enum FingerJoint {
+    Metacarpal,
+    PhalanxProximal,
+    PhalanxIntermediate,
+    PhalanxDistal,
+    PhalanxTip,
+}
+
+impl<J> Default for Hand<J> {
+    fn default() -> Self {
+        Hand {
+            wrist: None,
+            thumb_metacarpal: None,
+            thumb_phalanx_proximal: None,
+            thumb_phalanx_distal: None,
+            thumb_phalanx_tip: None,
+            index: Finger::default(),
+            middle: Finger::default(),
+            ring: Finger::default(),
+            little: Finger::default(),
+        }
+    }
+}
+
+impl<J> Default for Finger<J> {
+    fn default() -> Self {
+        Finger {
+            metacarpal: None,
+            phalanx_proximal: None,
+            phalanx_intermediate: None,
+            phalanx_distal: None,
+            phalanx_tip: None,
+        }
+    }
+}
+
+impl<J> Hand<J> {
+    pub fn get(&self, joint: HandJoint) -> Option<&J> {
+        match joint {
+            HandJoint::Wrist => self.wrist.as_ref(),
+            HandJoint::ThumbMetacarpal => self.thumb_metacarpal.as_ref(),
+            HandJoint::ThumbPhalanxProximal => self.thumb_phalanx_proximal.as_ref(),
+            HandJoint::ThumbPhalanxDistal => self.thumb_phalanx_distal.as_ref(),
+            HandJoint::ThumbPhalanxTip => self.thumb_phalanx_tip.as_ref(),
+            HandJoint::Index(joint) => self.index.get(joint),
+            HandJoint::Middle(joint) => self.middle.get(joint),
+            HandJoint::Ring(joint) => self.ring.get(joint),
+            HandJoint::Little(joint) => self.little.get(joint),
+        }
+    }
+
+    pub fn map<R>(&self, mut map: impl FnMut(Option<&J>, HandJoint) -> Option<R>) -> Hand<R> {
+        Hand {
+            wrist: map(self.wrist.as_ref(), HandJoint::Wrist),
+            thumb_metacarpal: map(self.thumb_metacarpal.as_ref(), HandJoint::ThumbMetacarpal),
+            thumb_phalanx_proximal: map(
+                self.thumb_phalanx_proximal.as_ref(),
+                HandJoint::ThumbPhalanxProximal,
+            ),
+            thumb_phalanx_distal: map(
+                self.thumb_phalanx_distal.as_ref(),
+                HandJoint::ThumbPhalanxDistal,
+            ),
+            thumb_phalanx_tip: map(self.thumb_phalanx_tip.as_ref(), HandJoint::ThumbPhalanxTip),
+            index: self.index.map(|j, f| map(j, HandJoint::Index(f))),
+            middle: self.middle.map(|j, f| map(j, HandJoint::Middle(f))),
+            ring: self.ring.map(|j, f| map(j, HandJoint::Ring(f))),
+            little: self.little.map(|j, f| map(j, HandJoint::Little(f))),
+        }
+    }
+}
+
+impl<J> Finger<J> {
+    pub fn get(&self, joint: FingerJoint) -> Option<&J> {
+        match joint {
+            FingerJoint::Metacarpal => self.metacarpal.as_ref(),
+            FingerJoint::PhalanxProximal => self.phalanx_proximal.as_ref(),
+            FingerJoint::PhalanxIntermediate => self.phalanx_intermediate.as_ref(),
+            FingerJoint::PhalanxDistal => self.phalanx_distal.as_ref(),
+            FingerJoint::PhalanxTip => self.phalanx_tip.as_ref(),
+        }
+    }
+
+    pub fn map<R>(&self, mut map: impl FnMut(Option<&J>, FingerJoint) -> Option<R>) -> Finger<R> {
+        Finger {
+            metacarpal: map(self.metacarpal.as_ref(), FingerJoint::Metacarpal),
+            phalanx_proximal: map(self.phalanx_proximal.as_ref(), FingerJoint::PhalanxProximal),
+            phalanx_intermediate: map(
+                self.phalanx_intermediate.as_ref(),
+                FingerJoint::PhalanxIntermediate,
+            ),
+            phalanx_distal: map(self.phalanx_distal.as_ref(), FingerJoint::PhalanxDistal),
+            phalanx_tip: map(self.phalanx_tip.as_ref(), FingerJoint::PhalanxTip),
+        }
+    }
+}
+
+// How close, in meters, the tips of the thumb and index finger come to start a pinch,
+// and how far apart they go to end it. The gap between these stops a pinch held
+// near the threshold from flickering.
+const PINCH_START_DISTANCE: f32 = 0.015;
+const PINCH_END_DISTANCE: f32 = 0.03;
+
+impl Hand<JointFrame> {
+    /// The distance between the tips of the thumb and index finger, if both are tracked
+    pub fn pinch_distance(&self) -> Option<f32> {
+        let thumb = self.thumb_phalanx_tip?.pose.translation;
+        let index = self.index.phalanx_tip?.pose.translation;
+        Some((thumb - index).length())
+    }
+}
+
+/// Select events worked out from a tracked hand pinching its thumb and index finger
+/// together, for runtimes whose hand tracking has no select action of its own, so
+/// bare hands can still click
+#[derive(Copy, Clone, Debug, Default)]
+pub struct PinchSelect {
+    pinching: bool,
+}
+
+impl PinchSelect {
+    /// Update from the hand's latest joints, returning the select event it caused, if any.
+    /// Losing track of the hand in the middle of a pinch ends it without selecting.
+    pub fn update(&mut self, hand: Option<&Hand<JointFrame>>) -> Option<SelectEvent> {
+        match hand.and_then(|hand| hand.pinch_distance()) {
+            Some(distance) if !self.pinching && distance < PINCH_START_DISTANCE => {
+                self.pinching = true;
+                Some(SelectEvent::Start)
+            }
+            Some(distance) if self.pinching && distance > PINCH_END_DISTANCE => {
+                self.pinching = false;
+                Some(SelectEvent::Select)
+            }
+            None if self.pinching => {
+                self.pinching = false;
+                Some(SelectEvent::End)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn pinching(&self) -> bool {
+        self.pinching
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use euclid::Vector3D;
+
+    // A hand with only its thumb and index fingertips tracked, this far apart
+    fn pinch(distance: f32) -> Hand<JointFrame> {
+        let tip = |x| JointFrame {
+            pose: RigidTransform3D::from_translation(Vector3D::new(x, 0.0, 0.0)),
+            radius: 0.01,
+        };
+        Hand {
+            thumb_phalanx_tip: Some(tip(0.0)),
+            index: Finger {
+                phalanx_tip: Some(tip(distance)),
+                ..Finger::default()
+            },
+            ..Hand::default()
+        }
+    }
+
+    #[test]
+    fn pinches_start_and_end_past_different_distances() {
+        let mut select = PinchSelect::default();
+        assert_eq!(select.update(Some(&pinch(0.02))), None);
+        assert_eq!(select.update(Some(&pinch(0.01))), Some(SelectEvent::Start));
+        assert!(select.pinching());
+        // Opening past the start distance, but not the end distance, keeps pinching
+        assert_eq!(select.update(Some(&pinch(0.02))), None);
+        assert_eq!(select.update(Some(&pinch(0.01))), None);
+        assert_eq!(select.update(Some(&pinch(0.025))), None);
+        assert!(select.pinching());
+        assert_eq!(select.update(Some(&pinch(0.04))), Some(SelectEvent::Select));
+        assert!(!select.pinching());
+        assert_eq!(select.update(Some(&pinch(0.02))), None);
+    }
+
+    #[test]
+    fn losing_the_hand_ends_a_pinch_without_selecting() {
+        let mut select = PinchSelect::default();
+        assert_eq!(select.update(Some(&pinch(0.0))), Some(SelectEvent::Start));
+        assert_eq!(select.update(None), Some(SelectEvent::End));
+        assert!(!select.pinching());
+        assert_eq!(select.update(None), None);
+
+        // Losing a fingertip loses the pinch as well
+        assert_eq!(select.update(Some(&pinch(0.0))), Some(SelectEvent::Start));
+        let mut hand = pinch(0.0);
+        hand.index.phalanx_tip = None;
+        assert_eq!(select.update(Some(&hand)), Some(SelectEvent::End));
+    }
+}
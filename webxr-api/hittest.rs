@@ -0,0 +1,82 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Hit testing against real-world geometry, as used by the WebXR Hit Test module.
+
+use crate::ApiSpace;
+use crate::HitTestSpace;
+use crate::InputId;
+use crate::Native;
+
+use euclid::RigidTransform3D;
+use euclid::Vector3D;
+
+#[cfg(feature = "ipc")]
+use serde::{Deserialize, Serialize};
+
+/// An identifier for a hit test source, unique within its session
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub struct HitTestId(pub u32);
+
+/// The kinds of real-world geometry a hit test can hit
+/// https://immersive-web.github.io/hit-test/#hit-test-trackable-type-enum
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub enum EntityType {
+    Point,
+    Plane,
+    Mesh,
+}
+
+/// The space a hit test ray is given relative to. The ray moves with it,
+/// so for example a viewer ray follows the viewer's gaze.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub enum BaseSpace {
+    Local,
+    Floor,
+    Viewer,
+    /// The target ray space of an input source
+    Input(InputId),
+}
+
+/// A ray, whose direction is normalized
+/// https://immersive-web.github.io/hit-test/#xrray-interface
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub struct Ray<Space> {
+    pub origin: Vector3D<f32, Space>,
+    pub direction: Vector3D<f32, Space>,
+}
+
+impl<Space> Ray<Space> {
+    pub fn transform<Dst>(&self, transform: &RigidTransform3D<f32, Space, Dst>) -> Ray<Dst> {
+        Ray {
+            origin: transform.rotation.transform_vector3d(self.origin) + transform.translation,
+            direction: transform.rotation.transform_vector3d(self.direction),
+        }
+    }
+}
+
+/// The description of a hit test source
+/// https://immersive-web.github.io/hit-test/#dictdef-xrhittestoptionsinit
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub struct HitTestInit {
+    pub space: BaseSpace,
+    pub ray: Ray<ApiSpace>,
+    /// The kinds of geometry to test against. The spec default is planes only.
+    pub entity_types: Vec<EntityType>,
+}
+
+/// Where a hit test source's ray hit the world this frame
+/// https://immersive-web.github.io/hit-test/#xrhittestresult-interface
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub struct HitTestResult {
+    pub id: HitTestId,
+    /// The pose of the hit point, whose Y axis is the normal of the surface hit
+    pub space: RigidTransform3D<f32, HitTestSpace, Native>,
+}
@@ -0,0 +1,291 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Suspending sessions the user has stopped using, to save battery on standalone
+//! devices. A session is idle while there is no input, or while the headset isn't worn.
+
+use crate::session::SessionMsg;
+use crate::Event;
+use crate::EventCallback;
+use crate::InputFrame;
+use crate::Sender;
+
+#[cfg(feature = "ipc")]
+use serde::{Deserialize, Serialize};
+
+use std::time::Duration;
+use std::time::Instant;
+
+// Thumbsticks and touchpads further than this from the middle are being used
+const AXIS_THRESHOLD: f32 = 0.2;
+
+/// How long a session can be idle before it stops producing frames, and ends
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub struct IdlePolicy {
+    /// How long the session can be idle before frame requests are held back,
+    /// until the user selects with an input or puts the headset back on
+    pub suspend_after: Duration,
+    /// How long before suspending the session to warn the embedder
+    pub warn_before: Duration,
+    /// How long the session can be suspended before it ends, or None to stay
+    /// suspended until the user comes back
+    pub end_after: Option<Duration>,
+}
+
+impl IdlePolicy {
+    // How long the session can be idle before the embedder is warned
+    fn warn_after(&self) -> Duration {
+        self.suspend_after
+            .checked_sub(self.warn_before)
+            .unwrap_or_default()
+    }
+}
+
+/// The changes in a session's idleness reported to the embedder
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub enum IdleState {
+    /// The session will be suspended after this long, unless the user comes back
+    Warning(Duration),
+    /// The session stopped producing frames
+    Suspended,
+    /// The user came back after a warning or while the session was suspended,
+    /// and frames are produced as usual
+    Active,
+    /// The session is ending, having been suspended for too long
+    Ended,
+}
+
+/// A callback for the changes in a session's idleness, such as to pause media
+#[cfg_attr(feature = "ipc", typetag::serde)]
+pub trait IdleCallback: 'static + Send {
+    fn callback(&mut self, state: IdleState);
+}
+
+/// How long the user has been idle, for the session thread to act on
+pub(crate) struct IdleTracker {
+    policy: Option<(IdlePolicy, Box<dyn IdleCallback>)>,
+    // When the user was last active, or took off the headset
+    idle_since: Instant,
+    present: bool,
+    warned: bool,
+    suspended: bool,
+}
+
+impl IdleTracker {
+    pub(crate) fn new() -> IdleTracker {
+        IdleTracker {
+            policy: None,
+            idle_since: Instant::now(),
+            present: true,
+            warned: false,
+            suspended: false,
+        }
+    }
+
+    /// Follow a new policy, or none, starting the idle time again
+    pub(crate) fn set_policy(&mut self, policy: Option<(IdlePolicy, Box<dyn IdleCallback>)>) {
+        self.policy = policy;
+        self.idle_since = Instant::now();
+        self.warned = false;
+        self.suspended = false;
+    }
+
+    pub(crate) fn suspended(&self) -> bool {
+        self.suspended
+    }
+
+    /// The user used an input, which keeps the session active while they are present.
+    /// Returns whether this resumed a suspended session.
+    pub(crate) fn activity(&mut self) -> bool {
+        if !self.present {
+            return false;
+        }
+        self.idle_since = Instant::now();
+        let resumed = self.suspended;
+        if self.warned {
+            self.warned = false;
+            self.suspended = false;
+            self.report(IdleState::Active);
+        }
+        resumed
+    }
+
+    /// The user put on or took off the headset. Returns whether this resumed a suspended session.
+    pub(crate) fn presence(&mut self, present: bool) -> bool {
+        if present == self.present {
+            return false;
+        }
+        self.present = present;
+        if present {
+            self.activity()
+        } else {
+            self.idle_since = Instant::now();
+            false
+        }
+    }
+
+    /// Whether any of the inputs are being used
+    pub(crate) fn inputs_active(inputs: &[InputFrame]) -> bool {
+        inputs.iter().any(|input| {
+            input.gamepad.as_ref().map_or(false, |gamepad| {
+                gamepad
+                    .buttons
+                    .iter()
+                    .any(|button| button.pressed || button.touched)
+                    || gamepad.axes.iter().any(|axis| axis.abs() > AXIS_THRESHOLD)
+            })
+        })
+    }
+
+    /// When the policy next needs checking, if it does
+    pub(crate) fn next_check(&self) -> Option<Instant> {
+        let (policy, _) = self.policy.as_ref()?;
+        let after = if !self.warned {
+            policy.warn_after()
+        } else if !self.suspended {
+            policy.suspend_after
+        } else {
+            policy.suspend_after + policy.end_after?
+        };
+        Some(self.idle_since + after)
+    }
+
+    /// Act on how long the user has been idle, returning whether the session should end
+    pub(crate) fn update(&mut self) -> bool {
+        let policy = match self.policy {
+            Some((policy, _)) => policy,
+            None => return false,
+        };
+        let idle = self.idle_since.elapsed();
+        if !self.warned && idle >= policy.warn_after() {
+            self.warned = true;
+            let remaining = policy.suspend_after.checked_sub(idle).unwrap_or_default();
+            self.report(IdleState::Warning(remaining));
+        }
+        if !self.suspended && idle >= policy.suspend_after {
+            self.suspended = true;
+            self.report(IdleState::Suspended);
+        }
+        let ended = self.suspended
+            && policy
+                .end_after
+                .map_or(false, |end_after| idle >= policy.suspend_after + end_after);
+        if ended {
+            self.report(IdleState::Ended);
+            self.policy = None;
+        }
+        ended
+    }
+
+    fn report(&mut self, state: IdleState) {
+        if let Some((_, ref mut callback)) = self.policy {
+            callback.callback(state);
+        }
+    }
+}
+
+/// An event callback which passes events on, and tells the session thread about
+/// the ones which show whether the user is there
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub(crate) struct IdleEventCallback {
+    pub(crate) callback: Box<dyn EventCallback>,
+    pub(crate) sender: Sender<SessionMsg>,
+}
+
+#[cfg_attr(feature = "ipc", typetag::serde)]
+impl EventCallback for IdleEventCallback {
+    fn callback(&mut self, event: Event) {
+        match event {
+            Event::Select(..) | Event::AddInput(..) => {
+                let _ = self.sender.send(SessionMsg::UserActivity);
+            }
+            Event::UserPresenceChange(present) => {
+                let _ = self.sender.send(SessionMsg::UserPresence(present));
+            }
+            _ => {}
+        }
+        self.callback.callback(event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::Receiver;
+
+    #[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+    struct SendState(Sender<IdleState>);
+
+    #[cfg_attr(feature = "ipc", typetag::serde)]
+    impl IdleCallback for SendState {
+        fn callback(&mut self, state: IdleState) {
+            let _ = self.0.send(state);
+        }
+    }
+
+    // A tracker which is idle as soon as it is made
+    fn tracker(end_after: Option<Duration>) -> (IdleTracker, Receiver<IdleState>) {
+        let (sender, receiver) = crate::channel().unwrap();
+        let policy = IdlePolicy {
+            suspend_after: Duration::from_secs(0),
+            warn_before: Duration::from_secs(0),
+            end_after,
+        };
+        let mut tracker = IdleTracker::new();
+        tracker.set_policy(Some((policy, Box::new(SendState(sender)))));
+        (tracker, receiver)
+    }
+
+    #[test]
+    fn suspends_until_activity() {
+        let (mut tracker, states) = tracker(None);
+        assert!(!tracker.update());
+        assert!(tracker.suspended());
+        assert_eq!(
+            states.try_recv().unwrap(),
+            IdleState::Warning(Duration::from_secs(0))
+        );
+        assert_eq!(states.try_recv().unwrap(), IdleState::Suspended);
+        assert_eq!(tracker.next_check(), None);
+
+        assert!(tracker.activity());
+        assert!(!tracker.suspended());
+        assert_eq!(states.try_recv().unwrap(), IdleState::Active);
+        assert!(states.try_recv().is_err());
+    }
+
+    #[test]
+    fn ends_after_being_suspended() {
+        let (mut tracker, states) = tracker(Some(Duration::from_secs(0)));
+        assert!(tracker.update());
+        assert_eq!(
+            states.try_recv().unwrap(),
+            IdleState::Warning(Duration::from_secs(0))
+        );
+        assert_eq!(states.try_recv().unwrap(), IdleState::Suspended);
+        assert_eq!(states.try_recv().unwrap(), IdleState::Ended);
+        assert_eq!(tracker.next_check(), None);
+        assert!(!tracker.update());
+    }
+
+    #[test]
+    fn activity_without_headset_does_not_resume() {
+        let (mut tracker, states) = tracker(None);
+        assert!(!tracker.presence(false));
+        tracker.update();
+        assert!(!tracker.activity());
+        assert!(tracker.suspended());
+
+        assert!(tracker.presence(true));
+        assert!(!tracker.suspended());
+        let mut last = None;
+        while let Ok(state) = states.try_recv() {
+            last = Some(state);
+        }
+        assert_eq!(last, Some(IdleState::Active));
+    }
+}
@@ -0,0 +1,64 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Tracked images, as used by the WebXR Image Tracking module.
+
+use crate::Native;
+use crate::TrackedImage;
+
+use euclid::default::Size2D;
+use euclid::RigidTransform3D;
+
+#[cfg(feature = "ipc")]
+use serde::{Deserialize, Serialize};
+
+/// An identifier for a tracked image, unique within its session
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub struct TrackedImageId(pub u32);
+
+/// A reference image for the device to look for in the real world
+/// https://immersive-web.github.io/marker-tracking/#dictdef-xrtrackedimageinit
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub struct TrackedImageInit {
+    /// The image's pixels, as 8-bit RGBA, row by row from the top
+    pub pixels: Vec<u8>,
+    /// The size of the image, in pixels
+    pub size: Size2D<i32>,
+    /// How wide the image is expected to be in the real world, in meters
+    pub width_in_meters: f32,
+}
+
+/// Whether the device can track a reference image, for example because it has enough detail
+/// https://immersive-web.github.io/marker-tracking/#enumdef-xrimagetrackingscore
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub enum TrackedImageScore {
+    Trackable,
+    Untrackable,
+}
+
+/// https://immersive-web.github.io/marker-tracking/#enumdef-xrimagetrackingstate
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub enum TrackedImageState {
+    /// The image is in view, and its pose was measured this frame
+    Tracked,
+    /// The image was seen before, and its pose is where the device expects it to be
+    Emulated,
+}
+
+/// The pose of a tracked image the device knows about this frame
+/// https://immersive-web.github.io/marker-tracking/#xrimagetrackingresult
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub struct TrackedImageFrame {
+    pub id: TrackedImageId,
+    /// The pose of the image's center, with the Y axis pointing out of the image
+    pub pose: RigidTransform3D<f32, TrackedImage, Native>,
+    pub state: TrackedImageState,
+    /// How wide the image was measured to be, in meters
+    pub measured_width_in_meters: f32,
+}
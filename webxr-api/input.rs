@@ -2,8 +2,11 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
 
+use crate::Hand;
 use crate::Input;
+use crate::JointFrame;
 use crate::Native;
+use crate::Velocity;
 
 use euclid::RigidTransform3D;
 
@@ -19,20 +22,35 @@ pub enum Handedness {
     Right,
 }
 
-#[derive(Copy, Clone, Debug)]
+/// https://immersive-web.github.io/webxr/#enumdef-xrtargetraymode
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
 #[cfg_attr(feature = "ipc", derive(serde::Serialize, serde::Deserialize))]
 pub enum TargetRayMode {
+    /// The ray follows the viewer's gaze, e.g. a headset's select button
     Gaze,
+    /// The ray comes from a tracked controller or hand
     TrackedPointer,
+    /// The ray comes from a tap on a touchscreen, through the viewer's view
     Screen,
+    /// The ray comes from a transient, operating-system generated pointer
+    /// https://immersive-web.github.io/webxr/#dom-xrtargetraymode-transient-pointer
+    TransientPointer,
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Clone, Debug)]
 #[cfg_attr(feature = "ipc", derive(serde::Serialize, serde::Deserialize))]
 pub struct InputSource {
     pub handedness: Handedness,
     pub target_ray_mode: TargetRayMode,
     pub id: InputId,
+    /// The input profile names, most specific first, e.g. `["oculus-touch-v3", "generic-trigger"]`
+    /// https://immersive-web.github.io/webxr/#dom-xrinputsource-profiles
+    pub profiles: Vec<String>,
+    /// Whether the input source has a grip, such as a controller or hand does.
+    /// Gaze and screen inputs never have one, whereas sources with one leave it out
+    /// of frames only while it is not tracked.
+    /// https://immersive-web.github.io/webxr/#dom-xrinputsource-gripspace
+    pub has_grip: bool,
 }
 
 #[derive(Clone, Debug)]
@@ -40,4 +58,79 @@ pub struct InputSource {
 pub struct InputFrame {
     pub id: InputId,
     pub target_ray_origin: RigidTransform3D<f32, Input, Native>,
+    /// The pose of the user's hand holding the input source, if it has a grip
+    /// and it is tracked this frame
+    pub grip_origin: Option<RigidTransform3D<f32, Input, Native>>,
+    /// How fast the input source is moving and turning, if the device knows
+    pub velocity: Velocity,
+    /// The button and axis state, for input sources which have them
+    pub gamepad: Option<Gamepad>,
+    /// The articulated hand joints, for input sources which are tracked hands
+    pub hand: Option<Box<Hand<JointFrame>>>,
+}
+
+/// The state of a gamepad attached to an input source
+/// https://immersive-web.github.io/webxr-gamepads-module/#gamepad-differences
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "ipc", derive(serde::Serialize, serde::Deserialize))]
+pub struct Gamepad {
+    pub mapping: GamepadMapping,
+    pub buttons: Vec<GamepadButton>,
+    pub axes: Vec<f32>,
+}
+
+/// https://www.w3.org/TR/gamepad/#gamepadbutton-interface
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "ipc", derive(serde::Serialize, serde::Deserialize))]
+pub struct GamepadButton {
+    pub pressed: bool,
+    /// Whether the user is touching the button. Pressed buttons are always touched,
+    /// but buttons which can sense touch, such as capacitive triggers, thumbsticks
+    /// and touchpads, can be touched without being pressed.
+    pub touched: bool,
+    /// The amount the button is pressed, from 0.0 to 1.0
+    pub value: f32,
+}
+
+/// https://www.w3.org/TR/gamepad/#dom-gamepadmappingtype
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "ipc", derive(serde::Serialize, serde::Deserialize))]
+pub enum GamepadMapping {
+    /// The buttons and axes are in no particular order
+    None,
+    /// https://immersive-web.github.io/webxr-gamepads-module/#xr-standard-gamepad-mapping
+    XrStandard,
+}
+
+/// Which primary action an input source performed
+/// https://immersive-web.github.io/webxr/#primary-action
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "ipc", derive(serde::Serialize, serde::Deserialize))]
+pub enum SelectKind {
+    Select,
+    Squeeze,
+}
+
+/// The stages of a primary action
+/// https://immersive-web.github.io/webxr/#eventdef-xrsession-selectstart
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "ipc", derive(serde::Serialize, serde::Deserialize))]
+pub enum SelectEvent {
+    /// The action started
+    Start,
+    /// The action ended without completing, e.g. the input source was disconnected
+    End,
+    /// The action completed
+    Select,
+}
+
+/// A request to vibrate the haptic actuator of an input source
+/// https://w3c.github.io/gamepad/extensions.html#dom-gamepadhapticactuator-pulse
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "ipc", derive(serde::Serialize, serde::Deserialize))]
+pub struct HapticPulse {
+    /// The strength of the vibration, from 0.0 to 1.0
+    pub intensity: f32,
+    /// How long to vibrate for, in milliseconds
+    pub duration: f64,
 }
@@ -0,0 +1,676 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Composition layers, as used by the WebXR Layers module.
+
+use crate::Device;
+use crate::DeviceImage;
+use crate::Error;
+use crate::FrameCapture;
+use crate::Layer;
+use crate::MirrorCallback;
+use crate::Native;
+use crate::SwapchainId;
+use crate::SwapchainManager;
+use crate::TextureHandle;
+use crate::ViewLayout;
+use crate::Viewer;
+use crate::Viewport;
+use crate::WebGLExternalImageApi;
+
+use euclid::default::Size2D as UntypedSize2D;
+use euclid::Rect;
+use euclid::RigidTransform3D;
+use euclid::Rotation3D;
+use euclid::Size2D;
+use euclid::Vector3D;
+
+use gleam::gl::GLsync;
+use gleam::gl::GLuint;
+
+use std::mem;
+use std::ptr;
+
+#[cfg(feature = "ipc")]
+use serde::{Deserialize, Serialize};
+
+/// An identifier for a layer, unique within its session
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub struct LayerId(pub u32);
+
+impl LayerId {
+    /// The projection layer every session starts with
+    pub const DEFAULT: LayerId = LayerId(0);
+}
+
+/// The description of a layer
+/// https://immersive-web.github.io/layers/#xrlayertypes
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub enum LayerInit {
+    /// A layer which fills the views, rendered with one viewport per view
+    /// https://immersive-web.github.io/layers/#xrprojectionlayertype
+    Projection,
+    /// A flat rectangle positioned in the world, with its size measured in meters
+    /// https://immersive-web.github.io/layers/#xrquadlayertype
+    Quad {
+        transform: RigidTransform3D<f32, Layer, Native>,
+        size: Size2D<f32, Layer>,
+    },
+    /// A cube map around the viewer, infinitely far away, such as a skybox. It turns
+    /// as the viewer does, but doesn't move as they move. Its texture is a cube map,
+    /// which content can render a static environment to once rather than every frame.
+    /// https://immersive-web.github.io/layers/#xrcubelayertype
+    Cube {
+        orientation: Rotation3D<f32, Layer, Native>,
+    },
+    /// A flat rectangle which moves with the viewer, for the embedder's own UI
+    /// such as permission prompts. These are always composited in front of
+    /// the other layers, in the order they were created.
+    /// https://immersive-web.github.io/layers/#xrquadlayertype
+    HeadLockedQuad {
+        transform: RigidTransform3D<f32, Layer, Viewer>,
+        size: Size2D<f32, Layer>,
+    },
+    /// A 2D surface from the embedder, such as a rendered DOM element,
+    /// which the device keeps in front of the other layers
+    /// https://immersive-web.github.io/dom-overlays/
+    DomOverlay {
+        overlay_type: DomOverlayType,
+        /// The part of the overlay which should capture input, in layer
+        /// coordinates where the overlay is from (0,0) to (1,1).
+        /// Input whose target ray hits this region is the embedder's to handle.
+        hit_region: Option<Rect<f32, Layer>>,
+    },
+}
+
+/// How a DOM overlay is placed
+/// https://immersive-web.github.io/dom-overlays/#enumdef-xrdomoverlaytype
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub enum DomOverlayType {
+    /// Covering the whole screen, for handheld devices
+    Screen,
+    /// Fixed in front of the viewer, for headsets
+    HeadLocked,
+}
+
+impl LayerInit {
+    pub fn is_projection(&self) -> bool {
+        match *self {
+            LayerInit::Projection => true,
+            _ => false,
+        }
+    }
+
+    pub fn is_dom_overlay(&self) -> bool {
+        match *self {
+            LayerInit::DomOverlay { .. } => true,
+            _ => false,
+        }
+    }
+
+    pub fn is_cube(&self) -> bool {
+        match *self {
+            LayerInit::Cube { .. } => true,
+            _ => false,
+        }
+    }
+
+    pub fn is_head_locked(&self) -> bool {
+        match *self {
+            LayerInit::HeadLockedQuad { .. } => true,
+            _ => false,
+        }
+    }
+
+    /// A head-locked quad centered in front of the viewer at a distance in meters,
+    /// facing them
+    pub fn hud(size: Size2D<f32, Layer>, distance: f32) -> LayerInit {
+        LayerInit::HeadLockedQuad {
+            transform: RigidTransform3D::from_translation(Vector3D::new(0.0, 0.0, -distance)),
+            size,
+        }
+    }
+}
+
+/// A scale and bias applied to the colors of a layer as it is composited,
+/// each color becoming `color * scale + bias`, with the RGBA components in order.
+/// This can fade a layer to black or white without content rendering it again.
+/// https://registry.khronos.org/OpenXR/specs/1.0/html/xrspec.html#XR_KHR_composition_layer_color_scale_bias
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub struct ColorScaleBias {
+    pub scale: [f32; 4],
+    pub bias: [f32; 4],
+}
+
+impl ColorScaleBias {
+    /// Leaves colors as they are
+    pub const IDENTITY: ColorScaleBias = ColorScaleBias {
+        scale: [1.0; 4],
+        bias: [0.0; 4],
+    };
+
+    pub fn is_identity(&self) -> bool {
+        *self == ColorScaleBias::IDENTITY
+    }
+
+    /// Apply the scale and bias to an 8-bit RGBA color
+    pub fn apply(&self, color: [u8; 4]) -> [u8; 4] {
+        let mut result = [0; 4];
+        for i in 0..4 {
+            let value = color[i] as f32 / 255.0 * self.scale[i] + self.bias[i];
+            result[i] = (value.max(0.0).min(1.0) * 255.0).round() as u8;
+        }
+        result
+    }
+}
+
+impl Default for ColorScaleBias {
+    fn default() -> ColorScaleBias {
+        ColorScaleBias::IDENTITY
+    }
+}
+
+/// How a layer is blended with the layers behind it
+/// https://immersive-web.github.io/layers/#dom-xrcompositionlayer-blendtexturesourcealpha
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub struct LayerBlend {
+    /// Whether the layer's alpha is used to blend it, rather than it being opaque
+    pub blend_source_alpha: bool,
+    /// Whether the layer's colors are already multiplied by its alpha, as WebGL's are by default
+    pub premultiplied_alpha: bool,
+}
+
+impl Default for LayerBlend {
+    fn default() -> LayerBlend {
+        LayerBlend {
+            blend_source_alpha: true,
+            premultiplied_alpha: true,
+        }
+    }
+}
+
+/// The texture a layer is to be rendered from this frame
+#[derive(Clone, Debug)]
+pub struct LayerTexture {
+    pub id: LayerId,
+    pub init: LayerInit,
+    /// The GL texture, or 0 if the layer's texture isn't GL's
+    pub texture_id: GLuint,
+    /// The texture as content's images gave it, or None for swapchain images.
+    /// Devices which can display platform textures, rather than GL's, get them here.
+    pub handle: Option<TextureHandle>,
+    pub size: UntypedSize2D<i32>,
+    /// The number of layers of the texture, which is more than one when the views
+    /// were rendered to a texture array, one per layer, rather than side by side
+    pub array_size: usize,
+    pub sync: GLsync,
+    /// Whether content rendered to the device image it was given for this layer,
+    /// in which case the device has nothing to copy
+    pub device_image: bool,
+    /// The swapchain image the layer is displayed from, for layers rendered to
+    /// one of the device's swapchains, in which case there is no texture or sync
+    pub swapchain_image: Option<(SwapchainId, usize)>,
+    /// The image of each view's swapchain, in the order of the views, for projection
+    /// layers rendered to a swapchain per view, in which case `swapchain_image` is None
+    /// and `size` is the size of the first view's swapchain
+    pub view_images: Vec<(SwapchainId, usize)>,
+    /// The scale and bias to apply to the layer's colors
+    pub color_scale_bias: ColorScaleBias,
+    pub blend: LayerBlend,
+}
+
+struct LayerInfo {
+    id: LayerId,
+    init: LayerInit,
+    images: Option<Box<dyn WebGLExternalImageApi>>,
+    swapchain: Option<SwapchainId>,
+    // A swapchain for each view, for layers which have them rather than one swapchain
+    view_swapchains: Vec<SwapchainId>,
+    color_scale_bias: ColorScaleBias,
+    blend: LayerBlend,
+}
+
+/// The layers belonging to a session, together with the WebGL images
+/// they are rendered from, and the order in which they are composited.
+pub(crate) struct LayerManager {
+    layers: Vec<LayerInfo>,
+    order: Vec<LayerId>,
+    next_id: u32,
+    // The textures rendered last frame, and the indices of the layers whose images
+    // were locked for it, whose buffers are reused for the next frame
+    textures: Vec<LayerTexture>,
+    locked: Vec<usize>,
+    spare_view_images: Vec<Vec<(SwapchainId, usize)>>,
+}
+
+impl LayerManager {
+    pub(crate) fn new() -> LayerManager {
+        let default = LayerInfo {
+            id: LayerId::DEFAULT,
+            init: LayerInit::Projection,
+            images: None,
+            swapchain: None,
+            view_swapchains: vec![],
+            color_scale_bias: ColorScaleBias::IDENTITY,
+            blend: LayerBlend::default(),
+        };
+        LayerManager {
+            layers: vec![default],
+            order: vec![LayerId::DEFAULT],
+            next_id: 1,
+            textures: vec![],
+            locked: vec![],
+            spare_view_images: vec![],
+        }
+    }
+
+    pub(crate) fn create_layer<D: Device>(
+        &mut self,
+        device: &mut D,
+        init: LayerInit,
+    ) -> Result<LayerId, Error> {
+        let id = LayerId(self.next_id);
+        device.create_layer(id, init)?;
+        self.next_id += 1;
+        self.layers.push(LayerInfo {
+            id,
+            init,
+            images: None,
+            swapchain: None,
+            view_swapchains: vec![],
+            color_scale_bias: ColorScaleBias::IDENTITY,
+            blend: LayerBlend::default(),
+        });
+        if init.is_head_locked() {
+            self.order.push(id);
+        }
+        Ok(id)
+    }
+
+    /// Change the parameters of a layer. The kind of layer cannot change.
+    pub(crate) fn update_layer<D: Device>(
+        &mut self,
+        device: &mut D,
+        id: LayerId,
+        init: LayerInit,
+    ) -> Result<(), Error> {
+        let layer = self
+            .layers
+            .iter_mut()
+            .find(|layer| layer.id == id)
+            .ok_or(Error::UnsupportedFeature)?;
+        if mem::discriminant(&layer.init) != mem::discriminant(&init) {
+            return Err(Error::UnsupportedFeature);
+        }
+        device.update_layer(id, init)?;
+        layer.init = init;
+        Ok(())
+    }
+
+    /// Destroy a layer, apart from the default projection layer,
+    /// which the session always has
+    pub(crate) fn destroy_layer<D: Device>(
+        &mut self,
+        device: &mut D,
+        id: LayerId,
+    ) -> Result<(), Error> {
+        if id == LayerId::DEFAULT {
+            return Err(Error::InvalidLayer);
+        }
+        if self.layers.iter().any(|layer| layer.id == id) {
+            self.layers.retain(|layer| layer.id != id);
+            self.order.retain(|layer| *layer != id);
+            device.destroy_layer(id);
+        }
+        Ok(())
+    }
+
+    /// Set the layers to be composited, back to front.
+    /// Unknown layers are ignored, and DOM overlays are always composited last,
+    /// apart from head-locked quads which are always composited whether or not
+    /// they are listed.
+    pub(crate) fn set_layers(&mut self, order: Vec<LayerId>) {
+        let layers = &self.layers;
+        let (overlays, mut order): (Vec<&LayerInfo>, Vec<&LayerInfo>) = order
+            .into_iter()
+            .filter_map(|id| layers.iter().find(|layer| layer.id == id))
+            .filter(|layer| !layer.init.is_head_locked())
+            .partition(|layer| layer.init.is_dom_overlay());
+        order.extend(overlays);
+        order.extend(layers.iter().filter(|layer| layer.init.is_head_locked()));
+        self.order = order.into_iter().map(|layer| layer.id).collect();
+    }
+
+    /// How content lays the views out in the swapchains the default projection layer
+    /// is rendered to, for the viewports given with each frame
+    pub(crate) fn view_layout(&self, swapchains: &SwapchainManager) -> ViewLayout {
+        let layer = match self
+            .layers
+            .iter()
+            .find(|layer| layer.id == LayerId::DEFAULT)
+        {
+            Some(layer) => layer,
+            None => return ViewLayout::SideBySide,
+        };
+        if !layer.view_swapchains.is_empty() {
+            return ViewLayout::SeparateSurfaces(layer.view_swapchains.len());
+        }
+        let array_size = layer
+            .swapchain
+            .and_then(|swapchain| swapchains.array_size(swapchain))
+            .unwrap_or(1);
+        if array_size > 1 {
+            ViewLayout::TextureArray(array_size)
+        } else {
+            ViewLayout::SideBySide
+        }
+    }
+
+    /// The device images for the composited layers, for the device which can share them,
+    /// replacing the contents of `images`
+    pub(crate) fn device_images<D: Device>(&self, device: &mut D, images: &mut Vec<DeviceImage>) {
+        images.clear();
+        images.extend(self.order.iter().filter_map(|id| device.device_image(*id)));
+    }
+
+    pub(crate) fn update_images(&mut self, id: LayerId, images: Box<dyn WebGLExternalImageApi>) {
+        if let Some(layer) = self.layers.iter_mut().find(|layer| layer.id == id) {
+            layer.images = Some(images);
+        }
+    }
+
+    /// Render a layer from a swapchain, rather than its WebGL images, or stop if None
+    pub(crate) fn set_swapchain(&mut self, id: LayerId, swapchain: Option<SwapchainId>) {
+        if let Some(layer) = self.layers.iter_mut().find(|layer| layer.id == id) {
+            layer.swapchain = swapchain;
+            layer.view_swapchains.clear();
+        }
+    }
+
+    /// Render a projection layer from a swapchain per view, or stop if there are none
+    pub(crate) fn set_view_swapchains(&mut self, id: LayerId, swapchains: Vec<SwapchainId>) {
+        if let Some(layer) = self.layers.iter_mut().find(|layer| layer.id == id) {
+            if layer.init.is_projection() {
+                layer.swapchain = None;
+                layer.view_swapchains = swapchains;
+            }
+        }
+    }
+
+    pub(crate) fn set_color_scale_bias(&mut self, id: LayerId, color_scale_bias: ColorScaleBias) {
+        if let Some(layer) = self.layers.iter_mut().find(|layer| layer.id == id) {
+            layer.color_scale_bias = color_scale_bias;
+        }
+    }
+
+    pub(crate) fn set_blend(&mut self, id: LayerId, blend: LayerBlend) {
+        if let Some(layer) = self.layers.iter_mut().find(|layer| layer.id == id) {
+            layer.blend = blend;
+        }
+    }
+
+    /// Stop rendering layers from a swapchain which has been destroyed
+    pub(crate) fn remove_swapchain(&mut self, swapchain: SwapchainId) {
+        for layer in &mut self.layers {
+            if layer.swapchain == Some(swapchain) {
+                layer.swapchain = None;
+            }
+            if layer.view_swapchains.contains(&swapchain) {
+                layer.view_swapchains.clear();
+            }
+        }
+    }
+
+    /// Lock the images of every composited layer, render them, then unlock them
+    /// with the device's sync object for the render.
+    /// Layers rendered to swapchains are displayed from their most recently released image.
+    /// If asked to, this also captures the rendered frame before unlocking, and
+    /// gives the part of the topmost projection layer's texture to mirror to the mirror.
+    pub(crate) fn render<D: Device>(
+        &mut self,
+        device: &mut D,
+        swapchains: &SwapchainManager,
+        capture: bool,
+        mirror: Option<(&mut dyn MirrorCallback, Rect<i32, Viewport>)>,
+    ) -> Option<FrameCapture> {
+        let LayerManager {
+            ref layers,
+            ref order,
+            ref mut textures,
+            ref mut locked,
+            ref mut spare_view_images,
+            ..
+        } = *self;
+        // The last frame's buffers are reused, so rendering doesn't allocate
+        for texture in textures.drain(..) {
+            if texture.view_images.capacity() > 0 {
+                spare_view_images.push(texture.view_images);
+            }
+        }
+        locked.clear();
+        for id in order {
+            let (index, layer) = match layers.iter().enumerate().find(|(_, layer)| layer.id == *id)
+            {
+                Some(layer) => layer,
+                None => continue,
+            };
+            if !layer.view_swapchains.is_empty() {
+                let mut view_images = spare_view_images.pop().unwrap_or_default();
+                view_images.clear();
+                view_images.extend(layer.view_swapchains.iter().filter_map(|swapchain| {
+                    Some((*swapchain, swapchains.released_image(*swapchain)?))
+                }));
+                // Layers are displayed once every view has been rendered
+                if view_images.len() < layer.view_swapchains.len() {
+                    spare_view_images.push(view_images);
+                    continue;
+                }
+                let first = layer.view_swapchains[0];
+                textures.push(LayerTexture {
+                    id: layer.id,
+                    init: layer.init,
+                    texture_id: 0,
+                    handle: None,
+                    size: swapchains.size(first).unwrap_or_default(),
+                    array_size: 1,
+                    sync: ptr::null(),
+                    device_image: true,
+                    swapchain_image: None,
+                    view_images,
+                    color_scale_bias: layer.color_scale_bias,
+                    blend: layer.blend,
+                });
+            } else if let Some(swapchain) = layer.swapchain {
+                if let Some(image) = swapchains.released_image(swapchain) {
+                    textures.push(LayerTexture {
+                        id: layer.id,
+                        init: layer.init,
+                        texture_id: 0,
+                        handle: None,
+                        size: swapchains.size(swapchain).unwrap_or_default(),
+                        array_size: swapchains.array_size(swapchain).unwrap_or(1),
+                        sync: ptr::null(),
+                        device_image: true,
+                        swapchain_image: Some((swapchain, image)),
+                        view_images: vec![],
+                        color_scale_bias: layer.color_scale_bias,
+                        blend: layer.blend,
+                    });
+                }
+            } else if let Some(ref images) = layer.images {
+                if let Ok((handle, size, sync)) = images.lock_handle() {
+                    let texture_id = match handle {
+                        TextureHandle::Gl(texture_id) => texture_id,
+                        _ => 0,
+                    };
+                    textures.push(LayerTexture {
+                        id: layer.id,
+                        init: layer.init,
+                        texture_id,
+                        handle: Some(handle),
+                        size,
+                        array_size: 1,
+                        sync,
+                        device_image: images.is_device_image(),
+                        swapchain_image: None,
+                        view_images: vec![],
+                        color_scale_bias: layer.color_scale_bias,
+                        blend: layer.blend,
+                    });
+                    locked.push(index);
+                }
+            }
+        }
+        if !textures.is_empty() {
+            device.render_layers(textures);
+        }
+        let captured = if capture {
+            device.capture_frame()
+        } else {
+            None
+        };
+        // Only GL textures can be mirrored
+        let mirrored = textures
+            .iter()
+            .rev()
+            .find(|layer| layer.init.is_projection() && layer.texture_id != 0);
+        if let (Some((mirror, source)), Some(layer)) = (mirror, mirrored) {
+            mirror.mirror(layer.texture_id, layer.size, source, layer.sync);
+        }
+        // The device may still be reading the textures, so content waits for
+        // it on the GPU, rather than the device finishing its work first
+        let sync = if locked.is_empty() {
+            ptr::null()
+        } else {
+            device.render_sync()
+        };
+        for images in locked
+            .iter()
+            .filter_map(|index| layers[*index].images.as_ref())
+        {
+            if sync.is_null() {
+                images.unlock();
+            } else {
+                images.unlock_with_sync(sync);
+            }
+        }
+        captured
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::EventCallback;
+    use crate::EventFilter;
+    use crate::Feature;
+    use crate::Floor;
+    use crate::Frame;
+    use crate::InputSource;
+    use crate::Views;
+
+    // A device which supports every kind of layer, and remembers which it destroyed
+    #[derive(Default)]
+    struct LayerDevice {
+        destroyed: Vec<LayerId>,
+    }
+
+    impl Device for LayerDevice {
+        fn floor_transform(&self) -> RigidTransform3D<f32, Native, Floor> {
+            RigidTransform3D::identity()
+        }
+
+        fn views(&self) -> Views {
+            unreachable!()
+        }
+
+        fn wait_for_animation_frame(&mut self) -> Frame {
+            unreachable!()
+        }
+
+        fn render_animation_frame(&mut self, _: u32, _: UntypedSize2D<i32>, _: GLsync) {}
+
+        fn create_layer(&mut self, _id: LayerId, _init: LayerInit) -> Result<(), Error> {
+            Ok(())
+        }
+
+        fn destroy_layer(&mut self, id: LayerId) {
+            self.destroyed.push(id);
+        }
+
+        fn granted_features(&self) -> Vec<Feature> {
+            vec![]
+        }
+
+        fn initial_inputs(&self) -> Vec<InputSource> {
+            vec![]
+        }
+
+        fn set_event_callback(&mut self, _callback: Box<dyn EventCallback>) {}
+
+        fn subscribe_events(&mut self, _filter: EventFilter, _callback: Box<dyn EventCallback>) {}
+
+        fn connected(&mut self) -> bool {
+            true
+        }
+
+        fn quit(&mut self) {}
+    }
+
+    fn quad() -> LayerInit {
+        LayerInit::Quad {
+            transform: RigidTransform3D::identity(),
+            size: Size2D::new(1.0, 1.0),
+        }
+    }
+
+    #[test]
+    fn create_and_destroy_layers() {
+        let mut device = LayerDevice::default();
+        let mut layers = LayerManager::new();
+        let first = layers.create_layer(&mut device, quad()).unwrap();
+        let second = layers.create_layer(&mut device, quad()).unwrap();
+        assert_ne!(first, LayerId::DEFAULT);
+        assert_ne!(first, second);
+        layers.set_layers(vec![LayerId::DEFAULT, first, second]);
+        assert_eq!(layers.order, vec![LayerId::DEFAULT, first, second]);
+
+        layers.destroy_layer(&mut device, first).unwrap();
+        assert_eq!(device.destroyed, vec![first]);
+        assert_eq!(layers.order, vec![LayerId::DEFAULT, second]);
+        // Destroying an unknown layer does nothing
+        layers.destroy_layer(&mut device, first).unwrap();
+        assert_eq!(device.destroyed, vec![first]);
+    }
+
+    #[test]
+    fn default_layer_cannot_be_destroyed() {
+        let mut device = LayerDevice::default();
+        let mut layers = LayerManager::new();
+        match layers.destroy_layer(&mut device, LayerId::DEFAULT) {
+            Err(Error::InvalidLayer) => {}
+            result => panic!("Expected InvalidLayer, got {:?}", result),
+        }
+        assert!(device.destroyed.is_empty());
+        assert_eq!(layers.order, vec![LayerId::DEFAULT]);
+    }
+
+    #[test]
+    fn head_locked_quads_are_always_composited_last() {
+        let mut device = LayerDevice::default();
+        let mut layers = LayerManager::new();
+        let hud = layers
+            .create_layer(&mut device, LayerInit::hud(Size2D::new(1.0, 1.0), 1.0))
+            .unwrap();
+        let quad = layers.create_layer(&mut device, quad()).unwrap();
+        layers.set_layers(vec![quad, LayerId::DEFAULT]);
+        assert_eq!(layers.order, vec![quad, LayerId::DEFAULT, hud]);
+    }
+}
@@ -4,106 +4,252 @@
 
 //! This crate defines the Rust API for WebXR. It is implemented by the `webxr` crate.
 
+mod anchor;
+mod body;
+mod camera;
+mod delivery;
+mod depth;
 mod device;
 mod error;
 mod events;
+mod face;
 mod frame;
+#[cfg(not(feature = "ipc"))]
+mod future;
+mod glstate;
+mod hand;
+mod hittest;
+mod idle;
+mod image;
 mod input;
+mod layer;
+mod lighting;
+mod mesh;
+mod mirror;
 mod mock;
+mod performance;
+#[cfg(feature = "recording")]
+mod recording;
 mod registry;
 mod session;
+mod swapchain;
+mod trace;
+mod transport;
+mod video;
 mod view;
 mod webgl;
 
+pub use anchor::AnchorFrame;
+pub use anchor::AnchorId;
+pub use anchor::ExportedAnchor;
+pub use anchor::GeospatialAnchorInit;
+
+pub use body::Body;
+pub use body::BodyJoint;
+pub use body::BodySide;
+pub use body::BodySideJoint;
+
+pub use camera::CameraImage;
+
+pub use depth::DepthData;
+pub use depth::DepthDataFormat;
+pub use depth::DepthInfo;
+pub use depth::DepthSensingConfig;
+pub use depth::DepthSensingInit;
+pub use depth::DepthUsage;
+
 pub use device::Device;
 pub use device::Discovery;
 
+pub use error::BackendError;
 pub use error::Error;
 
 pub use events::Event;
 pub use events::EventBuffer;
 pub use events::EventCallback;
+pub use events::EventCategory;
+pub use events::EventFilter;
+pub use events::SessionEndReason;
 pub use events::Visibility;
 
+pub use face::FaceExpressionSet;
+pub use face::FaceExpressions;
+
 pub use frame::Frame;
+pub use frame::FrameCapture;
+pub use frame::FrameStats;
+pub use frame::FrameTiming;
+pub use frame::Poses;
+pub use frame::Velocity;
 
+#[cfg(not(feature = "ipc"))]
+pub use future::Reply;
+
+pub use glstate::GlState;
+
+pub use hand::Finger;
+pub use hand::FingerJoint;
+pub use hand::Hand;
+pub use hand::HandJoint;
+pub use hand::JointFrame;
+pub use hand::PinchSelect;
+
+pub use hittest::BaseSpace;
+pub use hittest::EntityType;
+pub use hittest::HitTestId;
+pub use hittest::HitTestInit;
+pub use hittest::HitTestResult;
+pub use hittest::Ray;
+
+pub use idle::IdleCallback;
+pub(crate) use idle::IdleEventCallback;
+pub use idle::IdlePolicy;
+pub use idle::IdleState;
+pub(crate) use idle::IdleTracker;
+
+pub use image::TrackedImageFrame;
+pub use image::TrackedImageId;
+pub use image::TrackedImageInit;
+pub use image::TrackedImageScore;
+pub use image::TrackedImageState;
+
+pub use input::Gamepad;
+pub use input::GamepadButton;
+pub use input::GamepadMapping;
 pub use input::Handedness;
+pub use input::HapticPulse;
 pub use input::InputFrame;
 pub use input::InputId;
 pub use input::InputSource;
+pub use input::SelectEvent;
+pub use input::SelectKind;
 pub use input::TargetRayMode;
 
+pub use layer::ColorScaleBias;
+pub use layer::DomOverlayType;
+pub use layer::LayerBlend;
+pub use layer::LayerId;
+pub use layer::LayerInit;
+pub use layer::LayerTexture;
+
+pub use lighting::LightEstimate;
+
+pub use mesh::DetectedMesh;
+pub use mesh::MeshId;
+
+pub use mirror::MirrorCallback;
+pub use mirror::MirrorView;
+
+pub use mock::MockAnchorMsg;
+pub use mock::MockClock;
 pub use mock::MockDeviceInit;
 pub use mock::MockDeviceMsg;
 pub use mock::MockDiscovery;
 pub use mock::MockInputInit;
 pub use mock::MockInputMsg;
-
+pub use mock::MockRegion;
+pub use mock::MockScenario;
+pub use mock::MockScenarioAction;
+pub use mock::MockScenarioStep;
+pub use mock::MockWorld;
+pub use mock::Triangle;
+
+pub use performance::BatteryStatus;
+pub use performance::DeviceStatus;
+pub use performance::PerformanceDomain;
+pub use performance::PerformanceLevel;
+pub use performance::PerformanceNotification;
+pub use performance::PerformanceNotificationLevel;
+pub use performance::PerformanceSubDomain;
+pub use performance::ThermalState;
+
+#[cfg(feature = "recording")]
+pub use recording::read_recording;
+#[cfg(feature = "recording")]
+pub use recording::Record;
+#[cfg(feature = "recording")]
+pub use recording::RecordingDevice;
+#[cfg(feature = "recording")]
+pub use recording::SessionRecord;
+
+pub use registry::DiscoveryId;
 pub use registry::MainThreadRegistry;
+pub use registry::PermissionHandler;
+pub use registry::PermissionRequest;
 pub use registry::Registry;
-pub use registry::{MockDeviceCallback, SessionRequestCallback, SessionSupportCallback};
-
+pub use registry::{
+    DeviceChangeCallback, MockDeviceCallback, SessionCapabilitiesCallback, SessionRequestCallback,
+    SessionSupportCallback,
+};
+
+pub use session::ColorSpace;
+pub use session::EnvironmentBlendMode;
+pub use session::Feature;
+pub use session::FrameCaptureCallback;
 pub use session::FrameRequestCallback;
+pub use session::FrameRequestId;
+pub use session::FrameStatsCallback;
 pub use session::HighResTimeStamp;
 pub use session::MainThreadSession;
 pub use session::Session;
 pub use session::SessionBuilder;
+pub use session::SessionCapabilities;
+pub use session::SessionInit;
 pub use session::SessionMode;
 pub use session::SessionThread;
 
+pub use swapchain::Swapchain;
+pub use swapchain::SwapchainId;
+pub use swapchain::SwapchainInit;
+pub use swapchain::SwapchainLayout;
+pub(crate) use swapchain::SwapchainManager;
+pub use swapchain::TextureFormat;
+pub use swapchain::TextureUsage;
+
+pub use trace::span;
+pub use trace::Span;
+
+pub use transport::channel;
+pub use transport::recv_timeout;
+pub use transport::Message;
+pub use transport::Receiver;
+pub use transport::Sender;
+pub use transport::Transport;
+
+pub use video::VideoFrameCallback;
+pub use video::VideoInit;
+pub(crate) use video::VideoRecorder;
+#[cfg(not(feature = "ipc"))]
+pub use video::Y4mWriter;
+
+pub use view::Anchor;
+pub use view::ApiSpace;
+pub use view::Camera;
+pub use view::DepthBuffer;
 pub use view::Display;
+pub use view::Eye;
 pub use view::Floor;
+pub use view::HitTestSpace;
 pub use view::Input;
+pub use view::Joint;
+pub use view::Layer;
 pub use view::LeftEye;
+pub use view::MeshSpace;
+pub use view::MultiView;
 pub use view::Native;
+pub use view::NormalizedView;
 pub use view::RightEye;
+pub use view::TrackedImage;
 pub use view::View;
+pub use view::ViewLayout;
+pub use view::ViewTarget;
+pub use view::ViewViewport;
 pub use view::Viewer;
+pub use view::Viewpoint;
 pub use view::Viewport;
+pub use view::Viewports;
 pub use view::Views;
 
+pub use webgl::DeviceImage;
+pub use webgl::TextureHandle;
 pub use webgl::WebGLExternalImageApi;
-
-#[cfg(feature = "ipc")]
-use std::thread;
-
-use std::time::Duration;
-
-#[cfg(feature = "ipc")]
-pub use ipc_channel::ipc::IpcSender as Sender;
-
-#[cfg(feature = "ipc")]
-pub use ipc_channel::ipc::IpcReceiver as Receiver;
-
-#[cfg(feature = "ipc")]
-pub use ipc_channel::ipc::channel;
-
-#[cfg(not(feature = "ipc"))]
-pub use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
-
-#[cfg(not(feature = "ipc"))]
-fn channel<T>() -> Result<(Sender<T>, Receiver<T>), ()> {
-    Ok(std::sync::mpsc::channel())
-}
-
-#[cfg(not(feature = "ipc"))]
-pub fn recv_timeout<T>(receiver: &Receiver<T>, timeout: Duration) -> Result<T, RecvTimeoutError> {
-    receiver.recv_timeout(timeout)
-}
-
-#[cfg(feature = "ipc")]
-pub fn recv_timeout<T>(receiver: &Receiver<T>, timeout: Duration) -> Result<T, ipc_channel::Error>
-where
-    T: serde::Serialize + for<'a> serde::Deserialize<'a>,
-{
-    // Sigh, polling, sigh.
-    let mut delay = timeout / 1000;
-    while delay < timeout {
-        if let Ok(msg) = receiver.try_recv() {
-            return Ok(msg);
-        }
-        thread::sleep(delay);
-        delay = delay * 2;
-    }
-    receiver.try_recv()
-}
@@ -0,0 +1,26 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Estimates of real-world lighting, as used by the WebXR Lighting Estimation module.
+
+use crate::Native;
+
+use euclid::Vector3D;
+
+#[cfg(feature = "ipc")]
+use serde::{Deserialize, Serialize};
+
+/// An estimate of the lighting around the viewer
+/// https://immersive-web.github.io/lighting-estimation/#xrlightestimate
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub struct LightEstimate {
+    /// Nine RGB spherical harmonics coefficients, for bands 0 to 2,
+    /// oriented the same way as native coordinates.
+    pub spherical_harmonics_coefficients: [f32; 27],
+    /// A unit vector pointing towards the brightest light source
+    pub primary_light_direction: Vector3D<f32, Native>,
+    /// The RGB intensity of the brightest light source
+    pub primary_light_intensity: [f32; 3],
+}
@@ -0,0 +1,39 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Meshes of the real world, as used by the WebXR Mesh Detection module.
+
+use crate::HighResTimeStamp;
+use crate::MeshSpace;
+use crate::Native;
+
+use euclid::Point3D;
+use euclid::RigidTransform3D;
+
+#[cfg(feature = "ipc")]
+use serde::{Deserialize, Serialize};
+
+/// An identifier for a mesh, unique within its session
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub struct MeshId(pub u32);
+
+/// A chunk of the real world the device has built a mesh of. Devices send
+/// each mesh as an `Event::UpdateMesh` when they first detect it and whenever
+/// it changes, and an `Event::RemoveMesh` when they discard it.
+/// https://immersive-web.github.io/real-world-meshing/#xrmesh
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub struct DetectedMesh {
+    pub id: MeshId,
+    pub pose: RigidTransform3D<f32, MeshSpace, Native>,
+    pub vertices: Vec<Point3D<f32, MeshSpace>>,
+    /// The vertices of each triangle, three at a time, counterclockwise
+    /// when seen from the front of the surface
+    pub indices: Vec<u32>,
+    /// When the mesh last changed
+    pub last_changed_time: HighResTimeStamp,
+    /// What the device thinks the mesh is of, such as "table" or "wall"
+    pub semantic_label: Option<String>,
+}
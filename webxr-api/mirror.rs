@@ -0,0 +1,61 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Mirroring the frames content renders, for example to a desktop window, so
+//! that bystanders and developers can see what the user of a headset sees.
+
+use crate::Viewport;
+
+use euclid::default::Size2D as UntypedSize2D;
+use euclid::Rect;
+
+use gleam::gl::GLsync;
+use gleam::gl::GLuint;
+
+#[cfg(feature = "ipc")]
+use serde::{Deserialize, Serialize};
+
+/// Which part of each frame to mirror
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub enum MirrorView {
+    /// The first view, which is the left eye's for stereo devices
+    Left,
+    /// The right eye's view, or the only view for mono devices
+    Right,
+    /// The part of the frame covering every view, side by side
+    All,
+}
+
+/// Receives each frame content renders to the projection layer, for the embedder
+/// to copy into its own window or texture
+#[cfg_attr(feature = "ipc", typetag::serde)]
+pub trait MirrorCallback: 'static + Send {
+    /// Called on the device's thread when a frame is rendered, with the GL texture
+    /// content rendered to, its size, the part of it to mirror, and the sync object
+    /// to wait for before reading it. The texture is only valid during the call,
+    /// so it must be copied before returning.
+    fn mirror(
+        &mut self,
+        texture_id: GLuint,
+        size: UntypedSize2D<i32>,
+        source: Rect<i32, Viewport>,
+        sync: GLsync,
+    );
+}
+
+impl MirrorView {
+    /// The part of a frame with these viewports to mirror
+    pub fn source(&self, viewports: &[Rect<i32, Viewport>]) -> Option<Rect<i32, Viewport>> {
+        match *self {
+            MirrorView::Left => viewports.first().copied(),
+            MirrorView::Right => viewports.get(1).or(viewports.first()).copied(),
+            MirrorView::All => {
+                let mut views = viewports.iter();
+                let first = *views.next()?;
+                Some(views.fold(first, |all, view| all.union(view)))
+            }
+        }
+    }
+}
@@ -2,26 +2,54 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
 
+use crate::Anchor;
+use crate::AnchorId;
+use crate::Body;
+use crate::CameraImage;
+use crate::ColorSpace;
+use crate::DepthInfo;
+use crate::DeviceStatus;
 use crate::Discovery;
+use crate::EntityType;
 use crate::Error;
+use crate::FaceExpressions;
+use crate::Feature;
 use crate::Floor;
+use crate::Gamepad;
+use crate::GamepadButton;
+use crate::GeospatialAnchorInit;
+use crate::Hand;
 use crate::Handedness;
+use crate::HighResTimeStamp;
 use crate::Input;
 use crate::InputId;
 use crate::InputSource;
+use crate::JointFrame;
+use crate::LightEstimate;
 use crate::Native;
+use crate::PerformanceNotification;
 use crate::Receiver;
+use crate::SelectEvent;
+use crate::SelectKind;
 use crate::Sender;
 use crate::TargetRayMode;
+use crate::TrackedImage;
+use crate::TrackedImageId;
+use crate::TrackedImageState;
+use crate::Velocity;
 use crate::Viewer;
 use crate::Views;
+use crate::Visibility;
 
+use euclid::Point2D;
 use euclid::RigidTransform3D;
+use euclid::Vector3D;
 
 #[cfg(feature = "ipc")]
 use serde::{Deserialize, Serialize};
 
-/// A trait for discovering mock XR devices
+/// A trait for discovering mock XR devices. The registry asks each registered
+/// mock discovery in turn, so a discovery can refuse devices it can't simulate.
 pub trait MockDiscovery: 'static {
     fn simulate_device_connection(
         &mut self,
@@ -38,17 +66,101 @@ pub struct MockDeviceInit {
     pub supports_unbounded: bool,
     pub viewer_origin: RigidTransform3D<f32, Viewer, Native>,
     pub views: Views,
+    /// The features the device supports, in addition to unbounded
+    /// if `supports_unbounded` is set
+    pub supported_features: Vec<Feature>,
+    /// The frame rates the device can run at, in Hz
+    pub supported_frame_rates: Vec<f32>,
+    /// The color spaces the device can display frames in,
+    /// the first of which it starts in
+    pub supported_color_spaces: Vec<ColorSpace>,
+    /// The boundary of the play area, reported if bounded-floor is granted
+    pub bounds_geometry: Option<Vec<Point2D<f32, Floor>>>,
+    /// The real-world geometry hit tests are run against
+    pub world: Option<MockWorld>,
+    /// How the time of each frame is decided
+    pub clock: MockClock,
 }
 
-#[derive(Debug)]
+/// How a mock device decides the time of each frame, which is
+/// reported as the frame's predicted display time
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub enum MockClock {
+    /// The time since the session started
+    WallClock,
+    /// Each frame is this many milliseconds after the last
+    FixedTimestep(HighResTimeStamp),
+    /// Each frame waits for a `MockDeviceMsg::Tick`
+    Manual,
+}
+
+impl Default for MockClock {
+    fn default() -> MockClock {
+        MockClock::WallClock
+    }
+}
+
+#[derive(Clone, Debug)]
 #[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
 pub enum MockDeviceMsg {
     SetViewerOrigin(RigidTransform3D<f32, Viewer, Native>),
+    /// Set how fast the viewer is moving, which is reported as is rather than moving it
+    SetViewerVelocity(Velocity),
     SetViews(Views),
+    /// Move the floor, which resets the floor reference spaces
+    SetFloorOrigin(RigidTransform3D<f32, Floor, Native>),
+    /// Change the boundary of the play area, or remove it
+    SetBoundsGeometry(Option<Vec<Point2D<f32, Floor>>>),
     AddInputSource(MockInputInit),
     MessageInputSource(InputId, MockInputMsg),
+    MessageAnchor(AnchorId, MockAnchorMsg),
+    /// Set where on Earth the origin of native coordinates is, which geospatial anchors
+    /// are placed relative to, or that the device doesn't know. Native -Z faces north,
+    /// so the origin's heading is ignored.
+    SetGeospatialOrigin(Option<GeospatialAnchorInit>),
+    /// Set where a tracked image is, and whether it is in view,
+    /// or that it hasn't been seen
+    SetTrackedImagePose(
+        TrackedImageId,
+        Option<(
+            RigidTransform3D<f32, TrackedImage, Native>,
+            TrackedImageState,
+        )>,
+    ),
+    /// Set the joints of the user's body, if body tracking is enabled
+    SetBody(Option<Box<Body<JointFrame>>>),
+    /// Set the user's facial expression, if face tracking is enabled
+    SetFace(Option<FaceExpressions>),
+    /// Set the depth information reported for each view, if depth sensing is enabled
+    SetDepthInfo(Vec<DepthInfo>),
+    /// Set the real world's depth reported for each view, if occlusion is enabled
+    SetOcclusionDepth(Vec<DepthInfo>),
+    /// Set the lighting estimate, if light estimation is enabled
+    SetLightEstimate(Option<LightEstimate>),
+    /// Set the camera image reported for each view, if camera access is enabled
+    SetCameraImages(Vec<CameraImage>),
+    /// https://immersive-web.github.io/webxr-test-api/#dom-fakexrdevice-setworld
+    SetWorld(MockWorld),
+    /// https://immersive-web.github.io/webxr-test-api/#dom-fakexrdevice-clearworld
+    ClearWorld,
+    /// Start playing a scenario, with its times relative to the next frame's
+    PlayScenario(MockScenario),
+    /// Produce a frame this many milliseconds after the last,
+    /// if the device has a manual clock
+    Tick(HighResTimeStamp),
+    /// https://immersive-web.github.io/webxr-test-api/#dom-fakexrdevice-simulatevisibilitychange
+    VisibilityChange(Visibility),
+    /// Report the user putting on or taking off the headset, which is worn to start with
+    SetUserPresent(bool),
+    /// Report a change in how well the device is coping
+    PerformanceNotification(PerformanceNotification),
+    /// Change the battery level and temperature the device reports
+    SetDeviceStatus(DeviceStatus),
     Focus,
     Blur,
+    /// Disconnect the device, ending its session. The sender is sent a message
+    /// once the session has ended and the device has stopped running.
     Disconnect(Sender<()>),
 }
 
@@ -57,14 +169,117 @@ pub enum MockDeviceMsg {
 pub struct MockInputInit {
     pub source: InputSource,
     pub pointer_origin: RigidTransform3D<f32, Input, Native>,
+    /// The initial grip, for input sources which have one. This is reported
+    /// if the source has a grip.
+    pub grip_origin: Option<RigidTransform3D<f32, Input, Native>>,
+    /// The initial button and axis state, for input sources which have them
+    pub gamepad: Option<Gamepad>,
+    /// The initial joint poses, for input sources which are tracked hands.
+    /// These are reported if hand tracking is enabled.
+    pub hand: Option<Box<Hand<JointFrame>>>,
+    /// Whether the hand pinching selects, as on runtimes whose hand tracking has
+    /// no select action of its own
+    pub pinch_select: bool,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 #[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
 pub enum MockInputMsg {
     SetHandedness(Handedness),
     SetTargetRayMode(TargetRayMode),
+    SetProfiles(Vec<String>),
+    /// Set the target ray. For gaze input sources this is relative to
+    /// the viewer, so the ray follows the viewer's gaze.
     SetPointerOrigin(RigidTransform3D<f32, Input, Native>),
+    /// Set how fast the input source is moving, which is reported as is rather
+    /// than moving it
+    SetVelocity(Velocity),
+    /// Set the grip, or stop tracking it
+    SetGripOrigin(Option<RigidTransform3D<f32, Input, Native>>),
+    SetGamepad(Option<Gamepad>),
+    /// Set the joint poses, or stop the input source being a tracked hand
+    SetHand(Option<Box<Hand<JointFrame>>>),
+    /// Set the state of one button of the gamepad, if it has that button
+    SetButton(usize, GamepadButton),
+    /// Set the value of one axis of the gamepad, if it has that axis
+    SetAxis(usize, f32),
+    /// Perform a primary action, which is reported with the current frame
+    TriggerSelect(SelectKind, SelectEvent),
+    /// Disconnect the input source, which is reported as it being removed
     Disconnect,
+    /// Reconnect a disconnected input source, which is reported as it being added
     Reconnect,
 }
+
+/// Messages for simulating how the device tracks an anchor
+/// https://immersive-web.github.io/webxr-test-api/#fakexranchorcontroller
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub enum MockAnchorMsg {
+    /// Move the anchor, as though the device's understanding of the world changed
+    SetAnchorOrigin(RigidTransform3D<f32, Anchor, Native>),
+    /// Stop reporting the anchor until tracking resumes
+    PauseTracking,
+    ResumeTracking,
+    /// Stop tracking the anchor for good
+    StopTracking,
+}
+
+/// Fake real-world geometry
+/// https://immersive-web.github.io/webxr-test-api/#dictdef-fakexrworldinit
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub struct MockWorld {
+    pub regions: Vec<MockRegion>,
+}
+
+/// https://immersive-web.github.io/webxr-test-api/#dictdef-fakexrregioninit
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub struct MockRegion {
+    pub faces: Vec<Triangle>,
+    pub ty: EntityType,
+}
+
+/// https://immersive-web.github.io/webxr-test-api/#dictdef-fakexrtriangleinit
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub struct Triangle {
+    pub first: Vector3D<f32, Native>,
+    pub second: Vector3D<f32, Native>,
+    pub third: Vector3D<f32, Native>,
+}
+
+/// A script of changes to make to a mock device as time passes, for automated
+/// demos and tests. With the `ipc` feature, a scenario can be deserialized
+/// from a file in any format serde supports.
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub struct MockScenario {
+    pub steps: Vec<MockScenarioStep>,
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub struct MockScenarioStep {
+    /// When to make the change, in milliseconds after the scenario starts,
+    /// as measured by the device's clock
+    pub time: HighResTimeStamp,
+    pub action: MockScenarioAction,
+}
+
+/// The changes a scenario can make, which act like the `MockDeviceMsg` of the same name
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub enum MockScenarioAction {
+    SetViewerOrigin(RigidTransform3D<f32, Viewer, Native>),
+    SetFloorOrigin(RigidTransform3D<f32, Floor, Native>),
+    SetViews(Views),
+    AddInputSource(MockInputInit),
+    MessageInputSource(InputId, MockInputMsg),
+    VisibilityChange(Visibility),
+    SetUserPresent(bool),
+    SetWorld(MockWorld),
+    /// End the session, as though the device disconnected
+    Disconnect,
+}
@@ -0,0 +1,115 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Performance hints to the device, and its notifications of how it is coping,
+//! as in OpenXR's XR_EXT_performance_settings.
+
+#[cfg(feature = "ipc")]
+use serde::{Deserialize, Serialize};
+
+/// The processor a performance level or notification is for
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub enum PerformanceDomain {
+    Cpu,
+    Gpu,
+}
+
+/// How hard content would like the device to run a processor, trading
+/// battery life and heat for performance
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub enum PerformanceLevel {
+    /// As slow as content can bear, such as while showing a loading screen
+    PowerSavings,
+    /// Slower than content would like, but for as long as it runs
+    SustainedLow,
+    /// As fast as the device can keep up for as long as content runs
+    SustainedHigh,
+    /// Faster than the device can keep up, for short bursts
+    Boost,
+}
+
+/// What part of the device a performance notification is about
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub enum PerformanceSubDomain {
+    /// The device's compositor, which displays content's frames
+    Compositing,
+    /// Content's rendering
+    Rendering,
+    /// The device's temperature
+    Thermal,
+}
+
+/// How well the device is coping
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub enum PerformanceNotificationLevel {
+    Normal,
+    /// The device is close to being unable to keep up, and content should
+    /// do less if it can
+    Warning,
+    /// The device cannot keep up, and is for example reprojecting frames
+    Impaired,
+}
+
+/// A change in how well the device is coping
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub struct PerformanceNotification {
+    pub domain: PerformanceDomain,
+    pub sub_domain: PerformanceSubDomain,
+    pub from: PerformanceNotificationLevel,
+    pub to: PerformanceNotificationLevel,
+}
+
+/// How hot the device is running
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub enum ThermalState {
+    Normal,
+    /// The device is getting hot, and will soon be throttled
+    Warning,
+    /// The device is too hot, and is being throttled
+    Critical,
+}
+
+/// The device's battery
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub struct BatteryStatus {
+    /// How full the battery is, from 0.0 to 1.0
+    pub level: f32,
+    pub charging: bool,
+}
+
+/// The state of the device's battery and temperature, so the embedder can warn
+/// the user or render less before the device throttles itself
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub struct DeviceStatus {
+    /// None for devices without a battery, or which can't report it
+    pub battery: Option<BatteryStatus>,
+    pub thermal: ThermalState,
+}
+
+impl Default for DeviceStatus {
+    fn default() -> DeviceStatus {
+        DeviceStatus {
+            battery: None,
+            thermal: ThermalState::Normal,
+        }
+    }
+}
+
+impl From<PerformanceNotificationLevel> for ThermalState {
+    fn from(level: PerformanceNotificationLevel) -> ThermalState {
+        match level {
+            PerformanceNotificationLevel::Normal => ThermalState::Normal,
+            PerformanceNotificationLevel::Warning => ThermalState::Warning,
+            PerformanceNotificationLevel::Impaired => ThermalState::Critical,
+        }
+    }
+}
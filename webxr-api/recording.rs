@@ -0,0 +1,412 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Recording sessions to a file, so that they can be replayed without the device.
+
+use crate::Anchor;
+use crate::AnchorId;
+use crate::ColorSpace;
+use crate::DepthSensingConfig;
+use crate::Device;
+use crate::DeviceImage;
+use crate::EnvironmentBlendMode;
+use crate::Error;
+use crate::Event;
+use crate::EventCallback;
+use crate::EventFilter;
+use crate::ExportedAnchor;
+use crate::Feature;
+use crate::Floor;
+use crate::Frame;
+use crate::GeospatialAnchorInit;
+use crate::HapticPulse;
+use crate::HighResTimeStamp;
+use crate::HitTestId;
+use crate::HitTestInit;
+use crate::InputId;
+use crate::InputSource;
+use crate::LayerId;
+use crate::LayerInit;
+use crate::LayerTexture;
+use crate::Native;
+use crate::PerformanceDomain;
+use crate::PerformanceLevel;
+use crate::Poses;
+use crate::Receiver;
+use crate::Sender;
+use crate::SwapchainId;
+use crate::SwapchainInit;
+use crate::SwapchainLayout;
+use crate::TextureHandle;
+use crate::TrackedImageId;
+use crate::TrackedImageInit;
+use crate::TrackedImageScore;
+use crate::Viewport;
+use crate::Views;
+
+use euclid::default::Size2D as UntypedSize2D;
+use euclid::Point2D;
+use euclid::RigidTransform3D;
+use euclid::Size2D;
+
+use gleam::gl::GLsync;
+
+use serde::{Deserialize, Serialize};
+
+use std::fs::File;
+use std::io;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+
+/// One entry in a recording
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum Record {
+    /// The state of the device when the session started.
+    /// This is always the first record.
+    Session(SessionRecord),
+    Frame(Frame),
+    /// An event, which was sent before the next frame
+    Event(Event),
+}
+
+/// The state of a device when a session started
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SessionRecord {
+    pub floor_transform: RigidTransform3D<f32, Native, Floor>,
+    pub bounds_geometry: Option<Vec<Point2D<f32, Floor>>>,
+    pub views: Views,
+    pub resolution: Size2D<i32, Viewport>,
+    pub initial_inputs: Vec<InputSource>,
+    pub granted_features: Vec<Feature>,
+    pub supported_frame_rates: Vec<f32>,
+    pub depth_sensing_config: Option<DepthSensingConfig>,
+    pub environment_blend_mode: EnvironmentBlendMode,
+    pub max_frames_in_flight: usize,
+}
+
+/// Read the records written by a `RecordingDevice`.
+pub fn read_recording(path: &Path) -> io::Result<Vec<Record>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut records = vec![];
+    loop {
+        match bincode::deserialize_from(&mut reader) {
+            Ok(record) => records.push(record),
+            Err(err) => match *err {
+                bincode::ErrorKind::Io(ref err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                    return Ok(records);
+                }
+                _ => return Err(io::Error::new(io::ErrorKind::InvalidData, err)),
+            },
+        }
+    }
+}
+
+/// A device which records the frames and events of another device.
+/// GL textures, such as GPU depth buffers, are recorded by name only,
+/// so are not valid when the recording is replayed.
+pub struct RecordingDevice<D> {
+    device: D,
+    // Set to None if writing fails, after which nothing more is recorded
+    writer: Option<BufWriter<File>>,
+    sender: Sender<Event>,
+    receiver: Receiver<Event>,
+}
+
+// An event callback which passes events on, and sends them to be recorded
+#[derive(Serialize, Deserialize)]
+struct RecordingEventCallback {
+    callback: Box<dyn EventCallback>,
+    sender: Sender<Event>,
+}
+
+#[typetag::serde]
+impl EventCallback for RecordingEventCallback {
+    fn callback(&mut self, event: Event) {
+        let _ = self.sender.send(event.clone());
+        self.callback.callback(event);
+    }
+}
+
+impl<D: Device> RecordingDevice<D> {
+    pub fn new(device: D, path: &Path) -> io::Result<RecordingDevice<D>> {
+        let (sender, receiver) = crate::channel()
+            .map_err(|_| io::Error::new(io::ErrorKind::Other, "Failed to make channel"))?;
+        let mut recording = RecordingDevice {
+            writer: Some(BufWriter::new(File::create(path)?)),
+            sender,
+            receiver,
+            device,
+        };
+        let session = SessionRecord {
+            floor_transform: recording.device.floor_transform(),
+            bounds_geometry: recording.device.bounds_geometry(),
+            views: recording.device.views(),
+            resolution: recording.device.recommended_framebuffer_resolution(),
+            initial_inputs: recording.device.initial_inputs(),
+            granted_features: recording.device.granted_features(),
+            supported_frame_rates: recording.device.supported_frame_rates(),
+            depth_sensing_config: recording.device.depth_sensing_config(),
+            environment_blend_mode: recording.device.environment_blend_mode(),
+            max_frames_in_flight: recording.device.max_frames_in_flight(),
+        };
+        recording.record(&Record::Session(session));
+        Ok(recording)
+    }
+
+    fn record(&mut self, record: &Record) {
+        if let Some(ref mut writer) = self.writer {
+            if bincode::serialize_into(writer, record).is_err() {
+                self.writer = None;
+            }
+        }
+    }
+
+    fn record_events(&mut self) {
+        while let Ok(event) = self.receiver.try_recv() {
+            self.record(&Record::Event(event));
+        }
+    }
+
+    fn recording_callback(&self, callback: Box<dyn EventCallback>) -> Box<dyn EventCallback> {
+        Box::new(RecordingEventCallback {
+            callback,
+            sender: self.sender.clone(),
+        })
+    }
+}
+
+impl<D: Device> Device for RecordingDevice<D> {
+    fn floor_transform(&self) -> RigidTransform3D<f32, Native, Floor> {
+        self.device.floor_transform()
+    }
+
+    fn bounds_geometry(&self) -> Option<Vec<Point2D<f32, Floor>>> {
+        self.device.bounds_geometry()
+    }
+
+    fn views(&self) -> Views {
+        self.device.views()
+    }
+
+    fn views_into(&self, views: &mut Views) {
+        self.device.views_into(views)
+    }
+
+    fn recommended_framebuffer_resolution(&self) -> Size2D<i32, Viewport> {
+        self.device.recommended_framebuffer_resolution()
+    }
+
+    fn supported_frame_rates(&self) -> Vec<f32> {
+        self.device.supported_frame_rates()
+    }
+
+    fn update_frame_rate(&mut self, rate: f32) {
+        self.device.update_frame_rate(rate)
+    }
+
+    fn supported_color_spaces(&self) -> Vec<ColorSpace> {
+        self.device.supported_color_spaces()
+    }
+
+    fn color_space(&self) -> Option<ColorSpace> {
+        self.device.color_space()
+    }
+
+    fn update_color_space(&mut self, space: ColorSpace) {
+        self.device.update_color_space(space)
+    }
+
+    fn set_performance_level(&mut self, domain: PerformanceDomain, level: PerformanceLevel) {
+        self.device.set_performance_level(domain, level)
+    }
+
+    fn set_occlusion(&mut self, enabled: bool) -> Result<(), Error> {
+        self.device.set_occlusion(enabled)
+    }
+
+    fn update_clip_planes(&mut self, near: f32, far: f32) {
+        self.device.update_clip_planes(near, far)
+    }
+
+    fn update_viewport_scale(&mut self, scale: f32) -> f32 {
+        self.device.update_viewport_scale(scale)
+    }
+
+    fn swapchain_layout(&self) -> SwapchainLayout {
+        self.device.swapchain_layout()
+    }
+
+    fn supports_multiview(&self) -> bool {
+        self.device.supports_multiview()
+    }
+
+    fn max_frames_in_flight(&self) -> usize {
+        self.device.max_frames_in_flight()
+    }
+
+    fn frame_deadline(&self) -> Option<Duration> {
+        self.device.frame_deadline()
+    }
+
+    fn missed_frame(&mut self) {
+        self.device.missed_frame()
+    }
+
+    fn wait_for_animation_frame(&mut self) -> Frame {
+        let frame = self.device.wait_for_animation_frame();
+        self.record_events();
+        self.record(&Record::Frame(frame.clone()));
+        frame
+    }
+
+    fn poses_at(&mut self, time: HighResTimeStamp) -> Result<Poses, Error> {
+        self.device.poses_at(time)
+    }
+
+    fn render_animation_frame(&mut self, texture_id: u32, size: UntypedSize2D<i32>, sync: GLsync) {
+        self.device.render_animation_frame(texture_id, size, sync)
+    }
+
+    fn render_sync(&mut self) -> GLsync {
+        self.device.render_sync()
+    }
+
+    fn create_layer(&mut self, id: LayerId, init: LayerInit) -> Result<(), Error> {
+        self.device.create_layer(id, init)
+    }
+
+    fn update_layer(&mut self, id: LayerId, init: LayerInit) -> Result<(), Error> {
+        self.device.update_layer(id, init)
+    }
+
+    fn destroy_layer(&mut self, id: LayerId) {
+        self.device.destroy_layer(id)
+    }
+
+    fn create_swapchain(
+        &mut self,
+        id: SwapchainId,
+        init: SwapchainInit,
+    ) -> Result<Vec<TextureHandle>, Error> {
+        self.device.create_swapchain(id, init)
+    }
+
+    fn acquire_swapchain_image(&mut self, id: SwapchainId) -> Result<usize, Error> {
+        self.device.acquire_swapchain_image(id)
+    }
+
+    fn release_swapchain_image(&mut self, id: SwapchainId, index: usize) {
+        self.device.release_swapchain_image(id, index)
+    }
+
+    fn destroy_swapchain(&mut self, id: SwapchainId) {
+        self.device.destroy_swapchain(id)
+    }
+
+    fn device_image(&mut self, id: LayerId) -> Option<DeviceImage> {
+        self.device.device_image(id)
+    }
+
+    fn render_layers(&mut self, layers: &[LayerTexture]) {
+        self.device.render_layers(layers)
+    }
+
+    fn granted_features(&self) -> Vec<Feature> {
+        self.device.granted_features()
+    }
+
+    fn depth_sensing_config(&self) -> Option<DepthSensingConfig> {
+        self.device.depth_sensing_config()
+    }
+
+    fn environment_blend_mode(&self) -> EnvironmentBlendMode {
+        self.device.environment_blend_mode()
+    }
+
+    fn request_hit_test(&mut self, id: HitTestId, init: HitTestInit) -> Result<(), Error> {
+        self.device.request_hit_test(id, init)
+    }
+
+    fn cancel_hit_test(&mut self, id: HitTestId) {
+        self.device.cancel_hit_test(id)
+    }
+
+    fn create_anchor(
+        &mut self,
+        id: AnchorId,
+        pose: RigidTransform3D<f32, Anchor, Native>,
+    ) -> Result<(), Error> {
+        self.device.create_anchor(id, pose)
+    }
+
+    fn create_geospatial_anchor(
+        &mut self,
+        id: AnchorId,
+        init: GeospatialAnchorInit,
+    ) -> Result<(), Error> {
+        self.device.create_geospatial_anchor(id, init)
+    }
+
+    fn delete_anchor(&mut self, id: AnchorId) {
+        self.device.delete_anchor(id)
+    }
+
+    fn export_anchor(&mut self, id: AnchorId) -> Result<ExportedAnchor, Error> {
+        self.device.export_anchor(id)
+    }
+
+    fn import_anchor(&mut self, id: AnchorId, anchor: ExportedAnchor) -> Result<(), Error> {
+        self.device.import_anchor(id, anchor)
+    }
+
+    fn add_tracked_image(
+        &mut self,
+        id: TrackedImageId,
+        init: TrackedImageInit,
+    ) -> Result<TrackedImageScore, Error> {
+        self.device.add_tracked_image(id, init)
+    }
+
+    fn remove_tracked_image(&mut self, id: TrackedImageId) {
+        self.device.remove_tracked_image(id)
+    }
+
+    fn initial_inputs(&self) -> Vec<InputSource> {
+        self.device.initial_inputs()
+    }
+
+    fn input_haptic(&mut self, id: InputId, pulse: HapticPulse) {
+        self.device.input_haptic(id, pulse)
+    }
+
+    fn set_event_callback(&mut self, callback: Box<dyn EventCallback>) {
+        let callback = self.recording_callback(callback);
+        self.device.set_event_callback(callback)
+    }
+
+    fn subscribe_events(&mut self, filter: EventFilter, callback: Box<dyn EventCallback>) {
+        let callback = self.recording_callback(callback);
+        self.device.subscribe_events(filter, callback)
+    }
+
+    fn connected(&mut self) -> bool {
+        self.device.connected()
+    }
+
+    fn poll_events(&mut self) {
+        self.device.poll_events();
+        self.record_events();
+    }
+
+    fn quit(&mut self) {
+        self.device.quit();
+        self.record_events();
+        if let Some(ref mut writer) = self.writer {
+            let _ = writer.flush();
+        }
+    }
+}
@@ -4,6 +4,7 @@
 
 use crate::Discovery;
 use crate::Error;
+use crate::Feature;
 use crate::MainThreadSession;
 use crate::MockDeviceInit;
 use crate::MockDeviceMsg;
@@ -12,23 +13,39 @@ use crate::Receiver;
 use crate::Sender;
 use crate::Session;
 use crate::SessionBuilder;
+use crate::SessionCapabilities;
+use crate::SessionInit;
 use crate::SessionMode;
 
 #[cfg(feature = "ipc")]
 use serde::{Deserialize, Serialize};
 
+use std::cmp::Reverse;
+#[cfg(feature = "recording")]
+use std::path::PathBuf;
+
 #[derive(Clone)]
 #[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
 pub struct Registry {
     sender: Sender<RegistryMsg>,
 }
 
+/// An identifier for a registered discovery, which can be used to unregister it
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub struct DiscoveryId(pub u32);
+
 pub struct MainThreadRegistry {
-    discoveries: Vec<Box<dyn Discovery>>,
+    discoveries: Vec<(DiscoveryId, Box<dyn Discovery>)>,
+    next_discovery_id: u32,
+    device_change_callbacks: Vec<Box<dyn DeviceChangeCallback>>,
     sessions: Vec<Box<dyn MainThreadSession>>,
     mocks: Vec<Box<dyn MockDiscovery>>,
+    permission_handler: Option<Box<dyn PermissionHandler>>,
     sender: Sender<RegistryMsg>,
     receiver: Receiver<RegistryMsg>,
+    #[cfg(feature = "recording")]
+    recording: Option<PathBuf>,
 }
 
 #[cfg_attr(feature = "ipc", typetag::serde)]
@@ -36,16 +53,93 @@ pub trait SessionSupportCallback: 'static + Send {
     fn callback(&mut self, result: Result<(), Error>);
 }
 
+#[cfg_attr(feature = "ipc", typetag::serde)]
+pub trait SessionCapabilitiesCallback: 'static + Send {
+    fn callback(&mut self, capabilities: Vec<SessionCapabilities>);
+}
+
 #[cfg_attr(feature = "ipc", typetag::serde)]
 pub trait SessionRequestCallback: 'static + Send {
     fn callback(&mut self, result: Result<Session, Error>);
 }
 
+/// A callback for when the available devices change, after which
+/// which sessions are supported should be checked again.
+/// https://immersive-web.github.io/webxr/#eventdef-xrsystem-devicechange
+#[cfg_attr(feature = "ipc", typetag::serde)]
+pub trait DeviceChangeCallback: 'static + Send {
+    fn callback(&mut self);
+}
+
 #[cfg_attr(feature = "ipc", typetag::serde)]
 pub trait MockDeviceCallback: 'static + Send {
     fn callback(&mut self, result: Result<Sender<MockDeviceMsg>, Error>);
 }
 
+/// The embedder's say in which sessions start, and with which features, which is
+/// asked about each session request before any device is. This runs on the main thread.
+pub trait PermissionHandler: 'static {
+    /// Decide on a session request, now or later, such as once the user has
+    /// answered a prompt. Requests which are dropped undecided are denied.
+    fn request_permission(&mut self, request: PermissionRequest);
+}
+
+/// A session request waiting for the embedder's permission
+pub struct PermissionRequest {
+    mode: SessionMode,
+    init: SessionInit,
+    callback: Option<Box<dyn SessionRequestCallback>>,
+    sender: Sender<RegistryMsg>,
+}
+
+impl PermissionRequest {
+    pub fn mode(&self) -> SessionMode {
+        self.mode
+    }
+
+    /// The features the session asks for, which the embedder may want to ask the user about
+    pub fn init(&self) -> &SessionInit {
+        &self.init
+    }
+
+    /// Let the session start with the features the embedder allows. Sessions always get
+    /// their default reference spaces, which needn't be allowed. If a required feature
+    /// isn't allowed the session is denied, and optional ones go ungranted.
+    pub fn allow(mut self, features: &[Feature]) {
+        let mut callback = match self.callback.take() {
+            Some(callback) => callback,
+            None => return,
+        };
+        let mode = self.mode;
+        let allowed = |feature: &Feature| {
+            features.contains(feature)
+                || *feature == Feature::Viewer
+                || (mode != SessionMode::Inline && *feature == Feature::Local)
+        };
+        let mut init = self.init.clone();
+        if !init.required_features.iter().all(allowed) {
+            callback.callback(Err(Error::PermissionDenied));
+            return;
+        }
+        init.optional_features.retain(allowed);
+        let _ = self
+            .sender
+            .send(RegistryMsg::PermittedSession(mode, init, callback));
+    }
+
+    pub fn deny(self) {
+        // Dropping the request denies it
+    }
+}
+
+impl Drop for PermissionRequest {
+    fn drop(&mut self) {
+        if let Some(mut callback) = self.callback.take() {
+            callback.callback(Err(Error::PermissionDenied));
+        }
+    }
+}
+
 impl Registry {
     pub fn supports_session<C>(&mut self, mode: SessionMode, callback: C)
     where
@@ -56,13 +150,34 @@ impl Registry {
             .send(RegistryMsg::SupportsSession(mode, Box::new(callback)));
     }
 
-    pub fn request_session<C>(&mut self, mode: SessionMode, callback: C)
+    /// Find out what sessions the devices could start, without starting any. There is
+    /// an entry for each mode each device supports, in the order devices are tried.
+    pub fn session_capabilities<C>(&mut self, callback: C)
+    where
+        C: SessionCapabilitiesCallback,
+    {
+        let _ = self
+            .sender
+            .send(RegistryMsg::SessionCapabilities(Box::new(callback)));
+    }
+
+    pub fn request_session<C>(&mut self, mode: SessionMode, init: SessionInit, callback: C)
     where
         C: SessionRequestCallback,
     {
         let _ = self
             .sender
-            .send(RegistryMsg::RequestSession(mode, Box::new(callback)));
+            .send(RegistryMsg::RequestSession(mode, init, Box::new(callback)));
+    }
+
+    /// Call `callback` whenever the available devices change
+    pub fn subscribe_device_changes<C>(&mut self, callback: C)
+    where
+        C: DeviceChangeCallback,
+    {
+        let _ = self
+            .sender
+            .send(RegistryMsg::SubscribeDeviceChanges(Box::new(callback)));
     }
 
     pub fn simulate_device_connection<C>(&mut self, init: MockDeviceInit, callback: C)
@@ -84,10 +199,15 @@ impl MainThreadRegistry {
         let mocks = Vec::new();
         Ok(MainThreadRegistry {
             discoveries,
+            next_discovery_id: 0,
+            device_change_callbacks: Vec::new(),
             sessions,
             mocks,
+            permission_handler: None,
             sender,
             receiver,
+            #[cfg(feature = "recording")]
+            recording: None,
         })
     }
 
@@ -97,14 +217,58 @@ impl MainThreadRegistry {
         }
     }
 
-    pub fn register<D: Discovery>(&mut self, discovery: D) {
-        self.discoveries.push(Box::new(discovery));
+    /// Register a discovery, which can be done at any time, such as when
+    /// a runtime is installed. Content is told the available devices changed.
+    pub fn register<D: Discovery>(&mut self, discovery: D) -> DiscoveryId {
+        let id = self.add_discovery(Box::new(discovery), false);
+        self.device_changed();
+        id
+    }
+
+    /// Unregister a discovery, such as when its device is unplugged.
+    /// Sessions already started from it keep running until they end.
+    pub fn unregister(&mut self, id: DiscoveryId) {
+        let count = self.discoveries.len();
+        self.discoveries.retain(|(discovery, _)| *discovery != id);
+        if self.discoveries.len() != count {
+            self.device_changed();
+        }
+    }
+
+    fn add_discovery(&mut self, discovery: Box<dyn Discovery>, first: bool) -> DiscoveryId {
+        let id = DiscoveryId(self.next_discovery_id);
+        self.next_discovery_id += 1;
+        if first {
+            self.discoveries.insert(0, (id, discovery));
+        } else {
+            self.discoveries.push((id, discovery));
+        }
+        id
+    }
+
+    fn device_changed(&mut self) {
+        for callback in &mut self.device_change_callbacks {
+            callback.callback();
+        }
     }
 
     pub fn register_mock<D: MockDiscovery>(&mut self, discovery: D) {
         self.mocks.push(Box::new(discovery));
     }
 
+    /// Ask `handler` for permission before starting each session, rather than
+    /// granting every session the features its device supports
+    pub fn set_permission_handler<H: PermissionHandler>(&mut self, handler: H) {
+        self.permission_handler = Some(Box::new(handler));
+    }
+
+    /// Record the sessions started from now on to a file, replacing
+    /// any earlier recording, or stop recording if `path` is None.
+    #[cfg(feature = "recording")]
+    pub fn record_sessions(&mut self, path: Option<PathBuf>) {
+        self.recording = path;
+    }
+
     pub fn run_on_main_thread<S: MainThreadSession>(&mut self, session: S) {
         self.sessions.push(Box::new(session));
     }
@@ -128,17 +292,34 @@ impl MainThreadRegistry {
             RegistryMsg::SupportsSession(mode, mut callback) => {
                 callback.callback(self.supports_session(mode));
             }
-            RegistryMsg::RequestSession(mode, mut callback) => {
-                callback.callback(self.request_session(mode));
+            RegistryMsg::SessionCapabilities(mut callback) => {
+                callback.callback(self.session_capabilities());
+            }
+            RegistryMsg::RequestSession(mode, init, mut callback) => {
+                match self.permission_handler {
+                    Some(ref mut handler) => handler.request_permission(PermissionRequest {
+                        mode,
+                        init,
+                        callback: Some(callback),
+                        sender: self.sender.clone(),
+                    }),
+                    None => callback.callback(self.request_session(mode, init)),
+                }
+            }
+            RegistryMsg::PermittedSession(mode, init, mut callback) => {
+                callback.callback(self.request_session(mode, init));
             }
             RegistryMsg::SimulateDeviceConnection(init, mut callback) => {
                 callback.callback(self.simulate_device_connection(init));
             }
+            RegistryMsg::SubscribeDeviceChanges(callback) => {
+                self.device_change_callbacks.push(callback);
+            }
         }
     }
 
     fn supports_session(&mut self, mode: SessionMode) -> Result<(), Error> {
-        for discovery in &self.discoveries {
+        for (_, discovery) in &self.discoveries {
             if discovery.supports_session(mode) {
                 return Ok(());
             }
@@ -146,14 +327,41 @@ impl MainThreadRegistry {
         Err(Error::NoMatchingDevice)
     }
 
-    fn request_session(&mut self, mode: SessionMode) -> Result<Session, Error> {
-        for discovery in &mut self.discoveries {
-            let xr = SessionBuilder::new(&mut self.sessions);
-            if let Ok(session) = discovery.request_session(mode, xr) {
-                return Ok(session);
+    fn session_capabilities(&self) -> Vec<SessionCapabilities> {
+        let modes = [
+            SessionMode::Inline,
+            SessionMode::ImmersiveVR,
+            SessionMode::ImmersiveAR,
+        ];
+        self.discoveries
+            .iter()
+            .flat_map(|(_, discovery)| {
+                modes
+                    .iter()
+                    .filter_map(move |mode| discovery.capabilities(*mode))
+            })
+            .collect()
+    }
+
+    fn request_session(&mut self, mode: SessionMode, init: SessionInit) -> Result<Session, Error> {
+        // Try the devices which support more of the requested features first,
+        // otherwise keeping the most recently connected first.
+        let mut order: Vec<usize> = (0..self.discoveries.len()).collect();
+        order.sort_by_key(|&index| Reverse(preference(&*self.discoveries[index].1, mode, &init)));
+        let mut error = Error::NoMatchingDevice;
+        for index in order {
+            let discovery = &mut self.discoveries[index].1;
+            let xr = SessionBuilder::new(&mut self.sessions, init.transport);
+            #[cfg(feature = "recording")]
+            let xr = xr.record_to(self.recording.clone());
+            match discovery.request_session(mode, &init, xr) {
+                Ok(session) => return Ok(session),
+                // Report a missing feature in preference to a missing device
+                Err(Error::UnsupportedFeature) => error = Error::UnsupportedFeature,
+                Err(_) => (),
             }
         }
-        Err(Error::NoMatchingDevice)
+        Err(error)
     }
 
     fn simulate_device_connection(
@@ -163,7 +371,8 @@ impl MainThreadRegistry {
         for mock in &mut self.mocks {
             let (sender, receiver) = crate::channel().or(Err(Error::CommunicationError))?;
             if let Ok(discovery) = mock.simulate_device_connection(init.clone(), receiver) {
-                self.discoveries.insert(0, discovery);
+                self.add_discovery(discovery, true);
+                self.device_changed();
                 return Ok(sender);
             }
         }
@@ -171,9 +380,242 @@ impl MainThreadRegistry {
     }
 }
 
+// How well a device suits a session request. Devices which cannot support the
+// session come last, and devices which don't say what they support are treated
+// as supporting the required features but none of the optional ones.
+fn preference(discovery: &dyn Discovery, mode: SessionMode, init: &SessionInit) -> usize {
+    if !discovery.supports_session(mode) {
+        return 0;
+    }
+    let supported = match discovery.supported_features(mode) {
+        Some(supported) => supported,
+        None => return 1,
+    };
+    if init.validate(mode, &supported).is_err() {
+        return 0;
+    }
+    let optional = init
+        .optional_features
+        .iter()
+        .filter(|feature| supported.contains(feature))
+        .count();
+    optional + 1
+}
+
 #[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+#[allow(clippy::large_enum_variant)]
 enum RegistryMsg {
-    RequestSession(SessionMode, Box<dyn SessionRequestCallback>),
+    RequestSession(SessionMode, SessionInit, Box<dyn SessionRequestCallback>),
+    PermittedSession(SessionMode, SessionInit, Box<dyn SessionRequestCallback>),
     SupportsSession(SessionMode, Box<dyn SessionSupportCallback>),
+    SessionCapabilities(Box<dyn SessionCapabilitiesCallback>),
     SimulateDeviceConnection(MockDeviceInit, Box<dyn MockDeviceCallback>),
+    SubscribeDeviceChanges(Box<dyn DeviceChangeCallback>),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+    struct SendResult(Sender<Result<Session, Error>>);
+
+    #[cfg_attr(feature = "ipc", typetag::serde)]
+    impl SessionRequestCallback for SendResult {
+        fn callback(&mut self, result: Result<Session, Error>) {
+            let _ = self.0.send(result);
+        }
+    }
+
+    // A discovery which refuses every session, remembering which of them asked
+    struct TestDiscovery {
+        name: &'static str,
+        features: Option<Vec<Feature>>,
+        tried: Rc<RefCell<Vec<&'static str>>>,
+    }
+
+    impl Discovery for TestDiscovery {
+        fn request_session(
+            &mut self,
+            mode: SessionMode,
+            init: &SessionInit,
+            _xr: SessionBuilder,
+        ) -> Result<Session, Error> {
+            self.tried.borrow_mut().push(self.name);
+            if let Some(ref features) = self.features {
+                init.validate(mode, features)?;
+            }
+            Err(Error::NoMatchingDevice)
+        }
+
+        fn supports_session(&self, mode: SessionMode) -> bool {
+            mode != SessionMode::ImmersiveAR
+        }
+
+        fn supported_features(&self, _mode: SessionMode) -> Option<Vec<Feature>> {
+            self.features.clone()
+        }
+    }
+
+    type Asked = Rc<RefCell<Vec<SessionInit>>>;
+
+    // A discovery which refuses every session, remembering what they asked for
+    struct AskedDiscovery(Asked);
+
+    impl Discovery for AskedDiscovery {
+        fn request_session(
+            &mut self,
+            _mode: SessionMode,
+            init: &SessionInit,
+            _xr: SessionBuilder,
+        ) -> Result<Session, Error> {
+            self.0.borrow_mut().push(init.clone());
+            Err(Error::NoMatchingDevice)
+        }
+
+        fn supports_session(&self, _mode: SessionMode) -> bool {
+            true
+        }
+    }
+
+    // A permission handler which leaves its requests for the test to decide
+    struct Deferred(Rc<RefCell<Vec<PermissionRequest>>>);
+
+    impl PermissionHandler for Deferred {
+        fn request_permission(&mut self, request: PermissionRequest) {
+            self.0.borrow_mut().push(request);
+        }
+    }
+
+    // Request a session from a registry whose permission handler defers it
+    fn request_permission(
+        init: SessionInit,
+    ) -> (
+        MainThreadRegistry,
+        PermissionRequest,
+        Receiver<Result<Session, Error>>,
+        Asked,
+    ) {
+        let mut registry = MainThreadRegistry::new().unwrap();
+        let asked = Rc::new(RefCell::new(vec![]));
+        registry.register(AskedDiscovery(asked.clone()));
+        let requests = Rc::new(RefCell::new(vec![]));
+        registry.set_permission_handler(Deferred(requests.clone()));
+        let (sender, receiver) = crate::channel().unwrap();
+        registry
+            .registry()
+            .request_session(SessionMode::ImmersiveVR, init, SendResult(sender));
+        registry.run_one_frame();
+        let request = requests.borrow_mut().pop().unwrap();
+        (registry, request, receiver, asked)
+    }
+
+    fn registry(
+        discoveries: Vec<(&'static str, Option<Vec<Feature>>)>,
+    ) -> (MainThreadRegistry, Rc<RefCell<Vec<&'static str>>>) {
+        let mut registry = MainThreadRegistry::new().unwrap();
+        let tried = Rc::new(RefCell::new(vec![]));
+        for (name, features) in discoveries {
+            registry.register(TestDiscovery {
+                name,
+                features,
+                tried: tried.clone(),
+            });
+        }
+        (registry, tried)
+    }
+
+    fn optional(features: Vec<Feature>) -> SessionInit {
+        SessionInit {
+            optional_features: features,
+            ..SessionInit::default()
+        }
+    }
+
+    #[test]
+    fn devices_supporting_more_features_are_tried_first() {
+        let (mut registry, tried) = registry(vec![
+            ("none", Some(vec![])),
+            ("both", Some(vec![Feature::HitTest, Feature::Anchors])),
+            ("unknown", None),
+            ("one", Some(vec![Feature::Anchors])),
+        ]);
+        let init = optional(vec![Feature::HitTest, Feature::Anchors]);
+        let _ = registry.request_session(SessionMode::ImmersiveVR, init);
+        // Devices which don't say what they support rank with those supporting none
+        assert_eq!(*tried.borrow(), vec!["both", "one", "none", "unknown"]);
+    }
+
+    #[test]
+    fn devices_which_cannot_start_the_session_are_tried_last() {
+        let (mut registry, tried) = registry(vec![
+            ("without", Some(vec![])),
+            ("with", Some(vec![Feature::HitTest])),
+        ]);
+        let init = SessionInit {
+            required_features: vec![Feature::HitTest],
+            ..SessionInit::default()
+        };
+        let result = registry.request_session(SessionMode::ImmersiveVR, init);
+        assert_eq!(*tried.borrow(), vec!["with", "without"]);
+        // A missing feature is reported rather than a missing device
+        assert_eq!(result.err(), Some(Error::UnsupportedFeature));
+    }
+
+    #[test]
+    fn devices_are_not_asked_until_permission_is_given() {
+        let init = SessionInit {
+            required_features: vec![Feature::HitTest],
+            optional_features: vec![Feature::Anchors, Feature::LocalFloor],
+            ..SessionInit::default()
+        };
+        let (mut registry, request, results, asked) = request_permission(init);
+        assert_eq!(request.mode(), SessionMode::ImmersiveVR);
+        assert_eq!(request.init().required_features, vec![Feature::HitTest]);
+        assert!(asked.borrow().is_empty());
+
+        request.allow(&[Feature::HitTest, Feature::LocalFloor]);
+        registry.run_one_frame();
+        // The device is only asked for the optional features which were allowed
+        let asked = asked.borrow();
+        assert_eq!(asked.len(), 1);
+        assert_eq!(asked[0].required_features, vec![Feature::HitTest]);
+        assert_eq!(asked[0].optional_features, vec![Feature::LocalFloor]);
+        assert_eq!(results.recv().unwrap().err(), Some(Error::NoMatchingDevice));
+    }
+
+    #[test]
+    fn requests_are_denied_without_their_required_features() {
+        let init = SessionInit {
+            required_features: vec![Feature::HitTest],
+            ..SessionInit::default()
+        };
+        let (mut registry, request, results, asked) = request_permission(init);
+        // The default reference spaces needn't be allowed
+        request.allow(&[Feature::Local, Feature::Viewer]);
+        registry.run_one_frame();
+        assert!(asked.borrow().is_empty());
+        assert_eq!(results.recv().unwrap().err(), Some(Error::PermissionDenied));
+    }
+
+    #[test]
+    fn denied_and_dropped_requests_are_denied() {
+        let (_registry, request, results, _) = request_permission(SessionInit::default());
+        request.deny();
+        assert_eq!(results.recv().unwrap().err(), Some(Error::PermissionDenied));
+
+        let (_registry, request, results, _) = request_permission(SessionInit::default());
+        drop(request);
+        assert_eq!(results.recv().unwrap().err(), Some(Error::PermissionDenied));
+    }
+
+    #[test]
+    fn unsupported_modes_fail_without_a_device() {
+        let (mut registry, _) = registry(vec![("vr", Some(vec![]))]);
+        let result = registry.request_session(SessionMode::ImmersiveAR, SessionInit::default());
+        assert_eq!(result.err(), Some(Error::NoMatchingDevice));
+    }
 }
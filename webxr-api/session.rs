@@ -2,24 +2,75 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
 
+use crate::delivery::FrameDelivery;
+use crate::layer::LayerManager;
+use crate::Anchor;
+use crate::AnchorId;
+use crate::ColorScaleBias;
+use crate::DepthDataFormat;
+use crate::DepthSensingConfig;
+use crate::DepthSensingInit;
+use crate::DepthUsage;
 use crate::Device;
 use crate::Error;
 use crate::EventCallback;
+use crate::EventFilter;
+use crate::ExportedAnchor;
 use crate::Floor;
 use crate::Frame;
+use crate::FrameCapture;
+use crate::FrameStats;
+use crate::GeospatialAnchorInit;
+use crate::HapticPulse;
+use crate::HitTestId;
+use crate::HitTestInit;
+use crate::IdleCallback;
+use crate::IdleEventCallback;
+use crate::IdlePolicy;
+use crate::IdleTracker;
+use crate::InputId;
 use crate::InputSource;
+use crate::LayerBlend;
+use crate::LayerId;
+use crate::LayerInit;
+use crate::MirrorCallback;
+use crate::MirrorView;
 use crate::Native;
+use crate::PerformanceDomain;
+use crate::PerformanceLevel;
+use crate::Poses;
 use crate::Receiver;
 use crate::Sender;
+use crate::Swapchain;
+use crate::SwapchainId;
+use crate::SwapchainInit;
+use crate::SwapchainLayout;
+use crate::SwapchainManager;
+use crate::TrackedImageId;
+use crate::TrackedImageInit;
+use crate::TrackedImageScore;
+use crate::Transport;
+use crate::VideoFrameCallback;
+use crate::VideoInit;
+use crate::VideoRecorder;
+use crate::ViewLayout;
 use crate::Viewport;
 use crate::Views;
 use crate::WebGLExternalImageApi;
 
+use euclid::Point2D;
+use euclid::Rect;
 use euclid::RigidTransform3D;
 use euclid::Size2D;
 
+#[cfg(feature = "recording")]
+use crate::RecordingDevice;
+use std::collections::VecDeque;
+#[cfg(feature = "recording")]
+use std::path::PathBuf;
 use std::thread;
 use std::time::Duration;
+use std::time::Instant;
 
 #[cfg(feature = "ipc")]
 use serde::{Deserialize, Serialize};
@@ -27,6 +78,15 @@ use serde::{Deserialize, Serialize};
 // How long to wait for an rAF.
 static TIMEOUT: Duration = Duration::from_millis(5);
 
+// The most frames content has finished with that are kept for reuse.
+const MAX_SPARE_FRAMES: usize = 3;
+
+// The smallest viewport scale content can ask for.
+const MIN_VIEWPORT_SCALE: f32 = 0.1;
+
+// How often a suspended session checks whether the user has come back
+const SUSPENDED_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
 /// https://www.w3.org/TR/webxr/#xrsessionmode-enum
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 #[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
@@ -36,21 +96,292 @@ pub enum SessionMode {
     ImmersiveAR,
 }
 
+/// How content is shown over the real world
+/// https://immersive-web.github.io/webxr-ar-module/#xrenvironmentblendmode-enum
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub enum EnvironmentBlendMode {
+    /// The real world is not visible, as in VR headsets
+    Opaque,
+    /// The real world seen through a transparent display, which content is added to
+    Additive,
+    /// Content is alpha blended over the real world, as seen through cameras
+    AlphaBlend,
+}
+
+/// The color space the device's display shows frames in, which content should
+/// master its colors for so they look as intended
+/// https://registry.khronos.org/OpenXR/specs/1.0/html/xrspec.html#XR_FB_color_space
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub enum ColorSpace {
+    /// The display's native colors, without any color management
+    Unmanaged,
+    Rec2020,
+    Rec709,
+    RiftCV1,
+    RiftS,
+    Quest,
+    DisplayP3,
+    AdobeRgb,
+}
+
+/// The features a session can ask for
+/// https://immersive-web.github.io/webxr/#feature-dependencies
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub enum Feature {
+    /// https://immersive-web.github.io/webxr/#dom-xrreferencespacetype-viewer
+    Viewer,
+    /// https://immersive-web.github.io/webxr/#dom-xrreferencespacetype-local
+    Local,
+    /// https://immersive-web.github.io/webxr/#dom-xrreferencespacetype-local-floor
+    LocalFloor,
+    /// https://immersive-web.github.io/webxr/#dom-xrreferencespacetype-bounded-floor
+    BoundedFloor,
+    /// https://immersive-web.github.io/webxr/#dom-xrreferencespacetype-unbounded
+    Unbounded,
+    /// https://immersive-web.github.io/webxr-hand-input/
+    HandTracking,
+    /// https://immersive-web.github.io/hit-test/
+    HitTest,
+    /// https://immersive-web.github.io/anchors/
+    Anchors,
+    /// https://immersive-web.github.io/layers/
+    Layers,
+    /// https://immersive-web.github.io/dom-overlays/
+    DomOverlay,
+    /// https://immersive-web.github.io/depth-sensing/
+    DepthSensing,
+    /// https://immersive-web.github.io/lighting-estimation/
+    LightEstimation,
+    /// https://immersive-web.github.io/raw-camera-access/
+    CameraAccess,
+    /// https://immersive-web.github.io/marker-tracking/
+    ImageTracking,
+    /// https://immersive-web.github.io/real-world-meshing/
+    MeshDetection,
+    /// Anchors at places on Earth, which is not part of any WebXR specification
+    Geospatial,
+    /// https://immersive-web.github.io/body-tracking/
+    BodyTracking,
+    /// The user's facial expressions, which is not part of any WebXR specification.
+    /// These are sensitive, so embedders should only ask for them once the user
+    /// has agreed to share them.
+    FaceTracking,
+}
+
+impl Feature {
+    /// The feature's descriptor, as content names it when requesting a session and
+    /// as `XRSession.enabledFeatures` lists it, or None for features which aren't part
+    /// of a WebXR specification, which content can't ask for by name
+    pub fn name(&self) -> Option<&'static str> {
+        let name = match self {
+            Feature::Viewer => "viewer",
+            Feature::Local => "local",
+            Feature::LocalFloor => "local-floor",
+            Feature::BoundedFloor => "bounded-floor",
+            Feature::Unbounded => "unbounded",
+            Feature::HandTracking => "hand-tracking",
+            Feature::HitTest => "hit-test",
+            Feature::Anchors => "anchors",
+            Feature::Layers => "layers",
+            Feature::DomOverlay => "dom-overlay",
+            Feature::DepthSensing => "depth-sensing",
+            Feature::LightEstimation => "light-estimation",
+            Feature::CameraAccess => "camera-access",
+            Feature::ImageTracking => "image-tracking",
+            Feature::MeshDetection => "mesh-detection",
+            Feature::BodyTracking => "body-tracking",
+            Feature::Geospatial | Feature::FaceTracking => return None,
+        };
+        Some(name)
+    }
+}
+
+/// The features content asked for when requesting a session
+/// https://immersive-web.github.io/webxr/#dictdef-xrsessioninit
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub struct SessionInit {
+    pub required_features: Vec<Feature>,
+    pub optional_features: Vec<Feature>,
+    /// https://immersive-web.github.io/depth-sensing/#dom-xrsessioninit-depthsensing
+    pub depth_sensing: Option<DepthSensingInit>,
+    /// The swapchain layout the embedder would like, or None to let the device choose
+    pub swapchain_layout: Option<SwapchainLayout>,
+    /// How the session's messages are sent. This must be `Transport::Ipc` for
+    /// sessions which are sent to another process, and otherwise can be
+    /// `Transport::InProcess`, which is cheaper.
+    pub transport: Transport,
+}
+
+impl SessionInit {
+    /// Work out which features to grant, given the features a device supports.
+    /// This fails if a required feature is unsupported.
+    /// https://immersive-web.github.io/webxr/#resolve-the-requested-features
+    pub fn validate(
+        &self,
+        mode: SessionMode,
+        supported: &[Feature],
+    ) -> Result<Vec<Feature>, Error> {
+        // Every session gets its default features, which need not be listed as supported.
+        let mut granted = vec![Feature::Viewer];
+        if mode != SessionMode::Inline {
+            granted.push(Feature::Local);
+        }
+        for feature in &self.required_features {
+            if !granted.contains(feature) {
+                if !supported.contains(feature) {
+                    return Err(Error::UnsupportedFeature);
+                }
+                granted.push(*feature);
+            }
+        }
+        for feature in &self.optional_features {
+            if supported.contains(feature) && !granted.contains(feature) {
+                granted.push(*feature);
+            }
+        }
+        Ok(granted)
+    }
+
+    /// If depth sensing was granted, pick a configuration from those the device supports.
+    /// If there is no acceptable configuration, the session fails if depth sensing
+    /// was required, and otherwise loses the feature.
+    pub fn negotiate_depth_sensing(
+        &self,
+        granted: &mut Vec<Feature>,
+        usages: &[DepthUsage],
+        data_formats: &[DepthDataFormat],
+    ) -> Result<Option<DepthSensingConfig>, Error> {
+        if !granted.contains(&Feature::DepthSensing) {
+            return Ok(None);
+        }
+        let config = self
+            .depth_sensing
+            .clone()
+            .unwrap_or_default()
+            .negotiate(usages, data_formats);
+        if config.is_none() {
+            if self.required_features.contains(&Feature::DepthSensing) {
+                return Err(Error::UnsupportedFeature);
+            }
+            granted.retain(|feature| *feature != Feature::DepthSensing);
+        }
+        Ok(config)
+    }
+
+    /// Pick the swapchain layout the embedder asked for if the device supports it,
+    /// and otherwise the one the device prefers, which is the first it supports.
+    pub fn negotiate_swapchain_layout(&self, supported: &[SwapchainLayout]) -> SwapchainLayout {
+        self.swapchain_layout
+            .filter(|layout| supported.contains(layout))
+            .or_else(|| supported.first().copied())
+            .unwrap_or(SwapchainLayout::Shared)
+    }
+}
+
+/// What a session of one mode could be given, as a device knows before one is
+/// requested, so `isSessionSupported` and permission prompts needn't start a session
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub struct SessionCapabilities {
+    pub mode: SessionMode,
+    /// The features the session could be granted, or None if this isn't known
+    pub features: Option<Vec<Feature>>,
+    /// The ways the session could be shown over the real world, or empty if this isn't known
+    pub environment_blend_modes: Vec<EnvironmentBlendMode>,
+    /// The frame rates content could ask for, or empty if the device has no choice
+    /// or doesn't know before starting
+    pub frame_rates: Vec<f32>,
+    /// The number of views, such as one for a phone and two for a headset,
+    /// or None if this isn't known
+    pub view_count: Option<usize>,
+}
+
 /// https://www.w3.org/TR/hr-time/#dom-domhighrestimestamp
 pub type HighResTimeStamp = f64;
 
+/// An identifier for a frame request, which can be used to cancel it
+/// https://immersive-web.github.io/webxr/#dom-xrsession-cancelanimationframe
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub struct FrameRequestId(pub u32);
+
 /// https://www.w3.org/TR/webxr/#callbackdef-xrframerequestcallback
 #[cfg_attr(feature = "ipc", typetag::serde)]
 pub trait FrameRequestCallback: 'static + Send {
     fn callback(&mut self, time: HighResTimeStamp, frame: Frame);
 }
 
+/// A callback for receiving the aggregate frame statistics of a session
+#[cfg_attr(feature = "ipc", typetag::serde)]
+pub trait FrameStatsCallback: 'static + Send {
+    fn callback(&mut self, stats: FrameStats);
+}
+
+/// A callback for receiving a copy of a rendered frame
+#[cfg_attr(feature = "ipc", typetag::serde)]
+pub trait FrameCaptureCallback: 'static + Send {
+    fn callback(&mut self, capture: Result<FrameCapture, Error>);
+}
+
 // The messages that are sent from the content thread to the session thread.
+// Recycled frames aren't boxed, as that would allocate every frame.
 #[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
-enum SessionMsg {
-    UpdateWebGLExternalImageApi(Box<dyn WebGLExternalImageApi>),
-    RequestAnimationFrame(Box<dyn FrameRequestCallback>),
+#[allow(clippy::large_enum_variant)]
+pub(crate) enum SessionMsg {
+    UpdateWebGLExternalImageApi(LayerId, Box<dyn WebGLExternalImageApi>),
+    CreateLayer(LayerInit, Sender<Result<LayerId, Error>>),
+    UpdateLayer(LayerId, LayerInit),
+    DestroyLayer(LayerId),
+    SetLayers(Vec<LayerId>),
+    CreateSwapchain(SwapchainInit, Sender<Result<Swapchain, Error>>),
+    AcquireSwapchainImage(SwapchainId, Sender<Result<usize, Error>>),
+    ReleaseSwapchainImage(SwapchainId, usize),
+    DestroySwapchain(SwapchainId),
+    SetLayerSwapchain(LayerId, Option<SwapchainId>, Sender<Result<(), Error>>),
+    SetLayerViewSwapchains(LayerId, Vec<SwapchainId>, Sender<Result<(), Error>>),
+    SetLayerColorScaleBias(LayerId, ColorScaleBias),
+    SetLayerBlend(LayerId, LayerBlend),
+    RequestHitTest(HitTestInit, Sender<Result<HitTestId, Error>>),
+    CancelHitTest(HitTestId),
+    CreateAnchor(
+        RigidTransform3D<f32, Anchor, Native>,
+        Sender<Result<AnchorId, Error>>,
+    ),
+    CreateGeospatialAnchor(GeospatialAnchorInit, Sender<Result<AnchorId, Error>>),
+    DeleteAnchor(AnchorId),
+    ExportAnchor(AnchorId, Sender<Result<ExportedAnchor, Error>>),
+    ImportAnchor(ExportedAnchor, Sender<Result<AnchorId, Error>>),
+    AddTrackedImage(
+        TrackedImageInit,
+        Sender<Result<(TrackedImageId, TrackedImageScore), Error>>,
+    ),
+    RemoveTrackedImage(TrackedImageId),
+    QueryPoses(HighResTimeStamp, Sender<Result<Poses, Error>>),
+    RequestAnimationFrame(FrameRequestId, Box<dyn FrameRequestCallback>),
+    CancelAnimationFrame(FrameRequestId),
     SetEventCallback(Box<dyn EventCallback>),
+    SubscribeEvents(EventFilter, Box<dyn EventCallback>),
+    InputHaptic(InputId, HapticPulse),
+    UpdateFrameRate(f32),
+    UpdateColorSpace(ColorSpace),
+    UpdateViewportScale(f32),
+    UpdateClipPlanes(f32, f32),
+    SetPerformanceLevel(PerformanceDomain, PerformanceLevel),
+    SetOcclusion(bool, Sender<Result<(), Error>>),
+    SetFramesInFlight(usize),
+    RecycleFrame(Frame),
+    RequestFrameStats(Box<dyn FrameStatsCallback>),
+    SetIdlePolicy(Option<(IdlePolicy, Box<dyn IdleCallback>)>),
+    // Sent by the event callbacks, which see the device's input and presence events
+    UserActivity,
+    UserPresence(bool),
+    CaptureFrame(Box<dyn FrameCaptureCallback>),
+    SetMirror(Option<(MirrorView, Box<dyn MirrorCallback>)>),
+    RecordVideo(Option<(VideoInit, Box<dyn VideoFrameCallback>)>),
     RenderAnimationFrame,
     Quit,
 }
@@ -61,45 +392,427 @@ enum SessionMsg {
 #[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
 pub struct Session {
     floor_transform: RigidTransform3D<f32, Native, Floor>,
+    bounds_geometry: Option<Vec<Point2D<f32, Floor>>>,
     views: Views,
     resolution: Size2D<i32, Viewport>,
     sender: Sender<SessionMsg>,
+    transport: Transport,
     initial_inputs: Vec<InputSource>,
+    granted_features: Vec<Feature>,
+    supported_frame_rates: Vec<f32>,
+    depth_sensing_config: Option<DepthSensingConfig>,
+    environment_blend_mode: EnvironmentBlendMode,
+    supported_color_spaces: Vec<ColorSpace>,
+    color_space: Option<ColorSpace>,
+    swapchain_layout: SwapchainLayout,
+    multiview: bool,
+    max_frames_in_flight: usize,
+    next_frame_request_id: u32,
 }
 
 impl Session {
     pub fn floor_transform(&self) -> RigidTransform3D<f32, Native, Floor> {
-        self.floor_transform.clone()
+        self.floor_transform
+    }
+
+    /// The boundary of the play area when the session started, if the device has one.
+    /// Updates are delivered as `Event::UpdateBoundsGeometry`.
+    pub fn bounds_geometry(&self) -> Option<&[Point2D<f32, Floor>]> {
+        self.bounds_geometry.as_ref().map(|bounds| &bounds[..])
     }
 
     pub fn initial_inputs(&self) -> &[InputSource] {
         &self.initial_inputs
     }
 
+    /// The views when the session started. Updates are delivered as `Event::UpdateViews`.
     pub fn views(&self) -> Views {
         self.views.clone()
     }
 
+    /// The features the device granted when the session was created, after resolving
+    /// the requested ones against those it supports, which never change afterwards.
+    /// Their names, from `Feature::name`, are `XRSession.enabledFeatures`.
+    /// https://immersive-web.github.io/webxr/#dom-xrsession-enabledfeatures
+    pub fn granted_features(&self) -> &[Feature] {
+        &self.granted_features
+    }
+
+    pub fn is_feature_granted(&self, feature: Feature) -> bool {
+        self.granted_features.contains(&feature)
+    }
+
+    /// The resolution when the session started, which changes along with the views.
     pub fn recommended_framebuffer_resolution(&self) -> Size2D<i32, Viewport> {
         self.resolution
     }
 
+    /// The depth sensing configuration, if depth sensing was granted
+    /// https://immersive-web.github.io/depth-sensing/#dom-xrsession-depthusage
+    pub fn depth_sensing_config(&self) -> Option<DepthSensingConfig> {
+        self.depth_sensing_config
+    }
+
+    /// https://immersive-web.github.io/webxr-ar-module/#dom-xrsession-environmentblendmode
+    pub fn environment_blend_mode(&self) -> EnvironmentBlendMode {
+        self.environment_blend_mode
+    }
+
+    /// https://immersive-web.github.io/webxr/#dom-xrsession-supportedframerates
+    pub fn supported_frame_rates(&self) -> &[f32] {
+        &self.supported_frame_rates
+    }
+
+    /// The color spaces the device can display frames in, which is empty
+    /// if it can't change its color space
+    pub fn supported_color_spaces(&self) -> &[ColorSpace] {
+        &self.supported_color_spaces
+    }
+
+    /// The color space when the session started, if the device reports it.
+    /// Updates are delivered as `Event::ColorSpaceChange`.
+    pub fn color_space(&self) -> Option<ColorSpace> {
+        self.color_space
+    }
+
+    /// Ask the device to display frames in one of the supported color spaces.
+    /// If it does, an `Event::ColorSpaceChange` is sent.
+    pub fn update_color_space(&mut self, space: ColorSpace) -> Result<(), Error> {
+        if !self.supported_color_spaces.contains(&space) {
+            return Err(Error::UnsupportedFeature);
+        }
+        let _ = self.sender.send(SessionMsg::UpdateColorSpace(space));
+        Ok(())
+    }
+
+    /// Ask the device to change its frame rate to one of the supported frame rates.
+    /// If it does, an `Event::FrameRateChange` is sent.
+    /// https://immersive-web.github.io/webxr/#dom-xrsession-updatetargetframerate
+    pub fn update_target_frame_rate(&mut self, rate: f32) -> Result<(), Error> {
+        if !self.supported_frame_rates.contains(&rate) {
+            return Err(Error::UnsupportedFeature);
+        }
+        let _ = self.sender.send(SessionMsg::UpdateFrameRate(rate));
+        Ok(())
+    }
+
+    /// Hint how hard the device should run a processor, which it may ignore.
+    /// Changes in how well the device is coping are sent as
+    /// `Event::PerformanceNotification`.
+    pub fn set_performance_level(&mut self, domain: PerformanceDomain, level: PerformanceLevel) {
+        let _ = self
+            .sender
+            .send(SessionMsg::SetPerformanceLevel(domain, level));
+    }
+
+    /// Start or stop reporting the real world's depth in each frame, for the embedder
+    /// to occlude content by real-world geometry. This is independent of content's
+    /// depth sensing, and blocks waiting for the device, which fails to start if
+    /// it can't sense depth.
+    pub fn set_occlusion(&mut self, enabled: bool) -> Result<(), Error> {
+        let (sender, receiver) = self.transport.channel()?;
+        let _ = self.sender.send(SessionMsg::SetOcclusion(enabled, sender));
+        receiver.recv().unwrap_or(Err(Error::CommunicationError))
+    }
+
+    /// Set the depths of the near and far clip planes, in meters, which the device
+    /// uses for the views' projections from the next frame on. Invalid depths,
+    /// such as a negative near plane, are ignored.
+    /// https://immersive-web.github.io/webxr/#dom-xrrenderstate-depthnear
+    pub fn update_clip_planes(&mut self, near: f32, far: f32) {
+        if !(near > 0.0 && far > near) {
+            return;
+        }
+        let _ = self.sender.send(SessionMsg::UpdateClipPlanes(near, far));
+    }
+
+    /// Ask for the views to be rendered to smaller viewports from the next frame on,
+    /// for example to keep up the frame rate. The scale is clamped to between 0.1 and 1.0,
+    /// and the device may ignore it. The viewports to use are given in each `Frame`.
+    /// https://immersive-web.github.io/webxr/#dom-xrview-requestviewportscale
+    pub fn request_viewport_scale(&mut self, scale: f32) {
+        if scale.is_nan() {
+            return;
+        }
+        let scale = scale.max(MIN_VIEWPORT_SCALE).min(1.0);
+        let _ = self.sender.send(SessionMsg::UpdateViewportScale(scale));
+    }
+
+    /// Whether content should render projection layers to one shared swapchain,
+    /// or to a swapchain per view
+    pub fn swapchain_layout(&self) -> SwapchainLayout {
+        self.swapchain_layout
+    }
+
+    /// Whether content can render the views to the layers of a texture array, by
+    /// creating a swapchain with an `array_size` of the number of views, rather than
+    /// to side by side viewports. Engines with multiview support can then draw
+    /// both eyes with each draw call. Each frame's viewports then give the layer
+    /// to render each view to.
+    pub fn supports_multiview(&self) -> bool {
+        self.multiview
+    }
+
+    /// The most frames the device lets content be given before rendering the first of them
+    pub fn max_frames_in_flight(&self) -> usize {
+        self.max_frames_in_flight
+    }
+
+    /// Let content be given up to `frames` frames before rendering the first of them,
+    /// so the next frame's poses can be delivered while the previous frame's GPU work
+    /// completes. Frames are rendered in the order they were given. Content can always
+    /// request the next frame before rendering the last one it was given. This is
+    /// clamped between 1, which is the default, and the device's maximum.
+    pub fn set_frames_in_flight(&mut self, frames: usize) {
+        let frames = frames.min(self.max_frames_in_flight).max(1);
+        let _ = self.sender.send(SessionMsg::SetFramesInFlight(frames));
+    }
+
+    /// Hand back a frame content has finished with, so the next frame can reuse its
+    /// buffers. This only helps sessions with the in-process transport, where frames
+    /// aren't serialized, and otherwise does nothing.
+    pub fn recycle_frame(&mut self, frame: Frame) {
+        if self.transport == Transport::InProcess {
+            let _ = self.sender.send(SessionMsg::RecycleFrame(frame));
+        }
+    }
+
+    /// Set the WebGL images the default projection layer is rendered from.
     pub fn update_webgl_external_image_api<I>(&mut self, images: I)
     where
         I: WebGLExternalImageApi,
     {
+        self.update_layer_webgl_external_image_api(LayerId::DEFAULT, images);
+    }
+
+    /// Set the WebGL images a layer is rendered from.
+    pub fn update_layer_webgl_external_image_api<I>(&mut self, layer: LayerId, images: I)
+    where
+        I: WebGLExternalImageApi,
+    {
+        let _ = self.sender.send(SessionMsg::UpdateWebGLExternalImageApi(
+            layer,
+            Box::new(images),
+        ));
+    }
+
+    /// Create a new layer. This blocks waiting for the device to accept it.
+    /// The layer is not composited until it is passed to `set_layers`,
+    /// apart from head-locked quads which are composited straight away.
+    pub fn create_layer(&mut self, init: LayerInit) -> Result<LayerId, Error> {
+        let (sender, receiver) = self.transport.channel()?;
+        let _ = self.sender.send(SessionMsg::CreateLayer(init, sender));
+        receiver.recv().unwrap_or(Err(Error::CommunicationError))
+    }
+
+    /// Change the parameters of a layer, such as the hit region of a DOM overlay.
+    /// The kind of layer cannot change.
+    pub fn update_layer(&mut self, layer: LayerId, init: LayerInit) {
+        let _ = self.sender.send(SessionMsg::UpdateLayer(layer, init));
+    }
+
+    /// Destroy a layer. The default projection layer can't be destroyed,
+    /// since the session always renders it.
+    pub fn destroy_layer(&mut self, layer: LayerId) -> Result<(), Error> {
+        if layer == LayerId::DEFAULT {
+            return Err(Error::InvalidLayer);
+        }
+        let _ = self.sender.send(SessionMsg::DestroyLayer(layer));
+        Ok(())
+    }
+
+    /// Set the layers to be composited, back to front.
+    /// Head-locked quads are always composited in front, so need not be listed.
+    /// https://immersive-web.github.io/layers/#dom-xrrenderstateinit-layers
+    pub fn set_layers(&mut self, layers: Vec<LayerId>) {
+        let _ = self.sender.send(SessionMsg::SetLayers(layers));
+    }
+
+    /// Create a swapchain of the device's images, which layers can be rendered to
+    /// directly. This blocks waiting for the device, and fails if it cannot share
+    /// its images.
+    pub fn create_swapchain(&mut self, init: SwapchainInit) -> Result<Swapchain, Error> {
+        let (sender, receiver) = self.transport.channel()?;
+        let _ = self.sender.send(SessionMsg::CreateSwapchain(init, sender));
+        receiver.recv().unwrap_or(Err(Error::CommunicationError))
+    }
+
+    /// Wait for an image of the swapchain to be free, returning its index.
+    /// Once it has been rendered to, it should be passed to `release_swapchain_image`.
+    pub fn acquire_swapchain_image(&mut self, swapchain: SwapchainId) -> Result<usize, Error> {
+        let (sender, receiver) = self.transport.channel()?;
+        let _ = self
+            .sender
+            .send(SessionMsg::AcquireSwapchainImage(swapchain, sender));
+        receiver.recv().unwrap_or(Err(Error::CommunicationError))
+    }
+
+    /// Hand back an image content has finished rendering to. Layers rendered from the
+    /// swapchain are displayed from this image, until the next image is released.
+    pub fn release_swapchain_image(&mut self, swapchain: SwapchainId, index: usize) {
+        let _ = self
+            .sender
+            .send(SessionMsg::ReleaseSwapchainImage(swapchain, index));
+    }
+
+    pub fn destroy_swapchain(&mut self, swapchain: SwapchainId) {
+        let _ = self.sender.send(SessionMsg::DestroySwapchain(swapchain));
+    }
+
+    /// Render a layer from a swapchain rather than its WebGL images,
+    /// or from its WebGL images again if `swapchain` is None. This fails with
+    /// `Error::NotEnoughViewTargets` if the default projection layer is given a
+    /// texture array with fewer layers than there are views.
+    pub fn set_layer_swapchain(
+        &mut self,
+        layer: LayerId,
+        swapchain: Option<SwapchainId>,
+    ) -> Result<(), Error> {
+        let (sender, receiver) = self.transport.channel()?;
+        let _ = self
+            .sender
+            .send(SessionMsg::SetLayerSwapchain(layer, swapchain, sender));
+        receiver.recv().unwrap_or(Err(Error::CommunicationError))
+    }
+
+    /// Render a projection layer from a swapchain per view, in the order of the views,
+    /// for sessions with the `SwapchainLayout::PerView` layout. This replaces the layer's
+    /// swapchain, or with no swapchains renders it from its WebGL images again. This fails
+    /// with `Error::NotEnoughViewTargets` if the default projection layer is given fewer
+    /// swapchains than there are views.
+    pub fn set_layer_view_swapchains(
+        &mut self,
+        layer: LayerId,
+        swapchains: Vec<SwapchainId>,
+    ) -> Result<(), Error> {
+        let (sender, receiver) = self.transport.channel()?;
+        let _ = self.sender.send(SessionMsg::SetLayerViewSwapchains(
+            layer, swapchains, sender,
+        ));
+        receiver.recv().unwrap_or(Err(Error::CommunicationError))
+    }
+
+    /// Scale and bias the colors of a layer from the next frame on, for example to fade
+    /// it to black. Devices which can't change a layer's colors display it unchanged.
+    pub fn set_layer_color_scale_bias(&mut self, layer: LayerId, color_scale_bias: ColorScaleBias) {
+        let _ = self
+            .sender
+            .send(SessionMsg::SetLayerColorScaleBias(layer, color_scale_bias));
+    }
+
+    /// Change how a layer is blended with the layers behind it, which are those
+    /// before it in the order given to `set_layers`, from the next frame on
+    pub fn set_layer_blend(&mut self, layer: LayerId, blend: LayerBlend) {
+        let _ = self.sender.send(SessionMsg::SetLayerBlend(layer, blend));
+    }
+
+    /// Start hit testing. This blocks waiting for the device to accept it.
+    /// Results are reported in every frame until the hit test is cancelled.
+    /// https://immersive-web.github.io/hit-test/#dom-xrsession-requesthittestsource
+    pub fn request_hit_test(&mut self, init: HitTestInit) -> Result<HitTestId, Error> {
+        let (sender, receiver) = self.transport.channel()?;
+        let _ = self.sender.send(SessionMsg::RequestHitTest(init, sender));
+        receiver.recv().unwrap_or(Err(Error::CommunicationError))
+    }
+
+    /// The poses of the viewer and input sources predicted for a time, in the same
+    /// clock as the frames' predicted display times, for sampling poses later than
+    /// the frame's. This blocks waiting for the device to predict them.
+    pub fn query_poses(&mut self, time: HighResTimeStamp) -> Result<Poses, Error> {
+        let (sender, receiver) = self.transport.channel()?;
+        let _ = self.sender.send(SessionMsg::QueryPoses(time, sender));
+        receiver.recv().unwrap_or(Err(Error::CommunicationError))
+    }
+
+    /// https://immersive-web.github.io/hit-test/#dom-xrhittestsource-cancel
+    pub fn cancel_hit_test(&mut self, id: HitTestId) {
+        let _ = self.sender.send(SessionMsg::CancelHitTest(id));
+    }
+
+    /// Create an anchor at a pose, for example one from a hit test result.
+    /// This blocks waiting for the device to accept it.
+    /// https://immersive-web.github.io/anchors/#dom-xrframe-createanchor
+    pub fn create_anchor(
+        &mut self,
+        pose: RigidTransform3D<f32, Anchor, Native>,
+    ) -> Result<AnchorId, Error> {
+        let (sender, receiver) = self.transport.channel()?;
+        let _ = self.sender.send(SessionMsg::CreateAnchor(pose, sender));
+        receiver.recv().unwrap_or(Err(Error::CommunicationError))
+    }
+
+    /// Create an anchor at a place on Earth, which like other anchors is reported in
+    /// every frame for which the device knows where it is. This blocks waiting for
+    /// the device to accept it, which needs geospatial to be granted.
+    pub fn create_geospatial_anchor(
+        &mut self,
+        init: GeospatialAnchorInit,
+    ) -> Result<AnchorId, Error> {
+        let (sender, receiver) = self.transport.channel()?;
         let _ = self
             .sender
-            .send(SessionMsg::UpdateWebGLExternalImageApi(Box::new(images)));
+            .send(SessionMsg::CreateGeospatialAnchor(init, sender));
+        receiver.recv().unwrap_or(Err(Error::CommunicationError))
+    }
+
+    /// https://immersive-web.github.io/anchors/#dom-xranchor-delete
+    pub fn delete_anchor(&mut self, id: AnchorId) {
+        let _ = self.sender.send(SessionMsg::DeleteAnchor(id));
     }
 
-    pub fn request_animation_frame<C>(&mut self, callback: C)
+    /// Export an anchor as an opaque blob, which can be stored or shared, for
+    /// example through a cloud anchor service, and later imported with
+    /// `import_anchor`. This blocks waiting for the device to export it.
+    pub fn export_anchor(&mut self, id: AnchorId) -> Result<ExportedAnchor, Error> {
+        let (sender, receiver) = self.transport.channel()?;
+        let _ = self.sender.send(SessionMsg::ExportAnchor(id, sender));
+        receiver.recv().unwrap_or(Err(Error::CommunicationError))
+    }
+
+    /// Create an anchor from one exported by `export_anchor`, possibly in another
+    /// session, which like other anchors is reported in every frame for which the
+    /// device has found it. This blocks waiting for the device to accept it.
+    pub fn import_anchor(&mut self, anchor: ExportedAnchor) -> Result<AnchorId, Error> {
+        let (sender, receiver) = self.transport.channel()?;
+        let _ = self.sender.send(SessionMsg::ImportAnchor(anchor, sender));
+        receiver.recv().unwrap_or(Err(Error::CommunicationError))
+    }
+
+    /// Ask the device to look for a reference image in the real world. Images it
+    /// finds are reported in each frame. This blocks waiting for the device to
+    /// score the image, which it won't track if it is untrackable.
+    /// https://immersive-web.github.io/marker-tracking/#dom-xrsession-gettrackedimagescores
+    pub fn add_tracked_image(
+        &mut self,
+        init: TrackedImageInit,
+    ) -> Result<(TrackedImageId, TrackedImageScore), Error> {
+        let (sender, receiver) = self.transport.channel()?;
+        let _ = self.sender.send(SessionMsg::AddTrackedImage(init, sender));
+        receiver.recv().unwrap_or(Err(Error::CommunicationError))
+    }
+
+    /// Stop looking for an image added by `add_tracked_image`
+    pub fn remove_tracked_image(&mut self, id: TrackedImageId) {
+        let _ = self.sender.send(SessionMsg::RemoveTrackedImage(id));
+    }
+
+    pub fn request_animation_frame<C>(&mut self, callback: C) -> FrameRequestId
     where
         C: FrameRequestCallback,
     {
+        let id = FrameRequestId(self.next_frame_request_id);
+        self.next_frame_request_id = self.next_frame_request_id.wrapping_add(1);
         let _ = self
             .sender
-            .send(SessionMsg::RequestAnimationFrame(Box::new(callback)));
+            .send(SessionMsg::RequestAnimationFrame(id, Box::new(callback)));
+        id
+    }
+
+    /// Withdraw a frame request, so its callback is never called. Requests whose
+    /// frame has already been delivered are unaffected. Requests still pending
+    /// when the session ends are also never called.
+    pub fn cancel_animation_frame(&mut self, id: FrameRequestId) {
+        let _ = self.sender.send(SessionMsg::CancelAnimationFrame(id));
     }
 
     pub fn set_event_callback<C>(&mut self, callback: C)
@@ -111,6 +824,89 @@ impl Session {
             .send(SessionMsg::SetEventCallback(Box::new(callback)));
     }
 
+    /// https://w3c.github.io/gamepad/extensions.html#dom-gamepadhapticactuator-pulse
+    pub fn input_haptic(&mut self, id: InputId, pulse: HapticPulse) {
+        let _ = self.sender.send(SessionMsg::InputHaptic(id, pulse));
+    }
+
+    pub fn request_frame_stats<C>(&mut self, callback: C)
+    where
+        C: FrameStatsCallback,
+    {
+        let _ = self
+            .sender
+            .send(SessionMsg::RequestFrameStats(Box::new(callback)));
+    }
+
+    /// Suspend, and then end, the session once the user stops using it, telling
+    /// `callback` before suspending and whenever the session's idleness changes.
+    /// Sessions have no idle policy until one is set.
+    pub fn set_idle_policy<C>(&mut self, policy: IdlePolicy, callback: C)
+    where
+        C: IdleCallback,
+    {
+        let policy = Some((policy, Box::new(callback) as Box<dyn IdleCallback>));
+        let _ = self.sender.send(SessionMsg::SetIdlePolicy(policy));
+    }
+
+    pub fn clear_idle_policy(&mut self) {
+        let _ = self.sender.send(SessionMsg::SetIdlePolicy(None));
+    }
+
+    /// Receive a copy of the next frame rendered, as the device displayed it,
+    /// for example for screenshot tests. This fails if the device cannot do this.
+    pub fn capture_frame<C>(&mut self, callback: C)
+    where
+        C: FrameCaptureCallback,
+    {
+        let _ = self
+            .sender
+            .send(SessionMsg::CaptureFrame(Box::new(callback)));
+    }
+
+    /// Receive part of every frame content renders from now on, for copying to
+    /// the embedder's own window or texture
+    pub fn set_mirror<C>(&mut self, view: MirrorView, callback: C)
+    where
+        C: MirrorCallback,
+    {
+        let _ = self
+            .sender
+            .send(SessionMsg::SetMirror(Some((view, Box::new(callback)))));
+    }
+
+    /// Stop mirroring frames
+    pub fn clear_mirror(&mut self) {
+        let _ = self.sender.send(SessionMsg::SetMirror(None));
+    }
+
+    /// Record the frames content renders from now on as video, handing them to
+    /// `callback` as they are displayed. This stops any recording already going.
+    /// Devices which cannot capture their frames record nothing.
+    pub fn start_video_recording<C>(&mut self, init: VideoInit, callback: C)
+    where
+        C: VideoFrameCallback,
+    {
+        let _ = self
+            .sender
+            .send(SessionMsg::RecordVideo(Some((init, Box::new(callback)))));
+    }
+
+    pub fn stop_video_recording(&mut self) {
+        let _ = self.sender.send(SessionMsg::RecordVideo(None));
+    }
+
+    /// Receive the events matching `filter` through `callback`. Those events
+    /// are no longer sent to the callback given to `set_event_callback`.
+    pub fn subscribe_events<C>(&mut self, filter: EventFilter, callback: C)
+    where
+        C: EventCallback,
+    {
+        let _ = self
+            .sender
+            .send(SessionMsg::SubscribeEvents(filter, Box::new(callback)));
+    }
+
     pub fn render_animation_frame(&mut self) {
         let _ = self.sender.send(SessionMsg::RenderAnimationFrame);
     }
@@ -124,75 +920,551 @@ impl Session {
 pub struct SessionThread<D> {
     receiver: Receiver<SessionMsg>,
     sender: Sender<SessionMsg>,
-    images: Option<Box<dyn WebGLExternalImageApi>>,
+    transport: Transport,
+    layers: LayerManager,
+    swapchains: SwapchainManager,
+    next_hit_test_id: u32,
+    next_anchor_id: u32,
+    next_tracked_image_id: u32,
     timestamp: HighResTimeStamp,
     running: bool,
     device: D,
+    stats: FrameStats,
+    captures: Vec<Box<dyn FrameCaptureCallback>>,
+    mirror: Option<(MirrorView, Box<dyn MirrorCallback>)>,
+    video: Option<VideoRecorder>,
+    viewport_scale: f32,
+    // When content was given each frame it has not yet rendered, oldest first
+    frames_delivered: VecDeque<Instant>,
+    frames_in_flight: usize,
+    // When the device will next need a frame, if content is rendering one and the device has a deadline
+    deadline: Option<Instant>,
+    // Frames content has finished with, whose buffers can be reused
+    spare_frames: Vec<Frame>,
+    // Messages which arrived while waiting for a frame, to handle next
+    deferred: VecDeque<SessionMsg>,
+    idle: IdleTracker,
+    delivery: FrameDelivery,
+    // The frame request which arrived while the session was suspended, to answer on resuming
+    suspended_request: Option<(FrameRequestId, Box<dyn FrameRequestCallback>)>,
+    cpu_frame_time: Option<f64>,
+    gpu_submit_time: Option<f64>,
+    // The device's views and their scaled viewports, whose buffers are reused every frame
+    views: Views,
+    scaled_viewports: Vec<Rect<i32, Viewport>>,
 }
 
 impl<D: Device> SessionThread<D> {
     pub fn new(device: D) -> Result<SessionThread<D>, Error> {
-        let (sender, receiver) = crate::channel().or(Err(Error::CommunicationError))?;
+        SessionThread::new_with_transport(device, Transport::default())
+    }
+
+    /// A session thread whose session sends its messages with the given transport
+    pub fn new_with_transport(device: D, transport: Transport) -> Result<SessionThread<D>, Error> {
+        let (sender, receiver) = transport.channel()?;
 
         let timestamp = 0.0;
-        let images = None;
+        let layers = LayerManager::new();
         let running = true;
+        let views = device.views();
         Ok(SessionThread {
             sender,
             receiver,
+            transport,
             device,
-            images,
+            layers,
+            swapchains: SwapchainManager::new(),
+            next_hit_test_id: 0,
+            next_anchor_id: 0,
+            next_tracked_image_id: 0,
             timestamp,
             running,
+            stats: FrameStats::default(),
+            captures: vec![],
+            mirror: None,
+            video: None,
+            viewport_scale: 1.0,
+            frames_delivered: VecDeque::new(),
+            frames_in_flight: 1,
+            deadline: None,
+            spare_frames: vec![],
+            deferred: VecDeque::new(),
+            idle: IdleTracker::new(),
+            delivery: FrameDelivery::new(),
+            suspended_request: None,
+            cpu_frame_time: None,
+            gpu_submit_time: None,
+            views,
+            scaled_viewports: vec![],
         })
     }
 
     pub fn new_session(&mut self) -> Session {
         let floor_transform = self.device.floor_transform();
+        let bounds_geometry = self.device.bounds_geometry();
         let views = self.device.views();
         let resolution = self.device.recommended_framebuffer_resolution();
         let sender = self.sender.clone();
+        let transport = self.transport;
         let initial_inputs = self.device.initial_inputs();
+        let granted_features = self.device.granted_features();
+        let supported_frame_rates = self.device.supported_frame_rates();
+        let depth_sensing_config = self.device.depth_sensing_config();
+        let environment_blend_mode = self.device.environment_blend_mode();
+        let supported_color_spaces = self.device.supported_color_spaces();
+        let color_space = self.device.color_space();
+        let swapchain_layout = self.device.swapchain_layout();
+        let multiview = self.device.supports_multiview();
+        let max_frames_in_flight = self.device.max_frames_in_flight().max(1);
         Session {
             floor_transform,
+            bounds_geometry,
             views,
             resolution,
             sender,
+            transport,
             initial_inputs,
+            granted_features,
+            supported_frame_rates,
+            depth_sensing_config,
+            environment_blend_mode,
+            supported_color_spaces,
+            color_space,
+            swapchain_layout,
+            multiview,
+            max_frames_in_flight,
+            next_frame_request_id: 0,
         }
     }
 
     pub fn run(&mut self) {
-        while let Ok(msg) = self.receiver.recv() {
+        loop {
+            let msg = match self.deferred.pop_front() {
+                Some(msg) => msg,
+                None => match self.next_wake() {
+                    Some(wake) => {
+                        let now = Instant::now();
+                        let timeout = if wake > now {
+                            wake - now
+                        } else {
+                            Duration::default()
+                        };
+                        match crate::recv_timeout(&self.receiver, timeout) {
+                            Ok(msg) => msg,
+                            Err(_) if Instant::now() >= wake => {
+                                if self.missed_deadline() {
+                                    self.missed_frame();
+                                }
+                                if !self.check_idle() {
+                                    break;
+                                }
+                                continue;
+                            }
+                            Err(_) => break,
+                        }
+                    }
+                    None => match self.receiver.recv() {
+                        Ok(msg) => msg,
+                        Err(_) => break,
+                    },
+                },
+            };
             if !self.handle_msg(msg) {
                 break;
             }
         }
     }
 
+    // Content hasn't rendered in time, so the device shows its previous frame again
+    // rather than stalling, and gives content until the next deadline
+    fn missed_frame(&mut self) {
+        self.device.missed_frame();
+        self.stats.missed_frames += 1;
+        self.deadline = self.next_deadline();
+    }
+
+    fn next_deadline(&self) -> Option<Instant> {
+        self.device
+            .frame_deadline()
+            .map(|deadline| Instant::now() + deadline)
+    }
+
+    // When the thread needs to stop waiting for messages, to display a frame
+    // in place of one content missed or to check whether the user is idle
+    fn next_wake(&self) -> Option<Instant> {
+        let idle = if self.idle.suspended() {
+            // The device is polled for the user coming back
+            let poll = Instant::now() + SUSPENDED_POLL_INTERVAL;
+            Some(self.idle.next_check().map_or(poll, |check| check.min(poll)))
+        } else {
+            self.idle.next_check()
+        };
+        self.deadline.into_iter().chain(idle).min()
+    }
+
+    // Act on the user being idle, returning false if this ended the session
+    fn check_idle(&mut self) -> bool {
+        if self.idle.suspended() {
+            self.device.poll_events();
+        }
+        if self.idle.update() {
+            self.running = false;
+            self.device.quit();
+            return false;
+        }
+        true
+    }
+
+    // Answer the frame request held back while the session was suspended
+    fn resume(&mut self) {
+        if let Some((id, callback)) = self.suspended_request.take() {
+            self.deferred
+                .push_front(SessionMsg::RequestAnimationFrame(id, callback));
+        }
+    }
+
+    fn missed_deadline(&self) -> bool {
+        self.deadline
+            .map_or(false, |deadline| Instant::now() >= deadline)
+    }
+
+    // Whether a frame request was cancelled while waiting for its frame,
+    // or the session is ending. The other messages that arrived are deferred.
+    fn frame_request_cancelled(&mut self, id: FrameRequestId) -> bool {
+        let mut cancelled = false;
+        while let Ok(msg) = self.receiver.try_recv() {
+            match msg {
+                SessionMsg::CancelAnimationFrame(cancel) if cancel == id => cancelled = true,
+                SessionMsg::Quit => {
+                    cancelled = true;
+                    self.deferred.push_back(msg);
+                }
+                msg => self.deferred.push_back(msg),
+            }
+        }
+        cancelled
+    }
+
     fn handle_msg(&mut self, msg: SessionMsg) -> bool {
         if !self.device.connected() {
+            self.running = false;
             return false;
         }
         match msg {
-            SessionMsg::UpdateWebGLExternalImageApi(images) => {
-                self.images = Some(images);
+            SessionMsg::UpdateWebGLExternalImageApi(layer, images) => {
+                self.layers.update_images(layer, images);
+            }
+            SessionMsg::CreateLayer(init, sender) => {
+                let _ = sender.send(self.layers.create_layer(&mut self.device, init));
             }
-            SessionMsg::RequestAnimationFrame(mut callback) => {
-                let timestamp = self.timestamp;
-                let frame = self.device.wait_for_animation_frame();
-                callback.callback(timestamp, frame);
+            SessionMsg::UpdateLayer(layer, init) => {
+                let _ = self.layers.update_layer(&mut self.device, layer, init);
+            }
+            SessionMsg::DestroyLayer(layer) => {
+                let _ = self.layers.destroy_layer(&mut self.device, layer);
+            }
+            SessionMsg::SetLayers(layers) => {
+                self.layers.set_layers(layers);
+            }
+            SessionMsg::CreateSwapchain(init, sender) => {
+                let _ = sender.send(self.swapchains.create_swapchain(&mut self.device, init));
+            }
+            SessionMsg::AcquireSwapchainImage(swapchain, sender) => {
+                let _ = sender.send(self.swapchains.acquire_image(&mut self.device, swapchain));
+            }
+            SessionMsg::ReleaseSwapchainImage(swapchain, index) => {
+                self.swapchains
+                    .release_image(&mut self.device, swapchain, index);
+            }
+            SessionMsg::DestroySwapchain(swapchain) => {
+                self.swapchains
+                    .destroy_swapchain(&mut self.device, swapchain);
+                self.layers.remove_swapchain(swapchain);
+            }
+            SessionMsg::SetLayerSwapchain(layer, swapchain, sender) => {
+                let result = match swapchain {
+                    Some(swapchain) if !self.swapchains.contains(swapchain) => {
+                        Err(Error::InvalidLayer)
+                    }
+                    // Only the default projection layer's views are laid out for content
+                    Some(swapchain) if layer == LayerId::DEFAULT => {
+                        match self.swapchains.array_size(swapchain) {
+                            Some(layers) if layers > 1 => self
+                                .device
+                                .views()
+                                .check_layout(ViewLayout::TextureArray(layers)),
+                            _ => Ok(()),
+                        }
+                    }
+                    _ => Ok(()),
+                };
+                if result.is_ok() {
+                    self.layers.set_swapchain(layer, swapchain);
+                }
+                let _ = sender.send(result);
+            }
+            SessionMsg::SetLayerViewSwapchains(layer, swapchains, sender) => {
+                let result = if !swapchains
+                    .iter()
+                    .all(|swapchain| self.swapchains.contains(*swapchain))
+                {
+                    Err(Error::InvalidLayer)
+                } else if layer == LayerId::DEFAULT && !swapchains.is_empty() {
+                    let layout = ViewLayout::SeparateSurfaces(swapchains.len());
+                    self.device.views().check_layout(layout)
+                } else {
+                    Ok(())
+                };
+                if result.is_ok() {
+                    self.layers.set_view_swapchains(layer, swapchains);
+                }
+                let _ = sender.send(result);
+            }
+            SessionMsg::SetLayerColorScaleBias(layer, color_scale_bias) => {
+                self.layers.set_color_scale_bias(layer, color_scale_bias);
+            }
+            SessionMsg::SetLayerBlend(layer, blend) => {
+                self.layers.set_blend(layer, blend);
+            }
+            SessionMsg::RequestHitTest(init, sender) => {
+                let id = HitTestId(self.next_hit_test_id);
+                let result = self.device.request_hit_test(id, init);
+                if result.is_ok() {
+                    self.next_hit_test_id += 1;
+                }
+                let _ = sender.send(result.map(|()| id));
+            }
+            SessionMsg::CancelHitTest(id) => {
+                self.device.cancel_hit_test(id);
+            }
+            SessionMsg::CreateAnchor(pose, sender) => {
+                let id = AnchorId(self.next_anchor_id);
+                let result = self.device.create_anchor(id, pose);
+                if result.is_ok() {
+                    self.next_anchor_id += 1;
+                }
+                let _ = sender.send(result.map(|()| id));
+            }
+            SessionMsg::CreateGeospatialAnchor(init, sender) => {
+                let id = AnchorId(self.next_anchor_id);
+                let result = self.device.create_geospatial_anchor(id, init);
+                if result.is_ok() {
+                    self.next_anchor_id += 1;
+                }
+                let _ = sender.send(result.map(|()| id));
+            }
+            SessionMsg::DeleteAnchor(id) => {
+                self.device.delete_anchor(id);
+            }
+            SessionMsg::ExportAnchor(id, sender) => {
+                let _ = sender.send(self.device.export_anchor(id));
+            }
+            SessionMsg::ImportAnchor(anchor, sender) => {
+                let id = AnchorId(self.next_anchor_id);
+                let result = self.device.import_anchor(id, anchor);
+                if result.is_ok() {
+                    self.next_anchor_id += 1;
+                }
+                let _ = sender.send(result.map(|()| id));
+            }
+            SessionMsg::AddTrackedImage(init, sender) => {
+                let id = TrackedImageId(self.next_tracked_image_id);
+                let result = self.device.add_tracked_image(id, init);
+                if result.is_ok() {
+                    self.next_tracked_image_id += 1;
+                }
+                let _ = sender.send(result.map(|score| (id, score)));
+            }
+            SessionMsg::RemoveTrackedImage(id) => {
+                self.device.remove_tracked_image(id);
+            }
+            SessionMsg::QueryPoses(time, sender) => {
+                let _ = sender.send(self.device.poses_at(time));
+            }
+            SessionMsg::RequestAnimationFrame(id, callback) => {
+                if !self.check_idle() {
+                    return false;
+                }
+                if self.idle.suspended() {
+                    self.suspended_request = Some((id, callback));
+                    return true;
+                }
+                let wait = crate::span("wait_frame");
+                let mut frame = match self.spare_frames.pop() {
+                    Some(mut frame) => {
+                        self.device.fill_animation_frame(&mut frame);
+                        frame
+                    }
+                    None => self.device.wait_for_animation_frame(),
+                };
+                if self.frame_request_cancelled(id) {
+                    if self.spare_frames.len() < MAX_SPARE_FRAMES {
+                        self.spare_frames.push(frame);
+                    }
+                    return true;
+                }
+                if IdleTracker::inputs_active(&frame.inputs) {
+                    self.idle.activity();
+                }
+                let timestamp = frame
+                    .timing
+                    .predicted_display_time
+                    .unwrap_or(self.timestamp);
+                // Content requests the next frame before rendering the one it has, so that
+                // one isn't in flight yet. Beyond that, the oldest will never be rendered.
+                if self.frames_delivered.len() > self.frames_in_flight {
+                    self.frames_delivered.pop_front();
+                    self.stats.dropped_frames += 1;
+                }
+                self.frames_delivered.push_back(Instant::now());
+                if self.deadline.is_none() {
+                    self.deadline = self.next_deadline();
+                }
+                let layout = self.layers.view_layout(&self.swapchains);
+                self.device.views_into(&mut self.views);
+                let views = &self.views;
+                let scale = self.viewport_scale;
+                if let Err(Error::NotEnoughViewTargets) =
+                    views.layout_viewports_into(scale, layout, &mut frame.viewports)
+                {
+                    // Content can't give the projection layer too few swapchains for the views,
+                    // but the device can add views afterwards. Until content has been told with
+                    // `Event::UpdateViews` and given it new swapchains, the views are side by side.
+                    let layout = ViewLayout::SideBySide;
+                    let _ = views.layout_viewports_into(scale, layout, &mut frame.viewports);
+                }
+                self.layers
+                    .device_images(&mut self.device, &mut frame.device_images);
+                frame.timing.cpu_frame_time = self.cpu_frame_time;
+                frame.timing.gpu_submit_time = self.gpu_submit_time;
+                frame.timing.dropped_frames = self.stats.dropped_frames;
+                drop(wait);
+                if self.delivery.deliver(callback, timestamp, frame) {
+                    // The frame before this will never be rendered, unless it was already dropped
+                    self.stats.replaced_frames += 1;
+                    let delivered = self.frames_delivered.len();
+                    if delivered > 1 {
+                        self.frames_delivered.remove(delivered - 2);
+                    }
+                }
+            }
+            // Requests are otherwise only cancelled while waiting for their frame,
+            // so any others have already been delivered
+            SessionMsg::CancelAnimationFrame(cancel) => {
+                if self
+                    .suspended_request
+                    .as_ref()
+                    .map_or(false, |(id, _)| *id == cancel)
+                {
+                    self.suspended_request = None;
+                }
             }
             SessionMsg::SetEventCallback(callback) => {
-                self.device.set_event_callback(callback);
+                let sender = self.sender.clone();
+                let callback = IdleEventCallback { callback, sender };
+                self.device.set_event_callback(Box::new(callback));
+            }
+            SessionMsg::SubscribeEvents(filter, callback) => {
+                let sender = self.sender.clone();
+                let callback = IdleEventCallback { callback, sender };
+                self.device.subscribe_events(filter, Box::new(callback));
+            }
+            SessionMsg::SetIdlePolicy(policy) => {
+                self.idle.set_policy(policy);
+                self.resume();
+            }
+            SessionMsg::UserActivity => {
+                if self.idle.activity() {
+                    self.resume();
+                }
+            }
+            SessionMsg::UserPresence(present) => {
+                if self.idle.presence(present) {
+                    self.resume();
+                }
+            }
+            SessionMsg::InputHaptic(id, pulse) => {
+                self.device.input_haptic(id, pulse);
+            }
+            SessionMsg::UpdateFrameRate(rate) => {
+                self.device.update_frame_rate(rate);
+            }
+            SessionMsg::UpdateColorSpace(space) => {
+                self.device.update_color_space(space);
+            }
+            SessionMsg::UpdateViewportScale(scale) => {
+                self.viewport_scale = self.device.update_viewport_scale(scale);
+            }
+            SessionMsg::SetPerformanceLevel(domain, level) => {
+                self.device.set_performance_level(domain, level);
+            }
+            SessionMsg::SetOcclusion(enabled, sender) => {
+                let _ = sender.send(self.device.set_occlusion(enabled));
+            }
+            SessionMsg::UpdateClipPlanes(near, far) => {
+                self.device.update_clip_planes(near, far);
+            }
+            SessionMsg::SetFramesInFlight(frames) => {
+                self.frames_in_flight = frames.min(self.device.max_frames_in_flight()).max(1);
+                while self.frames_delivered.len() > self.frames_in_flight + 1 {
+                    self.frames_delivered.pop_front();
+                    self.stats.dropped_frames += 1;
+                }
+            }
+            SessionMsg::RecycleFrame(frame) => {
+                if self.spare_frames.len() < MAX_SPARE_FRAMES {
+                    self.spare_frames.push(frame);
+                }
+            }
+            SessionMsg::RequestFrameStats(mut callback) => {
+                let mut stats = self.stats;
+                stats.stalled_time = self.delivery.stalled_for().map_or(0.0, millis);
+                callback.callback(stats);
+            }
+            SessionMsg::CaptureFrame(callback) => {
+                self.captures.push(callback);
+            }
+            SessionMsg::SetMirror(mirror) => {
+                self.mirror = mirror;
+            }
+            SessionMsg::RecordVideo(video) => {
+                self.video = video.map(|(init, callback)| VideoRecorder::new(init, callback));
             }
             SessionMsg::RenderAnimationFrame => {
+                let _span = crate::span("render_frame");
                 self.timestamp += 1.0;
-                if let Some(ref images) = self.images {
-                    if let Ok((texture_id, size, sync)) = images.lock() {
-                        self.device.render_animation_frame(texture_id, size, sync);
-                        images.unlock();
-                    }
+                let start = Instant::now();
+                let record = self.video.as_ref().map_or(false, |video| video.due());
+                let capture = record || !self.captures.is_empty();
+                self.device.views_into(&mut self.views);
+                self.views
+                    .scaled_viewports_into(self.viewport_scale, &mut self.scaled_viewports);
+                let viewports = &self.scaled_viewports;
+                let mirror = self.mirror.as_mut().and_then(|(view, callback)| {
+                    let source = view.source(viewports)?;
+                    Some((&mut **callback as &mut dyn MirrorCallback, source))
+                });
+                let captured =
+                    self.layers
+                        .render(&mut self.device, &self.swapchains, capture, mirror);
+                let gpu_submit_time = millis(start.elapsed());
+                if let Some(delivered) = self.frames_delivered.pop_front() {
+                    let cpu_frame_time = millis(start.duration_since(delivered));
+                    self.stats.record(cpu_frame_time, gpu_submit_time);
+                    self.cpu_frame_time = Some(cpu_frame_time);
+                }
+                self.gpu_submit_time = Some(gpu_submit_time);
+                // The next frame content is rendering is due a period after this one
+                self.deadline = if self.frames_delivered.is_empty() {
+                    None
+                } else {
+                    self.next_deadline()
+                };
+                if let (true, Some(video), Some(captured)) = (record, &mut self.video, &captured) {
+                    video.record(captured, &self.scaled_viewports);
+                }
+                for mut callback in self.captures.drain(..) {
+                    callback.callback(captured.clone().ok_or(Error::UnsupportedFeature));
                 }
             }
             SessionMsg::Quit => {
@@ -205,6 +1477,10 @@ impl<D: Device> SessionThread<D> {
     }
 }
 
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs() as f64 * 1000.0 + f64::from(duration.subsec_nanos()) / 1_000_000.0
+}
+
 /// Devices that need to can run sessions on the main thread.
 pub trait MainThreadSession: 'static {
     fn run_one_frame(&mut self);
@@ -213,15 +1489,36 @@ pub trait MainThreadSession: 'static {
 
 impl<D: Device> MainThreadSession for SessionThread<D> {
     fn run_one_frame(&mut self) {
+        if !self.check_idle() {
+            return;
+        }
         let timestamp = self.timestamp;
         while timestamp == self.timestamp && self.running {
-            if let Ok(msg) = crate::recv_timeout(&self.receiver, TIMEOUT) {
+            if let Some(msg) = self.deferred.pop_front() {
+                if !self.handle_msg(msg) {
+                    break;
+                }
+            } else if let Ok(msg) = crate::recv_timeout(&self.receiver, TIMEOUT) {
                 if !self.handle_msg(msg) {
                     break;
                 }
+            } else if !self.device.connected() {
+                // The device can disconnect while content is idle.
+                self.running = false;
+            } else if self.missed_deadline() {
+                self.missed_frame();
+            } else if self.frames_delivered.is_empty() {
+                // Content has no frame to render, so rather than wait for it, let
+                // the other sessions on the main thread, such as an inline session
+                // alongside an immersive one, run their frames.
+                break;
             }
         }
-        while let Ok(msg) = self.receiver.try_recv() {
+        while let Some(msg) = self
+            .deferred
+            .pop_front()
+            .or_else(|| self.receiver.try_recv().ok())
+        {
             if !self.handle_msg(msg) {
                 break;
             }
@@ -236,22 +1533,62 @@ impl<D: Device> MainThreadSession for SessionThread<D> {
 /// A type for building XR sessions
 pub struct SessionBuilder<'a> {
     sessions: &'a mut Vec<Box<dyn MainThreadSession>>,
+    transport: Transport,
+    #[cfg(feature = "recording")]
+    recording: Option<PathBuf>,
 }
 
 impl<'a> SessionBuilder<'a> {
-    pub(crate) fn new(sessions: &'a mut Vec<Box<dyn MainThreadSession>>) -> SessionBuilder {
-        SessionBuilder { sessions }
+    pub(crate) fn new(
+        sessions: &'a mut Vec<Box<dyn MainThreadSession>>,
+        transport: Transport,
+    ) -> SessionBuilder<'a> {
+        SessionBuilder {
+            sessions,
+            transport,
+            #[cfg(feature = "recording")]
+            recording: None,
+        }
+    }
+
+    /// Record the session built to the file at `path`, if there is one
+    #[cfg(feature = "recording")]
+    pub(crate) fn record_to(mut self, path: Option<PathBuf>) -> SessionBuilder<'a> {
+        self.recording = path;
+        self
     }
 
     /// For devices which are happy to hand over thread management to webxr.
+    /// The device's frames run on a thread of its own, so they aren't held up
+    /// by the main thread, and devices which can should prefer this. The device
+    /// is made on that thread, so only its factory needs to be `Send`.
     pub fn spawn<D, F>(self, factory: F) -> Result<Session, Error>
     where
         F: 'static + FnOnce() -> Result<D, Error> + Send,
         D: Device,
     {
-        let (acks, ackr) = crate::channel().or(Err(Error::CommunicationError))?;
-        thread::spawn(
-            move || match factory().and_then(|device| SessionThread::new(device)) {
+        let transport = self.transport;
+        #[cfg(feature = "recording")]
+        {
+            if let Some(path) = self.recording {
+                return SessionBuilder::spawn_device(transport, move || {
+                    RecordingDevice::new(factory()?, &path).or(Err(Error::CommunicationError))
+                });
+            }
+        }
+        SessionBuilder::spawn_device(transport, factory)
+    }
+
+    fn spawn_device<D, F>(transport: Transport, factory: F) -> Result<Session, Error>
+    where
+        F: 'static + FnOnce() -> Result<D, Error> + Send,
+        D: Device,
+    {
+        // The session is only handed back to this thread, so need not cross a process
+        let (acks, ackr) = Transport::InProcess.channel()?;
+        thread::spawn(move || {
+            match factory().and_then(|device| SessionThread::new_with_transport(device, transport))
+            {
                 Ok(mut thread) => {
                     let session = thread.new_session();
                     let _ = acks.send(Ok(session));
@@ -260,21 +1597,224 @@ impl<'a> SessionBuilder<'a> {
                 Err(err) => {
                     let _ = acks.send(Err(err));
                 }
-            },
-        );
+            }
+        });
         ackr.recv().unwrap_or(Err(Error::CommunicationError))
     }
 
-    /// For devices that need to run on the main thread.
+    /// For devices that need to run on the main thread, such as those using the
+    /// embedder's GL context. Their frames are run by `MainThreadRegistry::run_one_frame`,
+    /// so are paced by the main thread.
     pub fn run_on_main_thread<D, F>(self, factory: F) -> Result<Session, Error>
     where
         F: 'static + FnOnce() -> Result<D, Error>,
         D: Device,
     {
         let device = factory()?;
-        let mut session_thread = SessionThread::new(device)?;
+        #[cfg(feature = "recording")]
+        {
+            if let Some(ref path) = self.recording {
+                let device =
+                    RecordingDevice::new(device, path).or(Err(Error::CommunicationError))?;
+                return SessionBuilder::run_device(self.sessions, self.transport, device);
+            }
+        }
+        SessionBuilder::run_device(self.sessions, self.transport, device)
+    }
+
+    fn run_device<D: Device>(
+        sessions: &mut Vec<Box<dyn MainThreadSession>>,
+        transport: Transport,
+        device: D,
+    ) -> Result<Session, Error> {
+        let mut session_thread = SessionThread::new_with_transport(device, transport)?;
         let session = session_thread.new_session();
-        self.sessions.push(Box::new(session_thread));
+        sessions.push(Box::new(session_thread));
         Ok(session)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::Eye;
+    use crate::MultiView;
+    use crate::View;
+
+    use euclid::default::Size2D as UntypedSize2D;
+    use euclid::Transform3D;
+    use gleam::gl::GLsync;
+    use gleam::gl::GLuint;
+
+    use std::alloc::GlobalAlloc;
+    use std::alloc::Layout;
+    use std::alloc::System;
+    use std::cell::Cell;
+
+    // Counts the allocations each thread makes, so tests can check code doesn't allocate
+    struct CountingAllocator;
+
+    thread_local! {
+        static ALLOCATIONS: Cell<usize> = Cell::new(0);
+    }
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    fn allocations() -> usize {
+        ALLOCATIONS.with(Cell::get)
+    }
+
+    // A device with three views, which displays nothing
+    struct MultiViewDevice(Views);
+
+    impl MultiViewDevice {
+        fn new() -> MultiViewDevice {
+            let view = |x| MultiView {
+                view: View {
+                    transform: RigidTransform3D::identity(),
+                    projection: Transform3D::identity(),
+                    viewport: Rect::new(Point2D::new(x, 0), Size2D::new(32, 32)),
+                },
+                eye: Eye::None,
+                secondary: false,
+            };
+            MultiViewDevice(Views::Multi(vec![view(0), view(32), view(64)]))
+        }
+    }
+
+    impl Device for MultiViewDevice {
+        fn floor_transform(&self) -> RigidTransform3D<f32, Native, Floor> {
+            RigidTransform3D::identity()
+        }
+
+        fn views(&self) -> Views {
+            self.0.clone()
+        }
+
+        fn views_into(&self, views: &mut Views) {
+            views.clone_from(&self.0)
+        }
+
+        fn wait_for_animation_frame(&mut self) -> Frame {
+            unreachable!()
+        }
+
+        fn render_animation_frame(&mut self, _: u32, _: UntypedSize2D<i32>, _: GLsync) {}
+
+        fn granted_features(&self) -> Vec<Feature> {
+            vec![]
+        }
+
+        fn initial_inputs(&self) -> Vec<InputSource> {
+            vec![]
+        }
+
+        fn set_event_callback(&mut self, _callback: Box<dyn EventCallback>) {}
+
+        fn subscribe_events(&mut self, _filter: EventFilter, _callback: Box<dyn EventCallback>) {}
+
+        fn connected(&mut self) -> bool {
+            true
+        }
+
+        fn quit(&mut self) {}
+    }
+
+    #[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+    struct IgnoreMirror;
+
+    #[cfg_attr(feature = "ipc", typetag::serde)]
+    impl MirrorCallback for IgnoreMirror {
+        fn mirror(&mut self, _: GLuint, _: UntypedSize2D<i32>, _: Rect<i32, Viewport>, _: GLsync) {}
+    }
+
+    fn features(required: Vec<Feature>, optional: Vec<Feature>) -> SessionInit {
+        SessionInit {
+            required_features: required,
+            optional_features: optional,
+            ..SessionInit::default()
+        }
+    }
+
+    #[test]
+    fn sessions_get_their_default_features() {
+        let init = SessionInit::default();
+        assert_eq!(
+            init.validate(SessionMode::Inline, &[]).unwrap(),
+            vec![Feature::Viewer]
+        );
+        assert_eq!(
+            init.validate(SessionMode::ImmersiveVR, &[]).unwrap(),
+            vec![Feature::Viewer, Feature::Local]
+        );
+        // Asking for a default feature doesn't need the device to list it
+        let init = features(vec![Feature::Local], vec![Feature::Viewer]);
+        assert_eq!(
+            init.validate(SessionMode::ImmersiveAR, &[]).unwrap(),
+            vec![Feature::Viewer, Feature::Local]
+        );
+    }
+
+    #[test]
+    fn unsupported_required_features_fail() {
+        let init = features(vec![Feature::LocalFloor, Feature::HitTest], vec![]);
+        let supported = [Feature::LocalFloor];
+        assert_eq!(
+            init.validate(SessionMode::ImmersiveVR, &supported),
+            Err(Error::UnsupportedFeature)
+        );
+        // Local is only a default feature of immersive sessions
+        let init = features(vec![Feature::Local], vec![]);
+        assert_eq!(
+            init.validate(SessionMode::Inline, &[]),
+            Err(Error::UnsupportedFeature)
+        );
+    }
+
+    #[test]
+    fn unsupported_optional_features_are_not_granted() {
+        let init = features(
+            vec![Feature::LocalFloor],
+            vec![Feature::HitTest, Feature::Anchors, Feature::LocalFloor],
+        );
+        let supported = [Feature::LocalFloor, Feature::Anchors];
+        assert_eq!(
+            init.validate(SessionMode::ImmersiveVR, &supported).unwrap(),
+            vec![
+                Feature::Viewer,
+                Feature::Local,
+                Feature::LocalFloor,
+                Feature::Anchors
+            ]
+        );
+    }
+
+    #[test]
+    fn rendering_frames_does_not_allocate() {
+        let mut thread = SessionThread::new(MultiViewDevice::new()).unwrap();
+        let mirror = Some((
+            MirrorView::All,
+            Box::new(IgnoreMirror) as Box<dyn MirrorCallback>,
+        ));
+        thread.handle_msg(SessionMsg::SetMirror(mirror));
+        // The first frame sizes the buffers later frames reuse
+        thread.handle_msg(SessionMsg::RenderAnimationFrame);
+        let before = allocations();
+        thread.handle_msg(SessionMsg::RenderAnimationFrame);
+        thread.handle_msg(SessionMsg::RenderAnimationFrame);
+        assert_eq!(allocations(), before);
+    }
+}
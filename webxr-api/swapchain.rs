@@ -0,0 +1,388 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Swapchains, which are sets of the device's textures that content renders layers to,
+//! acquiring an image to render each frame and releasing it once rendered.
+
+use crate::Device;
+use crate::Error;
+use crate::TextureHandle;
+
+use euclid::default::Size2D;
+
+#[cfg(feature = "ipc")]
+use serde::{Deserialize, Serialize};
+
+/// An identifier for a swapchain, unique within its session
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub struct SwapchainId(pub u32);
+
+/// The description of a swapchain
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub struct SwapchainInit {
+    /// The size of each image, in pixels
+    pub size: Size2D<i32>,
+    /// The number of images content would like, which the device may change
+    pub image_count: usize,
+    pub format: TextureFormat,
+    /// How content will use the images, which they must be created to allow
+    pub usage: TextureUsage,
+    /// The number of layers of each image. Multiview rendering uses a layer per view,
+    /// for devices which support it, and otherwise the views are side by side in one.
+    pub array_size: usize,
+}
+
+/// How content renders the views of projection layers to swapchains, which the
+/// embedder can ask for when requesting a session, since compositors differ in
+/// which they prefer. The device reports the layout it chose.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub enum SwapchainLayout {
+    /// One swapchain wide enough for all the views, rendered to their viewports
+    Shared,
+    /// A swapchain for each view, the size of its viewport, which are given to
+    /// projection layers with `Session::set_layer_view_swapchains`
+    PerView,
+}
+
+/// The pixel format of a swapchain's images, named as in WebGPU
+/// https://gpuweb.github.io/gpuweb/#enumdef-gputextureformat
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub enum TextureFormat {
+    /// The format of WebGL's default framebuffer, and of GL swapchains
+    Rgba8Unorm,
+    Rgba8UnormSrgb,
+    Bgra8Unorm,
+    Bgra8UnormSrgb,
+    Rgba16Float,
+}
+
+/// The ways content can use a swapchain's images, named as in WebGPU, so content
+/// rendering with WebGPU can import them with the usage it needs
+/// https://gpuweb.github.io/gpuweb/#typedefdef-gputextureusageflags
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub struct TextureUsage(u8);
+
+impl TextureUsage {
+    pub const COPY_SRC: TextureUsage = TextureUsage(1 << 0);
+    pub const COPY_DST: TextureUsage = TextureUsage(1 << 1);
+    pub const TEXTURE_BINDING: TextureUsage = TextureUsage(1 << 2);
+    pub const RENDER_ATTACHMENT: TextureUsage = TextureUsage(1 << 3);
+
+    pub fn union(self, other: TextureUsage) -> TextureUsage {
+        TextureUsage(self.0 | other.0)
+    }
+
+    pub fn contains(&self, usage: TextureUsage) -> bool {
+        self.0 & usage.0 == usage.0
+    }
+}
+
+impl Default for SwapchainInit {
+    /// A swapchain which can be rendered to like a GL framebuffer
+    fn default() -> SwapchainInit {
+        SwapchainInit {
+            size: Size2D::zero(),
+            image_count: 3,
+            format: TextureFormat::Rgba8Unorm,
+            usage: TextureUsage::RENDER_ATTACHMENT,
+            array_size: 1,
+        }
+    }
+}
+
+/// A swapchain, as seen by content
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub struct Swapchain {
+    pub id: SwapchainId,
+    pub size: Size2D<i32>,
+    pub format: TextureFormat,
+    pub usage: TextureUsage,
+    /// The number of layers of each image. With more than one, each view is rendered
+    /// to the layer of the same index, at its viewport's size but at the origin.
+    pub array_size: usize,
+    /// The images, which content should import once and then render to by index
+    pub images: Vec<TextureHandle>,
+}
+
+struct SwapchainInfo {
+    id: SwapchainId,
+    size: Size2D<i32>,
+    array_size: usize,
+    // The image which was most recently released, if any
+    released: Option<usize>,
+}
+
+/// The swapchains belonging to a session, and which of their images are ready to display.
+pub(crate) struct SwapchainManager {
+    swapchains: Vec<SwapchainInfo>,
+    next_id: u32,
+}
+
+impl SwapchainManager {
+    pub(crate) fn new() -> SwapchainManager {
+        SwapchainManager {
+            swapchains: vec![],
+            next_id: 0,
+        }
+    }
+
+    pub(crate) fn create_swapchain<D: Device>(
+        &mut self,
+        device: &mut D,
+        init: SwapchainInit,
+    ) -> Result<Swapchain, Error> {
+        let id = SwapchainId(self.next_id);
+        let images = device.create_swapchain(id, init)?;
+        self.next_id += 1;
+        self.swapchains.push(SwapchainInfo {
+            id,
+            size: init.size,
+            array_size: init.array_size,
+            released: None,
+        });
+        Ok(Swapchain {
+            id,
+            size: init.size,
+            format: init.format,
+            usage: init.usage,
+            array_size: init.array_size,
+            images,
+        })
+    }
+
+    pub(crate) fn acquire_image<D: Device>(
+        &mut self,
+        device: &mut D,
+        id: SwapchainId,
+    ) -> Result<usize, Error> {
+        if !self.swapchains.iter().any(|swapchain| swapchain.id == id) {
+            return Err(Error::UnsupportedFeature);
+        }
+        device.acquire_swapchain_image(id)
+    }
+
+    pub(crate) fn release_image<D: Device>(
+        &mut self,
+        device: &mut D,
+        id: SwapchainId,
+        index: usize,
+    ) {
+        if let Some(swapchain) = self
+            .swapchains
+            .iter_mut()
+            .find(|swapchain| swapchain.id == id)
+        {
+            device.release_swapchain_image(id, index);
+            swapchain.released = Some(index);
+        }
+    }
+
+    pub(crate) fn destroy_swapchain<D: Device>(&mut self, device: &mut D, id: SwapchainId) {
+        if self.swapchains.iter().any(|swapchain| swapchain.id == id) {
+            self.swapchains.retain(|swapchain| swapchain.id != id);
+            device.destroy_swapchain(id);
+        }
+    }
+
+    pub(crate) fn contains(&self, id: SwapchainId) -> bool {
+        self.swapchains.iter().any(|swapchain| swapchain.id == id)
+    }
+
+    pub(crate) fn size(&self, id: SwapchainId) -> Option<Size2D<i32>> {
+        self.swapchains
+            .iter()
+            .find(|swapchain| swapchain.id == id)
+            .map(|swapchain| swapchain.size)
+    }
+
+    pub(crate) fn array_size(&self, id: SwapchainId) -> Option<usize> {
+        self.swapchains
+            .iter()
+            .find(|swapchain| swapchain.id == id)
+            .map(|swapchain| swapchain.array_size)
+    }
+
+    /// The image of a swapchain to display, which is the one most recently released
+    pub(crate) fn released_image(&self, id: SwapchainId) -> Option<usize> {
+        self.swapchains
+            .iter()
+            .find(|swapchain| swapchain.id == id)
+            .and_then(|swapchain| swapchain.released)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::EventCallback;
+    use crate::EventFilter;
+    use crate::Feature;
+    use crate::Floor;
+    use crate::Frame;
+    use crate::InputSource;
+    use crate::Native;
+    use crate::Views;
+
+    use euclid::RigidTransform3D;
+
+    use gleam::gl::GLsync;
+
+    use std::collections::VecDeque;
+
+    // A device whose swapchains hand out their free images in turn, failing once
+    // every image has been acquired, and which remembers which it destroyed
+    #[derive(Default)]
+    struct SwapchainDevice {
+        free: Vec<(SwapchainId, VecDeque<usize>)>,
+        destroyed: Vec<SwapchainId>,
+    }
+
+    impl SwapchainDevice {
+        fn free(&mut self, id: SwapchainId) -> Option<&mut VecDeque<usize>> {
+            self.free
+                .iter_mut()
+                .find(|(swapchain, _)| *swapchain == id)
+                .map(|(_, free)| free)
+        }
+    }
+
+    impl Device for SwapchainDevice {
+        fn floor_transform(&self) -> RigidTransform3D<f32, Native, Floor> {
+            RigidTransform3D::identity()
+        }
+
+        fn views(&self) -> Views {
+            unreachable!()
+        }
+
+        fn wait_for_animation_frame(&mut self) -> Frame {
+            unreachable!()
+        }
+
+        fn render_animation_frame(&mut self, _: u32, _: Size2D<i32>, _: GLsync) {}
+
+        fn create_swapchain(
+            &mut self,
+            id: SwapchainId,
+            init: SwapchainInit,
+        ) -> Result<Vec<TextureHandle>, Error> {
+            self.free.push((id, (0..init.image_count).collect()));
+            Ok((0..init.image_count)
+                .map(|index| TextureHandle::Gl(index as u32 + 1))
+                .collect())
+        }
+
+        fn acquire_swapchain_image(&mut self, id: SwapchainId) -> Result<usize, Error> {
+            self.free(id)
+                .and_then(|free| free.pop_front())
+                .ok_or_else(|| Error::backend("test", "no free images", None))
+        }
+
+        fn release_swapchain_image(&mut self, id: SwapchainId, index: usize) {
+            if let Some(free) = self.free(id) {
+                free.push_back(index);
+            }
+        }
+
+        fn destroy_swapchain(&mut self, id: SwapchainId) {
+            self.free.retain(|(swapchain, _)| *swapchain != id);
+            self.destroyed.push(id);
+        }
+
+        fn granted_features(&self) -> Vec<Feature> {
+            vec![]
+        }
+
+        fn initial_inputs(&self) -> Vec<InputSource> {
+            vec![]
+        }
+
+        fn set_event_callback(&mut self, _callback: Box<dyn EventCallback>) {}
+
+        fn subscribe_events(&mut self, _filter: EventFilter, _callback: Box<dyn EventCallback>) {}
+
+        fn connected(&mut self) -> bool {
+            true
+        }
+
+        fn quit(&mut self) {}
+    }
+
+    fn init(image_count: usize) -> SwapchainInit {
+        SwapchainInit {
+            size: Size2D::new(64, 32),
+            image_count,
+            ..SwapchainInit::default()
+        }
+    }
+
+    #[test]
+    fn released_images_are_displayed_in_order() {
+        let mut device = SwapchainDevice::default();
+        let mut swapchains = SwapchainManager::new();
+        let swapchain = swapchains.create_swapchain(&mut device, init(2)).unwrap();
+        let id = swapchain.id;
+        assert_eq!(swapchain.images.len(), 2);
+        assert_eq!(swapchains.size(id), Some(Size2D::new(64, 32)));
+        // Nothing is displayed until an image has been released
+        assert_eq!(swapchains.released_image(id), None);
+
+        let first = swapchains.acquire_image(&mut device, id).unwrap();
+        let second = swapchains.acquire_image(&mut device, id).unwrap();
+        assert_ne!(first, second);
+        swapchains.release_image(&mut device, id, first);
+        assert_eq!(swapchains.released_image(id), Some(first));
+        swapchains.release_image(&mut device, id, second);
+        assert_eq!(swapchains.released_image(id), Some(second));
+        assert_eq!(swapchains.acquire_image(&mut device, id).unwrap(), first);
+    }
+
+    #[test]
+    fn acquiring_fails_once_every_image_is_acquired() {
+        let mut device = SwapchainDevice::default();
+        let mut swapchains = SwapchainManager::new();
+        let id = swapchains
+            .create_swapchain(&mut device, init(1))
+            .unwrap()
+            .id;
+        let index = swapchains.acquire_image(&mut device, id).unwrap();
+        assert!(swapchains.acquire_image(&mut device, id).is_err());
+        swapchains.release_image(&mut device, id, index);
+        assert_eq!(swapchains.acquire_image(&mut device, id).unwrap(), index);
+    }
+
+    #[test]
+    fn destroyed_swapchains_are_forgotten() {
+        let mut device = SwapchainDevice::default();
+        let mut swapchains = SwapchainManager::new();
+        let first = swapchains
+            .create_swapchain(&mut device, init(2))
+            .unwrap()
+            .id;
+        let second = swapchains
+            .create_swapchain(&mut device, init(2))
+            .unwrap()
+            .id;
+        assert_ne!(first, second);
+
+        swapchains.destroy_swapchain(&mut device, first);
+        assert_eq!(device.destroyed, vec![first]);
+        assert!(!swapchains.contains(first));
+        assert!(swapchains.contains(second));
+        assert_eq!(
+            swapchains.acquire_image(&mut device, first).unwrap_err(),
+            Error::UnsupportedFeature
+        );
+        // Only swapchains the manager knows about are destroyed
+        swapchains.destroy_swapchain(&mut device, first);
+        assert_eq!(device.destroyed, vec![first]);
+    }
+}
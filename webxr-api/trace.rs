@@ -0,0 +1,49 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Spans around the stages of the frame pipeline, for finding where frame time goes.
+//!
+//! With the `trace` feature, each span is logged when it ends, at trace level with
+//! the `webxr::trace` target, with its name, thread and duration in microseconds.
+//! These can be forwarded to a tracing subscriber, and from there to a profiler.
+//! Without the feature, spans do nothing.
+
+#[cfg(feature = "trace")]
+use std::thread;
+#[cfg(feature = "trace")]
+use std::time::Instant;
+
+/// A stage of the frame pipeline, which lasts until this is dropped
+#[must_use]
+pub struct Span {
+    #[cfg(feature = "trace")]
+    name: &'static str,
+    #[cfg(feature = "trace")]
+    start: Instant,
+}
+
+/// Start a span, which should be named for the stage it covers, such as `wait_frame`
+#[inline]
+pub fn span(_name: &'static str) -> Span {
+    Span {
+        #[cfg(feature = "trace")]
+        name: _name,
+        #[cfg(feature = "trace")]
+        start: Instant::now(),
+    }
+}
+
+#[cfg(feature = "trace")]
+impl Drop for Span {
+    fn drop(&mut self) {
+        log::trace!(
+            target: "webxr::trace",
+            "span={} thread={:?} start={:?} duration_us={}",
+            self.name,
+            thread::current().id(),
+            self.start,
+            self.start.elapsed().as_micros(),
+        );
+    }
+}
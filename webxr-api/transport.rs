@@ -0,0 +1,240 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! The channels messages are sent over. These are in-process channels, or with
+//! the `ipc` feature, can be ipc-channels, which is chosen for each session.
+//!
+//! `Transport` is a closed set of channel kinds rather than a trait embedders
+//! implement. Sessions make channels for many message types, so a transport trait
+//! would need a generic method, which trait objects can't have, or generic associated
+//! types, which need a newer compiler than this crate supports. Erasing the message
+//! types instead would mean serializing them ourselves, which breaks sending the ends
+//! of ipc-channels inside messages. New kinds of channel are added as variants here.
+
+use crate::Error;
+
+use std::fmt;
+use std::sync::mpsc;
+#[cfg(feature = "ipc")]
+use std::thread;
+use std::time::Duration;
+
+#[cfg(feature = "ipc")]
+use ipc_channel::ipc::IpcReceiver;
+#[cfg(feature = "ipc")]
+use ipc_channel::ipc::IpcSender;
+
+#[cfg(feature = "ipc")]
+use serde::de::DeserializeOwned;
+#[cfg(feature = "ipc")]
+use serde::ser::Error as _;
+#[cfg(feature = "ipc")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// How a channel's messages are sent
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub enum Transport {
+    /// Channels within this process, which are cheaper, but whose
+    /// ends cannot be sent to another process
+    InProcess,
+    /// ipc-channels, whose ends can be sent to another process
+    #[cfg(feature = "ipc")]
+    Ipc,
+}
+
+impl Default for Transport {
+    /// Channels are ipc-channels if the `ipc` feature is enabled
+    fn default() -> Transport {
+        #[cfg(feature = "ipc")]
+        return Transport::Ipc;
+        #[cfg(not(feature = "ipc"))]
+        return Transport::InProcess;
+    }
+}
+
+impl Transport {
+    pub fn channel<T: Message>(self) -> Result<(Sender<T>, Receiver<T>), Error> {
+        match self {
+            Transport::InProcess => {
+                let (sender, receiver) = mpsc::channel();
+                Ok((
+                    Sender(SenderImpl::InProcess(sender)),
+                    Receiver(ReceiverImpl::InProcess(receiver)),
+                ))
+            }
+            #[cfg(feature = "ipc")]
+            Transport::Ipc => {
+                let (sender, receiver) =
+                    ipc_channel::ipc::channel().or(Err(Error::CommunicationError))?;
+                Ok((
+                    Sender(SenderImpl::Ipc(sender)),
+                    Receiver(ReceiverImpl::Ipc(receiver)),
+                ))
+            }
+        }
+    }
+}
+
+/// The types which can be sent over a channel, which with the
+/// `ipc` feature are those which can be serialized
+#[cfg(feature = "ipc")]
+pub trait Message: Serialize + DeserializeOwned {}
+
+#[cfg(feature = "ipc")]
+impl<T: Serialize + DeserializeOwned> Message for T {}
+
+/// The types which can be sent over a channel, which with the
+/// `ipc` feature are those which can be serialized
+#[cfg(not(feature = "ipc"))]
+pub trait Message {}
+
+#[cfg(not(feature = "ipc"))]
+impl<T> Message for T {}
+
+/// Make a channel with the default transport
+pub fn channel<T: Message>() -> Result<(Sender<T>, Receiver<T>), Error> {
+    Transport::default().channel()
+}
+
+pub struct Sender<T: Message>(SenderImpl<T>);
+
+enum SenderImpl<T: Message> {
+    InProcess(mpsc::Sender<T>),
+    #[cfg(feature = "ipc")]
+    Ipc(IpcSender<T>),
+}
+
+impl<T: Message> Sender<T> {
+    pub fn send(&self, msg: T) -> Result<(), Error> {
+        let _span = crate::span("channel_send");
+        match self.0 {
+            SenderImpl::InProcess(ref sender) => {
+                sender.send(msg).or(Err(Error::CommunicationError))
+            }
+            #[cfg(feature = "ipc")]
+            SenderImpl::Ipc(ref sender) => sender.send(msg).or(Err(Error::CommunicationError)),
+        }
+    }
+}
+
+impl<T: Message> Clone for Sender<T> {
+    fn clone(&self) -> Sender<T> {
+        match self.0 {
+            SenderImpl::InProcess(ref sender) => Sender(SenderImpl::InProcess(sender.clone())),
+            #[cfg(feature = "ipc")]
+            SenderImpl::Ipc(ref sender) => Sender(SenderImpl::Ipc(sender.clone())),
+        }
+    }
+}
+
+impl<T: Message> fmt::Debug for Sender<T> {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("Sender")
+    }
+}
+
+pub struct Receiver<T: Message>(ReceiverImpl<T>);
+
+enum ReceiverImpl<T: Message> {
+    InProcess(mpsc::Receiver<T>),
+    #[cfg(feature = "ipc")]
+    Ipc(IpcReceiver<T>),
+}
+
+impl<T: Message> Receiver<T> {
+    pub fn recv(&self) -> Result<T, Error> {
+        let _span = crate::span("channel_recv");
+        match self.0 {
+            ReceiverImpl::InProcess(ref receiver) => {
+                receiver.recv().or(Err(Error::CommunicationError))
+            }
+            #[cfg(feature = "ipc")]
+            ReceiverImpl::Ipc(ref receiver) => receiver.recv().or(Err(Error::CommunicationError)),
+        }
+    }
+
+    pub fn try_recv(&self) -> Result<T, Error> {
+        match self.0 {
+            ReceiverImpl::InProcess(ref receiver) => {
+                receiver.try_recv().or(Err(Error::CommunicationError))
+            }
+            #[cfg(feature = "ipc")]
+            ReceiverImpl::Ipc(ref receiver) => {
+                receiver.try_recv().or(Err(Error::CommunicationError))
+            }
+        }
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, Error> {
+        match self.0 {
+            ReceiverImpl::InProcess(ref receiver) => receiver
+                .recv_timeout(timeout)
+                .or(Err(Error::CommunicationError)),
+            #[cfg(feature = "ipc")]
+            ReceiverImpl::Ipc(ref receiver) => {
+                // Sigh, polling, sigh.
+                let mut delay = timeout / 1000;
+                while delay < timeout {
+                    if let Ok(msg) = receiver.try_recv() {
+                        return Ok(msg);
+                    }
+                    thread::sleep(delay);
+                    delay *= 2;
+                }
+                receiver.try_recv().or(Err(Error::CommunicationError))
+            }
+        }
+    }
+}
+
+impl<T: Message> fmt::Debug for Receiver<T> {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("Receiver")
+    }
+}
+
+pub fn recv_timeout<T: Message>(receiver: &Receiver<T>, timeout: Duration) -> Result<T, Error> {
+    receiver.recv_timeout(timeout)
+}
+
+// Only the ends of ipc-channels can be sent to another process
+
+#[cfg(feature = "ipc")]
+impl<T: Message> Serialize for Sender<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.0 {
+            SenderImpl::Ipc(ref sender) => sender.serialize(serializer),
+            SenderImpl::InProcess(_) => {
+                Err(S::Error::custom("Cannot serialize an in-process sender"))
+            }
+        }
+    }
+}
+
+#[cfg(feature = "ipc")]
+impl<'de, T: Message> Deserialize<'de> for Sender<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Sender<T>, D::Error> {
+        IpcSender::deserialize(deserializer).map(|sender| Sender(SenderImpl::Ipc(sender)))
+    }
+}
+
+#[cfg(feature = "ipc")]
+impl<T: Message> Serialize for Receiver<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.0 {
+            ReceiverImpl::Ipc(ref receiver) => receiver.serialize(serializer),
+            ReceiverImpl::InProcess(_) => {
+                Err(S::Error::custom("Cannot serialize an in-process receiver"))
+            }
+        }
+    }
+}
+
+#[cfg(feature = "ipc")]
+impl<'de, T: Message> Deserialize<'de> for Receiver<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Receiver<T>, D::Error> {
+        IpcReceiver::deserialize(deserializer).map(|receiver| Receiver(ReceiverImpl::Ipc(receiver)))
+    }
+}
@@ -0,0 +1,211 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Recording the frames of a session as video, for bug reports and capturing
+//! content. Frames are captured as the device displays them, cropped to a view
+//! and scaled, then handed to a callback which can encode them. Without the
+//! `ipc` feature, `Y4mWriter` writes them to an uncompressed video file.
+
+use crate::FrameCapture;
+use crate::HighResTimeStamp;
+use crate::MirrorView;
+use crate::Viewport;
+
+use euclid::Point2D;
+use euclid::Rect;
+use euclid::Size2D;
+
+#[cfg(not(feature = "ipc"))]
+use std::io;
+#[cfg(not(feature = "ipc"))]
+use std::io::Write;
+use std::time::Instant;
+
+#[cfg(feature = "ipc")]
+use serde::{Deserialize, Serialize};
+
+/// How to record a session's frames
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub struct VideoInit {
+    /// Which part of each frame to record
+    pub view: MirrorView,
+    /// The most frames to record each second
+    pub fps: f64,
+    /// The size to scale frames to, or None to keep the size they are displayed at
+    pub size: Option<Size2D<i32, Viewport>>,
+}
+
+/// Receives the frames of a video recording
+#[cfg_attr(feature = "ipc", typetag::serde)]
+pub trait VideoFrameCallback: 'static + Send {
+    /// A frame, with the time since recording started in milliseconds
+    fn frame(&mut self, time: HighResTimeStamp, frame: FrameCapture);
+
+    /// Recording stopped, either because it was asked to or the session ended
+    fn finish(&mut self) {}
+}
+
+/// A video recording in progress, on the session thread
+pub(crate) struct VideoRecorder {
+    init: VideoInit,
+    callback: Box<dyn VideoFrameCallback>,
+    started: Instant,
+    // When the next frame is due, in milliseconds since recording started
+    next: HighResTimeStamp,
+}
+
+impl VideoRecorder {
+    pub(crate) fn new(init: VideoInit, callback: Box<dyn VideoFrameCallback>) -> VideoRecorder {
+        VideoRecorder {
+            init,
+            callback,
+            started: Instant::now(),
+            next: 0.0,
+        }
+    }
+
+    fn time(&self) -> HighResTimeStamp {
+        let elapsed = self.started.elapsed();
+        elapsed.as_secs() as f64 * 1000.0 + f64::from(elapsed.subsec_nanos()) / 1_000_000.0
+    }
+
+    /// Whether the next frame rendered should be recorded
+    pub(crate) fn due(&self) -> bool {
+        self.time() >= self.next
+    }
+
+    /// Record a captured frame, whose views are in the given viewports
+    pub(crate) fn record(&mut self, capture: &FrameCapture, viewports: &[Rect<i32, Viewport>]) {
+        let time = self.time();
+        let interval = 1000.0 / self.init.fps.max(1.0);
+        self.next = (time / interval).floor() * interval + interval;
+        let all = MirrorView::All.source(viewports);
+        let (source, all) = match (self.init.view.source(viewports), all) {
+            (Some(source), Some(all)) if !all.is_empty() => (source, all),
+            _ => return,
+        };
+        // The capture is of all the views, at the size the device displayed them
+        let x_scale = capture.size.width as f32 / all.size.width as f32;
+        let y_scale = capture.size.height as f32 / all.size.height as f32;
+        let crop = Rect::new(
+            Point2D::new(
+                ((source.min_x() - all.min_x()) as f32 * x_scale) as i32,
+                ((source.min_y() - all.min_y()) as f32 * y_scale) as i32,
+            ),
+            Size2D::new(
+                (source.size.width as f32 * x_scale) as i32,
+                (source.size.height as f32 * y_scale) as i32,
+            ),
+        );
+        let size = self.init.size.unwrap_or(crop.size);
+        if size.width <= 0 || size.height <= 0 || crop.is_empty() {
+            return;
+        }
+        // Scale with the nearest pixel, keeping rows from the bottom
+        let mut data = Vec::with_capacity(size.area() as usize * 4);
+        for y in 0..size.height {
+            let source_y = crop.min_y() + y * crop.size.height / size.height;
+            for x in 0..size.width {
+                let source_x = crop.min_x() + x * crop.size.width / size.width;
+                let index = ((source_y * capture.size.width + source_x) * 4) as usize;
+                match capture.data.get(index..index + 4) {
+                    Some(pixel) => data.extend_from_slice(pixel),
+                    None => data.extend_from_slice(&[0, 0, 0, 255]),
+                }
+            }
+        }
+        self.callback.frame(time, FrameCapture { size, data });
+    }
+}
+
+impl Drop for VideoRecorder {
+    fn drop(&mut self) {
+        self.callback.finish();
+    }
+}
+
+/// Writes a video recording as an uncompressed YUV4MPEG2 file, which most video
+/// tools can play or convert. Content which renders slower than the video's frame
+/// rate has its frames repeated, so the video plays at the speed it was recorded.
+#[cfg(not(feature = "ipc"))]
+pub struct Y4mWriter<W: Write + Send + 'static> {
+    writer: W,
+    fps: f64,
+    // The size of the video, taken from its first frame
+    size: Option<Size2D<i32, Viewport>>,
+    // How many frames have been written
+    written: u64,
+    error: Option<io::Error>,
+}
+
+#[cfg(not(feature = "ipc"))]
+impl<W: Write + Send + 'static> Y4mWriter<W> {
+    /// Write a video playing at the given frame rate, which should match
+    /// the one it was recorded at
+    pub fn new(writer: W, fps: f64) -> Y4mWriter<W> {
+        Y4mWriter {
+            writer,
+            fps: fps.max(1.0),
+            size: None,
+            written: 0,
+            error: None,
+        }
+    }
+
+    /// The first error writing the video, after which nothing more is written
+    pub fn error(&self) -> Option<&io::Error> {
+        self.error.as_ref()
+    }
+
+    fn write_frame(&mut self, time: HighResTimeStamp, frame: &FrameCapture) -> io::Result<()> {
+        let size = *self.size.get_or_insert(frame.size);
+        if self.written == 0 {
+            let rate = (self.fps * 1000.0).round() as u64;
+            writeln!(
+                self.writer,
+                "YUV4MPEG2 W{} H{} F{}:1000 Ip A1:1 C444",
+                size.width, size.height, rate
+            )?;
+        }
+        // Frames of another size, from the window being resized, are left out
+        if frame.size != size {
+            return Ok(());
+        }
+        // Full range BT.601, with each plane's rows from the top
+        let mut planes = vec![0; size.area() as usize * 3];
+        let (luma, chroma) = planes.split_at_mut(size.area() as usize);
+        let (blue, red) = chroma.split_at_mut(size.area() as usize);
+        for (index, pixel) in frame.data.chunks_exact(4).enumerate() {
+            let (x, y) = (index as i32 % size.width, index as i32 / size.width);
+            let flipped = ((size.height - 1 - y) * size.width + x) as usize;
+            let (r, g, b) = (pixel[0] as f32, pixel[1] as f32, pixel[2] as f32);
+            luma[flipped] = (0.299 * r + 0.587 * g + 0.114 * b) as u8;
+            blue[flipped] = (128.0 - 0.168736 * r - 0.331264 * g + 0.5 * b) as u8;
+            red[flipped] = (128.0 + 0.5 * r - 0.418688 * g - 0.081312 * b) as u8;
+        }
+        let due = (time * self.fps / 1000.0).floor() as u64 + 1;
+        while self.written < due {
+            self.writer.write_all(b"FRAME\n")?;
+            self.writer.write_all(&planes)?;
+            self.written += 1;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "ipc"))]
+impl<W: Write + Send + 'static> VideoFrameCallback for Y4mWriter<W> {
+    fn frame(&mut self, time: HighResTimeStamp, frame: FrameCapture) {
+        if self.error.is_none() {
+            self.error = self.write_frame(time, &frame).err();
+        }
+    }
+
+    fn finish(&mut self) {
+        if self.error.is_none() {
+            self.error = self.writer.flush().err();
+        }
+    }
+}
@@ -4,8 +4,11 @@
 
 //! This crate uses `euclid`'s typed units, and exposes different coordinate spaces.
 
+use crate::Error;
+
 use euclid::Rect;
 use euclid::RigidTransform3D;
+use euclid::Size2D;
 use euclid::Transform3D;
 
 #[cfg(feature = "ipc")]
@@ -55,15 +58,82 @@ pub enum Display {}
 #[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
 pub enum Viewport {}
 
+/// The coordinate space of one of the views of a display with any number of views,
+/// such as a wall of a CAVE or an observer camera
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub enum Viewpoint {}
+
 /// The coordinate space of an input device
 #[derive(Clone, Copy, Debug)]
 #[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
 pub enum Input {}
 
+/// The coordinate space of a hand joint
+/// https://immersive-web.github.io/webxr-hand-input/#xrjointspace-interface
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub enum Joint {}
+
+/// The coordinate space of a camera
+/// https://immersive-web.github.io/raw-camera-access/#xr-camera-section
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub enum Camera {}
+
+/// The normalized coordinate space of a view, where the view is from (0,0) to (1,1)
+/// https://immersive-web.github.io/depth-sensing/#normalized-view-coordinates
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub enum NormalizedView {}
+
+/// The coordinate space of a depth buffer. Sizes are measured in depth values,
+/// positions are normalized so that the buffer is from (0,0) to (1,1).
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub enum DepthBuffer {}
+
+/// The coordinate space of a composition layer
+/// https://immersive-web.github.io/layers/#xrcompositionlayer
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub enum Layer {}
+
+/// The coordinate space of an anchor
+/// https://immersive-web.github.io/anchors/#dom-xranchor-anchorspace
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub enum Anchor {}
+
+/// The coordinate space of a tracked image
+/// https://immersive-web.github.io/marker-tracking/#dom-xrimagetrackingresult-imagespace
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub enum TrackedImage {}
+
+/// The coordinate space of a detected mesh
+/// https://immersive-web.github.io/real-world-meshing/#dom-xrmesh-meshspace
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub enum MeshSpace {}
+
+/// The coordinate space a hit test ray is given in, relative to its base space
+/// https://immersive-web.github.io/hit-test/#dom-xrhittestoptionsinit-space
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub enum ApiSpace {}
+
+/// The coordinate space of a hit test result, whose Y axis is the normal of the surface hit
+/// https://immersive-web.github.io/hit-test/#xrhittestresult-interface
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub enum HitTestSpace {}
+
 /// For each eye, the transform from the viewer to that eye,
 /// its projection onto its display, and its display viewport.
 /// For stereo displays, we have a `View<LeftEye>` and a `View<RightEye>`.
 /// For mono displays, we hagve a `View<Viewer>` (where the transform is the identity).
+/// For other displays, we have a `View<Viewpoint>` for each view.
 /// https://immersive-web.github.io/webxr/#xrview
 #[derive(Clone, Debug)]
 #[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
@@ -73,10 +143,341 @@ pub struct View<Eye> {
     pub viewport: Rect<i32, Viewport>,
 }
 
-/// Whether a device is mono or stereo, and the views it supports.
+/// Which eye a view is displayed to
+/// https://immersive-web.github.io/webxr/#enumdef-xreye
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub enum Eye {
+    None,
+    Left,
+    Right,
+}
+
+/// One of the views of a display with any number of views
 #[derive(Clone, Debug)]
 #[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub struct MultiView {
+    pub view: View<Viewpoint>,
+    pub eye: Eye,
+    /// Whether this view is secondary, such as an observer camera,
+    /// which content may choose not to render
+    /// https://immersive-web.github.io/webxr/#secondary-view
+    pub secondary: bool,
+}
+
+/// How content lays its views out in the textures it renders them to
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub enum ViewLayout {
+    /// Side by side in one texture, at each view's viewport
+    SideBySide,
+    /// Each view in the layer of a texture array with its index, as with multiview
+    /// rendering, for an array with this many layers
+    TextureArray(usize),
+    /// Each view in a texture of its own, such as a swapchain per view
+    /// or each wall of a CAVE, for this many textures
+    SeparateSurfaces(usize),
+}
+
+/// Which texture a view is rendered to
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub enum ViewTarget {
+    /// The texture shared by the views laid out side by side
+    SideBySide,
+    /// This layer of a texture array
+    ArrayLayer(usize),
+    /// The surface of this index, in view order
+    Surface(usize),
+}
+
+/// The rect one view is rendered to
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub struct ViewViewport {
+    /// The index of the view, in view order
+    pub view: usize,
+    pub eye: Eye,
+    /// Whether the view is secondary, which content may choose not to render
+    pub secondary: bool,
+    pub target: ViewTarget,
+    /// The rect of the target to render the view to
+    pub rect: Rect<i32, Viewport>,
+}
+
+/// Where to render each of any number of views
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub struct Viewports {
+    pub viewports: Vec<ViewViewport>,
+}
+
+impl Viewports {
+    pub fn len(&self) -> usize {
+        self.viewports.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.viewports.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.viewports.clear()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &ViewViewport> {
+        self.viewports.iter()
+    }
+
+    /// The viewport of the view with this index
+    pub fn get(&self, view: usize) -> Option<&ViewViewport> {
+        self.viewports.iter().find(|viewport| viewport.view == view)
+    }
+
+    /// The rects of the views, in view order
+    pub fn rects(&self) -> Vec<Rect<i32, Viewport>> {
+        self.viewports
+            .iter()
+            .map(|viewport| viewport.rect)
+            .collect()
+    }
+}
+
+/// Whether a device is mono or stereo, and the views it supports.
+#[derive(Debug)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
 pub enum Views {
     Mono(View<Viewer>),
     Stereo(View<LeftEye>, View<RightEye>),
+    /// Any number of views, in the order content should see them
+    Multi(Vec<MultiView>),
+}
+
+impl Clone for Views {
+    fn clone(&self) -> Views {
+        match *self {
+            Views::Mono(ref view) => Views::Mono(view.clone()),
+            Views::Stereo(ref left, ref right) => Views::Stereo(left.clone(), right.clone()),
+            Views::Multi(ref views) => Views::Multi(views.clone()),
+        }
+    }
+
+    // Reusing the buffer of multiple views, so devices can update them every frame
+    fn clone_from(&mut self, source: &Views) {
+        match (self, source) {
+            (Views::Multi(views), Views::Multi(source)) => views.clone_from(source),
+            (this, source) => *this = source.clone(),
+        }
+    }
+}
+
+impl<Eye> View<Eye> {
+    pub fn cast_unit<NewEye>(&self) -> View<NewEye> {
+        View {
+            transform: self.transform.cast_unit(),
+            projection: self.projection.with_source(),
+            viewport: self.viewport,
+        }
+    }
+}
+
+impl Views {
+    /// The views as a list, whatever shape the display is
+    pub fn to_multi(&self) -> Vec<MultiView> {
+        let primary = |view: View<Viewpoint>, eye| MultiView {
+            view,
+            eye,
+            secondary: false,
+        };
+        match *self {
+            Views::Mono(ref view) => vec![primary(view.cast_unit(), Eye::None)],
+            Views::Stereo(ref left, ref right) => vec![
+                primary(left.cast_unit(), Eye::Left),
+                primary(right.cast_unit(), Eye::Right),
+            ],
+            Views::Multi(ref views) => views.clone(),
+        }
+    }
+
+    /// The viewports of the views, in view order
+    pub fn viewports(&self) -> Vec<Rect<i32, Viewport>> {
+        match *self {
+            Views::Mono(ref view) => vec![view.viewport],
+            Views::Stereo(ref left, ref right) => vec![left.viewport, right.viewport],
+            Views::Multi(ref views) => views.iter().map(|view| view.view.viewport).collect(),
+        }
+    }
+
+    /// The viewports of the views, in view order, each scaled down by `scale`
+    /// while keeping its origin, and at least one pixel in size
+    /// https://immersive-web.github.io/webxr/#xrviewport-scale
+    pub fn scaled_viewports(&self, scale: f32) -> Vec<Rect<i32, Viewport>> {
+        let mut viewports = Vec::new();
+        self.scaled_viewports_into(scale, &mut viewports);
+        viewports
+    }
+
+    /// Whether the views can be rendered with the given layout, which fails with
+    /// `Error::NotEnoughViewTargets` if there are more views than array layers or surfaces
+    pub fn check_layout(&self, layout: ViewLayout) -> Result<(), Error> {
+        let count = match *self {
+            Views::Mono(_) => 1,
+            Views::Stereo(..) => 2,
+            Views::Multi(ref views) => views.len(),
+        };
+        match layout {
+            ViewLayout::TextureArray(targets) | ViewLayout::SeparateSurfaces(targets)
+                if targets < count =>
+            {
+                Err(Error::NotEnoughViewTargets)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Like `scaled_viewports`, but replacing the contents of `viewports`,
+    /// so its buffer can be reused
+    pub fn scaled_viewports_into(&self, scale: f32, viewports: &mut Vec<Rect<i32, Viewport>>) {
+        viewports.clear();
+        match *self {
+            Views::Mono(ref view) => viewports.push(scale_viewport(view.viewport, scale)),
+            Views::Stereo(ref left, ref right) => {
+                viewports.push(scale_viewport(left.viewport, scale));
+                viewports.push(scale_viewport(right.viewport, scale));
+            }
+            Views::Multi(ref views) => viewports.extend(
+                views
+                    .iter()
+                    .map(|view| scale_viewport(view.view.viewport, scale)),
+            ),
+        }
+    }
+
+    /// Where to render each view for the given layout, scaled down by `scale` as for
+    /// `scaled_viewports`, replacing the contents of `viewports`. Views rendered to
+    /// array layers or surfaces of their own are rendered from their target's origin.
+    /// Fails if there are more views than array layers or surfaces.
+    pub fn layout_viewports_into(
+        &self,
+        scale: f32,
+        layout: ViewLayout,
+        viewports: &mut Viewports,
+    ) -> Result<(), Error> {
+        self.check_layout(layout)?;
+        viewports.clear();
+        let mut push = |view, viewport, eye, secondary| {
+            let rect = scale_viewport(viewport, scale);
+            let (target, rect) = match layout {
+                ViewLayout::SideBySide => (ViewTarget::SideBySide, rect),
+                ViewLayout::TextureArray(_) => {
+                    (ViewTarget::ArrayLayer(view), Rect::from_size(rect.size))
+                }
+                ViewLayout::SeparateSurfaces(_) => {
+                    (ViewTarget::Surface(view), Rect::from_size(rect.size))
+                }
+            };
+            viewports.viewports.push(ViewViewport {
+                view,
+                eye,
+                secondary,
+                target,
+                rect,
+            });
+        };
+        match *self {
+            Views::Mono(ref view) => push(0, view.viewport, Eye::None, false),
+            Views::Stereo(ref left, ref right) => {
+                push(0, left.viewport, Eye::Left, false);
+                push(1, right.viewport, Eye::Right, false);
+            }
+            Views::Multi(ref views) => {
+                for (index, view) in views.iter().enumerate() {
+                    push(index, view.view.viewport, view.eye, view.secondary);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// A viewport scaled down while keeping its origin, and at least one pixel in size
+fn scale_viewport(viewport: Rect<i32, Viewport>, scale: f32) -> Rect<i32, Viewport> {
+    let width = ((viewport.size.width as f32) * scale).round() as i32;
+    let height = ((viewport.size.height as f32) * scale).round() as i32;
+    Rect::new(viewport.origin, Size2D::new(width.max(1), height.max(1)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use euclid::Point2D;
+
+    fn view(x: i32, width: i32) -> MultiView {
+        MultiView {
+            view: View {
+                transform: RigidTransform3D::identity(),
+                projection: Transform3D::identity(),
+                viewport: Rect::new(Point2D::new(x, 0), Size2D::new(width, 50)),
+            },
+            eye: Eye::None,
+            secondary: true,
+        }
+    }
+
+    fn three_views() -> Views {
+        let mut left = view(0, 100);
+        left.eye = Eye::Left;
+        left.secondary = false;
+        let mut right = view(100, 100);
+        right.eye = Eye::Right;
+        right.secondary = false;
+        Views::Multi(vec![left, right, view(200, 40)])
+    }
+
+    #[test]
+    fn multi_views_side_by_side() {
+        let mut viewports = Viewports::default();
+        three_views()
+            .layout_viewports_into(0.5, ViewLayout::SideBySide, &mut viewports)
+            .unwrap();
+        assert_eq!(viewports.len(), 3);
+        let second = viewports.viewports[1];
+        assert_eq!(second.view, 1);
+        assert_eq!(second.eye, Eye::Right);
+        assert_eq!(second.target, ViewTarget::SideBySide);
+        assert_eq!(
+            second.rect,
+            Rect::new(Point2D::new(100, 0), Size2D::new(50, 25))
+        );
+        assert!(viewports.viewports[2].secondary);
+    }
+
+    #[test]
+    fn multi_views_in_texture_array() {
+        let mut viewports = Viewports::default();
+        three_views()
+            .layout_viewports_into(1.0, ViewLayout::TextureArray(3), &mut viewports)
+            .unwrap();
+        let third = viewports.viewports[2];
+        assert_eq!(third.target, ViewTarget::ArrayLayer(2));
+        assert_eq!(third.rect, Rect::from_size(Size2D::new(40, 50)));
+    }
+
+    #[test]
+    fn too_few_targets_for_views() {
+        let views = three_views();
+        let mut viewports = Viewports::default();
+        let layout = ViewLayout::SeparateSurfaces(2);
+        match views.layout_viewports_into(1.0, layout, &mut viewports) {
+            Err(Error::NotEnoughViewTargets) => {}
+            result => panic!("Expected NotEnoughViewTargets, got {:?}", result),
+        }
+        assert_eq!(
+            views.check_layout(ViewLayout::TextureArray(2)),
+            Err(Error::NotEnoughViewTargets)
+        );
+        assert_eq!(views.check_layout(ViewLayout::TextureArray(3)), Ok(()));
+        assert_eq!(views.check_layout(ViewLayout::SideBySide), Ok(()));
+    }
 }
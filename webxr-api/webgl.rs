@@ -5,17 +5,86 @@
 //! The WebGL functionality needed by WebXR.
 
 use crate::Error;
+use crate::LayerId;
 use euclid::default::Size2D;
 use gleam::gl::GLsync;
 use gleam::gl::GLuint;
 
-/// A trait to get access a GL texture from a WebGL context.
+#[cfg(feature = "ipc")]
+use serde::{Deserialize, Serialize};
+
+/// A trait to get access a GL texture from a WebGL context, or the
+/// platform texture content rendered to.
+/// Implementations should implement at least one of `lock` and `lock_handle`.
 #[cfg_attr(feature = "ipc", typetag::serde)]
 pub trait WebGLExternalImageApi: 'static + Send {
     /// Lock the WebGL context, and get back a texture id, the size of the texture,
     /// and a sync object for the texture.
-    fn lock(&self) -> Result<(GLuint, Size2D<i32>, GLsync), Error>;
+    fn lock(&self) -> Result<(GLuint, Size2D<i32>, GLsync), Error> {
+        Err(Error::UnsupportedFeature)
+    }
+
+    /// Lock the texture, and get back a handle to it, its size, and a sync object
+    /// for it, which is null for textures that aren't GL's. Embedders which don't
+    /// render with GL can implement this rather than `lock`, returning a handle
+    /// such as a D3D11 texture or an IOSurface. The default returns the GL texture
+    /// from `lock`. Devices are given the texture by its handle.
+    fn lock_handle(&self) -> Result<(TextureHandle, Size2D<i32>, GLsync), Error> {
+        self.lock()
+            .map(|(texture_id, size, sync)| (TextureHandle::Gl(texture_id), size, sync))
+    }
 
     /// Unlock the WebGL context.
     fn unlock(&self);
+
+    /// Unlock the WebGL context, once the GPU has finished with the texture, which is
+    /// when `sync` is signalled. Content should wait for it with glWaitSync before
+    /// rendering to the texture again. The default unlocks straight away, which
+    /// suits implementations whose unlocking already waits for the device.
+    fn unlock_with_sync(&self, _sync: GLsync) {
+        self.unlock()
+    }
+
+    /// Whether the locked texture is the device image content was given
+    /// for the layer this frame, rather than content's own texture.
+    fn is_device_image(&self) -> bool {
+        false
+    }
+}
+
+/// A handle to a texture, which another GL context or process can import
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub enum TextureHandle {
+    /// A GL texture, in a context which shares textures with content's
+    Gl(GLuint),
+    /// A pointer to an `ID3D11Texture2D`, on the device's D3D11 device,
+    /// for use within the same process
+    D3D11Texture(usize),
+    /// A DXGI shared handle, as made by `IDXGIResource1::CreateSharedHandle`,
+    /// which can be opened by D3D11 or D3D12, and so by wgpu
+    Dxgi(u64),
+    /// An `EGLImage`, which can be bound with `glEGLImageTargetTexture2DOES`
+    EglImage(usize),
+    /// An IOSurface, which can be imported into Metal, and so into wgpu
+    IoSurface(u32),
+    /// A dma-buf, which can be imported with `EGL_EXT_image_dma_buf_import`,
+    /// or into Vulkan, and so into wgpu, with `VK_EXT_external_memory_dma_buf`
+    DmaBuf {
+        fd: i32,
+        fourcc: u32,
+        modifier: u64,
+        offset: u32,
+        stride: u32,
+    },
+}
+
+/// A texture of the device's, such as one from its swapchain, which content
+/// can render a layer to directly, sparing the device from copying it
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+pub struct DeviceImage {
+    pub layer: LayerId,
+    pub handle: TextureHandle,
+    pub size: Size2D<i32>,
 }
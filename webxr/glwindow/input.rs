@@ -0,0 +1,582 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Emulating a moving viewer and a controller with the keyboard and mouse.
+//!
+//! The keyboard and mouse move the viewer, as described by `GlWindowCamera`.
+//! Tab toggles a controller, whose target ray follows the mouse pointer, with the
+//! left and right mouse buttons performing select and squeeze. A physical
+//! gamepad can drive a second controller, as described by `GlWindowGamepad`.
+//! Touching the window, as on a phone in handheld AR, adds a transient screen
+//! input source for the duration of the tap, which selects when it lifts.
+
+use euclid::default::Point2D as UntypedPoint2D;
+use euclid::Angle;
+use euclid::RigidTransform3D;
+use euclid::Rotation3D;
+use euclid::Vector3D;
+
+use glutin::MouseButton;
+use glutin::VirtualKeyCode;
+
+use std::f32::consts::FRAC_PI_2;
+use std::mem;
+use std::time::Instant;
+
+use webxr_api::Gamepad;
+use webxr_api::GamepadButton;
+use webxr_api::GamepadMapping;
+use webxr_api::Handedness;
+use webxr_api::Input;
+use webxr_api::InputFrame;
+use webxr_api::InputId;
+use webxr_api::InputSource;
+use webxr_api::Native;
+use webxr_api::SelectEvent;
+use webxr_api::SelectKind;
+use webxr_api::TargetRayMode;
+use webxr_api::Viewer;
+
+// Meters per second
+const MOVE_SPEED: f32 = 1.0;
+// How much holding shift speeds up moving, and control slows it down
+const FAST_SPEED_FACTOR: f32 = 4.0;
+const SLOW_SPEED_FACTOR: f32 = 0.25;
+// Radians per pixel
+const TURN_SPEED: f32 = 0.005;
+// Meters
+const MIN_ORBIT_DISTANCE: f32 = 0.1;
+// Meters per second and radians per second at full stick
+const GAMEPAD_MOVE_SPEED: f32 = 0.5;
+const GAMEPAD_TURN_SPEED: f32 = 2.0;
+// Stick positions nearer the center than this count as the center
+const DEAD_ZONE: f32 = 0.15;
+// How far a trigger must be pressed to start an action, and released to complete it
+const PRESS_THRESHOLD: f32 = 0.6;
+const RELEASE_THRESHOLD: f32 = 0.4;
+const EPSILON: f32 = 1e-6;
+
+/// How the keyboard and mouse move the viewer. In each of these, dragging
+/// with the left mouse button turns the viewer, and holding shift or control
+/// moves faster or slower.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum GlWindowCamera {
+    /// W, A, S and D walk along the floor
+    Walk,
+    /// W, A, S and D move in the direction the viewer is looking,
+    /// and Q and E move down and up
+    Fly,
+    /// The viewer circles the origin, looking at it,
+    /// and W and S move towards and away from it
+    Orbit,
+}
+
+/// Keyboard and mouse input to the window, used to emulate XR input
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GlWindowEvent {
+    KeyDown(VirtualKeyCode),
+    KeyUp(VirtualKeyCode),
+    /// The mouse pointer moved, in pixels from the top left of the window
+    MouseMove(UntypedPoint2D<f32>),
+    MouseDown(MouseButton),
+    MouseUp(MouseButton),
+    /// A finger touched the screen, in pixels from the top left of the window.
+    /// Only one touch is tracked at a time.
+    TouchStart(UntypedPoint2D<f32>),
+    /// The finger touching the screen moved
+    TouchMove(UntypedPoint2D<f32>),
+    /// The finger lifted from the screen, which selects
+    TouchEnd,
+    /// The touch was interrupted, such as by a system gesture, without selecting
+    TouchCancel,
+    /// The window was closed, which ends the session
+    Close,
+}
+
+/// The state of a physical gamepad, such as an Xbox controller,
+/// which is used to emulate a second controller. The left stick moves
+/// it, the right stick turns it, and the right and left triggers
+/// perform select and squeeze. The triggers and left stick are reported
+/// as the controller's xr-standard trigger, squeeze and thumbstick.
+///
+/// Gamepads which can sense touch report it separately from pressing. For ones
+/// which can't, the triggers count as touched while they are pulled at all, and
+/// the stick while it is pushed or clicked.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct GlWindowGamepad {
+    /// The stick positions, from -1.0 to 1.0, with positive x to the right and y up
+    pub left_stick: [f32; 2],
+    pub right_stick: [f32; 2],
+    /// How far the triggers are pressed, from 0.0 to 1.0
+    pub left_trigger: f32,
+    pub right_trigger: f32,
+    /// Whether the left stick is clicked down
+    pub left_stick_pressed: bool,
+    /// Whether the user is touching the left stick and triggers, for gamepads which can tell
+    pub left_stick_touched: bool,
+    pub left_trigger_touched: bool,
+    pub right_trigger_touched: bool,
+}
+
+// A controller driven by a physical gamepad
+struct GamepadController {
+    // The position of the controller relative to the viewer
+    offset: Vector3D<f32, Viewer>,
+    yaw: f32,
+    pitch: f32,
+    state: GlWindowGamepad,
+    // The actions the controller is in the middle of
+    pressed: Vec<SelectKind>,
+}
+
+// A finger touching the screen
+struct ScreenTouch {
+    point: UntypedPoint2D<f32>,
+    // Whether the finger has lifted, so the input source goes once its
+    // last select event has been reported
+    ended: bool,
+}
+
+/// A change to an emulated controller, to be reported as an event
+pub(super) enum InputAction {
+    Add(InputSource),
+    Remove(InputId),
+    Select(InputId, SelectKind, SelectEvent),
+}
+
+pub(super) struct InputEmulator {
+    position: Vector3D<f32, Native>,
+    yaw: f32,
+    pitch: f32,
+    // The movement keys being held
+    keys: Vec<VirtualKeyCode>,
+    cursor: Option<UntypedPoint2D<f32>>,
+    // Whether the viewer is being turned by dragging the mouse
+    turning: bool,
+    controller: bool,
+    // The actions the controller is in the middle of
+    pressed: Vec<SelectKind>,
+    gamepad: Option<GamepadController>,
+    touch: Option<ScreenTouch>,
+    actions: Vec<InputAction>,
+    last_update: Instant,
+}
+
+impl InputEmulator {
+    pub(super) fn new(position: Vector3D<f32, Native>) -> InputEmulator {
+        InputEmulator {
+            position,
+            yaw: 0.0,
+            pitch: 0.0,
+            keys: vec![],
+            cursor: None,
+            turning: false,
+            controller: false,
+            pressed: vec![],
+            gamepad: None,
+            touch: None,
+            actions: vec![],
+            last_update: Instant::now(),
+        }
+    }
+
+    pub(super) fn handle_event(&mut self, event: GlWindowEvent) {
+        match event {
+            GlWindowEvent::KeyDown(VirtualKeyCode::Tab) => self.toggle_controller(),
+            GlWindowEvent::KeyDown(key) => {
+                if is_movement_key(key) && !self.keys.contains(&key) {
+                    self.keys.push(key);
+                }
+            }
+            GlWindowEvent::KeyUp(key) => self.keys.retain(|held| *held != key),
+            GlWindowEvent::MouseMove(position) => {
+                if let (true, Some(last)) = (self.turning, self.cursor) {
+                    self.yaw -= (position.x - last.x) * TURN_SPEED;
+                    self.pitch -= (position.y - last.y) * TURN_SPEED;
+                    self.pitch = self.pitch.max(-FRAC_PI_2).min(FRAC_PI_2);
+                }
+                self.cursor = Some(position);
+            }
+            GlWindowEvent::MouseDown(button) => match select_kind(button) {
+                Some(kind) if self.controller => self.press(kind),
+                Some(SelectKind::Select) => self.turning = true,
+                _ => (),
+            },
+            GlWindowEvent::MouseUp(button) => match select_kind(button) {
+                Some(kind) if self.pressed.contains(&kind) => {
+                    self.pressed.retain(|pressed| *pressed != kind);
+                    self.select(kind, SelectEvent::Select);
+                }
+                Some(SelectKind::Select) => self.turning = false,
+                _ => (),
+            },
+            GlWindowEvent::TouchStart(point) => {
+                if self.touch.is_none() {
+                    self.touch = Some(ScreenTouch {
+                        point,
+                        ended: false,
+                    });
+                    self.actions.push(InputAction::Add(InputSource {
+                        handedness: Handedness::None,
+                        target_ray_mode: TargetRayMode::Screen,
+                        id: SCREEN_ID,
+                        profiles: vec!["generic-touchscreen".into()],
+                        has_grip: false,
+                    }));
+                    self.actions.push(InputAction::Select(
+                        SCREEN_ID,
+                        SelectKind::Select,
+                        SelectEvent::Start,
+                    ));
+                }
+            }
+            GlWindowEvent::TouchMove(point) => match self.touch {
+                Some(ref mut touch) if !touch.ended => touch.point = point,
+                _ => (),
+            },
+            GlWindowEvent::TouchEnd | GlWindowEvent::TouchCancel => match self.touch {
+                Some(ref mut touch) if !touch.ended => {
+                    touch.ended = true;
+                    let event = if event == GlWindowEvent::TouchEnd {
+                        SelectEvent::Select
+                    } else {
+                        SelectEvent::End
+                    };
+                    self.actions
+                        .push(InputAction::Select(SCREEN_ID, SelectKind::Select, event));
+                }
+                _ => (),
+            },
+            GlWindowEvent::Close => (),
+        }
+    }
+
+    /// Move the viewer by the keys held since the last update, and the gamepad
+    /// controller by the gamepad's state, returning the viewer's pose
+    pub(super) fn update(
+        &mut self,
+        camera: GlWindowCamera,
+        gamepad: Option<GlWindowGamepad>,
+    ) -> RigidTransform3D<f32, Viewer, Native> {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_update);
+        let elapsed = elapsed.as_secs() as f32 + elapsed.subsec_nanos() as f32 / 1e9;
+        self.last_update = now;
+        self.update_gamepad(gamepad, elapsed);
+
+        let mut movement = Vector3D::zero();
+        let mut speed = MOVE_SPEED;
+        for key in &self.keys {
+            movement += match key {
+                VirtualKeyCode::W => Vector3D::new(0.0, 0.0, -1.0),
+                VirtualKeyCode::S => Vector3D::new(0.0, 0.0, 1.0),
+                VirtualKeyCode::A => Vector3D::new(-1.0, 0.0, 0.0),
+                VirtualKeyCode::D => Vector3D::new(1.0, 0.0, 0.0),
+                VirtualKeyCode::Q => Vector3D::new(0.0, -1.0, 0.0),
+                VirtualKeyCode::E => Vector3D::new(0.0, 1.0, 0.0),
+                VirtualKeyCode::LShift | VirtualKeyCode::RShift => {
+                    speed *= FAST_SPEED_FACTOR;
+                    Vector3D::zero()
+                }
+                VirtualKeyCode::LControl | VirtualKeyCode::RControl => {
+                    speed *= SLOW_SPEED_FACTOR;
+                    Vector3D::zero()
+                }
+                _ => Vector3D::zero(),
+            };
+        }
+        if camera == GlWindowCamera::Walk {
+            // Walk along the floor, whichever way the viewer is looking up or down
+            movement.y = 0.0;
+        }
+        if movement.length() > EPSILON {
+            movement = movement.normalize() * speed * elapsed;
+        }
+
+        let heading: Rotation3D<f32, Viewer, Native> =
+            Rotation3D::around_y(Angle::radians(self.yaw));
+        let rotation = Rotation3D::around_x(Angle::radians(self.pitch)).post_rotate(&heading);
+        match camera {
+            GlWindowCamera::Walk => {
+                self.position += heading.transform_vector3d(movement);
+            }
+            GlWindowCamera::Fly => {
+                self.position += rotation.transform_vector3d(movement);
+            }
+            GlWindowCamera::Orbit => {
+                // Moving forwards and backwards moves towards and away from the origin,
+                // from behind which the viewer looks at it
+                let distance = (self.position.length() + movement.z).max(MIN_ORBIT_DISTANCE);
+                self.position = rotation.transform_vector3d(Vector3D::new(0.0, 0.0, distance));
+            }
+        }
+
+        RigidTransform3D::new(rotation, self.position)
+    }
+
+    /// The window pixel the mouse pointer is over, if it has been in the window
+    pub(super) fn cursor(&self) -> Option<UntypedPoint2D<f32>> {
+        self.cursor
+    }
+
+    /// The window pixel being touched, if there is a touch
+    pub(super) fn touch(&self) -> Option<UntypedPoint2D<f32>> {
+        self.touch.as_ref().map(|touch| touch.point)
+    }
+
+    /// The input frames of the active controllers. The mouse controller's and the
+    /// screen touch's target rays start at the viewer and go in the given directions,
+    /// relative to the viewer.
+    pub(super) fn input_frames(
+        &self,
+        viewer: &RigidTransform3D<f32, Viewer, Native>,
+        direction: Option<Vector3D<f32, Viewer>>,
+        touch_direction: Option<Vector3D<f32, Viewer>>,
+    ) -> Vec<InputFrame> {
+        let mut frames = vec![];
+        if self.controller {
+            let rotation = direction
+                .map(rotation_from_forward)
+                .unwrap_or_else(Rotation3D::identity);
+            let target_ray_origin =
+                RigidTransform3D::from_rotation(rotation).post_transform(viewer);
+            frames.push(InputFrame {
+                id: INPUT_ID,
+                target_ray_origin,
+                grip_origin: None,
+                velocity: Default::default(),
+                gamepad: None,
+                hand: None,
+            });
+        }
+        if let Some(ref gamepad) = self.gamepad {
+            let rotation: Rotation3D<f32, Input, Viewer> =
+                Rotation3D::<_, Input, Input>::around_x(Angle::radians(gamepad.pitch))
+                    .post_rotate(&Rotation3D::around_y(Angle::radians(gamepad.yaw)));
+            let target_ray_origin =
+                RigidTransform3D::new(rotation, gamepad.offset).post_transform(viewer);
+            frames.push(InputFrame {
+                id: GAMEPAD_ID,
+                target_ray_origin,
+                grip_origin: Some(target_ray_origin),
+                velocity: Default::default(),
+                gamepad: Some(gamepad.xr_gamepad()),
+                hand: None,
+            });
+        }
+        if self.touch.is_some() {
+            let rotation = touch_direction
+                .map(rotation_from_forward)
+                .unwrap_or_else(Rotation3D::identity);
+            let target_ray_origin =
+                RigidTransform3D::from_rotation(rotation).post_transform(viewer);
+            frames.push(InputFrame {
+                id: SCREEN_ID,
+                target_ray_origin,
+                grip_origin: None,
+                velocity: Default::default(),
+                gamepad: None,
+                hand: None,
+            });
+        }
+        frames
+    }
+
+    pub(super) fn take_actions(&mut self) -> Vec<InputAction> {
+        // A lifted touch's source goes after its select event, whose frame includes it
+        if self.touch.as_ref().map_or(false, |touch| touch.ended) {
+            self.touch = None;
+            self.actions.push(InputAction::Remove(SCREEN_ID));
+        }
+        mem::replace(&mut self.actions, Vec::new())
+    }
+
+    fn toggle_controller(&mut self) {
+        if self.controller {
+            // Any actions in progress end without completing
+            for kind in mem::replace(&mut self.pressed, Vec::new()) {
+                self.select(kind, SelectEvent::End);
+            }
+            self.actions.push(InputAction::Remove(INPUT_ID));
+        } else {
+            // The left mouse button selects rather than turns while there is a controller
+            self.turning = false;
+            self.actions.push(InputAction::Add(InputSource {
+                handedness: Handedness::Right,
+                target_ray_mode: TargetRayMode::TrackedPointer,
+                id: INPUT_ID,
+                profiles: vec!["generic-trigger-squeeze".into()],
+                // The controller follows the mouse from the viewer's head, so it isn't held
+                has_grip: false,
+            }));
+        }
+        self.controller = !self.controller;
+    }
+
+    fn update_gamepad(&mut self, state: Option<GlWindowGamepad>, elapsed: f32) {
+        let state = match state {
+            Some(state) => state,
+            None => {
+                if let Some(gamepad) = self.gamepad.take() {
+                    // Any actions in progress end without completing
+                    for kind in gamepad.pressed {
+                        self.actions
+                            .push(InputAction::Select(GAMEPAD_ID, kind, SelectEvent::End));
+                    }
+                    self.actions.push(InputAction::Remove(GAMEPAD_ID));
+                }
+                return;
+            }
+        };
+        let gamepad = match self.gamepad {
+            Some(ref mut gamepad) => gamepad,
+            None => {
+                self.actions.push(InputAction::Add(InputSource {
+                    handedness: Handedness::Left,
+                    target_ray_mode: TargetRayMode::TrackedPointer,
+                    id: GAMEPAD_ID,
+                    profiles: vec!["generic-trigger-squeeze-thumbstick".into()],
+                    has_grip: true,
+                }));
+                self.gamepad.get_or_insert(GamepadController {
+                    offset: Vector3D::new(-0.2, -0.3, -0.4),
+                    yaw: 0.0,
+                    pitch: 0.0,
+                    state,
+                    pressed: vec![],
+                })
+            }
+        };
+
+        let [x, y] = state.left_stick;
+        let movement = Vector3D::new(dead_zone(x), 0.0, -dead_zone(y));
+        gamepad.offset += movement * GAMEPAD_MOVE_SPEED * elapsed;
+        let [x, y] = state.right_stick;
+        gamepad.yaw -= dead_zone(x) * GAMEPAD_TURN_SPEED * elapsed;
+        gamepad.pitch += dead_zone(y) * GAMEPAD_TURN_SPEED * elapsed;
+        gamepad.pitch = gamepad.pitch.max(-FRAC_PI_2).min(FRAC_PI_2);
+
+        let triggers = [
+            (SelectKind::Select, state.right_trigger),
+            (SelectKind::Squeeze, state.left_trigger),
+        ];
+        for (kind, value) in triggers {
+            let pressed = gamepad.pressed.contains(&kind);
+            if !pressed && value >= PRESS_THRESHOLD {
+                gamepad.pressed.push(kind);
+                self.actions
+                    .push(InputAction::Select(GAMEPAD_ID, kind, SelectEvent::Start));
+            } else if pressed && value < RELEASE_THRESHOLD {
+                gamepad.pressed.retain(|pressed| *pressed != kind);
+                self.actions
+                    .push(InputAction::Select(GAMEPAD_ID, kind, SelectEvent::Select));
+            }
+        }
+        gamepad.state = state;
+    }
+
+    fn press(&mut self, kind: SelectKind) {
+        if !self.pressed.contains(&kind) {
+            self.pressed.push(kind);
+            self.select(kind, SelectEvent::Start);
+        }
+    }
+
+    fn select(&mut self, kind: SelectKind, event: SelectEvent) {
+        self.actions
+            .push(InputAction::Select(INPUT_ID, kind, event));
+    }
+}
+
+impl GamepadController {
+    // The gamepad's state, using the xr-standard mapping
+    fn xr_gamepad(&self) -> Gamepad {
+        let trigger = |kind, value, touched| GamepadButton {
+            pressed: self.pressed.contains(&kind),
+            touched: touched || value > 0.0,
+            value,
+        };
+        let [x, y] = self.state.left_stick;
+        let pushed = dead_zone(x) != 0.0 || dead_zone(y) != 0.0;
+        let pressed = self.state.left_stick_pressed;
+        let thumbstick = GamepadButton {
+            pressed,
+            touched: self.state.left_stick_touched || pressed || pushed,
+            value: if pressed { 1.0 } else { 0.0 },
+        };
+        Gamepad {
+            mapping: GamepadMapping::XrStandard,
+            buttons: vec![
+                trigger(
+                    SelectKind::Select,
+                    self.state.right_trigger,
+                    self.state.right_trigger_touched,
+                ),
+                trigger(
+                    SelectKind::Squeeze,
+                    self.state.left_trigger,
+                    self.state.left_trigger_touched,
+                ),
+                // There is no touchpad, so its place is left empty
+                GamepadButton::default(),
+                thumbstick,
+            ],
+            // The xr-standard thumbstick is the second pair of axes, with y down
+            axes: vec![0.0, 0.0, dead_zone(x), -dead_zone(y)],
+        }
+    }
+}
+
+const INPUT_ID: InputId = InputId(0);
+const GAMEPAD_ID: InputId = InputId(1);
+const SCREEN_ID: InputId = InputId(2);
+
+fn dead_zone(value: f32) -> f32 {
+    if value.abs() < DEAD_ZONE {
+        0.0
+    } else {
+        value
+    }
+}
+
+fn is_movement_key(key: VirtualKeyCode) -> bool {
+    match key {
+        VirtualKeyCode::W
+        | VirtualKeyCode::A
+        | VirtualKeyCode::S
+        | VirtualKeyCode::D
+        | VirtualKeyCode::Q
+        | VirtualKeyCode::E
+        | VirtualKeyCode::LShift
+        | VirtualKeyCode::RShift
+        | VirtualKeyCode::LControl
+        | VirtualKeyCode::RControl => true,
+        _ => false,
+    }
+}
+
+fn select_kind(button: MouseButton) -> Option<SelectKind> {
+    match button {
+        MouseButton::Left => Some(SelectKind::Select),
+        MouseButton::Right => Some(SelectKind::Squeeze),
+        _ => None,
+    }
+}
+
+// The rotation taking the -Z axis, which target rays point along, to the given direction
+fn rotation_from_forward(direction: Vector3D<f32, Viewer>) -> Rotation3D<f32, Input, Viewer> {
+    let forward = Vector3D::new(0.0, 0.0, -1.0);
+    let direction = direction.normalize();
+    let axis = forward.cross(direction);
+    if axis.length() < EPSILON {
+        if direction.z < 0.0 {
+            Rotation3D::identity()
+        } else {
+            Rotation3D::around_y(Angle::pi())
+        }
+    } else {
+        let angle = Angle::radians(forward.dot(direction).max(-1.0).min(1.0).acos());
+        Rotation3D::around_axis(axis.cast_unit(), angle)
+    }
+}
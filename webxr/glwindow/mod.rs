@@ -2,17 +2,22 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
 
+use euclid::default::Point2D as UntypedPoint2D;
+use euclid::default::Rect as UntypedRect;
 use euclid::default::Size2D as UntypedSize2D;
 use euclid::Angle;
 use euclid::Point2D;
 use euclid::Rect;
 use euclid::RigidTransform3D;
+use euclid::Rotation3D;
 use euclid::Size2D;
 use euclid::Transform3D;
 use euclid::Trig;
 use euclid::Vector3D;
 
 use gleam::gl;
+use gleam::gl::GLenum;
+use gleam::gl::GLint;
 use gleam::gl::GLsizei;
 use gleam::gl::GLsync;
 use gleam::gl::GLuint;
@@ -21,40 +26,171 @@ use gleam::gl::Gl;
 use glutin::EventsLoop;
 use glutin::EventsLoopClosed;
 
+use std::ptr;
 use std::rc::Rc;
+use std::time::Instant;
 
+use self::input::InputAction;
+use self::input::InputEmulator;
+use self::pacing::FramePacer;
+use self::pacing::FRAME_RATES;
+use self::shader::GlWindowShader;
+use self::shader::ANAGLYPH_SHADER;
+use self::shader::COLOR_SHADER;
+use self::shader::COPY_SHADER;
+use self::shader::CUBE_SHADER;
+use self::shader::DISTORTION_SHADER;
+use self::shader::PANORAMA_SHADER;
+
+use webxr_api::ColorScaleBias;
 use webxr_api::Device;
 use webxr_api::Discovery;
 use webxr_api::Display;
+use webxr_api::EnvironmentBlendMode;
 use webxr_api::Error;
 use webxr_api::Event;
 use webxr_api::EventBuffer;
 use webxr_api::EventCallback;
+use webxr_api::EventFilter;
+use webxr_api::Feature;
 use webxr_api::Floor;
 use webxr_api::Frame;
+use webxr_api::FrameCapture;
+use webxr_api::GlState;
 use webxr_api::InputSource;
+use webxr_api::Layer;
+use webxr_api::LayerBlend;
+use webxr_api::LayerId;
+use webxr_api::LayerInit;
+use webxr_api::LayerTexture;
 use webxr_api::Native;
 use webxr_api::Session;
 use webxr_api::SessionBuilder;
+use webxr_api::SessionCapabilities;
+use webxr_api::SessionEndReason;
+use webxr_api::SessionInit;
 use webxr_api::SessionMode;
+use webxr_api::Velocity;
 use webxr_api::View;
+use webxr_api::Viewer;
+use webxr_api::Viewport;
 use webxr_api::Views;
+use webxr_api::Visibility;
+
+mod input;
+mod pacing;
+mod sensor;
+mod shader;
+
+pub use self::input::GlWindowCamera;
+pub use self::input::GlWindowEvent;
+pub use self::input::GlWindowGamepad;
+pub use self::pacing::GlWindowFramePacing;
+pub use self::sensor::GlWindowOrientation;
 
 const HEIGHT: f32 = 1.0;
-const EYE_DISTANCE: f32 = 0.25;
+const DEFAULT_IPD: f32 = 0.5;
 const NEAR: f32 = 0.1;
 const FAR: f32 = 100.0;
 
+// From GL 4.5 and KHR_robustness, which gleam doesn't have
+const GL_CONTEXT_LOST: GLenum = 0x0507;
+
+/// How the views are shown in the window
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum GlWindowMode {
+    /// A single view filling the window
+    Blit,
+    /// The left and right eyes' views side by side, each filling half the window
+    StereoLeftRight,
+    /// The left and right eyes' views merged into one image filling the window,
+    /// with the left eye in red and the right in cyan, for viewing with
+    /// red/cyan glasses. This is only available if the window was in this
+    /// mode when the session started.
+    StereoRedCyan,
+    /// The left eye's view fills the window, and the right eye's fills a second
+    /// window, such as one on another output, which is made with `new_window`
+    /// if the window was in this mode when the session started. Otherwise the
+    /// views are shown side by side.
+    StereoWindows,
+}
+
+/// The radial distortion coefficients of a lens, such as a phone viewer's.
+/// The views are drawn with barrel distortion to cancel out the lens's distortion.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LensDistortion {
+    pub k1: f32,
+    pub k2: f32,
+}
+
+/// The real world shown behind content in immersive-ar sessions. This is
+/// composited in the `Blit` and `StereoLeftRight` modes, as are cube layers,
+/// which are shown instead of it.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum GlWindowBackground {
+    /// A GL texture, such as the latest frame from a webcam, which fills each view
+    Camera(GLuint),
+    /// A GL texture containing an equirectangular panorama, which stays
+    /// fixed in the world as the viewer turns
+    Panorama(GLuint),
+}
+
 pub trait GlWindow {
     fn make_current(&mut self);
     fn swap_buffers(&mut self);
     fn size(&self) -> UntypedSize2D<GLsizei>;
+    #[allow(clippy::result_unit_err)]
     fn new_window(&self) -> Result<Box<dyn GlWindow>, ()>;
+    fn get_mode(&self) -> GlWindowMode {
+        GlWindowMode::StereoLeftRight
+    }
+    /// The distance between the eyes in meters, used by the stereo modes
+    fn interpupillary_distance(&self) -> f32 {
+        DEFAULT_IPD
+    }
+    /// The distortion of the lens the window is viewed through, if any. Whether
+    /// there is a lens is checked when the session starts, but its coefficients
+    /// can change at any time.
+    fn get_lens_distortion(&self) -> Option<LensDistortion> {
+        None
+    }
+    /// How the keyboard and mouse move the viewer
+    fn get_camera(&self) -> GlWindowCamera {
+        GlWindowCamera::Walk
+    }
+    /// The state of a physical gamepad, if one is connected, which can be
+    /// read with a library such as gilrs
+    fn get_gamepad(&self) -> Option<GlWindowGamepad> {
+        None
+    }
+    /// The keyboard and mouse input since this was last called,
+    /// which is used to move the viewer and emulate a controller
+    fn take_events(&mut self) -> Vec<GlWindowEvent> {
+        vec![]
+    }
+    /// How frames are paced. If they are paced at a fixed rate,
+    /// content can change the rate to any of 60, 72 or 90Hz.
+    fn get_frame_pacing(&self) -> GlWindowFramePacing {
+        GlWindowFramePacing::Swap
+    }
+    /// The background for immersive-ar sessions, which is shared with
+    /// the window's context. Without one content is shown over a flat color.
+    fn get_background(&self) -> Option<GlWindowBackground> {
+        None
+    }
+    /// The orientation of the viewer, for windows on devices with motion sensors,
+    /// such as phones in a cardboard viewer. The viewer then turns with the device,
+    /// rather than with the mouse. `GlWindowOrientation` can work this out from
+    /// the sensors' readings.
+    fn get_orientation(&self) -> Option<Rotation3D<f32, Viewer, Native>> {
+        None
+    }
 }
 
 pub struct GlWindowDiscovery {
     gl: Rc<dyn Gl>,
     factory: Box<dyn Fn() -> Result<Box<dyn GlWindow>, ()>>,
+    ar: bool,
 }
 
 impl GlWindowDiscovery {
@@ -62,23 +198,72 @@ impl GlWindowDiscovery {
         gl: Rc<dyn Gl>,
         factory: Box<dyn Fn() -> Result<Box<dyn GlWindow>, ()>>,
     ) -> GlWindowDiscovery {
-        GlWindowDiscovery { gl, factory }
+        GlWindowDiscovery {
+            gl,
+            factory,
+            ar: false,
+        }
+    }
+
+    /// A discovery which also supports immersive-ar sessions, which alpha
+    /// blend content over the window's background.
+    pub fn new_with_ar(
+        gl: Rc<dyn Gl>,
+        factory: Box<dyn Fn() -> Result<Box<dyn GlWindow>, ()>>,
+    ) -> GlWindowDiscovery {
+        GlWindowDiscovery {
+            gl,
+            factory,
+            ar: true,
+        }
     }
 }
 
 impl Discovery for GlWindowDiscovery {
-    fn request_session(&mut self, mode: SessionMode, xr: SessionBuilder) -> Result<Session, Error> {
+    fn request_session(
+        &mut self,
+        mode: SessionMode,
+        init: &SessionInit,
+        xr: SessionBuilder,
+    ) -> Result<Session, Error> {
         if self.supports_session(mode) {
+            let granted_features = init.validate(mode, &[Feature::LocalFloor])?;
             let gl = self.gl.clone();
             let window = (self.factory)().or(Err(Error::NoMatchingDevice))?;
-            xr.run_on_main_thread(move || GlWindowDevice::new(gl, window))
+            let ar = mode == SessionMode::ImmersiveAR;
+            xr.run_on_main_thread(move || GlWindowDevice::new(gl, window, granted_features, ar))
         } else {
             Err(Error::NoMatchingDevice)
         }
     }
 
     fn supports_session(&self, mode: SessionMode) -> bool {
-        mode == SessionMode::ImmersiveVR
+        mode == SessionMode::ImmersiveVR || (self.ar && mode == SessionMode::ImmersiveAR)
+    }
+
+    fn supported_features(&self, mode: SessionMode) -> Option<Vec<Feature>> {
+        if self.supports_session(mode) {
+            Some(vec![Feature::LocalFloor])
+        } else {
+            None
+        }
+    }
+
+    fn capabilities(&self, mode: SessionMode) -> Option<SessionCapabilities> {
+        // The window, whose mode decides the views and frame pacing, is only
+        // created when a session starts
+        let blend_mode = if mode == SessionMode::ImmersiveAR {
+            EnvironmentBlendMode::AlphaBlend
+        } else {
+            EnvironmentBlendMode::Opaque
+        };
+        Some(SessionCapabilities {
+            mode,
+            features: Some(self.supported_features(mode)?),
+            environment_blend_modes: vec![blend_mode],
+            frame_rates: vec![],
+            view_count: None,
+        })
     }
 }
 
@@ -88,8 +273,50 @@ pub struct GlWindowDevice {
     read_fbo: GLuint,
     events: EventBuffer,
     connected: bool,
+    granted_features: Vec<Feature>,
+    viewport_scale: f32,
+    // The depths of the near and far clip planes, which the projections use
+    clip_planes: (f32, f32),
+    input: InputEmulator,
+    pacer: FramePacer,
+    distortion_shader: Option<GlWindowShader>,
+    anaglyph_shader: Option<GlWindowShader>,
+    // Whether this is an immersive-ar session, and the shaders it composites with
+    ar: bool,
+    copy_shader: Option<GlWindowShader>,
+    panorama_shader: Option<GlWindowShader>,
+    // The scale and bias of the projection layer's colors, and the shader applying them
+    color_scale_bias: ColorScaleBias,
+    color_shader: Option<GlWindowShader>,
+    // How the projection layer is blended over the background
+    blend: LayerBlend,
+    // The cube layer behind the projection layer being rendered, and the shader drawing it
+    cube: Option<(GLuint, GLsync, Rotation3D<f32, Layer, Native>)>,
+    cube_shader: Option<GlWindowShader>,
+    // The viewer's pose in the latest frame, which the panorama background follows,
+    // and when that was, which its velocity is worked out from
+    viewer: RigidTransform3D<f32, Viewer, Native>,
+    viewer_time: Instant,
+    // The second window, in the separate windows mode
+    right_window: Option<RightWindow>,
+    // The window sizes and mode the views were last reported for
+    views_for: ViewsFor,
+    // The sync object for the last frame rendered, which content waits for
+    render_sync: GLsync,
 }
 
+struct RightWindow {
+    window: Box<dyn GlWindow>,
+    // Framebuffers aren't shared between contexts, so each window needs its own
+    read_fbo: GLuint,
+}
+
+type ViewsFor = (
+    UntypedSize2D<GLsizei>,
+    Option<UntypedSize2D<GLsizei>>,
+    GlWindowMode,
+);
+
 impl Device for GlWindowDevice {
     fn floor_transform(&self) -> RigidTransform3D<f32, Native, Floor> {
         let translation = Vector3D::new(-HEIGHT, 0.0, 0.0);
@@ -97,60 +324,237 @@ impl Device for GlWindowDevice {
     }
 
     fn views(&self) -> Views {
-        let left = self.view(false);
-        let right = self.view(true);
+        let window_size = Size2D::from_untyped(self.window.size());
+        let (left_viewport, right_viewport) = match (self.window.get_mode(), &self.right_window) {
+            (GlWindowMode::Blit, _) => {
+                let viewport = Rect::new(Point2D::zero(), window_size);
+                return Views::Mono(self.view(viewport, 0.0));
+            }
+            // Both views fill the window, so are each the size of the window
+            (GlWindowMode::StereoRedCyan, _) => (
+                Rect::new(Point2D::zero(), window_size),
+                Rect::new(Point2D::new(window_size.width, 0), window_size),
+            ),
+            // Each view is the size of its own window
+            (GlWindowMode::StereoWindows, Some(right)) => (
+                Rect::new(Point2D::zero(), window_size),
+                Rect::new(
+                    Point2D::new(window_size.width, 0),
+                    Size2D::from_untyped(right.window.size()),
+                ),
+            ),
+            (GlWindowMode::StereoLeftRight, _) | (GlWindowMode::StereoWindows, None) => {
+                let viewport_size = Size2D::new(window_size.width / 2, window_size.height);
+                (
+                    Rect::new(Point2D::zero(), viewport_size),
+                    Rect::new(Point2D::new(viewport_size.width, 0), viewport_size),
+                )
+            }
+        };
+        let eye_distance = self.window.interpupillary_distance() / 2.0;
+        let left = self.view(left_viewport, -eye_distance);
+        let right = self.view(right_viewport, eye_distance);
         Views::Stereo(left, right)
     }
 
+    fn supported_frame_rates(&self) -> Vec<f32> {
+        match self.window.get_frame_pacing() {
+            GlWindowFramePacing::Swap => vec![],
+            _ => FRAME_RATES.to_vec(),
+        }
+    }
+
+    fn update_frame_rate(&mut self, rate: f32) {
+        let pacing = self.window.get_frame_pacing();
+        if self
+            .pacer
+            .rate(pacing)
+            .map_or(false, |current| current != rate)
+        {
+            self.pacer.set_rate(rate);
+            self.events.callback(Event::FrameRateChange(rate));
+        }
+    }
+
+    fn environment_blend_mode(&self) -> EnvironmentBlendMode {
+        if self.ar {
+            EnvironmentBlendMode::AlphaBlend
+        } else {
+            EnvironmentBlendMode::Opaque
+        }
+    }
+
+    fn create_layer(&mut self, _id: LayerId, init: LayerInit) -> Result<(), Error> {
+        // Cube layers are drawn behind the projection layer, as the background is
+        match init {
+            LayerInit::Projection | LayerInit::Cube { .. } => Ok(()),
+            _ => Err(Error::UnsupportedFeature),
+        }
+    }
+
+    fn update_clip_planes(&mut self, near: f32, far: f32) {
+        if (near, far) != self.clip_planes {
+            self.clip_planes = (near, far);
+            self.events.callback(Event::UpdateViews(self.views()));
+        }
+    }
+
+    fn update_viewport_scale(&mut self, scale: f32) -> f32 {
+        self.viewport_scale = scale;
+        scale
+    }
+
     fn wait_for_animation_frame(&mut self) -> Frame {
+        let swap = webxr_api::span("swap_buffers");
         self.window.swap_buffers();
-        let translation = Vector3D::new(0.0, 0.0, -5.0);
-        let transform = RigidTransform3D::from_translation(translation);
-        Frame {
+        if let Some(ref mut right) = self.right_window {
+            right.window.swap_buffers();
+        }
+        drop(swap);
+        let pacing = webxr_api::span("frame_pacing");
+        self.pacer.wait(self.window.get_frame_pacing());
+        drop(pacing);
+        // Once the context is lost nothing more can be displayed
+        self.report_gl_error();
+        // The views are the size of the window, so if it has been resized the
+        // views change, and content should render at the new size
+        let views_for = self.views_for();
+        if views_for != self.views_for {
+            self.views_for = views_for;
+            self.events.callback(Event::UpdateViews(self.views()));
+        }
+        let input = webxr_api::span("poll_input");
+        for event in self.window.take_events() {
+            match event {
+                GlWindowEvent::Close => self.end_session(SessionEndReason::Disconnected),
+                event => self.input.handle_event(event),
+            }
+        }
+        let camera = self.window.get_camera();
+        let gamepad = self.window.get_gamepad();
+        let mut transform = self.input.update(camera, gamepad);
+        if let Some(orientation) = self.window.get_orientation() {
+            transform.rotation = orientation;
+        }
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.viewer_time);
+        let elapsed = elapsed.as_secs() as f32 + elapsed.subsec_nanos() as f32 / 1e9;
+        let velocity = Velocity::between(&self.viewer, &transform, elapsed);
+        self.viewer = transform;
+        self.viewer_time = now;
+        let direction = self
+            .input
+            .cursor()
+            .and_then(|cursor| self.cursor_direction(cursor));
+        let touch_direction = self
+            .input
+            .touch()
+            .and_then(|touch| self.cursor_direction(touch));
+        let inputs = self
+            .input
+            .input_frames(&transform, direction, touch_direction);
+        drop(input);
+        let frame = Frame {
             transform,
-            inputs: vec![],
+            velocity,
+            inputs,
+            depth: vec![],
+            occlusion_depth: vec![],
+            light_estimate: None,
+            camera_images: vec![],
+            hit_test_results: vec![],
+            anchors: vec![],
+            tracked_images: vec![],
+            body: None,
+            face: None,
+            viewports: Default::default(),
+            device_images: vec![],
+            timing: Default::default(),
+        };
+        for action in self.input.take_actions() {
+            let event = match action {
+                InputAction::Add(source) => Event::AddInput(source),
+                InputAction::Remove(id) => Event::RemoveInput(id),
+                InputAction::Select(id, kind, event) => {
+                    Event::Select(id, kind, event, Box::new(frame.clone()))
+                }
+            };
+            self.events.callback(event);
         }
+        frame
     }
 
     fn render_animation_frame(&mut self, texture_id: u32, size: UntypedSize2D<i32>, sync: GLsync) {
+        let _span = webxr_api::span("render_submit");
+        // The window's context is shared with the embedder, whose state is put back
+        // afterwards, with its context current again
         self.window.make_current();
+        let state = GlState::save(&self.gl);
+        self.render(texture_id, size, sync);
+        self.draw_color_scale_bias();
+        self.window.make_current();
+        drop(state);
+        self.report_gl_error();
+    }
 
-        let width = size.width as GLsizei;
-        let height = size.height as GLsizei;
-        let inner_size = self.window.size();
-
-        self.gl.clear_color(0.2, 0.3, 0.3, 1.0);
-        self.gl.clear(gl::COLOR_BUFFER_BIT);
-        self.gl.wait_sync(sync, 0, gl::TIMEOUT_IGNORED);
-        debug_assert_eq!(self.gl.get_error(), gl::NO_ERROR);
+    fn render_layers(&mut self, layers: &[LayerTexture]) {
+        let index = layers
+            .iter()
+            .rposition(|layer| layer.init.is_projection() && layer.texture_id != 0);
+        if let Some(index) = index {
+            let layer = &layers[index];
+            // Only the frontmost cube layer behind the projection layer can be seen
+            self.cube = layers[..index]
+                .iter()
+                .rev()
+                .find_map(|layer| match layer.init {
+                    LayerInit::Cube { orientation } if layer.texture_id != 0 => {
+                        Some((layer.texture_id, layer.sync, orientation))
+                    }
+                    _ => None,
+                });
+            self.color_scale_bias = layer.color_scale_bias;
+            self.blend = layer.blend;
+            self.render_animation_frame(layer.texture_id, layer.size, layer.sync);
+            self.cube = None;
+        }
+    }
 
-        self.gl
-            .bind_framebuffer(gl::READ_FRAMEBUFFER, self.read_fbo);
-        debug_assert_eq!(self.gl.get_error(), gl::NO_ERROR);
+    fn render_sync(&mut self) -> GLsync {
+        // Content waits for this before rendering to its texture again, so the
+        // previous one has been waited for, and deleting it is deferred until then
+        if !self.render_sync.is_null() {
+            self.gl.delete_sync(self.render_sync);
+        }
+        self.window.make_current();
+        self.render_sync = self.gl.fence_sync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0);
+        // The fence must be flushed to be sure of being signalled in other contexts
+        self.gl.flush();
+        self.render_sync
+    }
 
-        self.gl.framebuffer_texture_2d(
-            gl::READ_FRAMEBUFFER,
-            gl::COLOR_ATTACHMENT0,
-            gl::TEXTURE_2D,
-            texture_id,
-            0,
-        );
-        debug_assert_eq!(self.gl.get_error(), gl::NO_ERROR);
+    fn capture_frame(&mut self) -> Option<FrameCapture> {
+        // The frame has been blitted to the window, but not yet swapped.
+        // In separate windows mode this only captures the left eye's window.
+        self.window.make_current();
+        let size = self.window.size();
+        let state = GlState::save(&self.gl);
+        self.gl.bind_framebuffer(gl::READ_FRAMEBUFFER, 0);
+        let data = self
+            .gl
+            .read_pixels(0, 0, size.width, size.height, gl::RGBA, gl::UNSIGNED_BYTE);
+        drop(state);
+        if !self.report_gl_error() {
+            return None;
+        }
+        Some(FrameCapture {
+            size: Size2D::from_untyped(size),
+            data,
+        })
+    }
 
-        self.gl.viewport(0, 0, width, height);
-        self.gl.blit_framebuffer(
-            0,
-            0,
-            width,
-            height,
-            0,
-            0,
-            inner_size.width,
-            inner_size.height,
-            gl::COLOR_BUFFER_BIT,
-            gl::NEAREST,
-        );
-        debug_assert_eq!(self.gl.get_error(), gl::NO_ERROR);
+    fn granted_features(&self) -> Vec<Feature> {
+        self.granted_features.clone()
     }
 
     fn initial_inputs(&self) -> Vec<InputSource> {
@@ -161,45 +565,333 @@ impl Device for GlWindowDevice {
         self.events.upgrade(callback)
     }
 
+    fn subscribe_events(&mut self, filter: EventFilter, callback: Box<dyn EventCallback>) {
+        self.events.subscribe(filter, callback)
+    }
+
     fn connected(&mut self) -> bool {
         self.connected
     }
 
     fn quit(&mut self) {
-        if !self.connected {
-            self.connected = false;
-            self.events.callback(Event::SessionEnd);
-        }
+        self.end_session(SessionEndReason::Requested);
     }
 }
 
 impl GlWindowDevice {
-    fn new(gl: Rc<dyn Gl>, mut window: Box<dyn GlWindow>) -> Result<GlWindowDevice, Error> {
+    fn new(
+        gl: Rc<dyn Gl>,
+        mut window: Box<dyn GlWindow>,
+        granted_features: Vec<Feature>,
+        ar: bool,
+    ) -> Result<GlWindowDevice, Error> {
+        // The window is shown as soon as the session starts, and we have no
+        // way to tell when it loses focus, so it stays visible.
+        let mut events = EventBuffer::default();
+        events.callback(Event::VisibilityChange(Visibility::Visible));
+
         window.make_current();
         let read_fbo = gl.gen_framebuffers(1)[0];
-        debug_assert_eq!(gl.get_error(), gl::NO_ERROR);
+        gl_result(&*gl)?;
+
+        // Shaders which fail to compile are reported, and the window shows the
+        // views without them
+        let mut shader = |wanted: bool, fragment_shader| {
+            if !wanted {
+                return None;
+            }
+            GlWindowShader::new(gl.clone(), fragment_shader)
+                .map_err(|error| events.callback(Event::Error(error)))
+                .ok()
+        };
+        let distortion_shader = shader(window.get_lens_distortion().is_some(), DISTORTION_SHADER);
+        let anaglyph_shader = shader(
+            window.get_mode() == GlWindowMode::StereoRedCyan,
+            ANAGLYPH_SHADER,
+        );
+        // Content is also composited over cube layers, which any session can have
+        let copy_shader = shader(true, COPY_SHADER);
+        let panorama_shader = shader(ar, PANORAMA_SHADER);
+        let color_shader = shader(true, COLOR_SHADER);
+        let cube_shader = shader(true, CUBE_SHADER).map(GlWindowShader::for_cube_maps);
+
+        let right_window = if window.get_mode() == GlWindowMode::StereoWindows {
+            let mut right = window.new_window().or(Err(Error::NoMatchingDevice))?;
+            right.make_current();
+            let read_fbo = gl.gen_framebuffers(1)[0];
+            gl_result(&*gl)?;
+            window.make_current();
+            Some(RightWindow {
+                window: right,
+                read_fbo,
+            })
+        } else {
+            None
+        };
+
+        let views_for = (
+            window.size(),
+            right_window.as_ref().map(|right| right.window.size()),
+            window.get_mode(),
+        );
 
         Ok(GlWindowDevice {
             gl,
             window,
             read_fbo,
-            events: Default::default(),
+            events,
             connected: true,
+            granted_features,
+            viewport_scale: 1.0,
+            clip_planes: (NEAR, FAR),
+            input: InputEmulator::new(Vector3D::new(0.0, 0.0, -5.0)),
+            pacer: FramePacer::new(),
+            distortion_shader,
+            anaglyph_shader,
+            ar,
+            copy_shader,
+            panorama_shader,
+            color_scale_bias: ColorScaleBias::IDENTITY,
+            color_shader,
+            blend: LayerBlend::default(),
+            cube: None,
+            cube_shader,
+            viewer: RigidTransform3D::identity(),
+            viewer_time: Instant::now(),
+            right_window,
+            views_for,
+            render_sync: ptr::null(),
         })
     }
 
-    fn view<Eye>(&self, is_right: bool) -> View<Eye> {
-        let window_size = self.window.size();
-        let viewport_size = Size2D::new(window_size.width / 2, window_size.height);
-        let viewport_x_origin = if is_right { viewport_size.width } else { 0 };
-        let viewport_origin = Point2D::new(viewport_x_origin, 0);
-        let viewport = Rect::new(viewport_origin, viewport_size);
-        let projection = self.perspective(NEAR, FAR);
-        let eye_distance = if is_right {
-            EYE_DISTANCE
-        } else {
-            -EYE_DISTANCE
+    fn render(&mut self, texture_id: u32, size: UntypedSize2D<i32>, sync: GLsync) {
+        self.window.make_current();
+
+        let width = size.width as GLsizei;
+        let height = size.height as GLsizei;
+        let inner_size = self.window.size();
+
+        self.gl.clear_color(0.2, 0.3, 0.3, 1.0);
+        self.gl.clear(gl::COLOR_BUFFER_BIT);
+        self.gl.wait_sync(sync, 0, gl::TIMEOUT_IGNORED);
+        if let Some((_, cube_sync, _)) = self.cube {
+            if !cube_sync.is_null() {
+                self.gl.wait_sync(cube_sync, 0, gl::TIMEOUT_IGNORED);
+            }
+        }
+
+        self.gl
+            .bind_framebuffer(gl::READ_FRAMEBUFFER, self.read_fbo);
+
+        self.gl.framebuffer_texture_2d(
+            gl::READ_FRAMEBUFFER,
+            gl::COLOR_ATTACHMENT0,
+            gl::TEXTURE_2D,
+            texture_id,
+            0,
+        );
+
+        self.gl.viewport(0, 0, width, height);
+
+        let views = self.views();
+        let scaled_viewports = views.scaled_viewports(self.viewport_scale);
+        let mode = self.window.get_mode();
+
+        // In separate windows mode, each view fills its window
+        if let (GlWindowMode::StereoWindows, Some(right_window), [left, right]) =
+            (mode, &mut self.right_window, &scaled_viewports[..])
+        {
+            blit(&*self.gl, left, Rect::new(Point2D::zero(), inner_size));
+
+            right_window.window.make_current();
+            self.gl.clear_color(0.2, 0.3, 0.3, 1.0);
+            self.gl.clear(gl::COLOR_BUFFER_BIT);
+            self.gl.wait_sync(sync, 0, gl::TIMEOUT_IGNORED);
+            self.gl
+                .bind_framebuffer(gl::READ_FRAMEBUFFER, right_window.read_fbo);
+            self.gl.framebuffer_texture_2d(
+                gl::READ_FRAMEBUFFER,
+                gl::COLOR_ATTACHMENT0,
+                gl::TEXTURE_2D,
+                texture_id,
+                0,
+            );
+            let right_size = right_window.window.size();
+            blit(&*self.gl, right, Rect::new(Point2D::zero(), right_size));
+            return;
+        }
+
+        // In red/cyan mode, both views are merged into the whole window
+        let anaglyph = mode == GlWindowMode::StereoRedCyan;
+        if let (true, Some(shader), [left, right]) =
+            (anaglyph, &self.anaglyph_shader, &scaled_viewports[..])
+        {
+            let destination = Rect::new(Point2D::zero(), inner_size);
+            shader.draw(
+                texture_id,
+                destination,
+                &[
+                    ("left_source", &texture_source(left, size)),
+                    ("right_source", &texture_source(right, size)),
+                ],
+            );
+            return;
+        }
+
+        // Otherwise each view was rendered to the scaled part of its viewport,
+        // which we stretch to fill that view's part of the window.
+        let x_scale = inner_size.width as f32 / width as f32;
+        let y_scale = inner_size.height as f32 / height as f32;
+        let viewports = views.viewports();
+        let distortion = self.window.get_lens_distortion();
+        for (viewport, scaled) in viewports.iter().zip(scaled_viewports.iter()) {
+            let destination = Rect::new(
+                Point2D::new(
+                    (viewport.min_x() as f32 * x_scale) as GLint,
+                    (viewport.min_y() as f32 * y_scale) as GLint,
+                ),
+                Size2D::new(
+                    (viewport.size.width as f32 * x_scale) as GLint,
+                    (viewport.size.height as f32 * y_scale) as GLint,
+                ),
+            );
+            let source = texture_source(scaled, size);
+            // In immersive-ar sessions, and over cube layers, content is alpha blended
+            // over the background, unless its layer is opaque
+            let background = (self.ar || self.cube.is_some()) && self.blend.blend_source_alpha;
+            if let (true, Some(shader)) = (background, &self.copy_shader) {
+                self.draw_background(viewport, destination);
+                self.gl.enable(gl::BLEND);
+                if self.blend.premultiplied_alpha {
+                    self.gl.blend_func(gl::ONE, gl::ONE_MINUS_SRC_ALPHA);
+                } else {
+                    self.gl.blend_func(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+                }
+                shader.draw(texture_id, destination, &[("source", &source)]);
+                self.gl.disable(gl::BLEND);
+                continue;
+            }
+            if let (Some(distortion), Some(shader)) = (distortion, &self.distortion_shader) {
+                let coefficients = [distortion.k1, distortion.k2];
+                shader.draw(
+                    texture_id,
+                    destination,
+                    &[("source", &source), ("coefficients", &coefficients)],
+                );
+                continue;
+            }
+            blit(&*self.gl, scaled, destination);
+        }
+    }
+
+    // Scale and bias what was drawn to each window, with the shader drawing the bias
+    // and blending adding it to what was drawn times the scale
+    fn draw_color_scale_bias(&mut self) {
+        let shader = match self.color_shader {
+            Some(ref shader) if !self.color_scale_bias.is_identity() => shader,
+            _ => return,
         };
+        let ColorScaleBias { scale, bias } = self.color_scale_bias;
+        let windows = Some(&mut self.window)
+            .into_iter()
+            .chain(self.right_window.as_mut().map(|right| &mut right.window));
+        for window in windows {
+            window.make_current();
+            let destination = Rect::new(Point2D::zero(), window.size());
+            self.gl.enable(gl::BLEND);
+            self.gl.blend_color(scale[0], scale[1], scale[2], scale[3]);
+            self.gl.blend_func(gl::ONE, gl::CONSTANT_COLOR);
+            shader.draw(0, destination, &[("bias", &bias)]);
+            self.gl.disable(gl::BLEND);
+        }
+        self.window.make_current();
+    }
+
+    /// Report a GL error as recoverable, or end the session if the context was
+    /// lost, returning whether there was no error.
+    fn report_gl_error(&mut self) -> bool {
+        match gl_result(&*self.gl) {
+            Ok(()) => true,
+            Err(Error::Backend(ref error)) if error.code == Some(GL_CONTEXT_LOST as i64) => {
+                self.end_session(SessionEndReason::ContextLost);
+                false
+            }
+            Err(error) => {
+                self.events.callback(Event::Error(error));
+                false
+            }
+        }
+    }
+
+    fn end_session(&mut self, reason: SessionEndReason) {
+        if self.connected {
+            self.connected = false;
+            self.events.callback(Event::SessionEnd(reason));
+        }
+    }
+
+    fn views_for(&self) -> ViewsFor {
+        (
+            self.window.size(),
+            self.right_window.as_ref().map(|right| right.window.size()),
+            self.window.get_mode(),
+        )
+    }
+
+    // Draw the window's background for a view, shown in the given part of the window,
+    // or the cube layer behind content if there is one
+    fn draw_background(&self, viewport: &Rect<i32, Viewport>, destination: UntypedRect<GLint>) {
+        // The views look along -z, reaching as far up as `perspective` makes them
+        let extent = [
+            1.0,
+            viewport.size.height as f32 / viewport.size.width as f32,
+        ];
+        if let (Some((texture_id, _, orientation)), Some(shader)) = (self.cube, &self.cube_shader) {
+            let m = self
+                .viewer
+                .rotation
+                .post_rotate(&orientation.inverse())
+                .to_transform();
+            let rotation = [
+                m.m11, m.m12, m.m13, m.m21, m.m22, m.m23, m.m31, m.m32, m.m33,
+            ];
+            shader.draw(
+                texture_id,
+                destination,
+                &[("rotation", &rotation), ("extent", &extent)],
+            );
+            return;
+        }
+        match (self.window.get_background(), &self.copy_shader) {
+            (Some(GlWindowBackground::Camera(texture_id)), Some(shader)) => {
+                shader.draw(
+                    texture_id,
+                    destination,
+                    &[("source", &[0.0, 0.0, 1.0, 1.0])],
+                );
+            }
+            (Some(GlWindowBackground::Panorama(texture_id)), _) => {
+                if let Some(ref shader) = self.panorama_shader {
+                    let m = self.viewer.rotation.to_transform();
+                    let rotation = [
+                        m.m11, m.m12, m.m13, m.m21, m.m22, m.m23, m.m31, m.m32, m.m33,
+                    ];
+                    shader.draw(
+                        texture_id,
+                        destination,
+                        &[("rotation", &rotation), ("extent", &extent)],
+                    );
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // The view of an eye offset horizontally from the viewer by `eye_distance`,
+    // shown in the given viewport of the window
+    fn view<Eye>(&self, viewport: Rect<i32, Viewport>, eye_distance: f32) -> View<Eye> {
+        let (near, far) = self.clip_planes;
+        let projection = self.perspective(viewport.size, near, far);
         let translation = Vector3D::new(eye_distance, 0.0, 0.0);
         let transform = RigidTransform3D::from_translation(translation);
         View {
@@ -209,15 +901,36 @@ impl GlWindowDevice {
         }
     }
 
-    fn perspective<Eye>(&self, near: f32, far: f32) -> Transform3D<f32, Eye, Display> {
+    // The direction, relative to the viewer, of the part of the view shown
+    // at a pixel of the window, which is the inverse of `perspective`
+    fn cursor_direction(&self, cursor: UntypedPoint2D<f32>) -> Option<Vector3D<f32, Viewer>> {
+        // Viewports are measured from the bottom of the window
+        let point = Point2D::new(cursor.x, self.window.size().height as f32 - cursor.y);
+        let viewport = self
+            .views()
+            .viewports()
+            .into_iter()
+            .find(|viewport| viewport.to_f32().contains(point))?
+            .to_f32();
+        let x = 2.0 * (point.x - viewport.min_x()) / viewport.size.width - 1.0;
+        let y = 2.0 * (point.y - viewport.min_y()) / viewport.size.height - 1.0;
+        let aspect = viewport.size.width / viewport.size.height;
+        Some(Vector3D::new(x, y / aspect, -1.0))
+    }
+
+    fn perspective<Eye>(
+        &self,
+        size: Size2D<i32, Viewport>,
+        near: f32,
+        far: f32,
+    ) -> Transform3D<f32, Eye, Display> {
         // https://github.com/toji/gl-matrix/blob/bd3307196563fbb331b40fc6ebecbbfcc2a4722c/src/mat4.js#L1271
-        let size = self.window.size();
         let width = size.width as f32;
         let height = size.height as f32;
-        let fov_up = Angle::radians(f32::fast_atan2(2.0 * height, width));
+        let fov_up = Angle::radians(f32::fast_atan2(height, width));
         let f = 1.0 / fov_up.radians.tan();
         let nf = 1.0 / (near - far);
-        let aspect = (width / 2.0) / height;
+        let aspect = width / height;
 
         // Dear rustfmt, This is a 4x4 matrix, please leave it alone. Best, ajeffrey.
         {
@@ -233,4 +946,41 @@ impl GlWindowDevice {
     }
 }
 
+// Fail if GL has reported an error since this was last called
+fn gl_result(gl: &dyn Gl) -> Result<(), Error> {
+    match gl.get_error() {
+        gl::NO_ERROR => Ok(()),
+        error => Err(Error::backend(
+            "glwindow",
+            format!("GL error {:#x}", error),
+            Some(error as i64),
+        )),
+    }
+}
+
+fn blit(gl: &dyn Gl, source: &Rect<i32, Viewport>, destination: UntypedRect<GLint>) {
+    gl.blit_framebuffer(
+        source.min_x(),
+        source.min_y(),
+        source.max_x(),
+        source.max_y(),
+        destination.min_x(),
+        destination.min_y(),
+        destination.max_x(),
+        destination.max_y(),
+        gl::COLOR_BUFFER_BIT,
+        gl::NEAREST,
+    );
+}
+
+// Where a viewport is in a texture of the given size, as fractions of its size
+fn texture_source(viewport: &Rect<i32, Viewport>, size: UntypedSize2D<i32>) -> [f32; 4] {
+    [
+        viewport.min_x() as f32 / size.width as f32,
+        viewport.min_y() as f32 / size.height as f32,
+        viewport.size.width as f32 / size.width as f32,
+        viewport.size.height as f32 / size.height as f32,
+    ]
+}
+
 pub type EventsLoopFactory = Box<dyn Fn() -> Result<EventsLoop, EventsLoopClosed>>;
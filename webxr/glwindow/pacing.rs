@@ -0,0 +1,88 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Pacing the frames of the glwindow device, so content runs at
+//! the rate it would on a headset rather than uncapped.
+
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+/// How the glwindow device paces its frames
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GlWindowFramePacing {
+    /// Frames are paced by swapping buffers, which waits for vsync if the
+    /// window's context has it enabled, and is otherwise uncapped.
+    Swap,
+    /// Frames are started at a fixed rate in Hz by sleeping until each is due.
+    /// The window's context should have vsync disabled.
+    Sleep(f32),
+    /// Like `Sleep`, but spinning for the end of each wait, which is more
+    /// accurate but keeps a core busy.
+    Spin(f32),
+}
+
+/// The frame rates content can choose from, when frames are paced at a fixed rate
+pub(super) const FRAME_RATES: [f32; 3] = [60.0, 72.0, 90.0];
+
+// How long before a frame is due spinning takes over from sleeping
+const SPIN_TIME: Duration = Duration::from_millis(2);
+
+pub(super) struct FramePacer {
+    // The rate content asked for, which overrides the window's
+    rate: Option<f32>,
+    next_frame: Option<Instant>,
+}
+
+impl FramePacer {
+    pub(super) fn new() -> FramePacer {
+        FramePacer {
+            rate: None,
+            next_frame: None,
+        }
+    }
+
+    pub(super) fn set_rate(&mut self, rate: f32) {
+        self.rate = Some(rate);
+    }
+
+    /// The rate frames are being paced at, if it is fixed
+    pub(super) fn rate(&self, pacing: GlWindowFramePacing) -> Option<f32> {
+        match pacing {
+            GlWindowFramePacing::Swap => None,
+            GlWindowFramePacing::Sleep(rate) | GlWindowFramePacing::Spin(rate) => {
+                Some(self.rate.unwrap_or(rate))
+            }
+        }
+    }
+
+    /// Wait until the next frame is due
+    pub(super) fn wait(&mut self, pacing: GlWindowFramePacing) {
+        let rate = match self.rate(pacing) {
+            Some(rate) if rate > 0.0 => rate,
+            _ => {
+                self.next_frame = None;
+                return;
+            }
+        };
+        let interval = Duration::from_nanos((1e9 / f64::from(rate)) as u64);
+        let now = Instant::now();
+        let due = match self.next_frame {
+            Some(due) => due,
+            None => now,
+        };
+        if let GlWindowFramePacing::Spin(_) = pacing {
+            let spin_from = now + SPIN_TIME;
+            if due > spin_from {
+                thread::sleep(due - spin_from);
+            }
+            while Instant::now() < due {}
+        } else if due > now {
+            thread::sleep(due - now);
+        }
+        // If we've fallen more than a frame behind, don't rush to catch up
+        let now = Instant::now();
+        self.next_frame = Some((due + interval).max(now));
+    }
+}
@@ -0,0 +1,89 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Tracking the orientation of a phone from its gyroscope and accelerometer,
+//! for 3DoF sessions in a cardboard viewer or a "magic window".
+
+use euclid::Angle;
+use euclid::Rotation3D;
+use euclid::Vector3D;
+
+use std::f32::EPSILON;
+use std::time::Duration;
+
+use webxr_api::Native;
+use webxr_api::Viewer;
+
+// How much each accelerometer reading pulls the orientation towards it,
+// which corrects the gyroscope's drift in pitch and roll
+const ACCELEROMETER_WEIGHT: f32 = 0.02;
+
+// Readings further than this from gravity, in m/s^2, are from the device being
+// moved rather than held, so they don't say which way is down
+const ACCELERATION_TOLERANCE: f32 = 1.0;
+
+const GRAVITY: f32 = 9.81;
+
+/// The orientation of the viewer, worked out from a device's motion sensors.
+/// The readings are in the viewer's coordinates, with x to the right, y up and
+/// z towards the user, so the embedder should first map the sensors' axes to
+/// these for the screen's orientation.
+///
+/// The gyroscope tracks how the device turns, and the accelerometer which way
+/// is down. Without a compass, the device's heading drifts slowly.
+#[derive(Clone, Copy, Debug)]
+pub struct GlWindowOrientation {
+    orientation: Rotation3D<f32, Viewer, Native>,
+}
+
+impl Default for GlWindowOrientation {
+    fn default() -> GlWindowOrientation {
+        GlWindowOrientation::new()
+    }
+}
+
+impl GlWindowOrientation {
+    /// Tracking starts with the viewer looking forwards
+    pub fn new() -> GlWindowOrientation {
+        GlWindowOrientation {
+            orientation: Rotation3D::identity(),
+        }
+    }
+
+    /// Turn the viewer by a gyroscope reading, in radians per second around each
+    /// axis, which lasted for `elapsed`
+    pub fn update_gyroscope(&mut self, rate: Vector3D<f32, Viewer>, elapsed: Duration) {
+        let speed = rate.length();
+        if speed <= EPSILON {
+            return;
+        }
+        let elapsed = elapsed.as_secs() as f32 + elapsed.subsec_nanos() as f32 / 1e9;
+        let angle = Angle::radians(speed * elapsed);
+        let turn = Rotation3D::<f32, Viewer, Viewer>::around_axis(rate / speed, angle);
+        self.orientation = self.orientation.pre_rotate(&turn).normalize();
+    }
+
+    /// Level the viewer by an accelerometer reading in m/s^2, which while the device
+    /// is held still points up, away from gravity
+    pub fn update_accelerometer(&mut self, acceleration: Vector3D<f32, Viewer>) {
+        let length = acceleration.length();
+        if (length - GRAVITY).abs() > ACCELERATION_TOLERANCE {
+            return;
+        }
+        let measured = self.orientation.transform_vector3d(acceleration / length);
+        let up = Vector3D::<f32, Native>::new(0.0, 1.0, 0.0);
+        let axis = measured.cross(up);
+        let sin = axis.length();
+        if sin <= EPSILON {
+            return;
+        }
+        let angle = Angle::radians(sin.atan2(measured.dot(up)) * ACCELEROMETER_WEIGHT);
+        let correction = Rotation3D::<f32, Native, Native>::around_axis(axis / sin, angle);
+        self.orientation = self.orientation.post_rotate(&correction).normalize();
+    }
+
+    pub fn orientation(&self) -> Rotation3D<f32, Viewer, Native> {
+        self.orientation
+    }
+}
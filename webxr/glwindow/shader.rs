@@ -0,0 +1,245 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Drawing the views to the window with a shader, for the modes which
+//! change the image rather than copying it.
+
+use euclid::default::Rect as UntypedRect;
+
+use gleam::gl;
+use gleam::gl::GLenum;
+use gleam::gl::GLint;
+use gleam::gl::GLuint;
+use gleam::gl::Gl;
+use gleam::gl::GlType;
+
+use std::rc::Rc;
+
+use webxr_api::Error;
+
+// Draws a rectangle filling the viewport, as a triangle strip of four vertices,
+// with `position` going from (0, 0) at the bottom left to (1, 1) at the top right.
+const VERTEX_SHADER: &str = "
+    out vec2 position;
+    void main() {
+        position = vec2(float(gl_VertexID & 1), float(gl_VertexID >> 1));
+        gl_Position = vec4(position * 2.0 - 1.0, 0.0, 1.0);
+    }
+";
+
+/// Barrel distortion, for viewing through a lens which has pincushion distortion.
+/// `source` is the part of the texture the view is in, and `coefficients` are the
+/// radial distortion coefficients k1 and k2.
+pub(super) const DISTORTION_SHADER: &str = "
+    uniform sampler2D image;
+    uniform vec4 source;
+    uniform vec2 coefficients;
+    in vec2 position;
+    out vec4 color;
+    void main() {
+        vec2 centered = position * 2.0 - 1.0;
+        float r2 = dot(centered, centered);
+        vec2 distorted = centered * (1.0 + coefficients.x * r2 + coefficients.y * r2 * r2);
+        if (abs(distorted.x) > 1.0 || abs(distorted.y) > 1.0) {
+            color = vec4(0.0, 0.0, 0.0, 1.0);
+        } else {
+            color = texture(image, source.xy + (distorted * 0.5 + 0.5) * source.zw);
+        }
+    }
+";
+
+/// Red/cyan anaglyph, merging the left view into the red channel and
+/// the right view into the green and blue channels. `left_source` and
+/// `right_source` are the parts of the texture the views are in.
+pub(super) const ANAGLYPH_SHADER: &str = "
+    uniform sampler2D image;
+    uniform vec4 left_source;
+    uniform vec4 right_source;
+    in vec2 position;
+    out vec4 color;
+    void main() {
+        vec4 left = texture(image, left_source.xy + position * left_source.zw);
+        vec4 right = texture(image, right_source.xy + position * right_source.zw);
+        color = vec4(left.r, right.g, right.b, 1.0);
+    }
+";
+
+/// Copies part of the texture, which when drawn with blending composites content
+/// over the background. `source` is the part of the texture the view is in.
+pub(super) const COPY_SHADER: &str = "
+    uniform sampler2D image;
+    uniform vec4 source;
+    in vec2 position;
+    out vec4 color;
+    void main() {
+        color = texture(image, source.xy + position * source.zw);
+    }
+";
+
+/// A solid color, which when drawn with blending against a constant color
+/// scales and biases the colors already drawn. `bias` is the color.
+pub(super) const COLOR_SHADER: &str = "
+    uniform vec4 bias;
+    in vec2 position;
+    out vec4 color;
+    void main() {
+        color = bias;
+    }
+";
+
+/// A cube map, seen from the viewer. `rotation` is the orientation of the
+/// view relative to the cube, and `extent` is as for the panorama shader.
+pub(super) const CUBE_SHADER: &str = "
+    uniform samplerCube image;
+    uniform mat3 rotation;
+    uniform vec2 extent;
+    in vec2 position;
+    out vec4 color;
+    void main() {
+        vec3 direction = rotation * vec3((position * 2.0 - 1.0) * extent, -1.0);
+        color = vec4(texture(image, direction).rgb, 1.0);
+    }
+";
+
+/// An equirectangular panorama, seen from the viewer. `rotation` is the
+/// orientation of the view, and `extent` is how far the view reaches
+/// left and up, at a distance of one meter.
+pub(super) const PANORAMA_SHADER: &str = "
+    uniform sampler2D image;
+    uniform mat3 rotation;
+    uniform vec2 extent;
+    in vec2 position;
+    out vec4 color;
+    void main() {
+        vec3 direction = normalize(rotation * vec3((position * 2.0 - 1.0) * extent, -1.0));
+        float longitude = atan(direction.x, -direction.z);
+        float latitude = asin(direction.y);
+        vec2 coordinates = vec2(longitude / 6.2831853 + 0.5, latitude / 3.1415927 + 0.5);
+        color = vec4(texture(image, coordinates).rgb, 1.0);
+    }
+";
+
+pub(super) struct GlWindowShader {
+    gl: Rc<dyn Gl>,
+    program: GLuint,
+    vao: GLuint,
+    // The kind of texture the shader samples
+    target: GLenum,
+}
+
+impl GlWindowShader {
+    /// Compile a shader program with the given fragment shader, which gets
+    /// the `position` from the vertex shader, or fail with the info log.
+    pub(super) fn new(gl: Rc<dyn Gl>, fragment_shader: &str) -> Result<GlWindowShader, Error> {
+        let header = match gl.get_type() {
+            GlType::Gl => "#version 150\n",
+            GlType::Gles => "#version 300 es\nprecision mediump float;\n",
+        };
+        let vertex_shader = compile(&*gl, gl::VERTEX_SHADER, header, VERTEX_SHADER)?;
+        let fragment_shader = match compile(&*gl, gl::FRAGMENT_SHADER, header, fragment_shader) {
+            Ok(fragment_shader) => fragment_shader,
+            Err(error) => {
+                gl.delete_shader(vertex_shader);
+                return Err(error);
+            }
+        };
+
+        let program = gl.create_program();
+        gl.attach_shader(program, vertex_shader);
+        gl.attach_shader(program, fragment_shader);
+        gl.link_program(program);
+        gl.delete_shader(vertex_shader);
+        gl.delete_shader(fragment_shader);
+        let mut status = [0];
+        unsafe { gl.get_program_iv(program, gl::LINK_STATUS, &mut status) };
+        if status[0] == 0 {
+            let log = gl.get_program_info_log(program);
+            log::warn!("Failed to link shader: {}", log);
+            gl.delete_program(program);
+            return Err(Error::backend(
+                "glwindow",
+                format!("Failed to link shader: {}", log),
+                None,
+            ));
+        }
+
+        // There are no vertex attributes, but core profiles need a vertex array to draw
+        let vao = gl.gen_vertex_arrays(1)[0];
+        super::gl_result(&*gl)?;
+        Ok(GlWindowShader {
+            gl,
+            program,
+            vao,
+            target: gl::TEXTURE_2D,
+        })
+    }
+
+    /// The same shader, drawing cube map textures rather than 2D ones
+    pub(super) fn for_cube_maps(self) -> GlWindowShader {
+        GlWindowShader {
+            target: gl::TEXTURE_CUBE_MAP,
+            ..self
+        }
+    }
+
+    /// Draw the texture into a rectangle of the window, setting the given float
+    /// uniforms. Uniforms can be vec2s, vec4s or column-major mat3s.
+    pub(super) fn draw(
+        &self,
+        texture_id: GLuint,
+        destination: UntypedRect<GLint>,
+        uniforms: &[(&str, &[f32])],
+    ) {
+        self.gl.use_program(self.program);
+        self.gl.bind_vertex_array(self.vao);
+
+        self.gl.active_texture(gl::TEXTURE0);
+        self.gl.bind_texture(self.target, texture_id);
+        // The texture has no mipmaps, so mipmapped filtering would leave it unsampleable
+        self.gl
+            .tex_parameter_i(self.target, gl::TEXTURE_MIN_FILTER, gl::LINEAR as GLint);
+        let image = self.gl.get_uniform_location(self.program, "image");
+        self.gl.uniform_1i(image, 0);
+        for (name, value) in uniforms {
+            let location = self.gl.get_uniform_location(self.program, name);
+            match value.len() {
+                2 => self.gl.uniform_2fv(location, value),
+                4 => self.gl.uniform_4fv(location, value),
+                9 => self.gl.uniform_matrix_3fv(location, false, value),
+                _ => debug_assert!(false, "Unexpected size of uniform {}", name),
+            }
+        }
+
+        self.gl.viewport(
+            destination.origin.x,
+            destination.origin.y,
+            destination.size.width,
+            destination.size.height,
+        );
+        self.gl.draw_arrays(gl::TRIANGLE_STRIP, 0, 4);
+
+        self.gl.bind_texture(self.target, 0);
+        self.gl.bind_vertex_array(0);
+        self.gl.use_program(0);
+    }
+}
+
+fn compile(gl: &dyn Gl, shader_type: GLenum, header: &str, source: &str) -> Result<GLuint, Error> {
+    let shader = gl.create_shader(shader_type);
+    gl.shader_source(shader, &[header.as_bytes(), source.as_bytes()]);
+    gl.compile_shader(shader);
+    let mut status = [0];
+    unsafe { gl.get_shader_iv(shader, gl::COMPILE_STATUS, &mut status) };
+    if status[0] == 0 {
+        let log = gl.get_shader_info_log(shader);
+        log::warn!("Failed to compile shader: {}", log);
+        gl.delete_shader(shader);
+        return Err(Error::backend(
+            "glwindow",
+            format!("Failed to compile shader: {}", log),
+            None,
+        ));
+    }
+    Ok(shader)
+}
@@ -0,0 +1,161 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Hit testing against the fake world of a mock device.
+
+use webxr_api::EntityType;
+use webxr_api::HitTestSpace;
+use webxr_api::MockWorld;
+use webxr_api::Native;
+use webxr_api::Ray;
+use webxr_api::Triangle;
+
+use euclid::Angle;
+use euclid::RigidTransform3D;
+use euclid::Rotation3D;
+use euclid::Vector3D;
+
+const EPSILON: f32 = 1e-6;
+
+/// The poses of every point where the ray hits a region of one of the given types,
+/// nearest first, with the Y axis of each pose facing back towards the ray.
+pub(super) fn hit_test(
+    world: &MockWorld,
+    ray: &Ray<Native>,
+    entity_types: &[EntityType],
+) -> Vec<RigidTransform3D<f32, HitTestSpace, Native>> {
+    let mut hits: Vec<(f32, RigidTransform3D<f32, HitTestSpace, Native>)> = world
+        .regions
+        .iter()
+        .filter(|region| entity_types.contains(&region.ty))
+        .flat_map(|region| region.faces.iter())
+        .filter_map(|triangle| intersect(triangle, ray))
+        .collect();
+    hits.sort_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    hits.into_iter().map(|(_, pose)| pose).collect()
+}
+
+// https://en.wikipedia.org/wiki/M%C3%B6ller%E2%80%93Trumbore_intersection_algorithm
+fn intersect(
+    triangle: &Triangle,
+    ray: &Ray<Native>,
+) -> Option<(f32, RigidTransform3D<f32, HitTestSpace, Native>)> {
+    let edge1 = triangle.second - triangle.first;
+    let edge2 = triangle.third - triangle.first;
+    let p = ray.direction.cross(edge2);
+    let det = edge1.dot(p);
+    if det.abs() < EPSILON {
+        // The ray is parallel to the triangle
+        return None;
+    }
+    let inv_det = 1.0 / det;
+    let s = ray.origin - triangle.first;
+    let u = s.dot(p) * inv_det;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+    let q = s.cross(edge1);
+    let v = ray.direction.dot(q) * inv_det;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+    let distance = edge2.dot(q) * inv_det;
+    if distance < EPSILON {
+        // The triangle is behind the ray
+        return None;
+    }
+    let point = ray.origin + ray.direction * distance;
+    let mut normal = edge1.cross(edge2).normalize();
+    if normal.dot(ray.direction) > 0.0 {
+        normal = -normal;
+    }
+    let rotation = rotation_from_up(normal);
+    Some((distance, RigidTransform3D::new(rotation, point)))
+}
+
+// The rotation taking the Y axis to the given unit vector
+fn rotation_from_up(normal: Vector3D<f32, Native>) -> Rotation3D<f32, HitTestSpace, Native> {
+    let up = Vector3D::new(0.0, 1.0, 0.0);
+    let axis = up.cross(normal);
+    if axis.length() < EPSILON {
+        if normal.y > 0.0 {
+            Rotation3D::identity()
+        } else {
+            Rotation3D::around_x(Angle::pi())
+        }
+    } else {
+        let angle = Angle::radians(up.dot(normal).max(-1.0).min(1.0).acos());
+        Rotation3D::around_axis(axis.cast_unit(), angle)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use webxr_api::MockRegion;
+
+    // A square of two triangles at a height, spanning 20m on each side
+    fn floor(y: f32, ty: EntityType) -> MockRegion {
+        let corner = |x, z| Vector3D::new(x, y, z);
+        MockRegion {
+            faces: vec![
+                Triangle {
+                    first: corner(-10.0, -10.0),
+                    second: corner(10.0, -10.0),
+                    third: corner(10.0, 10.0),
+                },
+                Triangle {
+                    first: corner(-10.0, -10.0),
+                    second: corner(10.0, 10.0),
+                    third: corner(-10.0, 10.0),
+                },
+            ],
+            ty,
+        }
+    }
+
+    fn world() -> MockWorld {
+        MockWorld {
+            regions: vec![
+                floor(-2.0, EntityType::Mesh),
+                floor(-1.0, EntityType::Plane),
+            ],
+        }
+    }
+
+    fn ray(y: f32) -> Ray<Native> {
+        Ray {
+            origin: Vector3D::new(0.5, 0.0, -0.25),
+            direction: Vector3D::new(0.0, y, 0.0),
+        }
+    }
+
+    #[test]
+    fn hits_are_nearest_first() {
+        let hits = hit_test(&world(), &ray(-1.0), &[EntityType::Plane, EntityType::Mesh]);
+        assert_eq!(hits.len(), 2);
+        assert_eq!(hits[0].translation, Vector3D::new(0.5, -1.0, -0.25));
+        assert_eq!(hits[1].translation, Vector3D::new(0.5, -2.0, -0.25));
+        // The floor faces up, back towards the ray
+        let up = hits[0]
+            .rotation
+            .transform_vector3d(Vector3D::new(0.0, 1.0, 0.0));
+        assert!((up - Vector3D::new(0.0, 1.0, 0.0)).length() < EPSILON);
+    }
+
+    #[test]
+    fn only_regions_of_the_given_types_are_hit() {
+        let hits = hit_test(&world(), &ray(-1.0), &[EntityType::Mesh]);
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].translation, Vector3D::new(0.5, -2.0, -0.25));
+        assert!(hit_test(&world(), &ray(-1.0), &[EntityType::Point]).is_empty());
+    }
+
+    #[test]
+    fn regions_behind_the_ray_are_not_hit() {
+        let hits = hit_test(&world(), &ray(1.0), &[EntityType::Plane, EntityType::Mesh]);
+        assert!(hits.is_empty());
+    }
+}
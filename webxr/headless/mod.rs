@@ -2,66 +2,371 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
 
+use webxr_api::Anchor;
+use webxr_api::AnchorFrame;
+use webxr_api::AnchorId;
+use webxr_api::ApiSpace;
+use webxr_api::BaseSpace;
+use webxr_api::Body;
+use webxr_api::CameraImage;
+use webxr_api::ColorScaleBias;
+use webxr_api::ColorSpace;
+use webxr_api::DepthDataFormat;
+use webxr_api::DepthInfo;
+use webxr_api::DepthSensingConfig;
+use webxr_api::DepthUsage;
+use webxr_api::DetectedMesh;
 use webxr_api::Device;
+use webxr_api::DeviceStatus;
 use webxr_api::Discovery;
+use webxr_api::EntityType;
+use webxr_api::EnvironmentBlendMode;
 use webxr_api::Error;
 use webxr_api::Event;
 use webxr_api::EventBuffer;
 use webxr_api::EventCallback;
+use webxr_api::EventFilter;
+use webxr_api::ExportedAnchor;
+use webxr_api::FaceExpressions;
+use webxr_api::Feature;
 use webxr_api::Floor;
 use webxr_api::Frame;
+use webxr_api::FrameCapture;
+use webxr_api::FrameTiming;
+use webxr_api::Gamepad;
+use webxr_api::GeospatialAnchorInit;
+use webxr_api::GlState;
+use webxr_api::Hand;
+use webxr_api::HighResTimeStamp;
+use webxr_api::HitTestId;
+use webxr_api::HitTestInit;
+use webxr_api::HitTestResult;
 use webxr_api::Input;
 use webxr_api::InputFrame;
 use webxr_api::InputSource;
+use webxr_api::JointFrame;
+use webxr_api::LayerId;
+use webxr_api::LayerInit;
+use webxr_api::LayerTexture;
+use webxr_api::LightEstimate;
+use webxr_api::MeshId;
+use webxr_api::MockAnchorMsg;
+use webxr_api::MockClock;
 use webxr_api::MockDeviceInit;
 use webxr_api::MockDeviceMsg;
 use webxr_api::MockDiscovery;
 use webxr_api::MockInputMsg;
+use webxr_api::MockScenarioAction;
+use webxr_api::MockScenarioStep;
+use webxr_api::MockWorld;
 use webxr_api::Native;
+use webxr_api::PerformanceSubDomain;
+use webxr_api::PinchSelect;
+use webxr_api::Poses;
 use webxr_api::Receiver;
+use webxr_api::SelectKind;
 use webxr_api::Sender;
 use webxr_api::Session;
 use webxr_api::SessionBuilder;
+use webxr_api::SessionCapabilities;
+use webxr_api::SessionEndReason;
+use webxr_api::SessionInit;
 use webxr_api::SessionMode;
+use webxr_api::SwapchainId;
+use webxr_api::SwapchainInit;
+use webxr_api::SwapchainLayout;
+use webxr_api::TargetRayMode;
+use webxr_api::TextureFormat;
+use webxr_api::TextureHandle;
+use webxr_api::TrackedImage;
+use webxr_api::TrackedImageFrame;
+use webxr_api::TrackedImageId;
+use webxr_api::TrackedImageInit;
+use webxr_api::TrackedImageScore;
+use webxr_api::TrackedImageState;
+use webxr_api::Velocity;
 use webxr_api::Viewer;
 use webxr_api::Views;
+use webxr_api::Visibility;
 
 use euclid::default::Size2D;
+use euclid::Angle;
+use euclid::Point2D;
 use euclid::RigidTransform3D;
+use euclid::Rotation3D;
+use euclid::Vector3D;
 
 use gleam::gl;
 use gleam::gl::GLsync;
 use gleam::gl::GLuint;
 use gleam::gl::Gl;
 
+use std::collections::VecDeque;
 use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::Condvar;
+use std::sync::Mutex;
+use std::time::Instant;
+
+mod hit_test;
+
+// The most images a swapchain can have
+const MAX_SWAPCHAIN_IMAGES: usize = 3;
+
+// A texture to capture, with the layer to capture for multiview textures
+type CaptureTexture = (GLuint, Option<i32>);
+
+// The mock device only keeps the texture it was last given, so it never waits on content
+const MAX_FRAMES_IN_FLIGHT: usize = 3;
 
 pub struct HeadlessMockDiscovery {
-    gl: Rc<dyn Gl>,
+    gl: Option<Rc<dyn Gl>>,
 }
 
 struct HeadlessDiscovery {
-    gl: Rc<dyn Gl>,
+    gl: Option<Rc<dyn Gl>>,
     init: MockDeviceInit,
+    receiver: Arc<(Mutex<MockChannel>, Condvar)>,
+}
+
+// The messages for a mock device, which every running session sees. A page can
+// have an inline and an immersive session at once, each with its own device.
+struct MockChannel {
+    // None while a session is blocked waiting for a message on it
     receiver: Option<Receiver<MockDeviceMsg>>,
+    // The messages each running session is yet to handle
+    queues: Vec<(usize, VecDeque<MockDeviceMsg>)>,
+    next_id: usize,
+}
+
+// A session's end of its mock device's messages. The condvar is signalled
+// when a session which was blocked on the receiver gives it back.
+struct MockReceiver {
+    channel: Arc<(Mutex<MockChannel>, Condvar)>,
+    id: usize,
+}
+
+impl MockChannel {
+    fn subscribe(channel: &Arc<(Mutex<MockChannel>, Condvar)>) -> MockReceiver {
+        let mut locked = channel.0.lock().unwrap();
+        let id = locked.next_id;
+        locked.next_id += 1;
+        locked.queues.push((id, VecDeque::new()));
+        MockReceiver {
+            channel: channel.clone(),
+            id,
+        }
+    }
+
+    fn dispatch(&mut self, msg: MockDeviceMsg) {
+        for (_, queue) in &mut self.queues {
+            queue.push_back(msg.clone());
+        }
+    }
+
+    fn pop(&mut self, id: usize) -> Option<MockDeviceMsg> {
+        self.queues
+            .iter_mut()
+            .find(|(queue, _)| *queue == id)
+            .and_then(|(_, queue)| queue.pop_front())
+    }
+}
+
+impl MockReceiver {
+    // While another session is blocked on the receiver, it passes on
+    // the messages as they arrive
+    fn try_recv(&self) -> Option<MockDeviceMsg> {
+        let mut channel = self.channel.0.lock().unwrap();
+        if let Some(receiver) = channel.receiver.take() {
+            while let Ok(msg) = receiver.try_recv() {
+                channel.dispatch(msg);
+            }
+            channel.receiver = Some(receiver);
+        }
+        channel.pop(self.id)
+    }
+
+    // Block until there is a message. The receiver is waited on without holding
+    // the lock, so the other sessions can carry on taking their messages.
+    fn recv(&self) -> Option<MockDeviceMsg> {
+        let (ref lock, ref returned) = *self.channel;
+        let mut channel = lock.lock().unwrap();
+        loop {
+            if let Some(msg) = channel.pop(self.id) {
+                return Some(msg);
+            }
+            match channel.receiver.take() {
+                Some(receiver) => {
+                    drop(channel);
+                    let msg = receiver.recv();
+                    channel = lock.lock().unwrap();
+                    channel.receiver = Some(receiver);
+                    returned.notify_all();
+                    channel.dispatch(msg.ok()?);
+                }
+                None => channel = returned.wait(channel).unwrap(),
+            }
+        }
+    }
+}
+
+impl Drop for MockReceiver {
+    fn drop(&mut self) {
+        if let Ok(mut channel) = self.channel.0.lock() {
+            channel.queues.retain(|(queue, _)| *queue != self.id);
+        }
+    }
 }
 
 struct InputInfo {
     source: InputSource,
     active: bool,
     pointer: RigidTransform3D<f32, Input, Native>,
+    grip: Option<RigidTransform3D<f32, Input, Native>>,
+    velocity: Velocity,
+    gamepad: Option<Gamepad>,
+    hand: Option<Box<Hand<JointFrame>>>,
+    // Working out select events from the hand, if it selects by pinching
+    pinch: Option<PinchSelect>,
+}
+
+struct AnchorInfo {
+    id: AnchorId,
+    pose: RigidTransform3D<f32, Anchor, Native>,
+    tracked: bool,
+    // Where on Earth a geospatial anchor is
+    geospatial: Option<GeospatialAnchorInit>,
+}
+
+// The mean radius of the Earth, in meters
+const EARTH_RADIUS: f64 = 6_371_000.0;
+
+// The pose of a place on Earth relative to the origin, given where on Earth the origin is.
+// The Earth is treated as flat around the origin, which is close enough nearby.
+fn geospatial_pose(
+    origin: &GeospatialAnchorInit,
+    place: &GeospatialAnchorInit,
+) -> RigidTransform3D<f32, Anchor, Native> {
+    let north = (place.latitude - origin.latitude).to_radians() * EARTH_RADIUS;
+    let east = (place.longitude - origin.longitude).to_radians()
+        * EARTH_RADIUS
+        * origin.latitude.to_radians().cos();
+    let up = place.altitude - origin.altitude;
+    let heading = place.heading.to_radians() as f32;
+    // Turning clockwise seen from above is turning around -Y
+    let rotation = Rotation3D::around_y(Angle::radians(-heading));
+    let translation = Vector3D::new(east as f32, up as f32, -north as f32);
+    RigidTransform3D::new(rotation, translation)
+}
+
+// Mock devices export an anchor as its pose in native space, as the rotation's
+// quaternion followed by the translation, in little-endian floats
+fn export_pose(pose: &RigidTransform3D<f32, Anchor, Native>) -> ExportedAnchor {
+    let rotation = pose.rotation;
+    let translation = pose.translation;
+    let values = [
+        rotation.i,
+        rotation.j,
+        rotation.k,
+        rotation.r,
+        translation.x,
+        translation.y,
+        translation.z,
+    ];
+    let mut bytes = Vec::with_capacity(values.len() * 4);
+    for value in &values {
+        bytes.extend_from_slice(&value.to_bits().to_le_bytes());
+    }
+    ExportedAnchor(bytes)
+}
+
+fn import_pose(anchor: &ExportedAnchor) -> Option<RigidTransform3D<f32, Anchor, Native>> {
+    if anchor.0.len() != 7 * 4 {
+        return None;
+    }
+    let values: Vec<f32> = anchor
+        .0
+        .chunks_exact(4)
+        .map(|bytes| f32::from_bits(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])))
+        .collect();
+    let rotation = Rotation3D::quaternion(values[0], values[1], values[2], values[3]);
+    let translation = Vector3D::new(values[4], values[5], values[6]);
+    Some(RigidTransform3D::new(rotation, translation))
+}
+
+struct TrackedImageInfo {
+    id: TrackedImageId,
+    width_in_meters: f32,
+    // Where the image is, once it has been seen
+    pose: Option<(
+        RigidTransform3D<f32, TrackedImage, Native>,
+        TrackedImageState,
+    )>,
 }
 
 struct HeadlessDevice {
-    gl: Rc<dyn Gl>,
+    gl: Option<Rc<dyn Gl>>,
     floor_transform: RigidTransform3D<f32, Native, Floor>,
     viewer_origin: RigidTransform3D<f32, Viewer, Native>,
+    viewer_velocity: Velocity,
     views: Views,
-    receiver: Receiver<MockDeviceMsg>,
+    receiver: MockReceiver,
     events: EventBuffer,
     inputs: Vec<InputInfo>,
     disconnect_callbacks: Vec<Sender<()>>,
     connected: bool,
+    // Whether the user is wearing the headset
+    user_present: bool,
+    granted_features: Vec<Feature>,
+    supported_frame_rates: Vec<f32>,
+    frame_rate: Option<f32>,
+    supported_color_spaces: Vec<ColorSpace>,
+    color_space: Option<ColorSpace>,
+    depth_sensing_config: Option<DepthSensingConfig>,
+    environment_blend_mode: EnvironmentBlendMode,
+    depth: Vec<DepthInfo>,
+    // The real world's depth, and whether the embedder wants it for occlusion
+    occlusion_depth: Vec<DepthInfo>,
+    occlusion: bool,
+    light_estimate: Option<LightEstimate>,
+    body: Option<Box<Body<JointFrame>>>,
+    face: Option<FaceExpressions>,
+    camera_images: Vec<CameraImage>,
+    status: DeviceStatus,
+    bounds_geometry: Option<Vec<Point2D<f32, Floor>>>,
+    world: Option<MockWorld>,
+    hit_tests: Vec<(HitTestId, HitTestInit)>,
+    anchors: Vec<AnchorInfo>,
+    tracked_images: Vec<TrackedImageInfo>,
+    geospatial_origin: Option<GeospatialAnchorInit>,
+    clock: MockClock,
+    started: Instant,
+    time: HighResTimeStamp,
+    ticks: VecDeque<HighResTimeStamp>,
+    // The steps of the scenario being played which are yet to happen,
+    // with their times relative to the device's clock
+    scenario: VecDeque<MockScenarioStep>,
+    // Whether a scenario has just been started, and should take
+    // its times from the next frame's
+    scenario_started: bool,
+    // The textures of the frame that was last rendered, for capturing them side by side,
+    // with the layer to capture for multiview textures, and the size of each
+    rendered: Option<(Vec<CaptureTexture>, Size2D<i32>)>,
+    // The scale and bias of the colors of the frame last rendered, applied when capturing it
+    color_scale_bias: ColorScaleBias,
+    // A framebuffer for reading back frames, created when first needed
+    read_fbo: Option<GLuint>,
+    swapchains: Vec<MockSwapchain>,
+    swapchain_layout: SwapchainLayout,
+}
+
+struct MockSwapchain {
+    id: SwapchainId,
+    size: Size2D<i32>,
+    array_size: usize,
+    textures: Vec<GLuint>,
+    // The image which will next be acquired
+    next: usize,
 }
 
 impl MockDiscovery for HeadlessMockDiscovery {
@@ -73,69 +378,589 @@ impl MockDiscovery for HeadlessMockDiscovery {
         Ok(Box::new(HeadlessDiscovery {
             gl: self.gl.clone(),
             init,
-            receiver: Some(receiver),
+            receiver: Arc::new((
+                Mutex::new(MockChannel {
+                    receiver: Some(receiver),
+                    queues: vec![],
+                    next_id: 0,
+                }),
+                Condvar::new(),
+            )),
         }))
     }
 }
 
 impl Discovery for HeadlessDiscovery {
-    fn request_session(&mut self, mode: SessionMode, xr: SessionBuilder) -> Result<Session, Error> {
-        if !self.supports_session(mode) {
-            return Err(Error::NoMatchingDevice);
-        }
-        let gl = self.gl.clone();
-        let receiver = self.receiver.take().ok_or(Error::NoMatchingDevice)?;
-        let viewer_origin = self.init.viewer_origin.clone();
+    fn request_session(
+        &mut self,
+        mode: SessionMode,
+        init: &SessionInit,
+        xr: SessionBuilder,
+    ) -> Result<Session, Error> {
+        let supported_features = self
+            .supported_features(mode)
+            .ok_or(Error::NoMatchingDevice)?;
+        let mut granted_features = init.validate(mode, &supported_features)?;
+        // The mock device reports whatever depth information the test gives it,
+        // so it can support any configuration.
+        let depth_sensing_config = init.negotiate_depth_sensing(
+            &mut granted_features,
+            &[DepthUsage::CpuOptimized, DepthUsage::GpuOptimized],
+            &[DepthDataFormat::LuminanceAlpha, DepthDataFormat::Float32],
+        )?;
+        // The mock device can display either layout
+        let swapchain_layout =
+            init.negotiate_swapchain_layout(&[SwapchainLayout::Shared, SwapchainLayout::PerView]);
+        // The mock device stands in for a phone, which shows AR through its camera
+        let environment_blend_mode = if mode == SessionMode::ImmersiveAR {
+            EnvironmentBlendMode::AlphaBlend
+        } else {
+            EnvironmentBlendMode::Opaque
+        };
+        // Sessions see the messages sent from when they start, so one started
+        // after others doesn't see the changes they were sent before then
+        let receiver = MockChannel::subscribe(&self.receiver);
+        let viewer_origin = self.init.viewer_origin;
         let floor_transform = self.init.floor_origin.inverse();
         let views = self.init.views.clone();
-        xr.run_on_main_thread(move || {
-            Ok(HeadlessDevice {
+        let supported_frame_rates = self.init.supported_frame_rates.clone();
+        let supported_color_spaces = self.init.supported_color_spaces.clone();
+        let color_space = supported_color_spaces.first().copied();
+        let bounds_geometry = self.init.bounds_geometry.clone();
+        let world = self.init.world.clone();
+        let clock = self.init.clock;
+        let device = move |gl| {
+            let mut device = HeadlessDevice {
                 gl,
                 floor_transform,
                 viewer_origin,
+                viewer_velocity: Default::default(),
                 views,
                 receiver,
                 events: Default::default(),
                 disconnect_callbacks: vec![],
                 connected: true,
+                user_present: true,
                 inputs: vec![],
-            })
-        })
+                granted_features,
+                supported_frame_rates,
+                frame_rate: None,
+                supported_color_spaces,
+                color_space,
+                depth_sensing_config,
+                environment_blend_mode,
+                depth: vec![],
+                occlusion_depth: vec![],
+                occlusion: false,
+                light_estimate: None,
+                body: None,
+                face: None,
+                camera_images: vec![],
+                status: Default::default(),
+                bounds_geometry,
+                world,
+                hit_tests: vec![],
+                anchors: vec![],
+                tracked_images: vec![],
+                geospatial_origin: None,
+                clock,
+                started: Instant::now(),
+                time: 0.0,
+                ticks: VecDeque::new(),
+                scenario: VecDeque::new(),
+                scenario_started: false,
+                rendered: None,
+                color_scale_bias: ColorScaleBias::IDENTITY,
+                read_fbo: None,
+                swapchains: vec![],
+                swapchain_layout,
+            };
+            if device.granted_features.contains(&Feature::MeshDetection) {
+                device.detect_meshes();
+            }
+            Ok(device)
+        };
+        // Without GL, nothing ties the device to the main thread
+        match self.gl.clone() {
+            Some(gl) => xr.run_on_main_thread(move || device(Some(gl))),
+            None => xr.spawn(move || device(None)),
+        }
     }
 
     fn supports_session(&self, mode: SessionMode) -> bool {
         mode == SessionMode::Inline || self.init.supports_immersive
     }
+
+    fn supported_features(&self, mode: SessionMode) -> Option<Vec<Feature>> {
+        if !self.supports_session(mode) {
+            return None;
+        }
+        let mut supported_features = self.init.supported_features.clone();
+        if self.init.supports_unbounded {
+            supported_features.push(Feature::Unbounded);
+        }
+        Some(supported_features)
+    }
+
+    fn capabilities(&self, mode: SessionMode) -> Option<SessionCapabilities> {
+        let blend_mode = if mode == SessionMode::ImmersiveAR {
+            EnvironmentBlendMode::AlphaBlend
+        } else {
+            EnvironmentBlendMode::Opaque
+        };
+        Some(SessionCapabilities {
+            mode,
+            features: Some(self.supported_features(mode)?),
+            environment_blend_modes: vec![blend_mode],
+            frame_rates: self.init.supported_frame_rates.clone(),
+            view_count: Some(self.init.views.viewports().len()),
+        })
+    }
 }
 
 impl Device for HeadlessDevice {
     fn floor_transform(&self) -> RigidTransform3D<f32, Native, Floor> {
-        self.floor_transform.clone()
+        self.floor_transform
+    }
+
+    fn bounds_geometry(&self) -> Option<Vec<Point2D<f32, Floor>>> {
+        if self.granted_features.contains(&Feature::BoundedFloor) {
+            self.bounds_geometry.clone()
+        } else {
+            None
+        }
     }
 
     fn views(&self) -> Views {
         self.views.clone()
     }
 
+    fn views_into(&self, views: &mut Views) {
+        views.clone_from(&self.views)
+    }
+
+    fn supported_frame_rates(&self) -> Vec<f32> {
+        self.supported_frame_rates.clone()
+    }
+
+    fn update_frame_rate(&mut self, rate: f32) {
+        if self.frame_rate != Some(rate) && self.supported_frame_rates.contains(&rate) {
+            self.frame_rate = Some(rate);
+            self.events.callback(Event::FrameRateChange(rate));
+        }
+    }
+
+    fn supported_color_spaces(&self) -> Vec<ColorSpace> {
+        self.supported_color_spaces.clone()
+    }
+
+    fn color_space(&self) -> Option<ColorSpace> {
+        self.color_space
+    }
+
+    fn update_color_space(&mut self, space: ColorSpace) {
+        if self.color_space != Some(space) && self.supported_color_spaces.contains(&space) {
+            self.color_space = Some(space);
+            self.events.callback(Event::ColorSpaceChange(space));
+        }
+    }
+
+    fn update_viewport_scale(&mut self, scale: f32) -> f32 {
+        // The mock device doesn't display anything, so it can use any scale.
+        scale
+    }
+
+    fn supports_multiview(&self) -> bool {
+        // Texture array swapchains are created in the same way as others
+        self.gl.is_some()
+    }
+
+    fn max_frames_in_flight(&self) -> usize {
+        MAX_FRAMES_IN_FLIGHT
+    }
+
     fn wait_for_animation_frame(&mut self) -> Frame {
-        while let Ok(msg) = self.receiver.try_recv() {
-            self.handle_msg(msg);
+        self.handle_msgs();
+        self.advance_clock();
+        self.play_scenario();
+        self.frame()
+    }
+
+    fn fill_animation_frame(&mut self, frame: &mut Frame) {
+        self.handle_msgs();
+        self.advance_clock();
+        self.play_scenario();
+        self.fill_frame(frame);
+    }
+
+    fn poses_at(&mut self, time: HighResTimeStamp) -> Result<Poses, Error> {
+        // The viewer moves as the scenario says it will by then,
+        // and otherwise stays still until the test moves it
+        let offset = if self.scenario_started {
+            self.time
+        } else {
+            0.0
+        };
+        let transform = self
+            .scenario
+            .iter()
+            .take_while(|step| step.time + offset <= time)
+            .filter_map(|step| match step.action {
+                MockScenarioAction::SetViewerOrigin(origin) => Some(origin),
+                _ => None,
+            })
+            .last()
+            .unwrap_or(self.viewer_origin);
+        let mut inputs = vec![];
+        self.input_frames(&mut inputs);
+        Ok(Poses {
+            time,
+            transform,
+            velocity: self.viewer_velocity,
+            inputs,
+        })
+    }
+
+    fn render_animation_frame(&mut self, texture_id: GLuint, size: Size2D<i32>, sync: GLsync) {
+        if let Some(ref gl) = self.gl {
+            if !sync.is_null() {
+                let _span = webxr_api::span("wait_sync");
+                gl.wait_sync(sync, 0, gl::TIMEOUT_IGNORED);
+            }
+        }
+        self.rendered = Some((vec![(texture_id, None)], size));
+    }
+
+    fn create_swapchain(
+        &mut self,
+        id: SwapchainId,
+        init: SwapchainInit,
+    ) -> Result<Vec<TextureHandle>, Error> {
+        // The swapchain's images are textures shared with content's context,
+        // which GL can render to, sample from and copy
+        let gl = self.gl.as_ref().ok_or(Error::UnsupportedFeature)?;
+        if init.format != TextureFormat::Rgba8Unorm {
+            return Err(Error::UnsupportedFeature);
+        }
+        // Multiview swapchains have a layer per view
+        if init.array_size == 0 || init.array_size > self.views().viewports().len() {
+            return Err(Error::UnsupportedFeature);
+        }
+        let count = init.image_count.min(MAX_SWAPCHAIN_IMAGES).max(1);
+        let state = GlState::save(gl);
+        let textures = gl.gen_textures(count as i32);
+        for texture in &textures {
+            if init.array_size > 1 {
+                gl.bind_texture(gl::TEXTURE_2D_ARRAY, *texture);
+                gl.tex_image_3d(
+                    gl::TEXTURE_2D_ARRAY,
+                    0,
+                    gl::RGBA as i32,
+                    init.size.width,
+                    init.size.height,
+                    init.array_size as i32,
+                    0,
+                    gl::RGBA,
+                    gl::UNSIGNED_BYTE,
+                    None,
+                );
+            } else {
+                gl.bind_texture(gl::TEXTURE_2D, *texture);
+                gl.tex_image_2d(
+                    gl::TEXTURE_2D,
+                    0,
+                    gl::RGBA as i32,
+                    init.size.width,
+                    init.size.height,
+                    0,
+                    gl::RGBA,
+                    gl::UNSIGNED_BYTE,
+                    None,
+                );
+            }
+        }
+        drop(state);
+        let handles = textures
+            .iter()
+            .map(|texture| TextureHandle::Gl(*texture))
+            .collect();
+        self.swapchains.push(MockSwapchain {
+            id,
+            size: init.size,
+            array_size: init.array_size,
+            textures,
+            next: 0,
+        });
+        Ok(handles)
+    }
+
+    fn swapchain_layout(&self) -> SwapchainLayout {
+        self.swapchain_layout
+    }
+
+    fn acquire_swapchain_image(&mut self, id: SwapchainId) -> Result<usize, Error> {
+        // Images are handed out in turn, without waiting for them to be displayed
+        let swapchain = self
+            .swapchains
+            .iter_mut()
+            .find(|swapchain| swapchain.id == id)
+            .ok_or(Error::UnsupportedFeature)?;
+        let index = swapchain.next;
+        swapchain.next = (index + 1) % swapchain.textures.len();
+        Ok(index)
+    }
+
+    fn destroy_swapchain(&mut self, id: SwapchainId) {
+        if let Some(index) = self
+            .swapchains
+            .iter()
+            .position(|swapchain| swapchain.id == id)
+        {
+            let swapchain = self.swapchains.remove(index);
+            if let Some(ref gl) = self.gl {
+                gl.delete_textures(&swapchain.textures);
+            }
+        }
+    }
+
+    fn render_layers(&mut self, layers: &[LayerTexture]) {
+        // The mock device can only read back GL textures
+        let layer = layers.iter().rev().find(|layer| {
+            layer.init.is_projection()
+                && (layer.swapchain_image.is_some()
+                    || !layer.view_images.is_empty()
+                    || layer.texture_id != 0)
+        });
+        let layer = match layer {
+            Some(layer) => layer,
+            None => return,
+        };
+        self.color_scale_bias = layer.color_scale_bias;
+        let swapchain = |(id, index): (SwapchainId, usize)| {
+            let swapchain = self
+                .swapchains
+                .iter()
+                .find(|swapchain| swapchain.id == id)?;
+            Some((*swapchain.textures.get(index)?, swapchain))
+        };
+        if !layer.view_images.is_empty() {
+            let textures: Option<Vec<CaptureTexture>> = layer
+                .view_images
+                .iter()
+                .map(|image| Some((swapchain(*image)?.0, None)))
+                .collect();
+            self.rendered = textures.map(|textures| (textures, layer.size));
+            return;
         }
-        let transform = self.viewer_origin;
-        let inputs = self
-            .inputs
+        match layer.swapchain_image {
+            Some(image) => {
+                self.rendered = swapchain(image).map(|(texture, swapchain)| {
+                    // Multiview textures have a layer per view
+                    let textures = if swapchain.array_size > 1 {
+                        (0..swapchain.array_size as i32)
+                            .map(|layer| (texture, Some(layer)))
+                            .collect()
+                    } else {
+                        vec![(texture, None)]
+                    };
+                    (textures, swapchain.size.to_untyped())
+                });
+            }
+            None => self.render_animation_frame(layer.texture_id, layer.size, layer.sync),
+        }
+    }
+
+    fn capture_frame(&mut self) -> Option<FrameCapture> {
+        // The mock device displays whatever texture it was last given.
+        let (textures, size) = self.rendered.take()?;
+        let gl = self.gl.as_ref()?;
+        let state = GlState::save(gl);
+        let read_fbo = *self
+            .read_fbo
+            .get_or_insert_with(|| gl.gen_framebuffers(1)[0]);
+        gl.bind_framebuffer(gl::READ_FRAMEBUFFER, read_fbo);
+        let views: Vec<Vec<u8>> = textures
             .iter()
-            .filter(|i| i.active)
-            .map(|i| InputFrame {
-                id: i.source.id,
-                target_ray_origin: i.pointer,
+            .map(|(texture_id, layer)| {
+                match layer {
+                    Some(layer) => gl.framebuffer_texture_layer(
+                        gl::READ_FRAMEBUFFER,
+                        gl::COLOR_ATTACHMENT0,
+                        *texture_id,
+                        0,
+                        *layer,
+                    ),
+                    None => gl.framebuffer_texture_2d(
+                        gl::READ_FRAMEBUFFER,
+                        gl::COLOR_ATTACHMENT0,
+                        gl::TEXTURE_2D,
+                        *texture_id,
+                        0,
+                    ),
+                }
+                gl.read_pixels(0, 0, size.width, size.height, gl::RGBA, gl::UNSIGNED_BYTE)
             })
             .collect();
-        Frame { transform, inputs }
+        drop(state);
+        // Views rendered to separate textures or layers are captured side by side,
+        // as they would have been rendered to a shared texture
+        let row = size.width as usize * 4;
+        let mut data: Vec<u8> = (0..size.height as usize)
+            .flat_map(|y| views.iter().flat_map(move |view| &view[y * row..][..row]))
+            .copied()
+            .collect();
+        let size = Size2D::new(size.width * textures.len() as i32, size.height);
+        if !self.color_scale_bias.is_identity() {
+            for pixel in data.chunks_exact_mut(4) {
+                let color = self
+                    .color_scale_bias
+                    .apply([pixel[0], pixel[1], pixel[2], pixel[3]]);
+                pixel.copy_from_slice(&color);
+            }
+        }
+        Some(FrameCapture {
+            size: euclid::Size2D::from_untyped(size),
+            data,
+        })
+    }
+
+    fn granted_features(&self) -> Vec<Feature> {
+        self.granted_features.clone()
+    }
+
+    fn depth_sensing_config(&self) -> Option<DepthSensingConfig> {
+        self.depth_sensing_config
+    }
+
+    fn set_occlusion(&mut self, enabled: bool) -> Result<(), Error> {
+        // The mock device can report whatever depth the test gives it
+        self.occlusion = enabled;
+        Ok(())
+    }
+
+    fn environment_blend_mode(&self) -> EnvironmentBlendMode {
+        self.environment_blend_mode
+    }
+
+    fn create_layer(&mut self, _id: LayerId, init: LayerInit) -> Result<(), Error> {
+        // The mock device doesn't composite, so it can accept any layer
+        // content has been granted the feature for, and the embedder's own.
+        let feature = match init {
+            LayerInit::Projection | LayerInit::HeadLockedQuad { .. } => return Ok(()),
+            LayerInit::Quad { .. } | LayerInit::Cube { .. } => Feature::Layers,
+            LayerInit::DomOverlay { .. } => Feature::DomOverlay,
+        };
+        if self.granted_features.contains(&feature) {
+            Ok(())
+        } else {
+            Err(Error::UnsupportedFeature)
+        }
+    }
+
+    fn request_hit_test(&mut self, id: HitTestId, init: HitTestInit) -> Result<(), Error> {
+        if !self.granted_features.contains(&Feature::HitTest) {
+            return Err(Error::UnsupportedFeature);
+        }
+        self.hit_tests.push((id, init));
+        Ok(())
+    }
+
+    fn cancel_hit_test(&mut self, id: HitTestId) {
+        self.hit_tests.retain(|(hit_test, _)| *hit_test != id);
+    }
+
+    fn create_anchor(
+        &mut self,
+        id: AnchorId,
+        pose: RigidTransform3D<f32, Anchor, Native>,
+    ) -> Result<(), Error> {
+        if !self.granted_features.contains(&Feature::Anchors) {
+            return Err(Error::UnsupportedFeature);
+        }
+        self.anchors.push(AnchorInfo {
+            id,
+            pose,
+            tracked: true,
+            geospatial: None,
+        });
+        Ok(())
+    }
+
+    fn create_geospatial_anchor(
+        &mut self,
+        id: AnchorId,
+        init: GeospatialAnchorInit,
+    ) -> Result<(), Error> {
+        if !self.granted_features.contains(&Feature::Geospatial) {
+            return Err(Error::UnsupportedFeature);
+        }
+        let pose = self
+            .geospatial_origin
+            .map(|origin| geospatial_pose(&origin, &init));
+        self.anchors.push(AnchorInfo {
+            id,
+            pose: pose.unwrap_or_else(RigidTransform3D::identity),
+            tracked: pose.is_some(),
+            geospatial: Some(init),
+        });
+        Ok(())
+    }
+
+    fn delete_anchor(&mut self, id: AnchorId) {
+        self.anchors.retain(|anchor| anchor.id != id);
+    }
+
+    fn export_anchor(&mut self, id: AnchorId) -> Result<ExportedAnchor, Error> {
+        let anchor = self
+            .anchors
+            .iter()
+            .find(|anchor| anchor.id == id)
+            .ok_or_else(|| Error::backend("headless", "no such anchor", None))?;
+        // Anchors can't be exported while the device doesn't know where they are
+        if !anchor.tracked {
+            return Err(Error::backend("headless", "anchor is not tracked", None));
+        }
+        Ok(export_pose(&anchor.pose))
+    }
+
+    fn import_anchor(&mut self, id: AnchorId, anchor: ExportedAnchor) -> Result<(), Error> {
+        if !self.granted_features.contains(&Feature::Anchors) {
+            return Err(Error::UnsupportedFeature);
+        }
+        let pose = import_pose(&anchor)
+            .ok_or_else(|| Error::backend("headless", "not an exported anchor", None))?;
+        self.anchors.push(AnchorInfo {
+            id,
+            pose,
+            tracked: true,
+            geospatial: None,
+        });
+        Ok(())
+    }
+
+    fn add_tracked_image(
+        &mut self,
+        id: TrackedImageId,
+        init: TrackedImageInit,
+    ) -> Result<TrackedImageScore, Error> {
+        if !self.granted_features.contains(&Feature::ImageTracking) {
+            return Err(Error::UnsupportedFeature);
+        }
+        // The mock device can find any image with pixels in it
+        let pixels = init.size.width.max(0) as usize * init.size.height.max(0) as usize;
+        if pixels == 0 || init.pixels.len() < pixels * 4 || init.width_in_meters <= 0.0 {
+            return Ok(TrackedImageScore::Untrackable);
+        }
+        self.tracked_images.push(TrackedImageInfo {
+            id,
+            width_in_meters: init.width_in_meters,
+            pose: None,
+        });
+        Ok(TrackedImageScore::Trackable)
     }
 
-    fn render_animation_frame(&mut self, _: GLuint, _: Size2D<i32>, sync: GLsync) {
-        self.gl.wait_sync(sync, 0, gl::TIMEOUT_IGNORED);
+    fn remove_tracked_image(&mut self, id: TrackedImageId) {
+        self.tracked_images.retain(|image| image.id != id);
     }
 
     fn initial_inputs(&self) -> Vec<InputSource> {
@@ -146,7 +971,14 @@ impl Device for HeadlessDevice {
         self.events.upgrade(callback)
     }
 
+    fn subscribe_events(&mut self, filter: EventFilter, callback: Box<dyn EventCallback>) {
+        self.events.subscribe(filter, callback)
+    }
+
     fn connected(&mut self) -> bool {
+        // Handling test messages here means a disconnection takes effect
+        // before the session does anything else.
+        self.handle_msgs();
         if self.connected {
             true
         } else {
@@ -157,57 +989,1185 @@ impl Device for HeadlessDevice {
         }
     }
 
+    fn poll_events(&mut self) {
+        self.handle_msgs();
+    }
+
     fn quit(&mut self) {
         self.connected = false;
-        self.events.callback(Event::SessionEnd);
+        self.events
+            .callback(Event::SessionEnd(SessionEndReason::Requested));
     }
 }
 
 impl HeadlessMockDiscovery {
     pub fn new(gl: Rc<dyn Gl>) -> HeadlessMockDiscovery {
-        HeadlessMockDiscovery { gl }
+        HeadlessMockDiscovery { gl: Some(gl) }
+    }
+
+    /// A mock discovery without GL, whose devices each run on their own thread
+    /// rather than the main thread. The frames they are given are not waited
+    /// for, and cannot be captured.
+    pub fn new_threaded() -> HeadlessMockDiscovery {
+        HeadlessMockDiscovery { gl: None }
     }
 }
 
 impl HeadlessDevice {
+    // Each mesh region of the world is reported as a detected mesh
+    fn set_world(&mut self, world: Option<MockWorld>) {
+        let detecting = self.granted_features.contains(&Feature::MeshDetection);
+        if detecting {
+            for id in self.mesh_ids() {
+                self.events.callback(Event::RemoveMesh(id));
+            }
+        }
+        self.world = world;
+        if detecting {
+            self.detect_meshes();
+        }
+    }
+
+    fn mesh_ids(&self) -> Vec<MeshId> {
+        let regions = self.world.iter().flat_map(|world| world.regions.iter());
+        regions
+            .enumerate()
+            .filter(|(_, region)| region.ty == EntityType::Mesh)
+            .map(|(index, _)| MeshId(index as u32))
+            .collect()
+    }
+
+    fn detect_meshes(&mut self) {
+        let regions = self.world.iter().flat_map(|world| world.regions.iter());
+        for (index, region) in regions.enumerate() {
+            if region.ty != EntityType::Mesh {
+                continue;
+            }
+            let vertices: Vec<_> = region
+                .faces
+                .iter()
+                .flat_map(|face| vec![face.first, face.second, face.third])
+                .map(|vertex| vertex.cast_unit().to_point())
+                .collect();
+            self.events.callback(Event::UpdateMesh(DetectedMesh {
+                id: MeshId(index as u32),
+                pose: RigidTransform3D::identity(),
+                indices: (0..vertices.len() as u32).collect(),
+                vertices,
+                last_changed_time: self.time,
+                semantic_label: None,
+            }));
+        }
+    }
+
+    fn set_status(&mut self, status: DeviceStatus) {
+        if self.status != status {
+            self.status = status;
+            self.events.callback(Event::DeviceStatus(status));
+        }
+    }
+
+    // The current state of the device, as a frame
+    fn frame(&self) -> Frame {
+        let mut frame = Frame {
+            transform: self.viewer_origin,
+            velocity: self.viewer_velocity,
+            inputs: vec![],
+            depth: vec![],
+            occlusion_depth: vec![],
+            light_estimate: None,
+            camera_images: vec![],
+            hit_test_results: vec![],
+            anchors: vec![],
+            tracked_images: vec![],
+            body: None,
+            face: None,
+            viewports: Default::default(),
+            device_images: vec![],
+            timing: Default::default(),
+        };
+        self.fill_frame(&mut frame);
+        frame
+    }
+
+    // Fill in a frame with the current state, reusing its buffers
+    fn fill_frame(&self, frame: &mut Frame) {
+        frame.transform = self.viewer_origin;
+        frame.velocity = self.viewer_velocity;
+        self.input_frames(&mut frame.inputs);
+        frame.depth.clear();
+        if self.depth_sensing_config.is_some() {
+            frame.depth.extend_from_slice(&self.depth);
+        }
+        frame.occlusion_depth.clear();
+        if self.occlusion {
+            frame
+                .occlusion_depth
+                .extend_from_slice(&self.occlusion_depth);
+        }
+        frame.light_estimate = if self.granted_features.contains(&Feature::LightEstimation) {
+            self.light_estimate
+        } else {
+            None
+        };
+        frame.body = if self.granted_features.contains(&Feature::BodyTracking) {
+            self.body.clone()
+        } else {
+            None
+        };
+        frame.face = if self.granted_features.contains(&Feature::FaceTracking) {
+            self.face.clone()
+        } else {
+            None
+        };
+        frame.camera_images.clear();
+        if self.granted_features.contains(&Feature::CameraAccess) {
+            frame.camera_images.extend_from_slice(&self.camera_images);
+        }
+        self.hit_test_results(&mut frame.hit_test_results);
+        frame.anchors.clear();
+        frame.anchors.extend(
+            self.anchors
+                .iter()
+                .filter(|anchor| anchor.tracked)
+                .map(|anchor| AnchorFrame {
+                    id: anchor.id,
+                    pose: anchor.pose,
+                }),
+        );
+        frame.tracked_images.clear();
+        frame
+            .tracked_images
+            .extend(self.tracked_images.iter().filter_map(|image| {
+                let (pose, state) = image.pose?;
+                Some(TrackedImageFrame {
+                    id: image.id,
+                    pose,
+                    state,
+                    measured_width_in_meters: image.width_in_meters,
+                })
+            }));
+        frame.viewports.clear();
+        frame.device_images.clear();
+        frame.timing = FrameTiming {
+            predicted_display_time: Some(self.time),
+            ..Default::default()
+        };
+    }
+
+    fn input_frames(&self, inputs: &mut Vec<InputFrame>) {
+        let hand_tracking = self.granted_features.contains(&Feature::HandTracking);
+        inputs.clear();
+        inputs.extend(self.inputs.iter().filter(|i| i.active).map(|i| InputFrame {
+            id: i.source.id,
+            target_ray_origin: self.target_ray_origin(i),
+            grip_origin: i.grip.filter(|_| i.source.has_grip),
+            velocity: i.velocity,
+            gamepad: i.gamepad.clone(),
+            hand: i.hand.clone().filter(|_| hand_tracking),
+        }));
+    }
+
+    fn target_ray_origin(&self, input: &InputInfo) -> RigidTransform3D<f32, Input, Native> {
+        if input.source.target_ray_mode == TargetRayMode::Gaze {
+            input
+                .pointer
+                .cast_unit::<Input, Viewer>()
+                .post_transform(&self.viewer_origin)
+        } else {
+            input.pointer
+        }
+    }
+
+    fn hit_test_results(&self, results: &mut Vec<HitTestResult>) {
+        results.clear();
+        let world = match self.world {
+            Some(ref world) => world,
+            None => return,
+        };
+        for (id, init) in &self.hit_tests {
+            let base_space = match self.base_space(init.space) {
+                Some(base_space) => base_space,
+                None => continue,
+            };
+            let ray = init.ray.transform(&base_space);
+            let poses = hit_test::hit_test(world, &ray, &init.entity_types);
+            results.extend(
+                poses
+                    .into_iter()
+                    .map(|space| HitTestResult { id: *id, space }),
+            );
+        }
+    }
+
+    // The pose of a base space, or None if it isn't being tracked
+    fn base_space(&self, space: BaseSpace) -> Option<RigidTransform3D<f32, ApiSpace, Native>> {
+        match space {
+            BaseSpace::Local => Some(RigidTransform3D::identity()),
+            BaseSpace::Floor => Some(self.floor_transform.inverse().cast_unit()),
+            BaseSpace::Viewer => Some(self.viewer_origin.cast_unit()),
+            BaseSpace::Input(id) => self
+                .inputs
+                .iter()
+                .find(|input| input.source.id == id && input.active)
+                .map(|input| self.target_ray_origin(input).cast_unit()),
+        }
+    }
+
+    fn handle_msgs(&mut self) {
+        let _span = webxr_api::span("poll_input");
+        while self.connected {
+            match self.receiver.try_recv() {
+                Some(msg) => self.handle_msg(msg),
+                None => break,
+            }
+        }
+    }
+
+    fn advance_clock(&mut self) {
+        match self.clock {
+            MockClock::WallClock => {
+                let elapsed = self.started.elapsed();
+                self.time = elapsed.as_secs() as f64 * 1000.0
+                    + f64::from(elapsed.subsec_nanos()) / 1_000_000.0;
+            }
+            MockClock::FixedTimestep(step) => self.time += step,
+            MockClock::Manual => {
+                while self.ticks.is_empty() && self.connected {
+                    match self.receiver.recv() {
+                        Some(msg) => self.handle_msg(msg),
+                        None => break,
+                    }
+                }
+                self.time += self.ticks.pop_front().unwrap_or(0.0);
+            }
+        }
+    }
+
+    fn play_scenario(&mut self) {
+        if self.scenario_started {
+            self.scenario_started = false;
+            for step in &mut self.scenario {
+                step.time += self.time;
+            }
+        }
+        while let Some(step) = self.scenario.pop_front() {
+            if step.time > self.time || !self.connected {
+                self.scenario.push_front(step);
+                break;
+            }
+            self.handle_action(step.action);
+        }
+    }
+
+    fn handle_action(&mut self, action: MockScenarioAction) {
+        let msg = match action {
+            MockScenarioAction::SetViewerOrigin(origin) => MockDeviceMsg::SetViewerOrigin(origin),
+            MockScenarioAction::SetFloorOrigin(origin) => MockDeviceMsg::SetFloorOrigin(origin),
+            MockScenarioAction::SetViews(views) => MockDeviceMsg::SetViews(views),
+            MockScenarioAction::AddInputSource(init) => MockDeviceMsg::AddInputSource(init),
+            MockScenarioAction::MessageInputSource(id, msg) => {
+                MockDeviceMsg::MessageInputSource(id, msg)
+            }
+            MockScenarioAction::VisibilityChange(visibility) => {
+                MockDeviceMsg::VisibilityChange(visibility)
+            }
+            MockScenarioAction::SetUserPresent(present) => MockDeviceMsg::SetUserPresent(present),
+            MockScenarioAction::SetWorld(world) => MockDeviceMsg::SetWorld(world),
+            MockScenarioAction::Disconnect => {
+                self.connected = false;
+                self.events
+                    .callback(Event::SessionEnd(SessionEndReason::Disconnected));
+                return;
+            }
+        };
+        self.handle_msg(msg);
+    }
+
     fn handle_msg(&mut self, msg: MockDeviceMsg) {
         match msg {
             MockDeviceMsg::SetViewerOrigin(viewer_origin) => {
                 self.viewer_origin = viewer_origin;
             }
+            MockDeviceMsg::SetViewerVelocity(velocity) => {
+                self.viewer_velocity = velocity;
+            }
             MockDeviceMsg::SetViews(views) => {
-                self.views = views;
+                self.views = views.clone();
+                self.events.callback(Event::UpdateViews(views));
+            }
+            MockDeviceMsg::SetFloorOrigin(floor_origin) => {
+                self.floor_transform = floor_origin.inverse();
+                self.events
+                    .callback(Event::UpdateFloorTransform(self.floor_transform));
+            }
+            MockDeviceMsg::SetBoundsGeometry(bounds_geometry) => {
+                self.bounds_geometry = bounds_geometry;
+                if self.granted_features.contains(&Feature::BoundedFloor) {
+                    self.events
+                        .callback(Event::UpdateBoundsGeometry(self.bounds_geometry.clone()));
+                }
+            }
+            MockDeviceMsg::MessageAnchor(id, msg) => {
+                if let Some(index) = self.anchors.iter().position(|a| a.id == id) {
+                    match msg {
+                        MockAnchorMsg::SetAnchorOrigin(pose) => self.anchors[index].pose = pose,
+                        MockAnchorMsg::PauseTracking => self.anchors[index].tracked = false,
+                        MockAnchorMsg::ResumeTracking => self.anchors[index].tracked = true,
+                        MockAnchorMsg::StopTracking => {
+                            self.anchors.remove(index);
+                        }
+                    }
+                }
+            }
+            MockDeviceMsg::SetGeospatialOrigin(origin) => {
+                self.geospatial_origin = origin;
+                for anchor in &mut self.anchors {
+                    if let Some(ref place) = anchor.geospatial {
+                        match origin {
+                            Some(ref origin) => {
+                                anchor.pose = geospatial_pose(origin, place);
+                                anchor.tracked = true;
+                            }
+                            None => anchor.tracked = false,
+                        }
+                    }
+                }
+            }
+            MockDeviceMsg::SetTrackedImagePose(id, pose) => {
+                if let Some(image) = self.tracked_images.iter_mut().find(|i| i.id == id) {
+                    image.pose = pose;
+                }
+            }
+            MockDeviceMsg::PlayScenario(scenario) => {
+                let mut steps = scenario.steps;
+                steps.sort_by(|a, b| {
+                    a.time
+                        .partial_cmp(&b.time)
+                        .unwrap_or(std::cmp::Ordering::Equal)
+                });
+                self.scenario = steps.into();
+                self.scenario_started = true;
+            }
+            MockDeviceMsg::Tick(delta) => {
+                if self.clock == MockClock::Manual {
+                    self.ticks.push_back(delta);
+                }
+            }
+            MockDeviceMsg::SetWorld(world) => self.set_world(Some(world)),
+            MockDeviceMsg::ClearWorld => self.set_world(None),
+            MockDeviceMsg::VisibilityChange(visibility) => {
+                self.events.callback(Event::VisibilityChange(visibility));
+            }
+            MockDeviceMsg::SetUserPresent(present) => {
+                if self.user_present != present {
+                    self.user_present = present;
+                    self.events.callback(Event::UserPresenceChange(present));
+                }
+            }
+            MockDeviceMsg::PerformanceNotification(notification) => {
+                self.events
+                    .callback(Event::PerformanceNotification(notification));
+                if notification.sub_domain == PerformanceSubDomain::Thermal {
+                    self.set_status(DeviceStatus {
+                        thermal: notification.to.into(),
+                        ..self.status
+                    });
+                }
+            }
+            MockDeviceMsg::SetDeviceStatus(status) => self.set_status(status),
+            MockDeviceMsg::SetDepthInfo(depth) => {
+                self.depth = depth;
+            }
+            MockDeviceMsg::SetOcclusionDepth(depth) => {
+                self.occlusion_depth = depth;
+            }
+            MockDeviceMsg::SetBody(body) => {
+                self.body = body;
+            }
+            MockDeviceMsg::SetFace(face) => {
+                self.face = face;
+            }
+            MockDeviceMsg::SetLightEstimate(light_estimate) => {
+                self.light_estimate = light_estimate;
+            }
+            MockDeviceMsg::SetCameraImages(camera_images) => {
+                self.camera_images = camera_images;
             }
             MockDeviceMsg::Focus => {
-                // TODO
+                self.events
+                    .callback(Event::VisibilityChange(Visibility::Visible));
             }
             MockDeviceMsg::Blur => {
-                // TODO
+                self.events
+                    .callback(Event::VisibilityChange(Visibility::VisibleBlurred));
             }
             MockDeviceMsg::AddInputSource(init) => {
+                self.events.callback(Event::AddInput(init.source.clone()));
                 self.inputs.push(InputInfo {
                     source: init.source,
                     pointer: init.pointer_origin,
+                    grip: init.grip_origin,
+                    velocity: Default::default(),
+                    gamepad: init.gamepad,
+                    hand: init.hand,
+                    pinch: if init.pinch_select {
+                        Some(PinchSelect::default())
+                    } else {
+                        None
+                    },
                     active: true,
                 });
-                self.events.callback(Event::AddInput(init.source))
             }
             MockDeviceMsg::MessageInputSource(id, msg) => {
                 if let Some(ref mut input) = self.inputs.iter_mut().find(|i| i.source.id == id) {
                     match msg {
-                        MockInputMsg::SetHandedness(h) => input.source.handedness = h,
-                        MockInputMsg::SetTargetRayMode(t) => input.source.target_ray_mode = t,
+                        MockInputMsg::SetHandedness(h) => {
+                            input.source.handedness = h;
+                            self.events
+                                .callback(Event::UpdateInput(id, input.source.clone()));
+                        }
+                        MockInputMsg::SetTargetRayMode(t) => {
+                            input.source.target_ray_mode = t;
+                            self.events
+                                .callback(Event::UpdateInput(id, input.source.clone()));
+                        }
+                        MockInputMsg::SetProfiles(p) => {
+                            input.source.profiles = p;
+                            self.events
+                                .callback(Event::UpdateInput(id, input.source.clone()));
+                        }
                         MockInputMsg::SetPointerOrigin(p) => input.pointer = p,
-                        MockInputMsg::Disconnect => input.active = false,
-                        MockInputMsg::Reconnect => input.active = true,
+                        MockInputMsg::SetGripOrigin(g) => input.grip = g,
+                        MockInputMsg::SetVelocity(v) => input.velocity = v,
+                        MockInputMsg::SetGamepad(g) => input.gamepad = g,
+                        MockInputMsg::SetHand(h) => {
+                            input.hand = h;
+                            let hand_tracking =
+                                self.granted_features.contains(&Feature::HandTracking);
+                            let event = match input.pinch {
+                                Some(ref mut pinch) if hand_tracking => {
+                                    pinch.update(input.hand.as_ref().map(|hand| &**hand))
+                                }
+                                _ => None,
+                            };
+                            if let Some(event) = event {
+                                let frame = Box::new(self.frame());
+                                self.events.callback(Event::Select(
+                                    id,
+                                    SelectKind::Select,
+                                    event,
+                                    frame,
+                                ));
+                            }
+                        }
+                        MockInputMsg::SetButton(index, button) => {
+                            if let Some(b) = input
+                                .gamepad
+                                .as_mut()
+                                .and_then(|g| g.buttons.get_mut(index))
+                            {
+                                *b = button;
+                            }
+                        }
+                        MockInputMsg::SetAxis(index, value) => {
+                            if let Some(a) =
+                                input.gamepad.as_mut().and_then(|g| g.axes.get_mut(index))
+                            {
+                                *a = value;
+                            }
+                        }
+                        MockInputMsg::TriggerSelect(kind, event) => {
+                            let frame = Box::new(self.frame());
+                            self.events.callback(Event::Select(id, kind, event, frame));
+                        }
+                        MockInputMsg::Disconnect => {
+                            if input.active {
+                                input.active = false;
+                                self.events.callback(Event::RemoveInput(id));
+                            }
+                        }
+                        MockInputMsg::Reconnect => {
+                            if !input.active {
+                                input.active = true;
+                                self.events.callback(Event::AddInput(input.source.clone()));
+                            }
+                        }
                     }
                 }
             }
             MockDeviceMsg::Disconnect(sender) => {
                 self.connected = false;
                 self.disconnect_callbacks.push(sender);
-                self.events.callback(Event::SessionEnd);
+                self.events
+                    .callback(Event::SessionEnd(SessionEndReason::Disconnected));
             }
         }
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    use webxr_api::FrameCaptureCallback;
+    use webxr_api::FrameRequestCallback;
+    use webxr_api::FrameStats;
+    use webxr_api::FrameStatsCallback;
+    use webxr_api::Handedness;
+    use webxr_api::InputId;
+    use webxr_api::MainThreadRegistry;
+    use webxr_api::MockDeviceCallback;
+    use webxr_api::MockInputInit;
+    use webxr_api::MockRegion;
+    use webxr_api::MockScenario;
+    use webxr_api::Ray;
+    use webxr_api::SelectEvent;
+    use webxr_api::SessionRequestCallback;
+    use webxr_api::Triangle;
+    use webxr_api::View;
+
+    use euclid::Rect;
+    use euclid::Transform3D;
+
+    #[cfg(feature = "ipc")]
+    use serde::{Deserialize, Serialize};
+
+    use std::thread;
+    use std::time::Duration;
+
+    #[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+    struct DeviceReply(webxr_api::Sender<Result<webxr_api::Sender<MockDeviceMsg>, Error>>);
+
+    #[cfg_attr(feature = "ipc", typetag::serde)]
+    impl MockDeviceCallback for DeviceReply {
+        fn callback(&mut self, result: Result<webxr_api::Sender<MockDeviceMsg>, Error>) {
+            let _ = self.0.send(result);
+        }
+    }
+
+    #[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+    struct SessionReply(webxr_api::Sender<Result<Session, Error>>);
+
+    #[cfg_attr(feature = "ipc", typetag::serde)]
+    impl SessionRequestCallback for SessionReply {
+        fn callback(&mut self, result: Result<Session, Error>) {
+            let _ = self.0.send(result);
+        }
+    }
+
+    #[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+    pub(crate) struct EventReply(pub(crate) webxr_api::Sender<Event>);
+
+    #[cfg_attr(feature = "ipc", typetag::serde)]
+    impl EventCallback for EventReply {
+        fn callback(&mut self, event: Event) {
+            let _ = self.0.send(event);
+        }
+    }
+
+    #[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+    struct FrameReply(webxr_api::Sender<Frame>);
+
+    #[cfg_attr(feature = "ipc", typetag::serde)]
+    impl FrameRequestCallback for FrameReply {
+        fn callback(&mut self, _time: f64, frame: Frame) {
+            let _ = self.0.send(frame);
+        }
+    }
+
+    #[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+    struct StatsReply(webxr_api::Sender<FrameStats>);
+
+    #[cfg_attr(feature = "ipc", typetag::serde)]
+    impl FrameStatsCallback for StatsReply {
+        fn callback(&mut self, stats: FrameStats) {
+            let _ = self.0.send(stats);
+        }
+    }
+
+    #[cfg_attr(feature = "ipc", derive(Serialize, Deserialize))]
+    struct CaptureReply(webxr_api::Sender<Result<FrameCapture, Error>>);
+
+    #[cfg_attr(feature = "ipc", typetag::serde)]
+    impl FrameCaptureCallback for CaptureReply {
+        fn callback(&mut self, capture: Result<FrameCapture, Error>) {
+            let _ = self.0.send(capture);
+        }
+    }
+
+    fn mock_channel() -> (
+        webxr_api::Sender<MockDeviceMsg>,
+        Arc<(Mutex<MockChannel>, Condvar)>,
+    ) {
+        let (sender, receiver) = webxr_api::channel().unwrap();
+        let channel = MockChannel {
+            receiver: Some(receiver),
+            queues: vec![],
+            next_id: 0,
+        };
+        (sender, Arc::new((Mutex::new(channel), Condvar::new())))
+    }
+
+    fn is_clear_world(msg: Option<MockDeviceMsg>) -> bool {
+        match msg {
+            Some(MockDeviceMsg::ClearWorld) => true,
+            _ => false,
+        }
+    }
+
+    pub(crate) fn device_init() -> MockDeviceInit {
+        let viewport = Rect::new(Point2D::new(0, 0), euclid::Size2D::new(64, 64));
+        let view = View {
+            transform: RigidTransform3D::identity(),
+            projection: Transform3D::identity(),
+            viewport,
+        };
+        MockDeviceInit {
+            floor_origin: RigidTransform3D::identity(),
+            supports_immersive: true,
+            supports_unbounded: false,
+            viewer_origin: RigidTransform3D::identity(),
+            views: Views::Mono(view),
+            supported_features: vec![],
+            supported_frame_rates: vec![],
+            supported_color_spaces: vec![ColorSpace::Rec709],
+            bounds_geometry: None,
+            world: None,
+            clock: MockClock::FixedTimestep(10.0),
+        }
+    }
+
+    // Connect a mock device, on a thread of its own, and start an immersive session on it
+    fn start_session(init: MockDeviceInit) -> (webxr_api::Sender<MockDeviceMsg>, Session) {
+        start_session_with(init, SessionInit::default())
+    }
+
+    fn start_session_with(
+        init: MockDeviceInit,
+        session_init: SessionInit,
+    ) -> (webxr_api::Sender<MockDeviceMsg>, Session) {
+        let mut registry = MainThreadRegistry::new().unwrap();
+        registry.register_mock(HeadlessMockDiscovery::new_threaded());
+        let device = connect_device(&mut registry, init);
+        (device, request_session(&mut registry, session_init))
+    }
+
+    // The registry needs a threaded mock discovery registered
+    pub(crate) fn connect_device(
+        registry: &mut MainThreadRegistry,
+        init: MockDeviceInit,
+    ) -> webxr_api::Sender<MockDeviceMsg> {
+        let (sender, receiver) = webxr_api::channel().unwrap();
+        registry
+            .registry()
+            .simulate_device_connection(init, DeviceReply(sender));
+        registry.run_one_frame();
+        receiver.recv().unwrap().unwrap()
+    }
+
+    pub(crate) fn request_session(
+        registry: &mut MainThreadRegistry,
+        session_init: SessionInit,
+    ) -> Session {
+        let (sender, receiver) = webxr_api::channel().unwrap();
+        registry.registry().request_session(
+            SessionMode::ImmersiveVR,
+            session_init,
+            SessionReply(sender),
+        );
+        registry.run_one_frame();
+        receiver.recv().unwrap().unwrap()
+    }
+
+    // Handling any message makes the session handle the device's test messages first
+    pub(crate) fn next_frame(session: &mut Session) -> Frame {
+        let (sender, frames) = webxr_api::channel().unwrap();
+        let _ = session.request_animation_frame(FrameReply(sender));
+        let frame = frames.recv().unwrap();
+        session.render_animation_frame();
+        frame
+    }
+
+    // Skip events until one that matches, failing if none arrives
+    pub(crate) fn wait_for_event<F>(events: &webxr_api::Receiver<Event>, matches: F) -> Event
+    where
+        F: Fn(&Event) -> bool,
+    {
+        loop {
+            let event = events
+                .recv_timeout(Duration::from_secs(5))
+                .expect("no matching event");
+            if matches(&event) {
+                return event;
+            }
+        }
+    }
+
+    #[test]
+    fn viewer_origin_moves_the_viewer() {
+        let (device, mut session) = start_session(device_init());
+        let origin = RigidTransform3D::from_translation(Vector3D::new(1.0, 1.5, -2.0));
+        device.send(MockDeviceMsg::SetViewerOrigin(origin)).unwrap();
+        let frame = next_frame(&mut session);
+        assert_eq!(frame.transform.translation, origin.translation);
+        session.end_session();
+    }
+
+    #[test]
+    fn floor_origin_resets_the_floor() {
+        let (device, mut session) = start_session(device_init());
+        let (sender, events) = webxr_api::channel().unwrap();
+        session.set_event_callback(EventReply(sender));
+        let origin = RigidTransform3D::from_translation(Vector3D::new(0.0, -1.5, 0.0));
+        device.send(MockDeviceMsg::SetFloorOrigin(origin)).unwrap();
+        let _ = next_frame(&mut session);
+        let event = wait_for_event(&events, |event| match event {
+            Event::UpdateFloorTransform(..) => true,
+            _ => false,
+        });
+        match event {
+            Event::UpdateFloorTransform(transform) => {
+                assert_eq!(transform.translation, Vector3D::new(0.0, 1.5, 0.0))
+            }
+            _ => unreachable!(),
+        }
+        session.end_session();
+    }
+
+    #[test]
+    fn visibility_changes_are_reported() {
+        let (device, mut session) = start_session(device_init());
+        let (sender, events) = webxr_api::channel().unwrap();
+        session.set_event_callback(EventReply(sender));
+        device
+            .send(MockDeviceMsg::VisibilityChange(Visibility::VisibleBlurred))
+            .unwrap();
+        let _ = next_frame(&mut session);
+        wait_for_event(&events, |event| match event {
+            Event::VisibilityChange(Visibility::VisibleBlurred) => true,
+            _ => false,
+        });
+        session.end_session();
+    }
+
+    #[test]
+    fn bounds_geometry_changes_are_reported() {
+        let mut init = device_init();
+        init.supported_features = vec![Feature::BoundedFloor];
+        let session_init = SessionInit {
+            required_features: vec![Feature::BoundedFloor],
+            ..SessionInit::default()
+        };
+        let (device, mut session) = start_session_with(init, session_init);
+        assert!(session.bounds_geometry().is_none());
+        let (sender, events) = webxr_api::channel().unwrap();
+        session.set_event_callback(EventReply(sender));
+        let bounds = vec![
+            Point2D::new(-1.0, -1.0),
+            Point2D::new(1.0, -1.0),
+            Point2D::new(1.0, 1.0),
+            Point2D::new(-1.0, 1.0),
+        ];
+        device
+            .send(MockDeviceMsg::SetBoundsGeometry(Some(bounds.clone())))
+            .unwrap();
+        let _ = next_frame(&mut session);
+        let event = wait_for_event(&events, |event| match event {
+            Event::UpdateBoundsGeometry(..) => true,
+            _ => false,
+        });
+        match event {
+            Event::UpdateBoundsGeometry(reported) => assert_eq!(reported, Some(bounds)),
+            _ => unreachable!(),
+        }
+        session.end_session();
+    }
+
+    #[test]
+    fn disconnect_ends_the_session_before_acknowledging() {
+        let (device, mut session) = start_session(device_init());
+        let (sender, events) = webxr_api::channel().unwrap();
+        session.set_event_callback(EventReply(sender));
+        let _ = next_frame(&mut session);
+        let (sender, disconnected) = webxr_api::channel().unwrap();
+        device.send(MockDeviceMsg::Disconnect(sender)).unwrap();
+        let (sender, frames) = webxr_api::channel().unwrap();
+        let _ = session.request_animation_frame(FrameReply(sender));
+        disconnected
+            .recv_timeout(Duration::from_secs(5))
+            .expect("disconnect not acknowledged");
+        // The session ended before it could produce the frame requested after disconnecting
+        match events.try_recv() {
+            Ok(Event::SessionEnd(SessionEndReason::Disconnected)) => (),
+            _ => panic!("session didn't end"),
+        }
+        assert!(frames.recv_timeout(Duration::from_millis(50)).is_err());
+    }
+
+    // A device with a floor plane a metre below the viewer, with hit testing enabled
+    fn start_hit_testing() -> (webxr_api::Sender<MockDeviceMsg>, Session) {
+        let corner = |x, z| Vector3D::new(x, -1.0, z);
+        let floor = MockRegion {
+            faces: vec![
+                Triangle {
+                    first: corner(-10.0, -10.0),
+                    second: corner(10.0, -10.0),
+                    third: corner(10.0, 10.0),
+                },
+                Triangle {
+                    first: corner(-10.0, -10.0),
+                    second: corner(10.0, 10.0),
+                    third: corner(-10.0, 10.0),
+                },
+            ],
+            ty: EntityType::Plane,
+        };
+        let mut init = device_init();
+        init.supported_features = vec![Feature::HitTest];
+        init.world = Some(MockWorld {
+            regions: vec![floor],
+        });
+        let session_init = SessionInit {
+            required_features: vec![Feature::HitTest],
+            ..SessionInit::default()
+        };
+        start_session_with(init, session_init)
+    }
+
+    fn hit_test_down(entity_types: Vec<EntityType>) -> HitTestInit {
+        HitTestInit {
+            space: BaseSpace::Viewer,
+            ray: Ray {
+                origin: Vector3D::new(0.5, 0.0, -0.25),
+                direction: Vector3D::new(0.0, -1.0, 0.0),
+            },
+            entity_types,
+        }
+    }
+
+    #[test]
+    fn hit_tests_report_hits_until_cancelled() {
+        let (_device, mut session) = start_hit_testing();
+        let id = session
+            .request_hit_test(hit_test_down(vec![EntityType::Plane]))
+            .unwrap();
+        let frame = next_frame(&mut session);
+        assert_eq!(frame.hit_test_results.len(), 1);
+        assert_eq!(frame.hit_test_results[0].id, id);
+        assert_eq!(
+            frame.hit_test_results[0].space.translation,
+            Vector3D::new(0.5, -1.0, -0.25)
+        );
+
+        session.cancel_hit_test(id);
+        assert!(next_frame(&mut session).hit_test_results.is_empty());
+        session.end_session();
+    }
+
+    #[test]
+    fn hit_tests_only_hit_the_given_types() {
+        let (_device, mut session) = start_hit_testing();
+        let _ = session
+            .request_hit_test(hit_test_down(vec![EntityType::Mesh, EntityType::Point]))
+            .unwrap();
+        assert!(next_frame(&mut session).hit_test_results.is_empty());
+        session.end_session();
+    }
+
+    #[test]
+    fn hit_tests_need_the_feature() {
+        let (_device, mut session) = start_session(device_init());
+        let result = session.request_hit_test(hit_test_down(vec![EntityType::Plane]));
+        assert_eq!(result.unwrap_err(), Error::UnsupportedFeature);
+        session.end_session();
+    }
+
+    fn start_anchoring() -> (webxr_api::Sender<MockDeviceMsg>, Session) {
+        let mut init = device_init();
+        init.supported_features = vec![Feature::Anchors];
+        let session_init = SessionInit {
+            required_features: vec![Feature::Anchors],
+            ..SessionInit::default()
+        };
+        start_session_with(init, session_init)
+    }
+
+    #[test]
+    fn anchors_are_reported_until_deleted() {
+        let (_device, mut session) = start_anchoring();
+        let pose = RigidTransform3D::from_translation(Vector3D::new(0.0, 0.0, -1.0));
+        let id = session.create_anchor(pose).unwrap();
+        let frame = next_frame(&mut session);
+        assert_eq!(frame.anchors.len(), 1);
+        assert_eq!(frame.anchors[0].id, id);
+        assert_eq!(frame.anchors[0].pose.translation, pose.translation);
+
+        session.delete_anchor(id);
+        assert!(next_frame(&mut session).anchors.is_empty());
+        session.end_session();
+    }
+
+    #[test]
+    fn anchors_are_not_reported_while_untracked() {
+        let (device, mut session) = start_anchoring();
+        let id = session.create_anchor(RigidTransform3D::identity()).unwrap();
+        device
+            .send(MockDeviceMsg::MessageAnchor(
+                id,
+                MockAnchorMsg::PauseTracking,
+            ))
+            .unwrap();
+        assert!(next_frame(&mut session).anchors.is_empty());
+        device
+            .send(MockDeviceMsg::MessageAnchor(
+                id,
+                MockAnchorMsg::ResumeTracking,
+            ))
+            .unwrap();
+        assert_eq!(next_frame(&mut session).anchors.len(), 1);
+        session.end_session();
+    }
+
+    #[test]
+    fn anchors_need_the_feature() {
+        let (_device, mut session) = start_session(device_init());
+        let result = session.create_anchor(RigidTransform3D::identity());
+        assert_eq!(result.unwrap_err(), Error::UnsupportedFeature);
+        session.end_session();
+    }
+
+    #[test]
+    fn exported_poses_import_unchanged() {
+        let rotation = Rotation3D::around_y(Angle::radians(0.5));
+        let pose = RigidTransform3D::new(rotation, Vector3D::new(1.0, -2.0, 3.5));
+        let imported = import_pose(&export_pose(&pose)).unwrap();
+        assert_eq!(imported.rotation, pose.rotation);
+        assert_eq!(imported.translation, pose.translation);
+        assert!(import_pose(&ExportedAnchor(vec![0; 27])).is_none());
+    }
+
+    #[test]
+    fn exported_anchors_import_into_other_sessions() {
+        let (_device, mut session) = start_anchoring();
+        let pose = RigidTransform3D::from_translation(Vector3D::new(0.0, 1.0, -1.0));
+        let id = session.create_anchor(pose).unwrap();
+        let exported = session.export_anchor(id).unwrap();
+        session.end_session();
+
+        let (_device, mut session) = start_anchoring();
+        let id = session.import_anchor(exported).unwrap();
+        let frame = next_frame(&mut session);
+        assert_eq!(frame.anchors.len(), 1);
+        assert_eq!(frame.anchors[0].id, id);
+        assert_eq!(frame.anchors[0].pose.translation, pose.translation);
+        assert!(session.import_anchor(ExportedAnchor(vec![])).is_err());
+        session.end_session();
+    }
+
+    #[test]
+    fn manual_clock_waits_for_ticks() {
+        let mut init = device_init();
+        init.clock = MockClock::Manual;
+        let (device, mut session) = start_session(init);
+        let (sender, frames) = webxr_api::channel().unwrap();
+        let _ = session.request_animation_frame(FrameReply(sender.clone()));
+        assert!(frames.recv_timeout(Duration::from_millis(50)).is_err());
+        device.send(MockDeviceMsg::Tick(5.0)).unwrap();
+        let frame = frames.recv().unwrap();
+        assert_eq!(frame.timing.predicted_display_time, Some(5.0));
+        session.render_animation_frame();
+
+        let _ = session.request_animation_frame(FrameReply(sender));
+        device.send(MockDeviceMsg::Tick(10.0)).unwrap();
+        let frame = frames.recv().unwrap();
+        assert_eq!(frame.timing.predicted_display_time, Some(15.0));
+        session.end_session();
+    }
+
+    #[test]
+    fn cancelled_frame_requests_are_never_called() {
+        let mut init = device_init();
+        init.clock = MockClock::Manual;
+        let (device, mut session) = start_session(init);
+        let (sender, cancelled_frames) = webxr_api::channel().unwrap();
+        let id = session.request_animation_frame(FrameReply(sender));
+        session.cancel_animation_frame(id);
+        let (sender, frames) = webxr_api::channel().unwrap();
+        let _ = session.request_animation_frame(FrameReply(sender));
+
+        // The cancelled request's frame is dropped, and the next request gets the next frame
+        device.send(MockDeviceMsg::Tick(5.0)).unwrap();
+        assert!(cancelled_frames.recv().is_err());
+        device.send(MockDeviceMsg::Tick(5.0)).unwrap();
+        let frame = frames.recv().unwrap();
+        assert_eq!(frame.timing.predicted_display_time, Some(10.0));
+        session.end_session();
+    }
+
+    #[test]
+    fn frames_requested_before_rendering_are_not_dropped() {
+        let (_device, mut session) = start_session(device_init());
+        let (sender, frames) = webxr_api::channel().unwrap();
+        // Content asks for the next frame before rendering the one it has
+        let _ = session.request_animation_frame(FrameReply(sender.clone()));
+        let _ = frames.recv().unwrap();
+        let _ = session.request_animation_frame(FrameReply(sender));
+        let frame = frames.recv().unwrap();
+        assert_eq!(frame.timing.dropped_frames, 0);
+        session.render_animation_frame();
+        session.render_animation_frame();
+        let (sender, stats) = webxr_api::channel().unwrap();
+        session.request_frame_stats(StatsReply(sender));
+        let stats = stats.recv().unwrap();
+        assert_eq!(stats.dropped_frames, 0);
+        assert_eq!(stats.frame_count, 2);
+        session.end_session();
+    }
+
+    #[test]
+    fn scenarios_step_with_the_device_clock() {
+        let (device, mut session) = start_session(device_init());
+        let (sender, events) = webxr_api::channel().unwrap();
+        session.set_event_callback(EventReply(sender));
+        let origin = |x| RigidTransform3D::from_translation(Vector3D::new(x, 0.0, 0.0));
+        let step = |time, action| MockScenarioStep { time, action };
+        let scenario = MockScenario {
+            steps: vec![
+                step(0.0, MockScenarioAction::SetViewerOrigin(origin(1.0))),
+                step(20.0, MockScenarioAction::SetViewerOrigin(origin(2.0))),
+                step(40.0, MockScenarioAction::Disconnect),
+            ],
+        };
+        device.send(MockDeviceMsg::PlayScenario(scenario)).unwrap();
+
+        // The device's clock steps 10ms a frame, from the first frame after the scenario starts
+        for &x in &[1.0, 1.0, 2.0, 2.0] {
+            let frame = next_frame(&mut session);
+            assert_eq!(frame.transform.translation.x, x);
+        }
+        // The next frame is the one the device disconnects in
+        let (sender, _frames) = webxr_api::channel().unwrap();
+        let _ = session.request_animation_frame(FrameReply(sender));
+        wait_for_event(&events, |event| match event {
+            Event::SessionEnd(SessionEndReason::Disconnected) => true,
+            _ => false,
+        });
+    }
+
+    fn add_gaze_input(device: &webxr_api::Sender<MockDeviceMsg>) -> InputId {
+        let id = InputId(1);
+        let source = InputSource {
+            handedness: Handedness::None,
+            target_ray_mode: TargetRayMode::Gaze,
+            id,
+            profiles: vec![],
+            has_grip: false,
+        };
+        let init = MockInputInit {
+            source,
+            pointer_origin: RigidTransform3D::identity(),
+            grip_origin: None,
+            gamepad: None,
+            hand: None,
+            pinch_select: false,
+        };
+        device.send(MockDeviceMsg::AddInputSource(init)).unwrap();
+        id
+    }
+
+    #[test]
+    fn gaze_rays_follow_the_viewer() {
+        let (device, mut session) = start_session(device_init());
+        let id = add_gaze_input(&device);
+        let origin = RigidTransform3D::from_translation(Vector3D::new(1.0, 1.5, -2.0));
+        device.send(MockDeviceMsg::SetViewerOrigin(origin)).unwrap();
+        let frame = next_frame(&mut session);
+        assert_eq!(frame.inputs[0].id, id);
+        assert_eq!(
+            frame.inputs[0].target_ray_origin.translation,
+            origin.translation
+        );
+        session.end_session();
+    }
+
+    #[test]
+    fn gaze_selects_are_reported_with_their_frame() {
+        let (device, mut session) = start_session(device_init());
+        let (sender, events) = webxr_api::channel().unwrap();
+        session.set_event_callback(EventReply(sender));
+        let id = add_gaze_input(&device);
+        let origin = RigidTransform3D::from_translation(Vector3D::new(0.0, 0.0, -0.5));
+        let select = MockInputMsg::TriggerSelect(SelectKind::Select, SelectEvent::Select);
+        device
+            .send(MockDeviceMsg::MessageInputSource(
+                id,
+                MockInputMsg::SetPointerOrigin(origin),
+            ))
+            .unwrap();
+        device
+            .send(MockDeviceMsg::MessageInputSource(id, select))
+            .unwrap();
+        let _ = next_frame(&mut session);
+        let event = wait_for_event(&events, |event| match event {
+            Event::Select(..) => true,
+            _ => false,
+        });
+        match event {
+            Event::Select(input, kind, event, frame) => {
+                assert_eq!(input, id);
+                assert_eq!(kind, SelectKind::Select);
+                assert_eq!(event, SelectEvent::Select);
+                assert_eq!(
+                    frame.inputs[0].target_ray_origin.translation,
+                    origin.translation
+                );
+            }
+            _ => unreachable!(),
+        }
+        session.end_session();
+    }
+
+    #[test]
+    fn captures_are_only_of_the_next_frame() {
+        let (_device, mut session) = start_session(device_init());
+        let (sender, captures) = webxr_api::channel().unwrap();
+        session.capture_frame(CaptureReply(sender));
+        let _ = next_frame(&mut session);
+        // Threaded devices have no GL to read the frame back with
+        let capture = captures.recv().unwrap();
+        assert_eq!(capture.err(), Some(Error::UnsupportedFeature));
+        // Once the session has answered the next frame request it has rendered the frame before
+        let _ = next_frame(&mut session);
+        let _ = next_frame(&mut session);
+        assert!(captures.try_recv().is_err());
+        session.end_session();
+    }
+
+    #[test]
+    fn every_session_sees_each_message() {
+        let (sender, channel) = mock_channel();
+        let first = MockChannel::subscribe(&channel);
+        let second = MockChannel::subscribe(&channel);
+        sender.send(MockDeviceMsg::ClearWorld).unwrap();
+        assert!(is_clear_world(first.try_recv()));
+        assert!(first.try_recv().is_none());
+        assert!(is_clear_world(second.try_recv()));
+    }
+
+    #[test]
+    fn blocked_session_does_not_hold_up_others() {
+        let (sender, channel) = mock_channel();
+        let blocked = MockChannel::subscribe(&channel);
+        let other = MockChannel::subscribe(&channel);
+        let waiting = thread::spawn(move || blocked.recv());
+        while channel.0.lock().unwrap().receiver.is_some() {
+            thread::yield_now();
+        }
+        // This would wait for the blocked session's message if it held the lock
+        assert!(other.try_recv().is_none());
+        sender.send(MockDeviceMsg::ClearWorld).unwrap();
+        assert!(is_clear_world(waiting.join().unwrap()));
+        assert!(is_clear_world(other.try_recv()));
+    }
+}
@@ -0,0 +1,346 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! A device which replays a session recorded with `MainThreadRegistry::record_sessions`.
+
+use webxr_api::read_recording;
+use webxr_api::DepthSensingConfig;
+use webxr_api::Device;
+use webxr_api::Discovery;
+use webxr_api::EnvironmentBlendMode;
+use webxr_api::Error;
+use webxr_api::Event;
+use webxr_api::EventBuffer;
+use webxr_api::EventCallback;
+use webxr_api::EventFilter;
+use webxr_api::Feature;
+use webxr_api::Floor;
+use webxr_api::Frame;
+use webxr_api::InputSource;
+use webxr_api::Native;
+use webxr_api::Record;
+use webxr_api::Session;
+use webxr_api::SessionBuilder;
+use webxr_api::SessionCapabilities;
+use webxr_api::SessionEndReason;
+use webxr_api::SessionInit;
+use webxr_api::SessionMode;
+use webxr_api::SessionRecord;
+use webxr_api::Viewport;
+use webxr_api::Views;
+
+use euclid::default::Size2D as UntypedSize2D;
+use euclid::Point2D;
+use euclid::RigidTransform3D;
+use euclid::Size2D;
+
+use gleam::gl;
+use gleam::gl::GLsync;
+use gleam::gl::GLuint;
+use gleam::gl::Gl;
+
+use std::io;
+use std::path::Path;
+use std::rc::Rc;
+use std::vec;
+
+/// Replays a recording as a device. Each session requested plays
+/// the recording from the start, whatever its mode.
+pub struct ReplayDiscovery {
+    gl: Option<Rc<dyn Gl>>,
+    session: SessionRecord,
+    records: Vec<Record>,
+}
+
+struct ReplayDevice {
+    gl: Option<Rc<dyn Gl>>,
+    session: SessionRecord,
+    records: vec::IntoIter<Record>,
+    frame: Frame,
+    events: EventBuffer,
+    connected: bool,
+}
+
+impl ReplayDiscovery {
+    pub fn new(gl: Rc<dyn Gl>, path: &Path) -> io::Result<ReplayDiscovery> {
+        ReplayDiscovery::open(Some(gl), path)
+    }
+
+    /// A replay discovery without GL, whose devices each run on their own thread
+    /// rather than the main thread. The frames they are given are not waited for.
+    pub fn new_threaded(path: &Path) -> io::Result<ReplayDiscovery> {
+        ReplayDiscovery::open(None, path)
+    }
+
+    fn open(gl: Option<Rc<dyn Gl>>, path: &Path) -> io::Result<ReplayDiscovery> {
+        let mut records = read_recording(path)?.into_iter();
+        let session = match records.next() {
+            Some(Record::Session(session)) => session,
+            _ => {
+                let err = "recording does not start with a session";
+                return Err(io::Error::new(io::ErrorKind::InvalidData, err));
+            }
+        };
+        Ok(ReplayDiscovery {
+            gl,
+            session,
+            records: records.collect(),
+        })
+    }
+}
+
+impl Discovery for ReplayDiscovery {
+    fn request_session(
+        &mut self,
+        mode: SessionMode,
+        init: &SessionInit,
+        xr: SessionBuilder,
+    ) -> Result<Session, Error> {
+        // The recorded session can only replay the features it was granted.
+        init.validate(mode, &self.session.granted_features)?;
+        let mut records = self.records.clone().into_iter();
+        let mut events = EventBuffer::default();
+        let frame = loop {
+            match records.next() {
+                Some(Record::Frame(frame)) => break frame,
+                Some(Record::Event(Event::SessionEnd(_))) => {}
+                Some(Record::Event(event)) => events.callback(event),
+                _ => return Err(Error::NoMatchingDevice),
+            }
+        };
+        let session = self.session.clone();
+        let device = move |gl| {
+            Ok(ReplayDevice {
+                gl,
+                session,
+                records,
+                frame,
+                events,
+                connected: true,
+            })
+        };
+        // Without GL, nothing ties the device to the main thread
+        match self.gl.clone() {
+            Some(gl) => xr.run_on_main_thread(move || device(Some(gl))),
+            None => xr.spawn(move || device(None)),
+        }
+    }
+
+    fn supports_session(&self, _mode: SessionMode) -> bool {
+        true
+    }
+
+    fn supported_features(&self, _mode: SessionMode) -> Option<Vec<Feature>> {
+        Some(self.session.granted_features.clone())
+    }
+
+    fn capabilities(&self, mode: SessionMode) -> Option<SessionCapabilities> {
+        // The replayed session is whatever was recorded, whichever mode is asked for
+        Some(SessionCapabilities {
+            mode,
+            features: Some(self.session.granted_features.clone()),
+            environment_blend_modes: vec![self.session.environment_blend_mode],
+            frame_rates: self.session.supported_frame_rates.clone(),
+            view_count: Some(self.session.views.viewports().len()),
+        })
+    }
+}
+
+impl Device for ReplayDevice {
+    fn floor_transform(&self) -> RigidTransform3D<f32, Native, Floor> {
+        self.session.floor_transform
+    }
+
+    fn bounds_geometry(&self) -> Option<Vec<Point2D<f32, Floor>>> {
+        self.session.bounds_geometry.clone()
+    }
+
+    fn views(&self) -> Views {
+        self.session.views.clone()
+    }
+
+    fn views_into(&self, views: &mut Views) {
+        views.clone_from(&self.session.views)
+    }
+
+    fn recommended_framebuffer_resolution(&self) -> Size2D<i32, Viewport> {
+        self.session.resolution
+    }
+
+    fn supported_frame_rates(&self) -> Vec<f32> {
+        self.session.supported_frame_rates.clone()
+    }
+
+    fn max_frames_in_flight(&self) -> usize {
+        self.session.max_frames_in_flight
+    }
+
+    fn wait_for_animation_frame(&mut self) -> Frame {
+        // The first frame was read when the session started, so the events
+        // before it have already been sent. The last frame is repeated
+        // once the recording ends.
+        let _span = webxr_api::span("replay_frame");
+        let frame = self.frame.clone();
+        loop {
+            match self.records.next() {
+                Some(Record::Frame(next)) => {
+                    self.frame = next;
+                    break;
+                }
+                // The session ends when the replay does, however the recording ended
+                Some(Record::Event(Event::SessionEnd(_))) => {}
+                Some(Record::Event(event)) => {
+                    if let Event::UpdateViews(ref views) = event {
+                        self.session.views = views.clone();
+                    }
+                    self.events.callback(event)
+                }
+                Some(Record::Session(_)) => {}
+                None => {
+                    self.quit();
+                    break;
+                }
+            }
+        }
+        frame
+    }
+
+    fn render_animation_frame(&mut self, _: GLuint, _: UntypedSize2D<i32>, sync: GLsync) {
+        if let Some(ref gl) = self.gl {
+            let _span = webxr_api::span("wait_sync");
+            gl.wait_sync(sync, 0, gl::TIMEOUT_IGNORED);
+        }
+    }
+
+    fn granted_features(&self) -> Vec<Feature> {
+        self.session.granted_features.clone()
+    }
+
+    fn depth_sensing_config(&self) -> Option<DepthSensingConfig> {
+        self.session.depth_sensing_config
+    }
+
+    fn environment_blend_mode(&self) -> EnvironmentBlendMode {
+        self.session.environment_blend_mode
+    }
+
+    fn initial_inputs(&self) -> Vec<InputSource> {
+        self.session.initial_inputs.clone()
+    }
+
+    fn set_event_callback(&mut self, callback: Box<dyn EventCallback>) {
+        self.events.upgrade(callback)
+    }
+
+    fn subscribe_events(&mut self, filter: EventFilter, callback: Box<dyn EventCallback>) {
+        self.events.subscribe(filter, callback)
+    }
+
+    fn connected(&mut self) -> bool {
+        self.connected
+    }
+
+    fn quit(&mut self) {
+        if self.connected {
+            self.connected = false;
+            self.events
+                .callback(Event::SessionEnd(SessionEndReason::Requested));
+        }
+    }
+}
+
+#[cfg(all(test, feature = "headless"))]
+mod tests {
+    use super::*;
+
+    use crate::headless::tests::connect_device;
+    use crate::headless::tests::device_init;
+    use crate::headless::tests::next_frame;
+    use crate::headless::tests::request_session;
+    use crate::headless::tests::wait_for_event;
+    use crate::headless::tests::EventReply;
+    use crate::headless::HeadlessMockDiscovery;
+    use webxr_api::MainThreadRegistry;
+    use webxr_api::MockDeviceMsg;
+    use webxr_api::Visibility;
+
+    use euclid::Vector3D;
+
+    use std::fs;
+    use std::thread;
+    use std::time::Duration;
+    use std::time::Instant;
+
+    // The recording is written once the session has ended, which it does on its own thread
+    fn wait_for_recording(path: &Path) -> Vec<Record> {
+        let start = Instant::now();
+        loop {
+            let records = read_recording(path).unwrap();
+            if let Some(Record::Event(Event::SessionEnd(_))) = records.last() {
+                return records;
+            }
+            assert!(
+                start.elapsed() < Duration::from_secs(5),
+                "recording not written"
+            );
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn replays_recorded_poses_and_events() {
+        let path = std::env::temp_dir().join(format!("webxr-replay-{}", std::process::id()));
+        let mut registry = MainThreadRegistry::new().unwrap();
+        registry.register_mock(HeadlessMockDiscovery::new_threaded());
+        registry.record_sessions(Some(path.clone()));
+        let device = connect_device(&mut registry, device_init());
+        let mut session = request_session(&mut registry, SessionInit::default());
+        let (sender, events) = webxr_api::channel().unwrap();
+        session.set_event_callback(EventReply(sender));
+        let origins: Vec<_> = (1..4)
+            .map(|x| RigidTransform3D::from_translation(Vector3D::new(x as f32, 1.5, 0.0)))
+            .collect();
+        for origin in &origins {
+            device
+                .send(MockDeviceMsg::SetViewerOrigin(*origin))
+                .unwrap();
+            let _ = next_frame(&mut session);
+        }
+        device
+            .send(MockDeviceMsg::VisibilityChange(Visibility::VisibleBlurred))
+            .unwrap();
+        let recorded = next_frame(&mut session);
+        wait_for_event(&events, |event| match event {
+            Event::VisibilityChange(Visibility::VisibleBlurred) => true,
+            _ => false,
+        });
+        session.end_session();
+        let _ = wait_for_recording(&path);
+
+        let mut registry = MainThreadRegistry::new().unwrap();
+        registry.register(ReplayDiscovery::new_threaded(&path).unwrap());
+        let mut session = request_session(&mut registry, SessionInit::default());
+        let (sender, events) = webxr_api::channel().unwrap();
+        session.set_event_callback(EventReply(sender));
+        for origin in &origins {
+            let frame = next_frame(&mut session);
+            assert_eq!(frame.transform.translation, origin.translation);
+        }
+        let replayed = next_frame(&mut session);
+        assert_eq!(
+            replayed.transform.translation,
+            recorded.transform.translation
+        );
+        assert_eq!(
+            replayed.timing.predicted_display_time,
+            recorded.timing.predicted_display_time
+        );
+        wait_for_event(&events, |event| match event {
+            Event::VisibilityChange(Visibility::VisibleBlurred) => true,
+            _ => false,
+        });
+        session.end_session();
+        let _ = fs::remove_file(&path);
+    }
+}